@@ -30,7 +30,7 @@ impl App {
         if !input.is_empty() {
             input.pop();
             self.term.clear_chars(input.len().saturating_add(1))?;
-            self.term.write(format!("{input}").as_bytes())?;
+            self.term.write_all(input.as_bytes())?;
             self.term.flush()?;
         }
         Ok(())
@@ -38,7 +38,7 @@ impl App {
 
     pub fn write(&mut self, input: &mut String) -> Result<()> {
         self.term.clear_chars(input.len().saturating_sub(1))?;
-        self.term.write(format!("{input}").as_bytes())?;
+        self.term.write_all(input.as_bytes())?;
         self.term.flush()?;
         Ok(())
     }
@@ -67,7 +67,7 @@ impl App {
             },
             Command::Sql(cmd) => match self.db.as_ref() {
                 Some(db) => {
-                    if let Err(msg) = repl::run(cmd, db) {
+                    if let Err(msg) = repl::run(*cmd, db) {
                         self.router(Command::Error(msg.to_string()))?
                     }
                 }
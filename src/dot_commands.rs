@@ -1,4 +1,4 @@
-use crate::sqlite::db::Database;
+use crate::sqlite::{db::Database, schemas::SchemaType};
 
 pub fn handle_dot_commands(command: String, db: &Database) {
     let words: Vec<_> = command.split_whitespace().collect();
@@ -11,12 +11,18 @@ pub fn handle_dot_commands(command: String, db: &Database) {
             let schemas = db.get_schemas_vec();
             let names = schemas
                 .iter()
+                .filter(|s| matches!(s.stype, SchemaType::Table | SchemaType::View))
                 .filter(|s| s.name != "sqlite_sequence")
                 .map(|s| s.name.clone())
                 .collect::<Vec<String>>()
                 .join(" ");
             println!("{}", names);
         }
+        [word] if *word == ".collations" => {
+            for name in db.collations.names() {
+                println!("{}", name);
+            }
+        }
         [word] if *word == ".schemas" => {
             let schemas = db.get_schemas_vec();
             println!("{:#?}", schemas);
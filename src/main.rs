@@ -21,7 +21,7 @@ fn main() -> Result<()> {
             app.router(Command::Load(db_file_path))?;
             println!();
             let stmt = args[2].parse()?;
-            app.router(Command::Sql(stmt))?;
+            app.router(Command::Sql(Box::new(stmt)))?;
         }
         _ => repl::start()?,
     }
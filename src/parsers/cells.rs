@@ -4,7 +4,7 @@ use nom::number::complete::be_u16;
 
 use crate::sqlite::pages::Page;
 
-use super::payload::Payload;
+use super::payload::{Payload, Record};
 use super::value::Value;
 
 #[derive(Debug, Clone, Copy)]
@@ -49,26 +49,50 @@ impl<'a> Cell<'a> {
     }
 }
 
-impl<'a> TryFrom<Cell<'a>> for Vec<Value> {
-    type Error = anyhow::Error;
-    fn try_from(value: Cell<'a>) -> Result<Self, Self::Error> {
-        let pl = value
+impl<'a> Cell<'a> {
+    /// The record of a cell with a payload, its header read so its columns can be decoded
+    /// one by one.
+    pub fn record(&self) -> anyhow::Result<Record<'a>> {
+        let pl = self
             .get_payload()
             .ok_or_else(|| anyhow::anyhow!("Table Interior cells have no payload"))?;
-        let (_, mut row) = pl
-            .parse()
+        let (_, record) = pl
+            .record()
             .map_err(|e| anyhow::anyhow!("Parse payload error: {}", e.to_string()))?;
+        Ok(record)
+    }
 
-        if let Cell::TableLeaf { row_id, .. } = value {
-            match row[0] {
-                Value::Null => row[0] = Value::Integer(row_id as i64),
-                _ => {}
-            }
+    /// Decode the `columns` of the cell's record, every one when `None`, leaving NULL in the
+    /// others. The column at `alias`, the INTEGER PRIMARY KEY of a table row, reads as the
+    /// rowid.
+    pub fn row(
+        &self,
+        columns: Option<&[usize]>,
+        alias: Option<usize>,
+    ) -> anyhow::Result<Vec<Value>> {
+        let mut row = self.record()?.row(columns);
+        if let Cell::TableLeaf { row_id, .. } = self {
+            alias_rowid(&mut row, *row_id, alias);
         }
         Ok(row)
     }
 }
 
+/// Read the NULL stored for the INTEGER PRIMARY KEY column at `alias` as the rowid it
+/// aliases. Tables without one keep their NULLs.
+pub fn alias_rowid(row: &mut [Value], row_id: u64, alias: Option<usize>) {
+    if let Some(value @ Value::Null) = alias.and_then(|alias| row.get_mut(alias)) {
+        *value = Value::Integer(row_id as i64);
+    }
+}
+
+impl<'a> TryFrom<Cell<'a>> for Vec<Value> {
+    type Error = anyhow::Error;
+    fn try_from(value: Cell<'a>) -> Result<Self, Self::Error> {
+        value.row(None, None)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CellIter<'p> {
     pub page: &'p Page,
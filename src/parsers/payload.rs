@@ -1,6 +1,13 @@
-use nom::{combinator::into, multi::many1, IResult};
+use nom::{
+    error::{Error, ErrorKind},
+    Err, IResult,
+};
 
-use super::{record_code::RecordCode, value::Value, varint::varint};
+use super::{
+    record_code::RecordCode,
+    value::Value,
+    varint::{encode_varint, varint},
+};
 
 #[derive(Clone, Copy, PartialEq)]
 /// Contains the payload part of the [Cell].
@@ -12,26 +19,204 @@ pub struct Payload<'a> {
 
 impl<'a> Payload<'a> {
     pub fn parse(&'a self) -> IResult<&'a [u8], Vec<Value>> {
+        let (rest, record) = self.record()?;
+        Ok((rest, record.row(None)))
+    }
+
+    /// Read the header of the record, leaving its columns to be decoded as they are needed.
+    pub fn record(&self) -> IResult<&'a [u8], Record<'a>> {
         let (_, header_size) = varint(self.payload)?;
-        let header = &self.payload[..header_size as usize];
-        let (header, _) = varint(header)?; // We don't need the size which is the first varint
-        let (_, codes): (_, Vec<RecordCode>) = many1(into(varint))(header)?;
-        let mut body = &self.payload[header_size as usize..];
-        let mut records = vec![];
-        for code in codes {
-            let (input, rec) = code.parse(body)?;
-            body = input;
-            records.push(rec);
+        let header_size = header_size as usize;
+        let mut header = self
+            .payload
+            .get(..header_size)
+            .ok_or(Err::Error(Error::new(self.payload, ErrorKind::Eof)))?;
+        (header, _) = varint(header)?; // We don't need the size which is the first varint
+        let body = &self.payload[header_size..];
+        let mut columns = vec![];
+        let mut offset = 0;
+        while !header.is_empty() {
+            let (rest, code) = varint(header)?;
+            let code = RecordCode::from(code);
+            columns.push((code, offset));
+            offset += code.size();
+            header = rest;
         }
-        Ok((body, records))
+        // Every column is in the body, so decoding one can't run past its end
+        let rest = body
+            .get(offset..)
+            .ok_or(Err::Error(Error::new(body, ErrorKind::Eof)))?;
+        Ok((rest, Record { body, columns }))
+    }
+}
+
+/// A record whose header has been read, so any of its columns can be decoded without
+/// decoding the ones before it.
+pub struct Record<'a> {
+    body: &'a [u8],
+    /// Serial type of each column, and where its value starts in the body.
+    columns: Vec<(RecordCode, usize)>,
+}
+
+impl Record<'_> {
+    /// Number of columns in the record.
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// The value of column `idx`, NULL past the last column as for a column added to the
+    /// table after the record was written.
+    pub fn column(&self, idx: usize) -> Value {
+        self.columns
+            .get(idx)
+            .and_then(|(code, offset)| code.parse(&self.body[*offset..]).ok())
+            .map_or(Value::Null, |(_, value)| value)
+    }
+
+    /// Decode `columns` into their place in `row`, leaving the others as they are.
+    pub fn decode(&self, columns: impl IntoIterator<Item = usize>, row: &mut [Value]) {
+        for idx in columns {
+            if let Some(slot) = row.get_mut(idx) {
+                *slot = self.column(idx);
+            }
+        }
+    }
+
+    /// A row as long as the record with `columns` decoded, every one when `None`, and NULL
+    /// in the others.
+    pub fn row(&self, columns: Option<&[usize]>) -> Vec<Value> {
+        match columns {
+            Some(columns) => {
+                let mut row = vec![Value::Null; self.len()];
+                self.decode(columns.iter().copied(), &mut row);
+                row
+            }
+            None => (0..self.len()).map(|idx| self.column(idx)).collect(),
+        }
+    }
+}
+
+/// Serialize values in SQLite's record format, which [`Payload::parse`] reads back.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut codes = vec![];
+    let mut body = vec![];
+    for value in values {
+        let code = RecordCode::from(value);
+        encode_varint(code.into(), &mut codes);
+        code.write(value, &mut body);
+    }
+
+    // The header size counts its own varint, which may grow the size it encodes
+    let mut header_size = codes.len() as u64 + 1;
+    let mut record = vec![];
+    encode_varint(header_size, &mut record);
+    if record.len() > 1 {
+        header_size = (codes.len() + record.len()) as u64;
+        record.clear();
+        encode_varint(header_size, &mut record);
     }
+    record.extend(codes);
+    record.extend(body);
+    record
 }
 
 impl<'a> std::fmt::Debug for Payload<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Payload")
             .field("size", &self.size)
-            .field("payload", &String::from_utf8_lossy(&self.payload))
+            .field("payload", &String::from_utf8_lossy(self.payload))
             .finish()
     }
 }
+
+#[cfg(test)]
+fn payload(record: &[u8]) -> Payload<'_> {
+    Payload {
+        size: record.len() as u64,
+        payload: record,
+        overflow: None,
+    }
+}
+
+#[test]
+fn test_record_columns() {
+    let values = vec![
+        Value::Null,
+        Value::Integer(1),
+        Value::Integer(-300),
+        Value::Integer(1 << 40),
+        Value::Float(2.5),
+        Value::Text("peru".into()),
+        Value::Blob(vec![1, 2, 3]),
+        Value::Integer(i64::MIN),
+    ];
+    let encoded = encode_record(&values);
+    let pl = payload(&encoded);
+    let (rest, record) = pl.record().unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(record.len(), values.len());
+    for (idx, value) in values.iter().enumerate() {
+        assert_eq!(&record.column(idx), value);
+    }
+    assert_eq!(record.column(values.len()), Value::Null);
+    assert_eq!(record.row(None), values);
+    assert_eq!(pl.parse().unwrap().1, values);
+
+    let mut expected = vec![Value::Null; values.len()];
+    expected[3] = values[3].clone();
+    expected[5] = values[5].clone();
+    assert_eq!(record.row(Some(&[3, 5])), expected);
+
+    // A body shorter than its header says is an error, not a short row
+    assert!(payload(&encoded[..encoded.len() - 1]).record().is_err());
+}
+
+/// Run with `cargo test --release bench_projected_decode -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_projected_decode() {
+    use std::time::Instant;
+
+    let records: Vec<Vec<u8>> = (0..200_000)
+        .map(|i| {
+            let row: Vec<Value> = (0..20)
+                .map(|col| match col % 3 {
+                    0 => Value::Integer(i * col),
+                    1 => Value::Text(format!("column {col} of row {i}")),
+                    _ => Value::Float(i as f64 / (col + 1) as f64),
+                })
+                .collect();
+            encode_record(&row)
+        })
+        .collect();
+
+    let start = Instant::now();
+    let decoded: usize = records
+        .iter()
+        .map(|record| payload(record).parse().unwrap().1.len())
+        .sum();
+    let full = start.elapsed();
+
+    let start = Instant::now();
+    let projected: usize = records
+        .iter()
+        .map(|record| {
+            payload(record)
+                .record()
+                .unwrap()
+                .1
+                .row(Some(&[2, 17]))
+                .len()
+        })
+        .sum();
+    let lazy = start.elapsed();
+
+    assert_eq!(decoded, projected);
+    println!(
+        "{} rows of 20 columns: all decoded {:?}, 2 decoded {:?} ({:.0}x)",
+        records.len(),
+        full,
+        lazy,
+        full.as_secs_f64() / lazy.as_secs_f64()
+    );
+}
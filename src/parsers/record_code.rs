@@ -43,7 +43,74 @@ impl From<u64> for RecordCode {
     }
 }
 
+impl From<&Value> for RecordCode {
+    /// The smallest serial type able to hold the value.
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => RecordCode::Null,
+            Value::Integer(0) => RecordCode::Zero,
+            Value::Integer(1) => RecordCode::One,
+            Value::Integer(n) => match n {
+                -0x80..=0x7f => RecordCode::I8,
+                -0x8000..=0x7fff => RecordCode::I16,
+                -0x80_0000..=0x7f_ffff => RecordCode::I24,
+                -0x8000_0000..=0x7fff_ffff => RecordCode::I32,
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => RecordCode::I48,
+                _ => RecordCode::I64,
+            },
+            Value::Float(_) => RecordCode::F64,
+            Value::Blob(b) => RecordCode::Blob(b.len()),
+            Value::Text(s) => RecordCode::String(s.len()),
+        }
+    }
+}
+
+impl From<RecordCode> for u64 {
+    fn from(code: RecordCode) -> Self {
+        use RecordCode::*;
+        match code {
+            Null => 0,
+            I8 => 1,
+            I16 => 2,
+            I24 => 3,
+            I32 => 4,
+            I48 => 5,
+            I64 => 6,
+            F64 => 7,
+            Zero => 8,
+            One => 9,
+            Blob(n) => n as u64 * 2 + 12,
+            String(n) => n as u64 * 2 + 13,
+        }
+    }
+}
+
 impl<'a> RecordCode {
+    /// Number of body bytes a value of this serial type takes.
+    pub fn size(self) -> usize {
+        match self {
+            RecordCode::Null | RecordCode::Zero | RecordCode::One => 0,
+            RecordCode::I8 => 1,
+            RecordCode::I16 => 2,
+            RecordCode::I24 => 3,
+            RecordCode::I32 => 4,
+            RecordCode::I48 => 6,
+            RecordCode::I64 | RecordCode::F64 => 8,
+            RecordCode::Blob(n) | RecordCode::String(n) => n,
+        }
+    }
+
+    /// Append the body bytes of `value`, which must be the value this code was made from.
+    pub fn write(self, value: &Value, out: &mut Vec<u8>) {
+        match value {
+            Value::Integer(n) => out.extend_from_slice(&n.to_be_bytes()[8 - self.size()..]),
+            Value::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+            Value::Blob(b) => out.extend_from_slice(b),
+            Value::Text(s) => out.extend_from_slice(s.as_bytes()),
+            Value::Null => {}
+        }
+    }
+
     pub fn parse(self, input: &'a [u8]) -> IResult<&'a [u8], Value> {
         match self {
             RecordCode::Null => Ok((input, Value::Null)),
@@ -64,7 +131,7 @@ impl<'a> RecordCode {
                 Ok((input, Value::Integer(n.into())))
             }
             RecordCode::I48 => {
-                let (input, n) = take(6 as usize)(input)?;
+                let (input, n) = take(6_usize)(input)?;
                 let mut x = 0u64;
                 for b in n {
                     x = (x << 8) | (*b as u64);
@@ -76,7 +143,7 @@ impl<'a> RecordCode {
             }
             RecordCode::I64 => {
                 let (input, n) = be_i64(input)?;
-                Ok((input, Value::Integer(n.into())))
+                Ok((input, Value::Integer(n)))
             }
             RecordCode::F64 => {
                 let (input, n) = be_f64(input)?;
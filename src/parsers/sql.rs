@@ -1,219 +1,930 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::iter;
 use std::str::FromStr;
-use std::{cmp::Ordering, collections::HashMap};
 
-use crate::sqlite::tables::{CellType, Column, Table};
+use crate::sqlite::{
+    aggregate::AggregateKind,
+    collation::{Collations, BINARY},
+    functions::{as_integer, as_text},
+    indexes::{Index, IndexedColumn},
+    predicate::BoundExpr,
+    tables::{CellType, Column, Table},
+    views::View,
+};
 
-use super::value::Value;
+use super::value::{text_to_numeric, Value};
 
+/// An item of the select list.
 #[derive(Debug, PartialEq, Clone)]
-pub enum SelectColumns {
-    Columns(Vec<String>),
-    All,
-    Count,
+pub enum SelectColumn {
+    /// `*`, or `table.*` when qualified, standing for every column of the table.
+    All(Option<String>),
+    Expr(ResultColumn),
 }
 
+/// An expression of the select list, with the name its result is shown under: its alias,
+/// or else the text of the expression.
 #[derive(Debug, PartialEq, Clone)]
-pub enum Condition {
-    Eq(String, String),
-    Greater(String, String),
-    GreaterEq(String, String),
-    Less(String, String),
-    LessEq(String, String),
-    Ne(String, String),
-    Between(String, String, String),
-}
-
-impl Condition {
-    pub fn unbox(&self) -> (&String, &String) {
+pub struct ResultColumn {
+    pub expr: Expr,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    Is,
+    IsNot,
+    And,
+    Or,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl Operator {
+    /// Whether the operator computes a value from its operands rather than comparing them.
+    pub fn is_arithmetic(self) -> bool {
+        !matches!(
+            self,
+            Operator::Eq
+                | Operator::Ne
+                | Operator::Less
+                | Operator::LessEq
+                | Operator::Greater
+                | Operator::GreaterEq
+                | Operator::Is
+                | Operator::IsNot
+                | Operator::And
+                | Operator::Or
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    BitNot,
+}
+
+/// An SQL expression, evaluated against a row with SQL's three-valued logic where NULL is UNKNOWN.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Literal(Value),
+    Column(String),
+    Not(Box<Expr>),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+    IsNull(Box<Expr>),
+    NotNull(Box<Expr>),
+    Collate(Box<Expr>, String),
+    /// A call such as `count(*)`, which has no arguments, or `sum(DISTINCT x)`.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    /// `expr [NOT] IN (a, b, ...)`.
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// `expr [NOT] IN (SELECT ...)`, comparing `expr` with the first column of each row.
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<Select>,
+        negated: bool,
+    },
+    /// A subquery used as a value: the first column of its first row, or NULL without rows.
+    Subquery(Box<Select>),
+    /// `EXISTS (SELECT ...)`, true when the subquery returns a row.
+    Exists(Box<Select>),
+    /// A window function call, `call OVER (...)`, whose value for a row is computed over
+    /// the rows of its window rather than those of a group.
+    Over {
+        call: Box<Expr>,
+        window: Box<Window>,
+    },
+}
+
+impl Expr {
+    pub fn binary(lhs: Expr, op: Operator, rhs: Expr) -> Self {
+        Expr::Binary(Box::new(lhs), op, Box::new(rhs))
+    }
+
+    /// Split the expression into the terms of its top-level `AND`s.
+    pub fn conjuncts(self) -> Vec<Expr> {
         match self {
-            Condition::Eq(col_name, val) => (col_name, val),
-            Condition::Greater(col_name, val) => (col_name, val),
-            Condition::GreaterEq(col_name, val) => (col_name, val),
-            Condition::Less(col_name, val) => (col_name, val),
-            Condition::LessEq(col_name, val) => (col_name, val),
-            Condition::Ne(col_name, val) => (col_name, val),
-            Condition::Between(col_name, from, _) => (col_name, from), // TODO: Handle this case
+            Expr::Binary(lhs, Operator::And, rhs) => {
+                let mut terms = lhs.conjuncts();
+                terms.extend(rhs.conjuncts());
+                terms
+            }
+            expr => vec![expr],
         }
     }
 
-    pub fn eval(&self, row: &Vec<Value>, columns: &HashMap<String, Column>) -> bool {
+    /// Visit this expression and every expression nested in it.
+    pub fn walk<'e>(&'e self, f: &mut impl FnMut(&'e Expr)) {
+        f(self);
         match self {
-            Condition::Eq(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n == val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => t.cmp(val) == Ordering::Equal,
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f == val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
+            // Subqueries resolve their names against their own tables, so aren't walked into
+            Expr::Literal(_) | Expr::Column(_) | Expr::Subquery(_) | Expr::Exists(_) => {}
+            Expr::Not(expr)
+            | Expr::Unary(_, expr)
+            | Expr::IsNull(expr)
+            | Expr::NotNull(expr)
+            | Expr::Collate(expr, _)
+            | Expr::InSelect { expr, .. } => expr.walk(f),
+            Expr::Binary(lhs, _, rhs) => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Expr::Function { args, .. } => args.iter().for_each(|arg| arg.walk(f)),
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk(f);
+                low.walk(f);
+                high.walk(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk(f);
+                list.iter().for_each(|item| item.walk(f));
+            }
+            // The call is computed over the window by the executor, so only what it reads is
+            // walked, and an aggregate called over a window doesn't make the query grouped
+            Expr::Over { call, window } => {
+                if let Expr::Function { args, .. } = call.as_ref() {
+                    args.iter().for_each(|arg| arg.walk(f));
                 }
+                window.partition_by.iter().for_each(|expr| expr.walk(f));
+                window.order_by.iter().for_each(|term| term.expr.walk(f));
+            }
+        }
+    }
 
-                false
+    /// Visit this expression and every expression nested in it, like [`Expr::walk`], and
+    /// also every expression of the subqueries it holds, which may name outer columns.
+    pub fn walk_nested(&self, f: &mut dyn FnMut(&Expr)) {
+        self.walk(&mut |expr| {
+            f(expr);
+            if let Expr::Subquery(select) | Expr::Exists(select) | Expr::InSelect { select, .. } =
+                expr
+            {
+                select.walk_exprs(f);
             }
-            Condition::GreaterEq(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n >= val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => {
-                            let comparison = t.cmp(val);
-                            comparison == Ordering::Greater || comparison == Ordering::Equal
-                        }
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f >= val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
-                }
+        });
+    }
+
+    /// The aggregate function this expression calls, if it is an aggregate call.
+    pub fn aggregate(&self) -> Option<AggregateKind> {
+        match self {
+            Expr::Function { name, args, .. } => AggregateKind::from_call(name, args.len()),
+            _ => None,
+        }
+    }
+
+    /// Whether an aggregate call appears anywhere in the expression.
+    pub fn has_aggregate(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| found |= expr.aggregate().is_some());
+        found
+    }
 
-                false
+    /// Rebuild the expression, replacing each subexpression for which `f` returns a new one.
+    /// Replacements are not visited again.
+    pub fn rewrite(&self, f: &mut impl FnMut(&Expr) -> Option<Expr>) -> Expr {
+        if let Some(expr) = f(self) {
+            return expr;
+        }
+        let mut rewrite = |expr: &Expr| Box::new(expr.rewrite(f));
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Subquery(_) | Expr::Exists(_) => {
+                self.clone()
             }
-            Condition::Greater(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n > val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => t.cmp(val) == Ordering::Greater,
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f > val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
+            Expr::Not(expr) => Expr::Not(rewrite(expr)),
+            Expr::Unary(op, expr) => Expr::Unary(*op, rewrite(expr)),
+            Expr::Binary(lhs, op, rhs) => Expr::Binary(rewrite(lhs), *op, rewrite(rhs)),
+            Expr::IsNull(expr) => Expr::IsNull(rewrite(expr)),
+            Expr::NotNull(expr) => Expr::NotNull(rewrite(expr)),
+            Expr::Collate(expr, name) => Expr::Collate(rewrite(expr), name.clone()),
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(|arg| *rewrite(arg)).collect(),
+                distinct: *distinct,
+            },
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expr::Between {
+                expr: rewrite(expr),
+                low: rewrite(low),
+                high: rewrite(high),
+                negated: *negated,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: rewrite(expr),
+                list: list.iter().map(|item| *rewrite(item)).collect(),
+                negated: *negated,
+            },
+            Expr::InSelect {
+                expr,
+                select,
+                negated,
+            } => Expr::InSelect {
+                expr: rewrite(expr),
+                select: select.clone(),
+                negated: *negated,
+            },
+            Expr::Over { call, window } => {
+                let call = match call.as_ref() {
+                    Expr::Function {
+                        name,
+                        args,
+                        distinct,
+                    } => Expr::Function {
+                        name: name.clone(),
+                        args: args.iter().map(|arg| *rewrite(arg)).collect(),
+                        distinct: *distinct,
+                    },
+                    call => call.clone(),
+                };
+                let window = Window {
+                    partition_by: window.partition_by.iter().map(|e| *rewrite(e)).collect(),
+                    order_by: window
+                        .order_by
+                        .iter()
+                        .map(|term| OrderingTerm {
+                            expr: *rewrite(&term.expr),
+                            ..term.clone()
+                        })
+                        .collect(),
+                    frame: window.frame.clone(),
+                };
+                Expr::Over {
+                    call: Box::new(call),
+                    window: Box::new(window),
                 }
-                false
             }
-            Condition::LessEq(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n <= val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => {
-                            let comparison = t.cmp(val);
-                            comparison == Ordering::Less || comparison == Ordering::Equal
-                        }
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f <= val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
+        }
+    }
+
+    /// Replace each aggregate call with a reference to the column its result is stored in,
+    /// appending calls not seen before to `calls`. The `n`th call is stored in the column
+    /// named by [`Expr::aggregate_column`].
+    pub fn extract_aggregates(&self, calls: &mut Vec<Expr>) -> Expr {
+        self.rewrite(&mut |expr| {
+            expr.aggregate()?;
+            let n = match calls.iter().position(|call| call == expr) {
+                Some(n) => n,
+                None => {
+                    calls.push(expr.clone());
+                    calls.len() - 1
                 }
+            };
+            Some(Expr::Column(Expr::aggregate_column(n)))
+        })
+    }
 
-                false
+    /// Name of the column holding the result of the `n`th aggregate call of a query, which
+    /// no identifier in SQL text can refer to.
+    pub fn aggregate_column(n: usize) -> String {
+        format!("#aggregate{}", n)
+    }
+
+    /// Replace each window function call with a reference to the column its result is
+    /// stored in, appending calls not seen before to `calls`. The `n`th call is stored in the
+    /// column named by [`Expr::window_column`].
+    pub fn extract_windows(&self, calls: &mut Vec<Expr>) -> Expr {
+        self.rewrite(&mut |expr| {
+            if !matches!(expr, Expr::Over { .. }) {
+                return None;
             }
-            Condition::Less(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n < val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => t.cmp(val) == Ordering::Less,
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f < val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
+            let n = match calls.iter().position(|call| call == expr) {
+                Some(n) => n,
+                None => {
+                    calls.push(expr.clone());
+                    calls.len() - 1
                 }
-                false
+            };
+            Some(Expr::Column(Expr::window_column(n)))
+        })
+    }
+
+    /// Name of the column holding the result of the `n`th window function call of a query.
+    pub fn window_column(n: usize) -> String {
+        format!("#window{}", n)
+    }
+
+    /// Whether a window function call appears anywhere in the expression.
+    pub fn has_window(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| found |= matches!(expr, Expr::Over { .. }));
+        found
+    }
+
+    /// The expression with arithmetic on literals computed, such as on the values of an
+    /// enclosing query's row in a subquery, so that comparing a column with it can use an
+    /// index.
+    pub fn fold_constants(&self) -> Expr {
+        self.rewrite(&mut |expr| {
+            let mut constant = matches!(expr, Expr::Unary(..) | Expr::Binary(..));
+            expr.walk(&mut |expr| match expr {
+                Expr::Literal(_) | Expr::Unary(..) => {}
+                Expr::Binary(_, op, _) if op.is_arithmetic() => {}
+                _ => constant = false,
+            });
+            // An expression that fails is left to fail when the query runs
+            constant
+                .then(|| expr.eval(&[], &HashMap::new(), &Collations::default()))
+                .and_then(Result::ok)
+                .map(Expr::Literal)
+        })
+    }
+
+    /// Whether the expression runs a subquery: a scalar one, or one under `IN` or `EXISTS`.
+    pub fn is_subquery(&self) -> bool {
+        matches!(
+            self,
+            Expr::Subquery(_) | Expr::Exists(_) | Expr::InSelect { .. }
+        )
+    }
+
+    /// Whether a subquery appears anywhere in the expression.
+    pub fn has_subquery(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| found |= expr.is_subquery());
+        found
+    }
+
+    /// Replace each subquery, together with the `IN` or `EXISTS` applied to it, with a
+    /// reference to the column its value is stored in, appending it to `subqueries`. Like
+    /// aggregates, the `n`th one is stored in the column named by [`Expr::subquery_column`].
+    pub fn extract_subqueries(&self, subqueries: &mut Vec<Expr>) -> Expr {
+        self.rewrite(&mut |expr| {
+            if !expr.is_subquery() {
+                return None;
             }
-            Condition::Ne(col_name, val) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let Ok(val) = val.parse::<i64>() {
-                                return *n != val;
-                            }
-                            false
-                        }
-                        Value::Text(t) => t.cmp(val) != Ordering::Equal,
-                        Value::Float(f) => {
-                            if let Ok(val) = val.parse::<f64>() {
-                                return *f != val;
-                            }
-                            false
-                        }
-                        Value::Null => val == "NULL",
-                        Value::Blob(_) => false,
-                    };
-                }
+            subqueries.push(expr.clone());
+            Some(Expr::Column(Expr::subquery_column(subqueries.len() - 1)))
+        })
+    }
+
+    /// Name of the column holding the value of the `n`th subquery of a query.
+    pub fn subquery_column(n: usize) -> String {
+        format!("#subquery{}", n)
+    }
+
+    /// The column and literal of a `column = literal` term, in either order.
+    pub fn equality_key(&self) -> Option<(&String, &Value)> {
+        match self {
+            Expr::Binary(lhs, Operator::Eq, rhs) => match (lhs.uncollated(), rhs.uncollated()) {
+                (Expr::Column(name), Expr::Literal(val))
+                | (Expr::Literal(val), Expr::Column(name)) => Some((name, val)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 
-                false
+    /// The column, inequality and literal of a `column < literal` term or the like, in either
+    /// order, the inequality turned around when the literal comes first.
+    pub fn range_key(&self) -> Option<(&String, Operator, &Value)> {
+        let Expr::Binary(lhs, op, rhs) = self else {
+            return None;
+        };
+        let flipped = match op {
+            Operator::Less => Operator::Greater,
+            Operator::LessEq => Operator::GreaterEq,
+            Operator::Greater => Operator::Less,
+            Operator::GreaterEq => Operator::LessEq,
+            _ => return None,
+        };
+        match (lhs.uncollated(), rhs.uncollated()) {
+            (Expr::Column(name), Expr::Literal(val)) => Some((name, *op, val)),
+            (Expr::Literal(val), Expr::Column(name)) => Some((name, flipped, val)),
+            _ => None,
+        }
+    }
+
+    /// The expression without any `COLLATE` operators applied to it.
+    pub fn uncollated(&self) -> &Expr {
+        match self {
+            Expr::Collate(expr, _) => expr.uncollated(),
+            expr => expr,
+        }
+    }
+
+    /// Affinity of the expression: a column's declared affinity, or none for anything else.
+    pub fn affinity(&self, columns: &HashMap<String, Column>) -> Option<CellType> {
+        match self {
+            Expr::Column(name) => columns.get(name).map(|c| c.cell_type),
+            Expr::Collate(expr, _) => expr.affinity(columns),
+            _ => None,
+        }
+    }
+
+    /// Collation carried by the expression, flagged `true` when given by a `COLLATE` operator
+    /// rather than inherited from a column definition.
+    pub fn collation<'c>(
+        &'c self,
+        columns: &'c HashMap<String, Column>,
+    ) -> Option<(&'c str, bool)> {
+        match self {
+            Expr::Collate(_, name) => Some((name, true)),
+            Expr::Unary(UnaryOperator::Plus, expr) => expr.collation(columns),
+            Expr::Column(name) => columns
+                .get(name)
+                .map(|c| (c.collation.as_deref().unwrap_or(BINARY), false)),
+            _ => None,
+        }
+    }
+
+    /// Collation the expression sorts by on its own, as in an ORDER BY term.
+    pub fn collation_name<'c>(&'c self, columns: &'c HashMap<String, Column>) -> &'c str {
+        self.collation(columns).map_or(BINARY, |(name, _)| name)
+    }
+
+    /// Collation used to compare `lhs` with `rhs`: an explicit `COLLATE` wins over a column's
+    /// collation, and the left operand wins over the right one.
+    pub fn comparison_collation<'c>(
+        lhs: &'c Expr,
+        rhs: &'c Expr,
+        columns: &'c HashMap<String, Column>,
+    ) -> &'c str {
+        Expr::joint_collation(lhs.collation(columns), rhs.collation(columns))
+    }
+
+    /// Collation used to compare operands carrying the given collations, as for
+    /// [`Expr::comparison_collation`].
+    pub fn joint_collation<'c>(
+        lhs: Option<(&'c str, bool)>,
+        rhs: Option<(&'c str, bool)>,
+    ) -> &'c str {
+        match (lhs, rhs) {
+            (Some((name, true)), _) | (_, Some((name, true))) => name,
+            (Some((name, false)), _) | (_, Some((name, false))) => name,
+            _ => BINARY,
+        }
+    }
+
+    /// Affinity an equality comparison applies to both operands, given theirs: comparing with
+    /// a numeric operand makes the other one numeric, and otherwise comparing with a text
+    /// operand makes the other one text.
+    pub fn comparison_affinity(lhs: Option<CellType>, rhs: Option<CellType>) -> Option<CellType> {
+        let numeric = |a: Option<CellType>| {
+            matches!(
+                a,
+                Some(CellType::Integer | CellType::Float | CellType::Numeric)
+            )
+        };
+        if numeric(lhs) || numeric(rhs) {
+            Some(CellType::Numeric)
+        } else if lhs == Some(CellType::Text) || rhs == Some(CellType::Text) {
+            Some(CellType::Text)
+        } else {
+            None
+        }
+    }
+
+    /// Evaluate the expression for a row described by `columns`. It is bound to the columns
+    /// first, as a [`BoundExpr`] is, so expressions evaluated for many rows are better bound
+    /// once.
+    pub fn eval(
+        &self,
+        row: &[Value],
+        columns: &HashMap<String, Column>,
+        collations: &Collations,
+    ) -> anyhow::Result<Value> {
+        BoundExpr::new(self, columns, collations).eval(row)
+    }
+
+    /// Affinity a comparison applies to its left and to its right operand before comparing
+    /// them: a column with numeric affinity makes the other side numeric, and otherwise a
+    /// text column makes a side without affinity text.
+    pub fn operand_affinities(
+        lhs: &Expr,
+        rhs: &Expr,
+        columns: &HashMap<String, Column>,
+    ) -> (Option<CellType>, Option<CellType>) {
+        let is_numeric = |t| matches!(t, CellType::Integer | CellType::Float | CellType::Numeric);
+        match (lhs.affinity(columns), rhs.affinity(columns)) {
+            (Some(a), b) if is_numeric(a) && !b.is_some_and(is_numeric) => {
+                (None, Some(CellType::Numeric))
             }
-            Condition::Between(col_name, from, to) => {
-                if let Some(column) = columns.get(col_name) {
-                    return match &row[column.idx] {
-                        Value::Integer(n) => {
-                            if let (Ok(from), Ok(to)) = (from.parse::<i64>(), to.parse::<i64>()) {
-                                return *n >= from && *n <= to;
-                            }
-                            false
-                        }
-                        Value::Text(t) => {
-                            (t.cmp(from) == Ordering::Greater || t.cmp(from) == Ordering::Equal)
-                                && (t.cmp(to) == Ordering::Less || t.cmp(to) == Ordering::Equal)
-                        }
-                        Value::Float(f) => {
-                            if let (Ok(from), Ok(to)) = (from.parse::<f64>(), to.parse::<f64>()) {
-                                return *f >= from && *f <= to;
-                            }
-                            false
-                        }
-                        _ => false,
-                    };
-                }
+            (a, Some(b)) if is_numeric(b) && !a.is_some_and(is_numeric) => {
+                (Some(CellType::Numeric), None)
+            }
+            (Some(CellType::Text), None) => (None, Some(CellType::Text)),
+            (None, Some(CellType::Text)) => (Some(CellType::Text), None),
+            _ => (None, None),
+        }
+    }
+}
 
-                false
+/// Compare two values with a comparison operator, ordering text by `collation`. A NULL
+/// operand makes the result NULL, except for `IS` and `IS NOT`, which treat NULLs as equal.
+pub fn compare(
+    op: Operator,
+    l: &Value,
+    r: &Value,
+    collation: &dyn Fn(&str, &str) -> Ordering,
+) -> Value {
+    let ordering = match (l, r) {
+        (Value::Null, Value::Null) => None,
+        (Value::Null, _) | (_, Value::Null) => {
+            return match op {
+                Operator::Is => Value::from(false),
+                Operator::IsNot => Value::from(true),
+                _ => Value::Null,
             }
         }
+        (l, r) => Some(l.compare_collated(r, collation)),
+    };
+    match (op, ordering) {
+        (Operator::Is, None) => Value::from(true),
+        (Operator::IsNot, None) => Value::from(false),
+        (_, None) => Value::Null,
+        (Operator::Eq | Operator::Is, Some(ord)) => Value::from(ord.is_eq()),
+        (Operator::Ne | Operator::IsNot, Some(ord)) => Value::from(ord.is_ne()),
+        (Operator::Less, Some(ord)) => Value::from(ord.is_lt()),
+        (Operator::LessEq, Some(ord)) => Value::from(ord.is_le()),
+        (Operator::Greater, Some(ord)) => Value::from(ord.is_gt()),
+        (Operator::GreaterEq, Some(ord)) => Value::from(ord.is_ge()),
+        (op, _) => unreachable!("{:?} is not a comparison", op),
+    }
+}
+
+/// The value as a number for arithmetic: text is read by its numeric prefix, as SQLite does.
+fn numeric(value: Value) -> Value {
+    match value {
+        Value::Text(s) => text_to_numeric(&s),
+        Value::Blob(b) => text_to_numeric(&String::from_utf8_lossy(&b)),
+        value => value,
+    }
+}
+
+pub fn unary(op: UnaryOperator, value: Value) -> Value {
+    match (op, value) {
+        (_, Value::Null) => Value::Null,
+        (UnaryOperator::Plus, value) => value,
+        (UnaryOperator::BitNot, value) => Value::Integer(!as_integer(&value)),
+        (UnaryOperator::Negate, value) => match numeric(value) {
+            Value::Integer(n) => n
+                .checked_neg()
+                .map_or(Value::Float(-(n as f64)), Value::Integer),
+            Value::Float(f) => Value::Float(-f),
+            value => value,
+        },
+    }
+}
+
+/// Apply an arithmetic, concatenation or bitwise operator with SQLite's rules: NULL operands
+/// give NULL, text is converted to a number, integers that overflow become reals, and
+/// dividing by zero gives NULL.
+pub fn arithmetic(op: Operator, l: Value, r: Value) -> Value {
+    if l == Value::Null || r == Value::Null {
+        return Value::Null;
+    }
+    match op {
+        Operator::Concat => {
+            return Value::Text(as_text(&l).unwrap_or_default() + &as_text(&r).unwrap_or_default())
+        }
+        Operator::BitAnd => return Value::Integer(as_integer(&l) & as_integer(&r)),
+        Operator::BitOr => return Value::Integer(as_integer(&l) | as_integer(&r)),
+        Operator::ShiftLeft | Operator::ShiftRight => {
+            let (n, mut shift) = (as_integer(&l), as_integer(&r));
+            let mut left = op == Operator::ShiftLeft;
+            // A negative shift goes the other way
+            if shift < 0 {
+                left = !left;
+                shift = shift.saturating_neg();
+            }
+            return Value::Integer(match (left, shift) {
+                (true, 64..) => 0,
+                (true, shift) => n << shift,
+                (false, 64..) => n >> 63,
+                (false, shift) => n >> shift,
+            });
+        }
+        _ => {}
+    }
+
+    let real = |value: &Value| match value {
+        Value::Integer(n) => *n as f64,
+        Value::Float(f) => *f,
+        _ => 0.0,
+    };
+    match (numeric(l), numeric(r)) {
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match op {
+                Operator::Add => a.checked_add(b),
+                Operator::Subtract => a.checked_sub(b),
+                Operator::Multiply => a.checked_mul(b),
+                Operator::Divide if b == 0 => return Value::Null,
+                Operator::Divide => a.checked_div(b),
+                Operator::Remainder if b == 0 => return Value::Null,
+                // Only MIN % -1 overflows, and its remainder is 0
+                _ => Some(a.checked_rem(b).unwrap_or(0)),
+            };
+            match result {
+                Some(n) => Value::Integer(n),
+                None => real_arithmetic(op, a as f64, b as f64),
+            }
+        }
+        (a, b) => real_arithmetic(op, real(&a), real(&b)),
+    }
+}
+
+fn real_arithmetic(op: Operator, a: f64, b: f64) -> Value {
+    let result = match op {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide if b == 0.0 => return Value::Null,
+        Operator::Divide => a / b,
+        // Reals are truncated to integers for the remainder, which is still a real
+        _ => match (a as i64, b as i64) {
+            (_, 0) => return Value::Null,
+            (a, b) => a.checked_rem(b).unwrap_or(0) as f64,
+        },
+    };
+    match result.is_nan() {
+        true => Value::Null,
+        false => Value::Float(result),
+    }
+}
+
+/// Postfix part of an equality-level expression, folded onto its left operand.
+enum EqualityTail {
+    Binary(Operator, Expr),
+    IsNull,
+    NotNull,
+    Between(Expr, Expr, bool),
+    InList(Vec<Expr>, bool),
+    InSelect(Box<Select>, bool),
+}
+
+/// Column constraints that affect how esquilait reads a table; the rest are only skipped.
+#[derive(Debug, PartialEq, Clone)]
+enum ColumnConstraint {
+    PrimaryKey,
+    AutoIncrement,
+    NotNull,
+    Collate(String),
+    Other,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+/// One `expr [ASC|DESC] [NULLS FIRST|LAST]` term of an ORDER BY clause.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub desc: bool,
+    pub nulls: Option<NullsOrder>,
+}
+
+impl OrderingTerm {
+    /// Whether NULLs come first; by default they sort as the smallest value, like SQLite.
+    pub fn nulls_first(&self) -> bool {
+        match self.nulls {
+            Some(NullsOrder::First) => true,
+            Some(NullsOrder::Last) => false,
+            None => !self.desc,
+        }
+    }
+}
+
+/// The rows a window function call sees for each row: those of the row's partition, in
+/// order, that fall within the frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Window {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Frame,
+}
+
+/// `ROWS` or `RANGE BETWEEN start AND end`, which defaults to the rows from the start of
+/// the partition to the last peer of the current row: the rows equal to it by ORDER BY.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    /// Whether the bounds count rows, rather than being ranges of ORDER BY values.
+    pub rows: bool,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame {
+            rows: false,
+            start: FrameBound::UnboundedPreceding,
+            end: FrameBound::CurrentRow,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+/// How a joined table's rows are combined with the rows of the tables before it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum JoinKind {
+    Inner,
+    /// Rows without a match are kept, with NULLs for the joined table's columns.
+    Left,
+    /// Every pairing of rows, as with a comma between the tables.
+    Cross,
+}
+
+/// What the rows of a joined table have to match on.
+#[derive(Debug, PartialEq, Clone)]
+pub enum JoinConstraint {
+    None,
+    On(Expr),
+    /// Columns that must be equal in both tables, and are only shown once.
+    Using(Vec<String>),
+    /// `USING` every column the tables have in common.
+    Natural,
+}
+
+/// A table of the FROM clause: a table of the database, or the rows of a subquery.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TableSource {
+    Table(String),
+    Subquery(Box<Select>),
+    /// The rows of a VALUES clause. A SELECT without FROM reads one row of no columns.
+    Values(Vec<Vec<Expr>>),
+}
+
+/// A table of the FROM clause joined to the tables before it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableSource,
+    pub alias: Option<String>,
+    pub constraint: JoinConstraint,
+}
+
+/// A table named by the WITH clause of a SELECT, which its queries can read like a view.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cte {
+    pub view: View,
+}
+
+impl Cte {
+    /// For a recursive table, the SELECT giving its first rows and the compound SELECTs after
+    /// it that read the table itself, which run once for each row the table gets.
+    pub fn recursion(&self) -> Option<(Select, &[(CompoundOperator, Select)])> {
+        let select = &self.view.select;
+        let first = select
+            .compound
+            .iter()
+            .position(|(_, select)| select.reads(&self.view.name))?;
+        let initial = Select {
+            compound: select.compound[..first].to_vec(),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            ..select.clone()
+        };
+        Some((initial, &select.compound[first..]))
+    }
+}
+
+/// How the rows of a compound SELECT are combined with those of the SELECTs before it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CompoundOperator {
+    /// Rows of either, without duplicates.
+    Union,
+    UnionAll,
+    /// Rows of both, without duplicates.
+    Intersect,
+    /// Rows before that the SELECT doesn't return, without duplicates.
+    Except,
+}
+
+impl Display for CompoundOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CompoundOperator::Union => "UNION",
+            CompoundOperator::UnionAll => "UNION ALL",
+            CompoundOperator::Intersect => "INTERSECT",
+            CompoundOperator::Except => "EXCEPT",
+        };
+        write!(f, "{name}")
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Select {
-    pub name: String,
-    pub columns: SelectColumns,
-    pub conds: Vec<Condition>,
+    /// Tables of the WITH clause, which the rest of the statement can name.
+    pub ctes: Vec<Cte>,
+    /// The first table of the FROM clause, its columns qualified by `alias` when it has one.
+    pub from: TableSource,
+    pub alias: Option<String>,
+    pub joins: Vec<Join>,
+    /// Whether duplicate result rows are removed, as in `SELECT DISTINCT`.
+    pub distinct: bool,
+    pub columns: Vec<SelectColumn>,
+    pub conds: Vec<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    /// SELECTs whose rows are combined with these, in order, with ORDER BY, LIMIT and OFFSET
+    /// then applying to the rows of the whole compound.
+    pub compound: Vec<(CompoundOperator, Select)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+impl Select {
+    /// Visit every expression of the statement, with [`Expr::walk_nested`], down into its
+    /// WITH clause, subqueries, join constraints and compound SELECTs.
+    pub fn walk_exprs(&self, f: &mut dyn FnMut(&Expr)) {
+        for cte in &self.ctes {
+            cte.view.select.walk_exprs(f);
+        }
+        let tables = iter::once(&self.from).chain(self.joins.iter().map(|join| &join.table));
+        for table in tables {
+            match table {
+                TableSource::Table(_) => {}
+                TableSource::Subquery(select) => select.walk_exprs(f),
+                TableSource::Values(rows) => rows.iter().flatten().for_each(|e| e.walk_nested(f)),
+            }
+        }
+        for join in &self.joins {
+            if let JoinConstraint::On(expr) = &join.constraint {
+                expr.walk_nested(f);
+            }
+        }
+        let columns = self.columns.iter().filter_map(|column| match column {
+            SelectColumn::Expr(column) => Some(&column.expr),
+            SelectColumn::All(_) => None,
+        });
+        columns
+            .chain(&self.conds)
+            .chain(&self.group_by)
+            .chain(&self.having)
+            .chain(self.order_by.iter().map(|term| &term.expr))
+            .chain(&self.limit)
+            .chain(&self.offset)
+            .for_each(|expr| expr.walk_nested(f));
+        for (_, select) in &self.compound {
+            select.walk_exprs(f);
+        }
+    }
+
+    /// Whether a table of its FROM clause is the one named.
+    pub fn reads(&self, name: &str) -> bool {
+        iter::once(&self.from)
+            .chain(self.joins.iter().map(|join| &join.table))
+            .any(|table| matches!(table, TableSource::Table(t) if t.eq_ignore_ascii_case(name)))
+    }
 }
 
 impl FromStr for Select {
@@ -221,46 +932,130 @@ impl FromStr for Select {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match sql::select(s) {
             Ok(s) => Ok(s),
-            Err(e) => {
-                return Err(anyhow::anyhow!("({})", e));
-            }
+            Err(e) => Err(anyhow::anyhow!("({})", e)),
+        }
+    }
+}
+
+/// A statement run from the prompt.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Select(Select),
+    /// `EXPLAIN QUERY PLAN`, describing how the SELECT reads its tables instead of running it.
+    ExplainQueryPlan(Select),
+    /// `EXPLAIN`, listing the bytecode program the SELECT compiles to instead of running it.
+    Explain(Select),
+    /// `ANALYZE`, gathering statistics on every table, or on the named table or index.
+    Analyze(Option<String>),
+}
+
+impl FromStr for Statement {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match sql::statement(s) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(anyhow::anyhow!("({})", e)),
         }
     }
 }
 
 peg::parser! {
   grammar sql() for str {
+    pub rule statement() -> Statement
+        =  _ kw("EXPLAIN") _ kw("QUERY") _ kw("PLAN") _ s:select() { Statement::ExplainQueryPlan(s) }
+        /  _ kw("EXPLAIN") _ s:select() { Statement::Explain(s) }
+        /  _ kw("ANALYZE") target:name()? _ { Statement::Analyze(target.map(String::from)) }
+        / s:select() { Statement::Select(s) }
+
     pub rule select() -> Select
-        =  operation() _ columns:columns() _ ("from" / "FROM") _ name:name() _ conds:search()? {
+        =  ctes:with()? first:select_core() compound:(o:compound_operator() _ s:select_core() { (o, s) })* _ order_by:order_by()? _ limit:limit()? _ {
             Select {
-                name: name.to_string(),
-                columns: match columns[..] {
-                    ["*"] => SelectColumns::All,
-                    ["count(*)"] => SelectColumns::Count,
-                    _ => SelectColumns::Columns(columns.iter().map(|c| c.to_string()).collect()),
-                },
-                conds: match conds {
-                    Some(conds) => conds,
-                    None => vec![]
-                }
+                ctes: ctes.unwrap_or_default(),
+                compound,
+                order_by: order_by.unwrap_or_default(),
+                limit: limit.as_ref().map(|(limit, _)| limit.clone()),
+                offset: limit.and_then(|(_, offset)| offset),
+                ..first
             }
         }
         / expected!("select")
 
+    /// A SELECT up to where a compound operator, ORDER BY or LIMIT can follow.
+    rule select_core() -> Select
+        =  kw("VALUES") _ rows:values_row() ++ (_ "," _) _ {
+            // Its columns are named column1, column2 and so on
+            let width = rows.first().map_or(0, Vec::len);
+            let columns = (1..=width)
+                .map(|i| {
+                    let name = format!("column{i}");
+                    SelectColumn::Expr(ResultColumn { expr: Expr::Column(name.clone()), name })
+                })
+                .collect();
+            Select {
+                ctes: vec![],
+                from: TableSource::Values(rows),
+                alias: None,
+                joins: vec![],
+                distinct: false,
+                columns,
+                conds: vec![],
+                group_by: vec![],
+                having: None,
+                compound: vec![],
+                order_by: vec![],
+                limit: None,
+                offset: None,
+            }
+        }
+        / operation() _ distinct:distinct()? columns:result_columns() _ from:from()? _ conds:search()? _ group_by:group_by()? _ having:having()? _ {
+            let (from, alias, joins) =
+                from.unwrap_or((TableSource::Values(vec![vec![]]), None, vec![]));
+            Select {
+                ctes: vec![],
+                from,
+                alias,
+                joins,
+                distinct: distinct.unwrap_or_default(),
+                columns,
+                conds: conds.unwrap_or_default(),
+                group_by: group_by.unwrap_or_default(),
+                having,
+                compound: vec![],
+                order_by: vec![],
+                limit: None,
+                offset: None,
+            }
+        }
+
+    rule from() -> (TableSource, Option<String>, Vec<Join>)
+        =  ("from" / "FROM") _ from:table_source() alias:table_alias()? joins:join()* {
+            (from, alias, joins)
+        }
+
+    rule values_row() -> Vec<Expr>
+        =  "(" _ exprs:expr() ++ (_ "," _) _ ")" { exprs }
+
+    rule compound_operator() -> CompoundOperator
+        =  kw("UNION") _ kw("ALL") { CompoundOperator::UnionAll }
+        / kw("UNION") { CompoundOperator::Union }
+        / kw("INTERSECT") { CompoundOperator::Intersect }
+        / kw("EXCEPT") { CompoundOperator::Except }
+
     pub rule create() -> Table
-        =  operation() _ name:name() _ "(" _ columns:column() ** "," _ ")" {
+        =  operation() _ if_not_exists()? name:name() _ "(" _ columns:column() ** "," _ table_constraint()* _ ")" _ table_options()? {
             let mut key: Option<String> = None;
-            let columns: Vec<_> = columns.iter().enumerate().map(|(idx, (n, t, pk, auto_inc, nullable))| {
-                if *pk {
+            let columns: Vec<_> = columns.into_iter().enumerate().map(|(idx, (n, t, pk, _auto_inc, nullable, collation))| {
+                if pk {
                     key = Some(n.to_string());
                 }
                 let name = n.to_string();
                 (name.clone(), Column {
                     name,
-                    cell_type: *t,
+                    cell_type: t,
                     idx,
-                    pk: *pk,
-                    nullable: *nullable,
+                    pk,
+                    nullable,
+                    collation,
                 })
             }).collect();
             Table {
@@ -270,9 +1065,48 @@ peg::parser! {
             }
         }
 
-    pub rule create_idx() -> (String, String)
-        =  operation() _ name() _ ("on" / "ON") _ table:name() _ "(" _ column:name() _ ")" {
-            (table.to_string(), column.to_string())
+    pub rule create_idx() -> Index
+        =  kw("CREATE") _ unique:(kw("UNIQUE") _)? kw("INDEX") _ if_not_exists()? name:name() _ kw("ON") _ table_name:name() _
+            "(" _ columns:indexed_column() ++ ("," _) _ ")" _ filter:(kw("WHERE") _ expr())? _ {
+            Index {
+                name: name.to_string(),
+                table_name: table_name.to_string(),
+                columns,
+                unique: unique.is_some(),
+                partial: filter.is_some(),
+            }
+        }
+
+    pub rule create_view() -> View
+        =  kw("CREATE") _ ((kw("TEMP") / kw("TEMPORARY")) _)? kw("VIEW") _ if_not_exists()? name:name()
+            columns:(_ "(" c:name() ++ (_ ",") _ ")" { c })? _ kw("AS") _ select:select() {
+            View {
+                name: name.to_string(),
+                columns: columns.unwrap_or_default().into_iter().map(str::to_string).collect(),
+                select,
+            }
+        }
+
+    rule with() -> Vec<Cte>
+        =  kw("WITH") _ (kw("RECURSIVE") _)? ctes:cte() ++ (_ "," _) _ { ctes }
+
+    rule cte() -> Cte
+        =  name:name() columns:(_ "(" c:name() ++ (_ ",") _ ")" { c })? _ kw("AS") _ "(" _ select:select() _ ")" {
+            let view = View {
+                name: name.to_string(),
+                columns: columns.unwrap_or_default().into_iter().map(str::to_string).collect(),
+                select,
+            };
+            Cte { view }
+        }
+
+    rule indexed_column() -> IndexedColumn
+        =  name:name() collation:(_ kw("COLLATE") _ c:name() { c })? order:(_ o:$(kw("ASC") / kw("DESC")) { o })? _ {
+            IndexedColumn {
+                name: name.to_string(),
+                collation: collation.map(|c| c.to_string()),
+                desc: order.is_some_and(|o| o.eq_ignore_ascii_case("DESC")),
+            }
         }
 
     rule _() = quiet!{[' ' | '\t' | '\r' | '\n']*}
@@ -280,37 +1114,256 @@ peg::parser! {
 
     rule word() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' ]+
 
+    rule ident_char() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
+
+    /// Case-insensitive keyword that is not the prefix of a longer identifier.
+    rule kw(k: &'static str)
+        = i:quiet!{$([_]*<{k.len()}>)} !ident_char() {?
+            if i.eq_ignore_ascii_case(k) { Ok(()) } else { Err(k) }
+        }
+
     rule name() -> &'input str
         =  quiet!{_ c:$word() { c }}
         / quiet!{_ "\"" c:$( word() ** _) "\"" { c }}
         / expected!("column_name")
 
-    rule value() -> &'input str
-        =  quiet!{v:$word() { v }}
-        / quiet!{['"' | '\''] v:$(word() ** _) ['"' | '\''] { v }}
-        / expected!("value")
+    /// A column, qualified by its table or not, looked up by its parts without their quotes.
+    rule column_name() -> String
+        =  quiet!{_ parts:(name_part() ++ ".") { parts.join(".") }}
+        / expected!("column_name")
 
-    rule search() -> Vec<Condition>
-        =  _ ("WHERE" / "where") cond:(condition() ** conditionals()) { cond }
+    rule name_part() -> &'input str
+        =  "\"" c:$([^ '"']*) "\"" { c }
+        / "`" c:$([^ '`']*) "`" { c }
+        / "[" c:$([^ ']']*) "]" { c }
+        / c:$(ident_char()+) { c }
+
+    rule literal() -> Value
+        =  quiet!{
+            n:$("-"? ['0'..='9']+ "." ['0'..='9']* exponent()?
+                / "-"? "." ['0'..='9']+ exponent()?
+                / "-"? ['0'..='9']+ exponent()) !ident_char() {?
+                n.parse().map(Value::Float).or(Err("real"))
+            }
+            / sign:$("-"?) ("0x" / "0X") h:$(['0'..='9' | 'a'..='f' | 'A'..='F']+) !ident_char() {?
+                u64::from_str_radix(h, 16)
+                    .map(|n| Value::Integer(if sign.is_empty() { n as i64 } else { (n as i64).wrapping_neg() }))
+                    .or(Err("hexadecimal integer"))
+            }
+            / n:$("-"? ['0'..='9']+) !ident_char() {
+                n.parse().map(Value::Integer).unwrap_or_else(|_| Value::Float(n.parse().unwrap_or_default()))
+            }
+            / "'" s:$(([^ '\''] / "''")*) "'" { Value::Text(s.replace("''", "'")) }
+            / ['x' | 'X'] "'" h:$(['0'..='9' | 'a'..='f' | 'A'..='F']*) "'" {?
+                (0..h.len())
+                    .step_by(2)
+                    .map(|i| h.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .map(Value::Blob)
+                    .ok_or("blob")
+            }
+            / kw("NULL") { Value::Null }
+            / kw("TRUE") { Value::Integer(1) }
+            / kw("FALSE") { Value::Integer(0) }
+        }
+        / expected!("literal")
+
+    rule exponent() = ['e' | 'E'] ['+' | '-']? ['0'..='9']+
+
+    rule search() -> Vec<Expr>
+        =  _ ("WHERE" / "where") _ e:expr() _ { e.conjuncts() }
         / expected!("search")
 
-    rule conditionals() -> ()
-        = quiet!{"AND" / "and" / "OR" / "or"}
-        / expected!("conditionals")
-
-    rule condition() -> Condition
-        = quiet!{
-            c:name() _ "=" _ v:value() _? { Condition::Eq(c.to_string(), v.to_string()) }
-            / c:name() _ ">=" _ v:value() _? { Condition::GreaterEq(c.to_string(), v.to_string()) }
-            / c:name() _ ">" _ v:value() _? { Condition::Greater(c.to_string(), v.to_string()) }
-            / c:name() _ "<=" _ v:value() _? { Condition::LessEq(c.to_string(), v.to_string()) }
-            / c:name() _ "<" _ v:value() _? { Condition::Less(c.to_string(), v.to_string()) }
-            / c:name() _ "!=" _ v:value() _? { Condition::Ne(c.to_string(), v.to_string()) }
-            / c:name() _ ("BETWEEN" / "between") _ f:value() _ ("AND" / "and") _ t:value() _? {
-                Condition::Between(c.to_string(), f.to_string(), t.to_string())
+    rule group_by() -> Vec<Expr>
+        =  kw("GROUP") _ kw("BY") _ terms:expr() ++ ("," _) { terms }
+        / expected!("group by")
+
+    rule having() -> Expr
+        =  kw("HAVING") _ e:expr() { e }
+        / expected!("having")
+
+    rule order_by() -> Vec<OrderingTerm>
+        =  kw("ORDER") _ kw("BY") _ terms:ordering_term() ++ ("," _) { terms }
+        / expected!("order by")
+
+    /// `LIMIT count [OFFSET skip]`, or the older `LIMIT skip, count`.
+    rule limit() -> (Expr, Option<Expr>)
+        =  kw("LIMIT") _ offset:expr() _ "," _ limit:expr() { (limit, Some(offset)) }
+        / kw("LIMIT") _ limit:expr() offset:(_ kw("OFFSET") _ o:expr() { o })? { (limit, offset) }
+        / expected!("limit")
+
+    rule ordering_term() -> OrderingTerm
+        =  expr:expr() order:(_ o:$(kw("ASC") / kw("DESC")) { o })? nulls:(_ kw("NULLS") _ n:$(kw("FIRST") / kw("LAST")) { n })? _ {
+            OrderingTerm {
+                expr,
+                desc: order.is_some_and(|o| o.eq_ignore_ascii_case("DESC")),
+                nulls: nulls.map(|n| if n.eq_ignore_ascii_case("FIRST") { NullsOrder::First } else { NullsOrder::Last }),
+            }
+        }
+
+    pub rule expr() -> Expr
+        =  l:and_expr() rest:(_ kw("OR") _ r:and_expr() { r })* {
+            rest.into_iter().fold(l, |l, r| Expr::binary(l, Operator::Or, r))
+        }
+
+    rule and_expr() -> Expr
+        =  l:not_expr() rest:(_ kw("AND") _ r:not_expr() { r })* {
+            rest.into_iter().fold(l, |l, r| Expr::binary(l, Operator::And, r))
+        }
+
+    rule not_expr() -> Expr
+        =  kw("NOT") _ e:not_expr() { Expr::Not(Box::new(e)) }
+        / equality()
+
+    rule equality() -> Expr
+        =  l:comparison() rest:(_ t:equality_tail() { t })* {
+            rest.into_iter().fold(l, |l, tail| match tail {
+                EqualityTail::Binary(op, r) => Expr::binary(l, op, r),
+                EqualityTail::IsNull => Expr::IsNull(Box::new(l)),
+                EqualityTail::NotNull => Expr::NotNull(Box::new(l)),
+                EqualityTail::Between(low, high, negated) => Expr::Between {
+                    expr: Box::new(l),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                },
+                EqualityTail::InList(list, negated) => Expr::InList {
+                    expr: Box::new(l),
+                    list,
+                    negated,
+                },
+                EqualityTail::InSelect(select, negated) => Expr::InSelect {
+                    expr: Box::new(l),
+                    select,
+                    negated,
+                },
+            })
+        }
+
+    rule equality_tail() -> EqualityTail
+        =  ("==" / "=") _ r:comparison() { EqualityTail::Binary(Operator::Eq, r) }
+        / ("!=" / "<>") _ r:comparison() { EqualityTail::Binary(Operator::Ne, r) }
+        / kw("IS") _ kw("NOT") _ r:comparison() { EqualityTail::Binary(Operator::IsNot, r) }
+        / kw("IS") _ r:comparison() { EqualityTail::Binary(Operator::Is, r) }
+        / kw("ISNULL") { EqualityTail::IsNull }
+        / kw("NOTNULL") { EqualityTail::NotNull }
+        / kw("NOT") _ kw("NULL") { EqualityTail::NotNull }
+        / negated:(kw("NOT") _)? kw("BETWEEN") _ low:comparison() _ kw("AND") _ high:comparison() {
+            EqualityTail::Between(low, high, negated.is_some())
+        }
+        / negated:(kw("NOT") _)? kw("IN") _ "(" _ s:select() _ ")" {
+            EqualityTail::InSelect(Box::new(s), negated.is_some())
+        }
+        / negated:(kw("NOT") _)? kw("IN") _ "(" _ list:expr() ** ("," _) _ ")" {
+            EqualityTail::InList(list, negated.is_some())
+        }
+
+    rule comparison() -> Expr
+        =  l:bitwise() rest:(_ op:comparison_operator() _ r:bitwise() { (op, r) })* {
+            rest.into_iter().fold(l, |l, (op, r)| Expr::binary(l, op, r))
+        }
+
+    rule comparison_operator() -> Operator
+        =  "<=" { Operator::LessEq }
+        / ">=" { Operator::GreaterEq }
+        / "<" ![ '>' | '<' ] { Operator::Less }
+        / ">" !">" { Operator::Greater }
+
+    rule bitwise() -> Expr
+        =  l:additive() rest:(_ op:bitwise_operator() _ r:additive() { (op, r) })* {
+            rest.into_iter().fold(l, |l, (op, r)| Expr::binary(l, op, r))
+        }
+
+    rule bitwise_operator() -> Operator
+        =  "<<" { Operator::ShiftLeft }
+        / ">>" { Operator::ShiftRight }
+        / "&" { Operator::BitAnd }
+        / "|" !"|" { Operator::BitOr }
+
+    rule additive() -> Expr
+        =  l:multiplicative() rest:(_ op:additive_operator() _ r:multiplicative() { (op, r) })* {
+            rest.into_iter().fold(l, |l, (op, r)| Expr::binary(l, op, r))
+        }
+
+    rule additive_operator() -> Operator
+        =  "+" { Operator::Add }
+        / "-" { Operator::Subtract }
+
+    rule multiplicative() -> Expr
+        =  l:concat() rest:(_ op:multiplicative_operator() _ r:concat() { (op, r) })* {
+            rest.into_iter().fold(l, |l, (op, r)| Expr::binary(l, op, r))
+        }
+
+    rule multiplicative_operator() -> Operator
+        =  "*" { Operator::Multiply }
+        / "/" { Operator::Divide }
+        / "%" { Operator::Remainder }
+
+    rule concat() -> Expr
+        =  l:collate() rest:(_ "||" _ r:collate() { r })* {
+            rest.into_iter().fold(l, |l, r| Expr::binary(l, Operator::Concat, r))
+        }
+
+    rule collate() -> Expr
+        =  e:unary() names:(_ kw("COLLATE") _ n:name() { n })* {
+            names.into_iter().fold(e, |e, name| Expr::Collate(Box::new(e), name.to_string()))
+        }
+
+    /// Prefix operators, binding tighter than any other. Negative numbers stay literals.
+    rule unary() -> Expr
+        =  primary()
+        / "-" _ e:unary() { Expr::Unary(UnaryOperator::Negate, Box::new(e)) }
+        / "+" _ e:unary() { Expr::Unary(UnaryOperator::Plus, Box::new(e)) }
+        / "~" _ e:unary() { Expr::Unary(UnaryOperator::BitNot, Box::new(e)) }
+
+    rule primary() -> Expr
+        =  "(" _ s:select() _ ")" { Expr::Subquery(Box::new(s)) }
+        / kw("EXISTS") _ "(" _ s:select() _ ")" { Expr::Exists(Box::new(s)) }
+        / "(" _ e:expr() _ ")" { e }
+        / v:literal() { Expr::Literal(v) }
+        / function_call()
+        / c:column_name() { Expr::Column(c) }
+        / expected!("expression")
+
+    rule function_call() -> Expr
+        =  name:$(ident_char()+) _ "(" _ call:(
+                "*" _ { (vec![], false) }
+                / distinct:(kw("DISTINCT") _)? args:expr() ** ("," _) _ { (args, distinct.is_some()) }
+            ) ")" window:(_ kw("OVER") _ "(" _ w:window() _ ")" { w })? {
+            let call = Expr::Function { name: name.to_ascii_lowercase(), args: call.0, distinct: call.1 };
+            match window {
+                Some(window) => Expr::Over { call: Box::new(call), window: Box::new(window) },
+                None => call,
             }
         }
-        / expected!("condition")
+
+    rule window() -> Window
+        =  partition_by:(kw("PARTITION") _ kw("BY") _ e:expr() ++ (_ "," _) _ { e })? order_by:(o:order_by() _ { o })? frame:frame()? {
+            Window {
+                partition_by: partition_by.unwrap_or_default(),
+                order_by: order_by.unwrap_or_default(),
+                frame: frame.unwrap_or_default(),
+            }
+        }
+
+    rule frame() -> Frame
+        =  rows:frame_units() _ kw("BETWEEN") _ start:frame_bound() _ kw("AND") _ end:frame_bound() _ {
+            Frame { rows, start, end }
+        }
+        / rows:frame_units() _ start:frame_bound() _ {
+            Frame { rows, start, end: FrameBound::CurrentRow }
+        }
+
+    rule frame_units() -> bool
+        =  kw("ROWS") { true }
+        / kw("RANGE") { false }
+
+    rule frame_bound() -> FrameBound
+        =  kw("UNBOUNDED") _ kw("PRECEDING") { FrameBound::UnboundedPreceding }
+        / kw("UNBOUNDED") _ kw("FOLLOWING") { FrameBound::UnboundedFollowing }
+        / kw("CURRENT") _ kw("ROW") { FrameBound::CurrentRow }
+        / e:expr() _ kw("PRECEDING") { FrameBound::Preceding(e) }
+        / e:expr() _ kw("FOLLOWING") { FrameBound::Following(e) }
 
     rule operation() -> ()
         =  quiet!{"CREATE TABLE"
@@ -339,57 +1392,175 @@ peg::parser! {
         / "create index"}
         / expected!("operation")
 
-    rule column() -> (&'input str, CellType, bool, bool, bool)
-        =  name:name() _ t:cell_type() _ pk:primary_key()? _ auto_inc:auto()? _ not_null:unnullable()? {
-            let is_pk = pk.is_some();
-            let is_nullable = if is_pk { false } else if not_null.is_some() { false } else { true };
-            (name, t, is_pk, auto_inc.is_some(), is_nullable)
+    rule if_not_exists() = kw("IF") _ kw("NOT") _ kw("EXISTS") _
+
+    rule column() -> (&'input str, CellType, bool, bool, bool, Option<String>)
+        =  !constraint_name() name:name() t:(_ t:type_name() { t })? constraints:(_ c:column_constraint() { c })* _ {
+            let t = t.unwrap_or_default().parse().unwrap_or(CellType::Blob);
+            let is_pk = constraints.contains(&ColumnConstraint::PrimaryKey);
+            let auto_inc = constraints.contains(&ColumnConstraint::AutoIncrement);
+            let is_nullable = !is_pk && !constraints.contains(&ColumnConstraint::NotNull);
+            let collation = constraints.into_iter().find_map(|c| match c {
+                ColumnConstraint::Collate(name) => Some(name),
+                _ => None,
+            });
+            (name, t, is_pk, auto_inc, is_nullable, collation)
         }
         / expected!("column")
 
-    rule primary_key() -> ()
-        =  "PRIMARY KEY"
-        / "primary key"
+    /// Declared type: one or more words, optionally followed by size arguments like `(10, 2)`.
+    rule type_name() -> &'input str
+        =  t:$((!constraint_keyword() word()) ++ " ") _ ("(" _ signed_number() _ ("," _ signed_number() _)? ")")? { t }
+
+    rule signed_number() = ['+' | '-']? ['0'..='9' | '.']+
+
+    rule constraint_keyword()
+        =  kw("CONSTRAINT") / kw("PRIMARY") / kw("NOT") / kw("NULL") / kw("UNIQUE") / kw("CHECK")
+        / kw("DEFAULT") / kw("COLLATE") / kw("REFERENCES") / kw("GENERATED") / kw("AS")
+
+    rule constraint_name() = _ (kw("CONSTRAINT") / kw("PRIMARY") / kw("UNIQUE") / kw("CHECK") / kw("FOREIGN"))
+
+    rule column_constraint() -> ColumnConstraint
+        =  kw("CONSTRAINT") _ name() { ColumnConstraint::Other }
+        / primary_key() (_ (kw("ASC") / kw("DESC")))? (_ conflict_clause())? { ColumnConstraint::PrimaryKey }
+        / auto() { ColumnConstraint::AutoIncrement }
+        / unnullable() (_ conflict_clause())? { ColumnConstraint::NotNull }
+        / kw("NULL") { ColumnConstraint::Other }
+        / kw("UNIQUE") (_ conflict_clause())? { ColumnConstraint::Other }
+        / kw("CHECK") _ parens() { ColumnConstraint::Other }
+        / kw("DEFAULT") _ (parens() / literal() / signed_number() / name()) { ColumnConstraint::Other }
+        / kw("COLLATE") _ c:name() { ColumnConstraint::Collate(c.to_string()) }
+        / foreign_key_clause() { ColumnConstraint::Other }
+        / (kw("GENERATED") _ kw("ALWAYS") _)? kw("AS") _ parens() (_ (kw("STORED") / kw("VIRTUAL")))? {
+            ColumnConstraint::Other
+        }
+
+    rule table_constraint()
+        =  "," _ (kw("CONSTRAINT") _ name() _)? (
+            (primary_key() / kw("UNIQUE")) _ parens() (_ conflict_clause())?
+            / kw("CHECK") _ parens()
+            / kw("FOREIGN") _ kw("KEY") _ parens() _ foreign_key_clause()
+        ) _
+
+    rule table_options() = (kw("WITHOUT") _ kw("ROWID") / kw("STRICT")) ++ (_ "," _)
+
+    rule conflict_clause() = kw("ON") _ kw("CONFLICT") _ word()
+
+    rule foreign_key_clause()
+        =  kw("REFERENCES") _ name() (_ parens())? (_ foreign_key_action())*
+
+    rule foreign_key_action()
+        =  kw("ON") _ (kw("DELETE") / kw("UPDATE")) _ (
+            kw("SET") _ (kw("NULL") / kw("DEFAULT")) / kw("CASCADE") / kw("RESTRICT") / kw("NO") _ kw("ACTION")
+        )
+        / kw("MATCH") _ name()
+        / (kw("NOT") _)? kw("DEFERRABLE") (_ kw("INITIALLY") _ (kw("DEFERRED") / kw("IMMEDIATE")))?
+
+    /// Balanced parentheses, used to skip over constraint bodies we don't interpret.
+    rule parens() = "(" ([^ '(' | ')'] / parens())* ")"
+
+    rule primary_key()
+        =  kw("PRIMARY") _ kw("KEY")
         / expected!("primary key")
 
-    rule auto() -> ()
-        =  "AUTOINCREMENT"
-        / "autoincrement"
+    rule auto()
+        =  kw("AUTOINCREMENT")
         / expected!("autoincrement")
 
-    rule unnullable() -> ()
-        =  quiet!{"NOT NULL"}
-        / quiet!{"not null"}
+    rule unnullable()
+        =  quiet!{kw("NOT") _ kw("NULL")}
         / expected!("nullable")
 
-    rule columns() -> Vec<&'input str>
-        =  "*" { vec!["*"] }
-        / "count(*)" { vec!["count(*)"] }
-        / columns:(name() ** ",") { columns }
+    rule distinct() -> bool
+        =  kw("DISTINCT") _ { true }
+        / kw("ALL") _ { false }
+
+    rule result_columns() -> Vec<SelectColumn>
+        =  columns:result_column() ++ ("," _) { columns }
         / expected!("columns")
 
-    rule cell_type() -> CellType
-        =  "integer" { CellType::Integer }
-        / "float" { CellType::Float }
-        / "text" { CellType::Text }
-        / "blob" { CellType::Blob }
-        / expected!("cell type")
+    rule result_column() -> SelectColumn
+        =  _ "*" _ { SelectColumn::All(None) }
+        / _ table:$(ident_char()+) "." "*" _ { SelectColumn::All(Some(table.to_string())) }
+        / _ text:$(expr()) alias:alias()? _ {?
+            // The span is parsed again to keep its text as the column's name
+            let expr = expr(text).or(Err("expression"))?;
+            let name = match (alias, &expr) {
+                (Some(alias), _) => alias,
+                (None, Expr::Column(name)) => name.rsplit('.').next().unwrap_or(name).to_string(),
+                (None, _) => text.trim().to_string(),
+            };
+            Ok(SelectColumn::Expr(ResultColumn { expr, name }))
+        }
+
+    /// `AS name`, where `AS` may be left out unless the name is a keyword that can follow.
+    rule alias() -> String
+        =  _ kw("AS") _ a:alias_name() { a }
+        / _ !clause_keyword() a:alias_name() { a }
+
+    rule alias_name() -> String
+        =  "\"" a:$([^ '"']*) "\"" { a.to_string() }
+        / "'" a:$(([^ '\''] / "''")*) "'" { a.replace("''", "'") }
+        / "[" a:$([^ ']']*) "]" { a.to_string() }
+        / "`" a:$([^ '`']*) "`" { a.to_string() }
+        / a:$(ident_char()+) { a.to_string() }
+
+    rule clause_keyword()
+        =  kw("FROM") / kw("WHERE") / kw("GROUP") / kw("HAVING") / kw("ORDER") / kw("LIMIT")
+        / kw("UNION") / kw("EXCEPT") / kw("INTERSECT") / kw("WINDOW")
+
+    /// Alias of a FROM clause table, which can't be a keyword that continues the clause.
+    rule table_alias() -> String
+        =  _ kw("AS") _ a:alias_name() { a }
+        / _ !(clause_keyword() / join_keyword()) a:alias_name() { a }
+
+    rule join_keyword()
+        =  kw("JOIN") / kw("INNER") / kw("LEFT") / kw("CROSS") / kw("NATURAL") / kw("ON") / kw("USING")
+
+    /// A table name, or a parenthesized subquery whose rows act as a table.
+    rule table_source() -> TableSource
+        =  _ "(" _ s:select() _ ")" { TableSource::Subquery(Box::new(s)) }
+        / n:name() { TableSource::Table(n.to_string()) }
+
+    rule join() -> Join
+        =  _ "," _ table:table_source() alias:table_alias()? {
+            Join { kind: JoinKind::Cross, table, alias, constraint: JoinConstraint::None }
+        }
+        / _ kw("NATURAL") _ kind:join_operator() _ table:table_source() alias:table_alias()? {
+            Join { kind, table, alias, constraint: JoinConstraint::Natural }
+        }
+        / _ kind:join_operator() _ table:table_source() alias:table_alias()? constraint:join_constraint()? {
+            Join { kind, table, alias, constraint: constraint.unwrap_or(JoinConstraint::None) }
+        }
+
+    rule join_operator() -> JoinKind
+        =  kw("LEFT") _ (kw("OUTER") _)? kw("JOIN") { JoinKind::Left }
+        / (kw("INNER") _)? kw("JOIN") { JoinKind::Inner }
+        / kw("CROSS") _ kw("JOIN") { JoinKind::Cross }
+
+    rule join_constraint() -> JoinConstraint
+        =  _ kw("ON") _ e:expr() { JoinConstraint::On(e) }
+        / _ kw("USING") _ "(" _ names:name() ++ (_ "," _) _ ")" {
+            JoinConstraint::Using(names.into_iter().map(|n| n.to_string()).collect())
+        }
   }
 }
 
-pub fn create_idx_sql(s: &str) -> Result<(String, String), anyhow::Error> {
-    let create = sql::create_idx(s).or_else(|e| {
-        Err(anyhow::anyhow!(
-            "Failed to parse create index statement: {:?}",
-            e
-        ))
-    })?;
+pub fn create_idx_sql(s: &str) -> Result<Index, anyhow::Error> {
+    let create = sql::create_idx(s)
+        .map_err(|e| anyhow::anyhow!("Failed to parse create index statement: {:?}", e))?;
+    Ok(create)
+}
+
+pub fn create_view_sql(s: &str) -> Result<View, anyhow::Error> {
+    let create = sql::create_view(s)
+        .map_err(|e| anyhow::anyhow!("Failed to parse create view statement: {:?}", e))?;
     Ok(create)
 }
 
 pub fn create_sql(s: &str) -> Result<Table, anyhow::Error> {
-    let create = sql::create(s)
-        .or_else(|e| Err(anyhow::anyhow!("Failed to parse create statement: {:?}", e)))?;
+    let create =
+        sql::create(s).map_err(|e| anyhow::anyhow!("Failed to parse create statement: {:?}", e))?;
     Ok(create)
 }
 
@@ -399,6 +1570,23 @@ fn assert_create(test: &str, expected: Table) {
     assert_eq!(answer, expected);
 }
 
+#[cfg(test)]
+fn eq(column: &str, value: Value) -> Expr {
+    Expr::binary(
+        Expr::Column(column.to_string()),
+        Operator::Eq,
+        Expr::Literal(value),
+    )
+}
+
+#[cfg(test)]
+fn column(name: &str) -> SelectColumn {
+    SelectColumn::Expr(ResultColumn {
+        expr: Expr::Column(name.to_string()),
+        name: name.to_string(),
+    })
+}
+
 #[cfg(test)]
 fn assert_select(test: &str, expected: Select) {
     let answer = Select::from_str(test).unwrap();
@@ -423,6 +1611,7 @@ fn test_create() {
                         idx: 0,
                         pk: true,
                         nullable: false,
+                        collation: None,
                     },
                 ),
                 (
@@ -433,6 +1622,7 @@ fn test_create() {
                         idx: 1,
                         pk: false,
                         nullable: false,
+                        collation: None,
                     },
                 ),
                 (
@@ -443,6 +1633,7 @@ fn test_create() {
                         idx: 2,
                         pk: false,
                         nullable: true,
+                        collation: None,
                     },
                 ),
                 (
@@ -453,6 +1644,7 @@ fn test_create() {
                         idx: 3,
                         pk: false,
                         nullable: true,
+                        collation: None,
                     },
                 ),
             ]),
@@ -466,9 +1658,19 @@ fn test_select_all_lowercase() {
     assert_select(
         "select * from apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -478,9 +1680,19 @@ fn test_select_all_uppercase() {
     assert_select(
         "SELECT * FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -490,9 +1702,19 @@ fn test_select_all_different_case() {
     assert_select(
         "select * FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -502,9 +1724,26 @@ fn test_count_rows() {
     assert_select(
         "SELECT count(*) FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::Count,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::Expr(ResultColumn {
+                expr: Expr::Function {
+                    name: "count".to_string(),
+                    args: vec![],
+                    distinct: false,
+                },
+                name: "count(*)".to_string(),
+            })],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -514,9 +1753,19 @@ fn test_select_col() {
     assert_select(
         "SELECT name FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::Columns(vec!["name".to_string()]),
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![column("name")],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -526,9 +1775,19 @@ fn test_select_cols_without_space_after_comma() {
     assert_select(
         "SELECT name,color FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::Columns(vec!["name".to_string(), "color".to_string()]),
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![column("name"), column("color")],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -538,9 +1797,19 @@ fn test_select_cols_with_space_after_comma() {
     assert_select(
         "SELECT name, color FROM apples",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::Columns(vec!["name".to_string(), "color".to_string()]),
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![column("name"), column("color")],
             conds: vec![],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -550,9 +1819,19 @@ fn test_select_where() {
     assert_select(
         "SELECT * FROM apples WHERE name = 'red'",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
-            conds: vec![Condition::Eq("name".to_string(), "red".to_string())],
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
+            conds: vec![eq("name", Value::Text("red".to_string()))],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -562,12 +1841,19 @@ fn test_select_cols_where() {
     assert_select(
         "SELECT name, eye_color FROM people WHERE eye_color = 'Dark Red'",
         Select {
-            name: "people".to_string(),
-            columns: SelectColumns::Columns(vec!["name".to_string(), "eye_color".to_string()]),
-            conds: vec![Condition::Eq(
-                "eye_color".to_string(),
-                "Dark Red".to_string(),
-            )],
+            ctes: vec![],
+            from: TableSource::Table("people".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![column("name"), column("eye_color")],
+            conds: vec![eq("eye_color", Value::Text("Dark Red".to_string()))],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -577,12 +1863,22 @@ fn test_select_where_and() {
     assert_select(
         "select * from apples where name = 'red' and id = 297",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
             conds: vec![
-                Condition::Eq("name".to_string(), "red".to_string()),
-                Condition::Eq("id".to_string(), "297".to_string()),
+                eq("name", Value::Text("red".to_string())),
+                eq("id", Value::Integer(297)),
             ],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
@@ -592,12 +1888,778 @@ fn test_select_where_more_than() {
     assert_select(
         "select * from apples where name = 'red' and id > 297",
         Select {
-            name: "apples".to_string(),
-            columns: SelectColumns::All,
+            ctes: vec![],
+            from: TableSource::Table("apples".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
             conds: vec![
-                Condition::Eq("name".to_string(), "red".to_string()),
-                Condition::Greater("id".to_string(), "297".to_string()),
+                eq("name", Value::Text("red".to_string())),
+                Expr::binary(
+                    Expr::Column("id".to_string()),
+                    Operator::Greater,
+                    Expr::Literal(Value::Integer(297)),
+                ),
             ],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         },
     );
 }
+
+#[cfg(test)]
+fn assert_literal(literal: &str, expected: Value) {
+    let stmt = format!("select * from t where c = {literal}");
+    let answer = Select::from_str(&stmt).unwrap();
+    assert_eq!(answer.conds, vec![eq("c", expected)]);
+}
+
+#[test]
+fn test_select_where_literals() {
+    assert_literal("297", Value::Integer(297));
+    assert_literal("-12", Value::Integer(-12));
+    assert_literal("0x1F", Value::Integer(31));
+    assert_literal("1.0", Value::Float(1.0));
+    assert_literal(".5e1", Value::Float(5.0));
+    assert_literal("'123'", Value::Text("123".to_string()));
+    assert_literal("'O''Neil'", Value::Text("O'Neil".to_string()));
+    assert_literal("''", Value::Text(String::new()));
+    assert_literal("x'0aFF'", Value::Blob(vec![0x0a, 0xff]));
+    assert_literal("NULL", Value::Null);
+    assert_literal("true", Value::Integer(1));
+    assert_literal("False", Value::Integer(0));
+}
+
+#[test]
+fn test_select_where_bad_blob_literal() {
+    assert!(Select::from_str("select * from t where c = x'abc'").is_err());
+}
+
+#[test]
+fn test_create_column_affinities() {
+    let table = Table::from_str(
+        "CREATE TABLE t (a INT, b VARCHAR(255) NOT NULL DEFAULT 'x', c DOUBLE PRECISION, \
+        d DECIMAL(10, 2) CHECK (d > 0), e, f BLOB REFERENCES other(id) ON DELETE CASCADE, \
+        PRIMARY KEY (a), UNIQUE (b, c))",
+    )
+    .unwrap();
+    let affinities: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+        .iter()
+        .map(|c| table.columns[*c].cell_type)
+        .collect();
+    assert_eq!(
+        affinities,
+        vec![
+            CellType::Integer,
+            CellType::Text,
+            CellType::Float,
+            CellType::Numeric,
+            CellType::Blob,
+            CellType::Blob,
+        ]
+    );
+    assert!(!table.columns["b"].nullable);
+}
+
+#[cfg(test)]
+fn eval_where(stmt: &str, row: &[Value]) -> Value {
+    let table = Table::from_str("create table t (a integer, b text, c, d text collate nocase)");
+    let select = Select::from_str(stmt).unwrap();
+    let terms = select.conds.into_iter();
+    let cond = terms
+        .reduce(|l, r| Expr::binary(l, Operator::And, r))
+        .unwrap();
+    cond.eval(row, &table.unwrap().columns, &Collations::default())
+        .unwrap()
+}
+
+#[test]
+fn test_where_precedence() {
+    assert_select(
+        "select * from t where not a = 1 or b is not null and c notnull",
+        Select {
+            ctes: vec![],
+            from: TableSource::Table("t".to_string()),
+            alias: None,
+            joins: vec![],
+            distinct: false,
+            columns: vec![SelectColumn::All(None)],
+            conds: vec![Expr::binary(
+                Expr::Not(Box::new(eq("a", Value::Integer(1)))),
+                Operator::Or,
+                Expr::binary(
+                    Expr::binary(
+                        Expr::Column("b".to_string()),
+                        Operator::IsNot,
+                        Expr::Literal(Value::Null),
+                    ),
+                    Operator::And,
+                    Expr::NotNull(Box::new(Expr::Column("c".to_string()))),
+                ),
+            )],
+            group_by: vec![],
+            having: None,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        },
+    );
+}
+
+#[test]
+fn test_where_three_valued_logic() {
+    use Value::*;
+    let row = [Null, Text("x".to_string()), Integer(5)];
+    assert_eq!(eval_where("select * from t where a > NULL", &row), Null);
+    assert_eq!(eval_where("select * from t where a != 5", &row), Null);
+    assert_eq!(eval_where("select * from t where not a = 5", &row), Null);
+    assert_eq!(
+        eval_where("select * from t where a = 1 or c = 5", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where a = 1 or c = 4", &row),
+        Null
+    );
+    assert_eq!(
+        eval_where("select * from t where a = 1 and c = 4", &row),
+        Integer(0)
+    );
+    assert_eq!(
+        eval_where("select * from t where a is null", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where a isnull", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where a not null", &row),
+        Integer(0)
+    );
+    assert_eq!(
+        eval_where("select * from t where c is not a", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where null is null", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where c between 1 and a", &row),
+        Null
+    );
+    assert_eq!(
+        eval_where("select * from t where c not between 6 and a", &row),
+        Integer(1)
+    );
+}
+
+#[test]
+fn test_where_comparison_affinity() {
+    use Value::*;
+    let row = [
+        Integer(123),
+        Text("123".to_string()),
+        Text("123".to_string()),
+    ];
+    assert_eq!(
+        eval_where("select * from t where a = '123'", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where b = 123", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where c = 123", &row),
+        Integer(0)
+    );
+    assert_eq!(eval_where("select * from t where a = b", &row), Integer(1));
+    assert_eq!(
+        eval_where("select * from t where '123' = 123", &row),
+        Integer(0)
+    );
+}
+
+#[test]
+fn test_where_collations() {
+    use Value::*;
+    let row = [
+        Integer(1),
+        Text("Red".to_string()),
+        Text("red  ".to_string()),
+        Text("RED".to_string()),
+    ];
+    assert_eq!(
+        eval_where("select * from t where b = 'red'", &row),
+        Integer(0)
+    );
+    assert_eq!(
+        eval_where("select * from t where d = 'red'", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where 'red' = d", &row),
+        Integer(1)
+    );
+    assert_eq!(eval_where("select * from t where b = d", &row), Integer(0));
+    assert_eq!(eval_where("select * from t where d = b", &row), Integer(1));
+    assert_eq!(
+        eval_where("select * from t where b COLLATE NOCASE = 'red'", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where d = 'red' collate binary", &row),
+        Integer(0)
+    );
+    assert_eq!(
+        eval_where("select * from t where c = 'red' collate rtrim", &row),
+        Integer(1)
+    );
+    assert_eq!(
+        eval_where("select * from t where d between 'a' and 'z'", &row),
+        Integer(1)
+    );
+}
+
+#[test]
+fn test_create_collations() {
+    let table = Table::from_str("create table t (a text collate nocase not null, b text)").unwrap();
+    assert_eq!(table.columns["a"].collation, Some("nocase".to_string()));
+    assert!(!table.columns["a"].nullable);
+    assert_eq!(table.columns["b"].collation, None);
+
+    let index = Index::from_str(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx ON t (a, b COLLATE RTRIM DESC) WHERE b IS NOT NULL",
+    )
+    .unwrap();
+    assert_eq!(
+        index,
+        Index {
+            name: "idx".to_string(),
+            table_name: "t".to_string(),
+            columns: vec![
+                IndexedColumn {
+                    name: "a".to_string(),
+                    collation: None,
+                    desc: false,
+                },
+                IndexedColumn {
+                    name: "b".to_string(),
+                    collation: Some("RTRIM".to_string()),
+                    desc: true,
+                },
+            ],
+            unique: true,
+            partial: true,
+        }
+    );
+    assert_eq!(index.collation(0, &table), Some("nocase".to_string()));
+    assert_eq!(index.collation(1, &table), Some("RTRIM".to_string()));
+}
+
+#[test]
+fn test_select_order_by() {
+    let select = Select::from_str(
+        "select * from t where a > 1 ORDER BY b collate nocase desc, 2, c NULLS last, d asc nulls first",
+    )
+    .unwrap();
+    assert_eq!(select.conds.len(), 1);
+    assert_eq!(
+        select.order_by,
+        vec![
+            OrderingTerm {
+                expr: Expr::Collate(
+                    Box::new(Expr::Column("b".to_string())),
+                    "nocase".to_string()
+                ),
+                desc: true,
+                nulls: None,
+            },
+            OrderingTerm {
+                expr: Expr::Literal(Value::Integer(2)),
+                desc: false,
+                nulls: None,
+            },
+            OrderingTerm {
+                expr: Expr::Column("c".to_string()),
+                desc: false,
+                nulls: Some(NullsOrder::Last),
+            },
+            OrderingTerm {
+                expr: Expr::Column("d".to_string()),
+                desc: false,
+                nulls: Some(NullsOrder::First),
+            },
+        ]
+    );
+    let nulls_first: Vec<_> = select.order_by.iter().map(|t| t.nulls_first()).collect();
+    assert_eq!(nulls_first, vec![false, true, false, true]);
+    assert!(Select::from_str("select * from t order by").is_err());
+}
+
+#[test]
+fn test_select_limit() {
+    let limits = |stmt: &str| {
+        let select = Select::from_str(stmt).unwrap();
+        (select.limit, select.offset)
+    };
+    let int = |n| Some(Expr::Literal(Value::Integer(n)));
+    assert_eq!(limits("select * from t limit 10"), (int(10), None));
+    assert_eq!(
+        limits("select * from t where a = 1 order by b LIMIT 10 OFFSET 5"),
+        (int(10), int(5))
+    );
+    assert_eq!(limits("select * from t limit 5, 10"), (int(10), int(5)));
+    assert_eq!(limits("select * from t"), (None, None));
+    assert!(Select::from_str("select * from t limit").is_err());
+}
+
+#[test]
+fn test_select_distinct() {
+    let distinct = |stmt: &str| Select::from_str(stmt).unwrap().distinct;
+    assert!(distinct("select DISTINCT name, color from apples"));
+    assert!(distinct("select distinct * from apples"));
+    assert!(!distinct("select all name from apples"));
+    assert!(!distinct("select distinction from apples"));
+    assert_eq!(
+        Select::from_str("select distinct name from apples")
+            .unwrap()
+            .columns,
+        vec![column("name")]
+    );
+}
+
+#[test]
+fn test_select_aggregates() {
+    let select = Select::from_str(
+        "select dept, COUNT(DISTINCT boss), group_concat(name, '; ') from emp \
+        where salary > 0 group by dept, boss having count(*) > 1 order by 2 desc",
+    )
+    .unwrap();
+    let call = |name: &str, args: Vec<Expr>, distinct| Expr::Function {
+        name: name.to_string(),
+        args,
+        distinct,
+    };
+    assert_eq!(
+        select.columns,
+        vec![
+            column("dept"),
+            SelectColumn::Expr(ResultColumn {
+                expr: call("count", vec![Expr::Column("boss".to_string())], true),
+                name: "COUNT(DISTINCT boss)".to_string(),
+            }),
+            SelectColumn::Expr(ResultColumn {
+                expr: call(
+                    "group_concat",
+                    vec![
+                        Expr::Column("name".to_string()),
+                        Expr::Literal(Value::Text("; ".to_string())),
+                    ],
+                    false,
+                ),
+                name: "group_concat(name, '; ')".to_string(),
+            }),
+        ]
+    );
+    assert_eq!(
+        select.group_by,
+        vec![
+            Expr::Column("dept".to_string()),
+            Expr::Column("boss".to_string())
+        ]
+    );
+    let having = select.having.unwrap();
+    assert!(having.has_aggregate());
+
+    let mut calls = vec![];
+    let extracted = having.extract_aggregates(&mut calls);
+    assert_eq!(calls, vec![call("count", vec![], false)]);
+    assert_eq!(
+        extracted,
+        Expr::binary(
+            Expr::Column(Expr::aggregate_column(0)),
+            Operator::Greater,
+            Expr::Literal(Value::Integer(1)),
+        )
+    );
+}
+
+#[test]
+fn test_select_expressions() {
+    let select = Select::from_str(
+        "select t.*, a + 1 AS next, b || 'x' suffix, t.c, -a from t order by next",
+    )
+    .unwrap();
+    let names = select
+        .columns
+        .iter()
+        .map(|column| match column {
+            SelectColumn::All(table) => format!("{:?}.*", table),
+            SelectColumn::Expr(column) => column.name.clone(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Some(\"t\").*", "next", "suffix", "c", "-a"]);
+    assert_eq!(
+        select.columns[4],
+        SelectColumn::Expr(ResultColumn {
+            expr: Expr::Unary(
+                UnaryOperator::Negate,
+                Box::new(Expr::Column("a".to_string()))
+            ),
+            name: "-a".to_string(),
+        })
+    );
+    // Without AS, a clause keyword is not taken for an alias
+    assert_eq!(
+        Select::from_str("select a from t where a").unwrap().columns,
+        vec![column("a")]
+    );
+}
+
+#[test]
+fn test_arithmetic() {
+    use Value::*;
+    let eval = |expr: &str| {
+        let table = Table::from_str("create table t (a integer, b text)").unwrap();
+        let row = [Integer(7), Text("2.5".to_string())];
+        sql::expr(expr)
+            .unwrap()
+            .eval(&row, &table.columns, &Collations::default())
+            .unwrap()
+    };
+    assert_eq!(eval("2 + 3 * 4 - (2 + 3) * 4"), Integer(-6));
+    assert_eq!(eval("a / 2"), Integer(3));
+    assert_eq!(eval("a % -3"), Integer(1));
+    assert_eq!(eval("a / 2.0"), Float(3.5));
+    assert_eq!(eval("b * 2"), Float(5.0));
+    assert_eq!(eval("5.5 % 2"), Float(1.0));
+    assert_eq!(eval("a / 0"), Null);
+    assert_eq!(eval("a + NULL"), Null);
+    assert_eq!(
+        eval("9223372036854775807 + 1"),
+        Float(9223372036854775808.0)
+    );
+    // The bitwise operators share one precedence level, left to right
+    assert_eq!(eval("1 << 3 | 6 & 3"), Integer(2));
+    assert_eq!(eval("-8 >> 1"), Integer(-4));
+    assert_eq!(eval("~a"), Integer(-8));
+    // || binds tighter than +, so the text 'a7' is added to 1
+    assert_eq!(eval("'a' || a + 1"), Integer(1));
+}
+
+#[test]
+fn test_select_joins() {
+    let select = Select::from_str(
+        "select e.name, p.title from emp AS e left outer join projects p on p.emp_id = e.id \
+        join depts using (dept, floor), people natural join teams where e.id > 1",
+    )
+    .unwrap();
+    assert_eq!(
+        (&select.from, select.alias.as_deref()),
+        (&TableSource::Table("emp".to_string()), Some("e"))
+    );
+    let join = |kind, name: &str, alias: Option<&str>, constraint| Join {
+        kind,
+        table: TableSource::Table(name.to_string()),
+        alias: alias.map(str::to_string),
+        constraint,
+    };
+    assert_eq!(
+        select.joins,
+        vec![
+            join(
+                JoinKind::Left,
+                "projects",
+                Some("p"),
+                JoinConstraint::On(Expr::binary(
+                    Expr::Column("p.emp_id".to_string()),
+                    Operator::Eq,
+                    Expr::Column("e.id".to_string()),
+                )),
+            ),
+            join(
+                JoinKind::Inner,
+                "depts",
+                None,
+                JoinConstraint::Using(vec!["dept".to_string(), "floor".to_string()]),
+            ),
+            join(JoinKind::Cross, "people", None, JoinConstraint::None),
+            join(JoinKind::Inner, "teams", None, JoinConstraint::Natural),
+        ]
+    );
+    assert_eq!(select.conds.len(), 1);
+
+    let select = Select::from_str("select * from a cross join b where a.x = b.y").unwrap();
+    assert_eq!(select.alias, None);
+    assert_eq!(
+        select.joins,
+        vec![join(JoinKind::Cross, "b", None, JoinConstraint::None)]
+    );
+
+    // Quoted parts of a qualified name are looked up without their quotes
+    let select =
+        Select::from_str(r#"select e."id", "e".name, [e]."first name" from emp e"#).unwrap();
+    let names: Vec<_> = select
+        .columns
+        .iter()
+        .map(|column| match column {
+            SelectColumn::Expr(ResultColumn { expr, .. }) => expr.clone(),
+            SelectColumn::All(_) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        names,
+        ["e.id", "e.name", "e.first name"].map(|name| Expr::Column(name.to_string()))
+    );
+}
+
+#[test]
+fn test_select_subqueries() {
+    let select = Select::from_str(
+        "select name, (select count(*) from projects p where p.emp_id = e.id) from emp e \
+        where id not in (select boss from emp) and exists (select 1 from depts) \
+        and dept in ('eng', 'ops')",
+    )
+    .unwrap();
+    let subquery = |sql: &str| Box::new(Select::from_str(sql).unwrap());
+    match &select.columns[1] {
+        SelectColumn::Expr(column) => assert_eq!(
+            column.expr,
+            Expr::Subquery(subquery(
+                "select count(*) from projects p where p.emp_id = e.id"
+            ))
+        ),
+        column => panic!("{:?} is not an expression", column),
+    }
+    assert_eq!(
+        select.conds,
+        vec![
+            Expr::InSelect {
+                expr: Box::new(Expr::Column("id".to_string())),
+                select: subquery("select boss from emp"),
+                negated: true,
+            },
+            Expr::Exists(subquery("select 1 from depts")),
+            Expr::InList {
+                expr: Box::new(Expr::Column("dept".to_string())),
+                list: vec![
+                    Expr::Literal(Value::Text("eng".to_string())),
+                    Expr::Literal(Value::Text("ops".to_string())),
+                ],
+                negated: false,
+            },
+        ]
+    );
+    assert!(select.conds[..2].iter().all(Expr::has_subquery));
+    assert!(!select.conds[2].has_subquery());
+
+    let select = Select::from_str("select t.n from (select count(*) n from emp) as t").unwrap();
+    assert_eq!(
+        (select.from, select.alias.as_deref()),
+        (
+            TableSource::Subquery(subquery("select count(*) n from emp")),
+            Some("t")
+        )
+    );
+}
+
+#[test]
+fn test_in_list() {
+    use Value::*;
+    let eval = |s: &str| {
+        sql::expr(s)
+            .unwrap()
+            .eval(&[], &HashMap::new(), &Collations::default())
+            .unwrap()
+    };
+    assert_eq!(eval("2 in (1, 2, 3)"), Integer(1));
+    assert_eq!(eval("2 not in (1, 3)"), Integer(1));
+    assert_eq!(eval("2 in (1, null)"), Null);
+    assert_eq!(eval("2 in (null, 2)"), Integer(1));
+    assert_eq!(eval("null in ()"), Integer(0));
+    assert_eq!(eval("'a' in ('A' collate nocase)"), Integer(1));
+    assert_eq!(
+        sql::expr("a = 3 * (2 + 1) and b < -(4 - 1)")
+            .unwrap()
+            .fold_constants(),
+        sql::expr("a = 9 and b < -3").unwrap()
+    );
+}
+
+#[test]
+fn test_select_with() {
+    let select = Select::from_str(
+        "WITH RECURSIVE chain(id, lvl) AS (select id, 0 from emp where boss is null \
+        union all select e.id, lvl + 1 from emp e join chain c on e.boss = c.id), \
+        rich as (select name from emp where salary > 5000 union select head from depts) \
+        select * from chain",
+    )
+    .unwrap();
+    assert_eq!(select.from, TableSource::Table("chain".to_string()));
+    let [chain, rich] = select.ctes.as_slice() else {
+        panic!("{:?} are not two tables", select.ctes);
+    };
+    assert_eq!(chain.view.name, "chain");
+    assert_eq!(chain.view.columns, ["id", "lvl"]);
+    let (initial, steps) = chain.recursion().unwrap();
+    assert_eq!(
+        initial,
+        Select::from_str("select id, 0 from emp where boss is null").unwrap()
+    );
+    assert_eq!(steps.len(), 1);
+    assert_eq!(steps[0].0, CompoundOperator::UnionAll);
+
+    assert!(rich.view.columns.is_empty());
+    assert_eq!(rich.view.select.compound[0].0, CompoundOperator::Union);
+    assert!(rich.recursion().is_none());
+}
+
+#[test]
+fn test_select_values() {
+    let select = Select::from_str("select 1 + 2 where 1").unwrap();
+    assert_eq!(select.from, TableSource::Values(vec![vec![]]));
+    assert_eq!(select.conds, vec![Expr::Literal(Value::Integer(1))]);
+
+    let select = Select::from_str("values (1, 'a'), (2, 'b') order by 1 desc").unwrap();
+    assert_eq!(
+        select.from,
+        TableSource::Values(vec![
+            vec![
+                Expr::Literal(Value::Integer(1)),
+                Expr::Literal(Value::Text("a".to_string()))
+            ],
+            vec![
+                Expr::Literal(Value::Integer(2)),
+                Expr::Literal(Value::Text("b".to_string()))
+            ],
+        ])
+    );
+    assert_eq!(select.columns, vec![column("column1"), column("column2")]);
+    assert_eq!(select.order_by.len(), 1);
+
+    let select = Select::from_str(
+        "with recursive c(x) as (select 1 union all select x + 1 from c where x < 5) select x from c",
+    )
+    .unwrap();
+    let (initial, _) = select.ctes[0].recursion().unwrap();
+    assert_eq!(initial, Select::from_str("select 1").unwrap());
+}
+
+#[test]
+fn test_select_compound() {
+    let select = Select::from_str(
+        "select name from emp union all select head from depts         INTERSECT select name from emp where salary > 5000 except select 'x' from depts         order by 1 desc limit 3",
+    )
+    .unwrap();
+    let operators: Vec<_> = select.compound.iter().map(|(o, _)| *o).collect();
+    assert_eq!(
+        operators,
+        [
+            CompoundOperator::UnionAll,
+            CompoundOperator::Intersect,
+            CompoundOperator::Except
+        ]
+    );
+    assert_eq!(select.from, TableSource::Table("emp".to_string()));
+    assert_eq!(select.order_by.len(), 1);
+    assert_eq!(select.limit, Some(Expr::Literal(Value::Integer(3))));
+    // ORDER BY and LIMIT belong to the whole compound, not to the last SELECT
+    let (_, last) = &select.compound[2];
+    assert!(last.order_by.is_empty() && last.limit.is_none());
+    assert_eq!(
+        select.compound[1].1.conds,
+        vec![Expr::binary(
+            Expr::Column("salary".to_string()),
+            Operator::Greater,
+            Expr::Literal(Value::Integer(5000))
+        )]
+    );
+    assert_eq!(CompoundOperator::UnionAll.to_string(), "UNION ALL");
+    assert!(Select::from_str("select name from emp limit 1 union select head from depts").is_err());
+}
+
+#[test]
+fn test_window_call() {
+    let select = Select::from_str(
+        "select sum(salary) OVER (PARTITION BY dept ORDER BY id DESC \
+        ROWS BETWEEN 2 PRECEDING AND UNBOUNDED FOLLOWING), rank() over () from emp",
+    )
+    .unwrap();
+    let exprs: Vec<_> = select
+        .columns
+        .iter()
+        .filter_map(|column| match column {
+            SelectColumn::Expr(column) => Some(column.expr.clone()),
+            SelectColumn::All(_) => None,
+        })
+        .collect();
+    let Expr::Over { call, window } = &exprs[0] else {
+        panic!("{:?} is not a window call", exprs[0]);
+    };
+    assert_eq!(call.aggregate(), Some(AggregateKind::Sum));
+    assert_eq!(window.partition_by, vec![Expr::Column("dept".to_string())]);
+    assert!(window.order_by[0].desc);
+    assert_eq!(
+        window.frame,
+        Frame {
+            rows: true,
+            start: FrameBound::Preceding(Expr::Literal(Value::Integer(2))),
+            end: FrameBound::UnboundedFollowing,
+        }
+    );
+    // An aggregate over a window doesn't group the rows
+    assert!(!exprs[0].has_aggregate());
+    assert!(exprs.iter().all(Expr::has_window));
+    let Expr::Over { window, .. } = &exprs[1] else {
+        panic!("{:?} is not a window call", exprs[1]);
+    };
+    assert_eq!(window.frame, Frame::default());
+
+    let mut calls = vec![];
+    let expr = Expr::binary(exprs[1].clone(), Operator::Add, exprs[1].clone());
+    assert_eq!(
+        expr.extract_windows(&mut calls),
+        Expr::binary(
+            Expr::Column(Expr::window_column(0)),
+            Operator::Add,
+            Expr::Column(Expr::window_column(0))
+        )
+    );
+    assert_eq!(calls.len(), 1);
+}
+
+#[test]
+fn test_explain_query_plan() {
+    let select = Select::from_str("select name from emp where id = 1").unwrap();
+    assert_eq!(
+        Statement::from_str("select name from emp where id = 1").unwrap(),
+        Statement::Select(select.clone())
+    );
+    assert_eq!(
+        Statement::from_str("explain Query PLAN select name from emp where id = 1").unwrap(),
+        Statement::ExplainQueryPlan(select.clone())
+    );
+    assert_eq!(
+        Statement::from_str("EXPLAIN select name from emp where id = 1").unwrap(),
+        Statement::Explain(select)
+    );
+    assert!(Statement::from_str("explain query plan").is_err());
+    assert_eq!(
+        Statement::from_str("ANALYZE").unwrap(),
+        Statement::Analyze(None)
+    );
+    assert_eq!(
+        Statement::from_str("analyze emp ").unwrap(),
+        Statement::Analyze(Some("emp".to_string()))
+    );
+    assert!(Statement::from_str("explainquery plan select name from emp").is_err());
+}
@@ -1,5 +1,8 @@
+use std::cmp::Ordering;
 use std::fmt::Display;
 
+use crate::sqlite::tables::CellType;
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Value {
     Null,
@@ -9,18 +12,212 @@ pub enum Value {
     Text(String),
 }
 
+impl Value {
+    /// Rank of the storage class in SQLite's sort order: NULL < numbers < TEXT < BLOB.
+    fn class_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Float(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+
+    /// Compare two values the way SQLite orders them, without any type conversion.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Float(b)) => cmp_int_real(*a, *b),
+            (Value::Float(a), Value::Integer(b)) => cmp_int_real(*b, *a).reverse(),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => self.class_rank().cmp(&other.class_rank()),
+        }
+    }
+
+    /// Compare like [`Value::compare`], but order two texts with a collating sequence.
+    pub fn compare_collated(
+        &self,
+        other: &Value,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> Ordering {
+        match (self, other) {
+            (Value::Text(a), Value::Text(b)) => collation(a, b),
+            _ => self.compare(other),
+        }
+    }
+
+    /// Truth of the value in a boolean context, or `None` when it is NULL (UNKNOWN).
+    pub fn truth(&self) -> Option<bool> {
+        match self {
+            Value::Null => None,
+            Value::Integer(n) => Some(*n != 0),
+            Value::Float(f) => Some(*f != 0.0),
+            Value::Text(s) => text_to_numeric(s).truth(),
+            Value::Blob(b) => text_to_numeric(&String::from_utf8_lossy(b)).truth(),
+        }
+    }
+
+    /// Convert the value according to a column affinity, as SQLite does before comparisons.
+    pub fn apply_affinity(self, affinity: CellType) -> Value {
+        match affinity {
+            CellType::Text => match self {
+                Value::Integer(_) | Value::Float(_) => Value::Text(self.to_string()),
+                v => v,
+            },
+            CellType::Integer | CellType::Numeric => match self {
+                Value::Text(ref s) => parse_numeric(s).unwrap_or(self),
+                Value::Float(f) => real_to_integer(f).map_or(self, Value::Integer),
+                v => v,
+            },
+            CellType::Float => match self {
+                Value::Text(ref s) => match parse_numeric(s) {
+                    Some(Value::Integer(n)) => Value::Float(n as f64),
+                    Some(v) => v,
+                    None => self,
+                },
+                Value::Integer(n) => Value::Float(n as f64),
+                v => v,
+            },
+            CellType::Blob => self,
+        }
+    }
+}
+
+fn cmp_int_real(a: i64, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Greater;
+    }
+    if b < -9.223_372_036_854_776e18 {
+        return Ordering::Greater;
+    }
+    if b >= 9.223_372_036_854_776e18 {
+        return Ordering::Less;
+    }
+    let truncated = b.trunc();
+    match a.cmp(&(truncated as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(b - truncated)).unwrap_or(Ordering::Equal),
+        ord => ord,
+    }
+}
+
+/// Losslessly convert a real to an integer, if it has no fractional part and fits in 64 bits.
+fn real_to_integer(f: f64) -> Option<i64> {
+    let n = f as i64;
+    (n as f64 == f && (-9.223_372_036_854_776e18..9.223_372_036_854_776e18).contains(&f))
+        .then_some(n)
+}
+
+/// Parse text that is a well-formed integer or real literal, allowing surrounding spaces.
+pub fn parse_numeric(s: &str) -> Option<Value> {
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace());
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if digits.is_empty() || !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    if let Ok(n) = s.parse::<i64>() {
+        return Some(Value::Integer(n));
+    }
+    if !digits
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return None;
+    }
+    let f = s.parse::<f64>().ok()?;
+    Some(real_to_integer(f).map_or(Value::Float(f), Value::Integer))
+}
+
+/// Convert text to a number using its longest numeric prefix, like SQLite's implicit casts.
+pub fn text_to_numeric(s: &str) -> Value {
+    let s = s.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    let bytes = s.as_bytes();
+    let digits_from = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+
+    let mut end = digits_from(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+    let mut is_real = false;
+    if bytes.get(end) == Some(&b'.') {
+        is_real = true;
+        end = digits_from(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let exp_digits = end + 1 + usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exp_end = digits_from(exp_digits);
+        if exp_end > exp_digits {
+            is_real = true;
+            end = exp_end;
+        }
+    }
+
+    let prefix = &s[..end];
+    match (prefix.parse::<i64>(), prefix.parse::<f64>()) {
+        (Ok(n), _) if !is_real => Value::Integer(n),
+        (_, Ok(f)) => Value::Float(f),
+        _ => Value::Integer(0),
+    }
+}
+
+/// Render a real the way SQLite does (`%!.15g`), always keeping a decimal point.
+pub fn real_to_text(f: f64) -> String {
+    if f.is_nan() {
+        return String::new();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if f == 0.0 {
+        return "0.0".to_string();
+    }
+    let sci = format!("{:.14e}", f);
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    if !(-4..15).contains(&exp) {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exp < 0 { '-' } else { '+' };
+        return format!("{}e{}{:02}", mantissa, sign, exp.abs());
+    }
+    let fixed = format!("{:.*}", (14 - exp) as usize, f);
+    trim_fraction(&fixed).to_string()
+}
+
+fn trim_fraction(s: &str) -> &str {
+    match s.find('.') {
+        Some(dot) => {
+            let trimmed = s.trim_end_matches('0');
+            if trimmed.len() == dot + 1 {
+                &s[..dot + 2]
+            } else {
+                trimmed
+            }
+        }
+        None => s,
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Null => write!(f, ""),
             Value::Integer(n) => write!(f, "{}", *n),
-            Value::Float(n) => write!(f, "{}", *n),
+            Value::Float(n) => write!(f, "{}", real_to_text(*n)),
             Value::Blob(b) => write!(f, "{:?}", b),
             Value::Text(s) => write!(f, "{}", s),
         }
     }
 }
 
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Integer(i64::from(b))
+    }
+}
+
 macro_rules! impl_from_value {
     ($($t:ty),* $(,)?) => {
         $(
@@ -40,3 +237,63 @@ macro_rules! impl_from_value {
 }
 
 impl_from_value!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64, usize);
+
+#[test]
+fn test_real_to_text() {
+    assert_eq!(real_to_text(2.0), "2.0");
+    assert_eq!(real_to_text(0.1), "0.1");
+    assert_eq!(real_to_text(-1.25), "-1.25");
+    assert_eq!(real_to_text(1.0 / 3.0), "0.333333333333333");
+    assert_eq!(real_to_text(1e20), "1.0e+20");
+    assert_eq!(real_to_text(1.5e-7), "1.5e-07");
+}
+
+#[test]
+fn test_compare_across_classes() {
+    use Value::*;
+    assert_eq!(Integer(1).compare(&Float(1.0)), Ordering::Equal);
+    assert_eq!(Integer(2).compare(&Float(1.5)), Ordering::Greater);
+    assert_eq!(Null.compare(&Integer(-5)), Ordering::Less);
+    assert_eq!(Integer(99).compare(&Text("1".into())), Ordering::Less);
+    assert_eq!(Text("z".into()).compare(&Blob(vec![0])), Ordering::Less);
+}
+
+#[test]
+fn test_text_to_numeric() {
+    assert_eq!(text_to_numeric("12abc"), Value::Integer(12));
+    assert_eq!(text_to_numeric("  -1.5e2x"), Value::Float(-150.0));
+    assert_eq!(text_to_numeric("3e"), Value::Integer(3));
+    assert_eq!(text_to_numeric("abc"), Value::Integer(0));
+    assert_eq!(Value::Text("0.0".into()).truth(), Some(false));
+    assert_eq!(Value::Text("1 apple".into()).truth(), Some(true));
+}
+
+#[test]
+fn test_apply_affinity() {
+    use Value::*;
+    assert_eq!(
+        Text("123".into()).apply_affinity(CellType::Integer),
+        Integer(123)
+    );
+    assert_eq!(
+        Text(" 1.0 ".into()).apply_affinity(CellType::Numeric),
+        Integer(1)
+    );
+    assert_eq!(
+        Text("1.5".into()).apply_affinity(CellType::Numeric),
+        Float(1.5)
+    );
+    assert_eq!(
+        Text("12abc".into()).apply_affinity(CellType::Integer),
+        Text("12abc".into())
+    );
+    assert_eq!(Integer(7).apply_affinity(CellType::Text), Text("7".into()));
+    assert_eq!(
+        Float(1.0).apply_affinity(CellType::Text),
+        Text("1.0".into())
+    );
+    assert_eq!(
+        Text("7".into()).apply_affinity(CellType::Blob),
+        Text("7".into())
+    );
+}
@@ -19,6 +19,24 @@ pub fn varint(input: &[u8]) -> IResult<&[u8], u64> {
     Ok((input, ans))
 }
 
+/// Append `n` to `out` as a varint, the inverse of [`varint`].
+pub fn encode_varint(n: u64, out: &mut Vec<u8>) {
+    // a full 64 bits need the special 9 byte form, whose last byte carries 8 bits
+    if n >> 56 != 0 {
+        let high = n >> 8;
+        for shift in (0..8).rev() {
+            out.push(((high >> (shift * 7)) & 0x7f) as u8 | 0x80);
+        }
+        out.push(n as u8);
+        return;
+    }
+    let groups = (1..9).find(|&g| n >> (7 * g) == 0).unwrap_or(8);
+    for shift in (1..groups).rev() {
+        out.push(((n >> (shift * 7)) & 0x7f) as u8 | 0x80);
+    }
+    out.push((n & 0x7f) as u8);
+}
+
 #[cfg(test)]
 fn assert_varint(input: &[u8], expected: u64) {
     let (_, answer) = varint(input).unwrap();
@@ -26,6 +44,7 @@ fn assert_varint(input: &[u8], expected: u64) {
 }
 
 #[test]
+#[allow(clippy::unusual_byte_groupings)] // grouped by the 7 bits each byte carries
 fn test_varint() {
     assert_varint(&[0x00], 0);
     assert_varint(&[0x7f], 0x7f);
@@ -50,3 +69,22 @@ fn test_varint() {
         0b1111111_0000000_1111111_0000000_1111111_0000000_1111111_0000000_11111111,
     );
 }
+
+#[test]
+fn test_encode_varint() {
+    for n in [
+        0,
+        0x7f,
+        0x80,
+        0x3fff,
+        0x4000,
+        1 << 55,
+        (1 << 56) - 1,
+        1 << 56,
+        u64::MAX,
+    ] {
+        let mut encoded = vec![];
+        encode_varint(n, &mut encoded);
+        assert_varint(&encoded, n);
+    }
+}
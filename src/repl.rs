@@ -1,23 +1,56 @@
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use console::Key;
+use itertools::Itertools;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::OsStr;
 use std::io::Write;
+use std::iter;
 use std::num::NonZeroU64;
+use std::ops::Bound;
 use std::path::Path;
+use std::rc::Rc;
 
 use crate::app::App;
-use crate::parsers::sql::{Select, SelectColumns};
+use crate::parsers::{
+    sql::{
+        CompoundOperator, Cte, Expr, OrderingTerm, ResultColumn, Select, SelectColumn, Statement,
+        TableSource,
+    },
+    value::Value,
+};
 use crate::sqlite::{
-    db::{Database, Search},
-    schemas::Schema,
-    tables::Table,
+    collation::BINARY,
+    cursor::IndexScan,
+    db::{Database, Row, Search},
+    functions::check_call,
+    hash_join::HashKey,
+    indexes::Index,
+    join::{JoinedTables, Outer, Source},
+    operators::{
+        self, ordered_search_rows, search_rows, Aggregate, BoxedOperator, CoveringRows, Filter,
+        IndexSeek, Limit, Map, Project, RowidFetch, Sort, Values, Window,
+    },
+    plan::{access_detail, render, Access, PlanNode},
+    predicate::BoundExpr,
+    schemas::{Schema, SchemaType},
+    sorter::{Distinct, SortKey},
+    stats::analyze,
+    tables::{CellType, Column, Table},
+    vdbe::{Builder, Insn, KeyColumn, Program, Scope},
+    views::View,
+    window::{check_window_call, WindowKind},
+};
+use crate::utils::{
+    find_covering_index, find_distinct_index, find_index_intersection, find_order_index,
+    find_rowid_range, find_table_index, index_columns, index_covers, print_rows, search_order,
+    wlog, OrderedScan,
 };
-use crate::utils::{find_table_index, print_rows};
 
 #[derive(Debug, Clone)]
 pub enum Command {
     Dot(String),
-    Sql(Select),
+    Sql(Box<Statement>),
     Load(String),
     Error(String),
     History,
@@ -43,11 +76,11 @@ impl From<&mut String> for Command {
             [word] if SHELL_TERMS.contains(word) => Command::Utility(word.to_string()),
             _ if value == "\u{b}\n" => Command::History, // Ctrl + k
             [cmd, path] if *cmd == "load" => match get_extension_from_filename(path) {
-                Some(ext) if ext == "db" => Command::Load(path.to_string()),
+                Some("db") => Command::Load(path.to_string()),
                 _ => Command::Error(format!("File \"{path}\" extension extraction failed")),
             },
             [cmd, stmt @ ..] if *cmd == "sql" => match stmt.join(" ").parse() {
-                Ok(stmt) => Command::Sql(stmt),
+                Ok(stmt) => Command::Sql(Box::new(stmt)),
                 Err(msg) => Command::Error(msg.to_string()),
             },
             _ => Command::Unknown,
@@ -55,51 +88,2274 @@ impl From<&mut String> for Command {
     }
 }
 
-pub fn run(stmt: Select, db: &Database) -> Result<()> {
+pub fn run(stmt: Statement, db: &Database) -> Result<()> {
+    let stmt = match stmt {
+        Statement::Select(stmt) => stmt,
+        Statement::ExplainQueryPlan(stmt) => {
+            let plan = explain(&stmt, db, None, &[], &mut 0)?;
+            for line in render(&plan) {
+                println!("{line}");
+            }
+            return Ok(());
+        }
+        Statement::Explain(stmt) => {
+            let query = prepare(&stmt, db, None, &[], Materialize::Columns)?;
+            let program = match compile(&query, db)? {
+                Some(program) => program,
+                None => bail!(
+                    "only SELECTs of one table without joins, subqueries, GROUP BY, DISTINCT, \
+                     window functions, compound operators or index intersections compile to \
+                     bytecode"
+                ),
+            };
+            for line in program.listing() {
+                println!("{line}");
+            }
+            return Ok(());
+        }
+        Statement::Analyze(target) => {
+            let analysis = analyze(db, target.as_deref())?;
+            db.set_statistics(&analysis)?;
+            let rows = analysis
+                .rows
+                .into_iter()
+                .map(|(table, idx, stat)| {
+                    vec![
+                        Value::Text(table),
+                        idx.map_or(Value::Null, Value::Text),
+                        Value::Text(stat),
+                    ]
+                })
+                .collect();
+            let names = ["tbl", "idx", "stat"].map(String::from).to_vec();
+            print_rows(rows, names);
+            // The file is opened read-only, so the statistics can't outlive the session
+            wlog(
+                "sqlite_stat1 was not written: the statistics are only used until the \
+                 database is closed",
+            );
+            return Ok(());
+        }
+    };
+    let query = prepare(&stmt, db, None, &[], Materialize::Rows)?;
+    let names = query
+        .result_columns
+        .iter()
+        .map(|c| c.name.clone())
+        .collect();
+    let rows = run_query(query, db)?;
+    print_rows(rows, names);
+    Ok(())
+}
+
+/// Run a prepared SELECT as a bytecode program, or as a tree of operators when the program
+/// can't express it.
+fn run_query(query: Query, db: &Database) -> Result<Vec<Row>> {
+    match compile(&query, db)? {
+        Some(program) => program.run(db),
+        None => execute(query, db),
+    }
+}
+
+/// A SELECT with its names resolved and checked, ready to be run.
+struct Query<'a> {
+    from: JoinedTables<'a>,
+    /// Tables of the WITH clauses its subqueries can name.
+    with: Vec<With>,
+    schemas: HashMap<String, Schema>,
+    result_columns: Vec<ResultColumn>,
+    conds: Vec<Expr>,
+    group_by: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<OrderingTerm>,
+    distinct: bool,
+    limit: Option<Expr>,
+    offset: Option<Expr>,
+    /// The SELECTs after this one, for the first SELECT of a compound.
+    compound: Option<Box<Compound<'a>>>,
+}
+
+impl Query<'_> {
+    /// Whether the rows are grouped, by GROUP BY or by aggregates over all of them.
+    fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.result_columns.iter().any(|c| c.expr.has_aggregate())
+            || self.order_by.iter().any(|t| t.expr.has_aggregate())
+    }
+}
+
+/// The SELECTs after the first of a compound SELECT, and how the rows of them all are sorted
+/// and limited.
+struct Compound<'a> {
+    selects: Vec<(CompoundOperator, Query<'a>)>,
+    /// The result columns as a table, whose columns the ORDER BY terms read.
+    table: Table,
+    order_by: Vec<OrderingTerm>,
+    limit: Option<Expr>,
+    offset: Option<Expr>,
+}
+
+/// What preparing a statement does with the tables of its FROM clause that are the rows of a
+/// query, such as subqueries and views: run the query for its rows, or only work out its
+/// columns, for EXPLAIN QUERY PLAN to describe the statement without running it.
+#[derive(Clone, Copy, PartialEq)]
+enum Materialize {
+    Rows,
+    Columns,
+}
+
+/// A table a WITH clause names, as the queries of its statement see it.
+#[derive(Clone)]
+enum With {
+    Cte(Rc<Cte>),
+    /// The row of a recursive table that its recursive SELECT reads as the whole table.
+    Working(Table, Row),
+}
+
+impl With {
+    fn name(&self) -> &str {
+        match self {
+            With::Cte(cte) => &cte.view.name,
+            With::Working(table, _) => &table.name,
+        }
+    }
+}
+
+/// Resolve the names of a SELECT against its tables, and check the collations, functions
+/// and subqueries it uses. A subquery's names can also refer to the columns of `outer`, the
+/// row of the enclosing query it is run for, and its tables to those of `with`, the WITH
+/// clauses of the statements it is part of. `materialize` says whether the queries of the
+/// FROM clause are run.
+fn prepare<'a>(
+    stmt: &Select,
+    db: &Database,
+    outer: Option<&'a Outer<'a>>,
+    with: &[With],
+    materialize: Materialize,
+) -> Result<Query<'a>> {
     let schemas = db.get_schemas();
-    let (schema, table): (&Schema, Table) = match schemas.get(&stmt.name) {
-        Some(s) => (s, s.try_into()?),
-        None => bail!("no such table: {}", stmt.name),
+    for (i, cte) in stmt.ctes.iter().enumerate() {
+        let name = &cte.view.name;
+        if stmt.ctes[..i]
+            .iter()
+            .any(|c| c.view.name.eq_ignore_ascii_case(name))
+        {
+            bail!("duplicate WITH table name: {}", name);
+        }
+    }
+    // The latest tables come first, as each one can only read those defined before it
+    let with: Vec<_> = stmt
+        .ctes
+        .iter()
+        .rev()
+        .map(|cte| With::Cte(Rc::new(cte.clone())))
+        .chain(with.iter().cloned())
+        .collect();
+    if !stmt.compound.is_empty() {
+        return prepare_compound(stmt, db, outer, with, materialize);
+    }
+    // A lone table read up to a LIMIT is only needed that far, which is how a recursive
+    // table without an end of its own is read
+    let simple = stmt.joins.is_empty()
+        && stmt.conds.is_empty()
+        && stmt.group_by.is_empty()
+        && stmt.having.is_none()
+        && stmt.order_by.is_empty()
+        && !stmt.distinct
+        && !stmt.columns.iter().any(|column| match column {
+            SelectColumn::Expr(column) => column.expr.has_aggregate() || column.expr.has_window(),
+            SelectColumn::All(_) => false,
+        });
+    let needed = match simple {
+        true => row_count(stmt.limit.clone(), db)
+            .ok()
+            .flatten()
+            .map(|limit| {
+                let offset = row_count(stmt.offset.clone(), db).ok().flatten();
+                (limit + offset.unwrap_or(0)) as usize
+            }),
+        false => None,
+    };
+    let from = JoinedTables::new(
+        stmt,
+        &schemas,
+        outer,
+        |name| named_table(name, db, &with, &schemas, needed, materialize),
+        |select, outer| derived_table(select, db, outer, &with, materialize),
+    )?;
+
+    let result_columns = from.select(stmt)?;
+    let resolve = |expr: &Expr| {
+        let expr = from.resolve(&with_aliases(expr, &result_columns, &from, false))?;
+        Ok(expr.fold_constants())
+    };
+    let conds = stmt.conds.iter().map(resolve).collect::<Result<Vec<_>>>()?;
+    let group_by = stmt
+        .group_by
+        .iter()
+        .enumerate()
+        .map(|(i, expr)| match expr {
+            Expr::Literal(Value::Integer(n)) => result_column(*n, &result_columns)
+                .map(|column| column.expr.clone())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} GROUP BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        result_columns.len()
+                    )
+                }),
+            expr => resolve(expr),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let having = stmt.having.as_ref().map(resolve).transpose()?;
+    let order_by = resolve_order_by(stmt.order_by.clone(), &result_columns, &from)?;
+    let exprs = || {
+        conds
+            .iter()
+            .chain(from.conditions())
+            .chain(&group_by)
+            .chain(&having)
+            .chain(order_by.iter().map(|t| &t.expr))
+            .chain(result_columns.iter().map(|c| &c.expr))
+    };
+
+    // Comparisons can't fall back to another collation, so unknown ones are an error upfront
+    let mut collations = vec![];
+    let mut functions = vec![];
+    let mut windows = vec![];
+    let mut subqueries = vec![];
+    for expr in exprs() {
+        expr.walk(&mut |expr| match expr {
+            Expr::Collate(_, name) => collations.push(name),
+            Expr::Column(name) => {
+                collations.extend(from.columns.get(name).and_then(|c| c.collation.as_ref()))
+            }
+            Expr::Function { .. } => functions.push(expr),
+            Expr::Over { call, .. } => windows.push(call),
+            _ if expr.is_subquery() => subqueries.push(expr),
+            _ => {}
+        });
+    }
+    if let Some(name) = collations
+        .iter()
+        .find(|name| db.collations.get(name).is_none())
+    {
+        bail!("no such collation sequence: {}", name);
+    }
+    for function in functions {
+        if let Expr::Function { name, args, .. } = function {
+            if WindowKind::is_window_only(name) {
+                bail!("misuse of window function {}()", name);
+            }
+            check_call(name, args.len())?;
+        }
+    }
+    for call in windows {
+        check_window_call(call)?;
+    }
+    // Aggregates only have a value once rows are grouped, after WHERE and GROUP BY
+    if group_by.iter().any(Expr::has_aggregate) {
+        bail!("aggregate functions are not allowed in the GROUP BY clause");
+    }
+    for expr in conds.iter().chain(from.conditions()) {
+        let mut misused = None;
+        expr.walk(&mut |expr| match expr {
+            Expr::Function { name, .. } if expr.aggregate().is_some() => {
+                misused.get_or_insert(name);
+            }
+            _ => {}
+        });
+        if let Some(name) = misused {
+            bail!("misuse of aggregate: {}()", name);
+        }
+    }
+    // and windows once the rows are filtered and grouped
+    for expr in conds
+        .iter()
+        .chain(from.conditions())
+        .chain(&group_by)
+        .chain(&having)
+    {
+        let mut misused = None;
+        expr.walk(&mut |expr| {
+            if let Expr::Over { call, .. } = expr {
+                if let Expr::Function { name, .. } = call.as_ref() {
+                    misused.get_or_insert(name);
+                }
+            }
+        });
+        if let Some(name) = misused {
+            bail!("misuse of window function {}()", name);
+        }
+    }
+    if from.conditions().any(Expr::has_subquery) {
+        bail!("subqueries in ON clauses are not supported");
+    }
+    // Subqueries are checked for a row of NULLs, as they only run once there are rows. Only
+    // their columns are needed, so the tables of their FROM clauses aren't read
+    let nulls = vec![Value::Null; from.columns.len()];
+    for subquery in subqueries {
+        let (select, single) = match subquery {
+            Expr::Subquery(select) | Expr::InSelect { select, .. } => (select, true),
+            Expr::Exists(select) => (select, false),
+            _ => continue,
+        };
+        let outer = Outer::new(&from, &nulls);
+        let columns = prepare(select, db, Some(&outer), &with, Materialize::Columns)?
+            .result_columns
+            .len();
+        if single && columns != 1 {
+            bail!("sub-select returns {} columns - expected 1", columns);
+        }
+    }
+
+    let mut query = Query {
+        from,
+        with,
+        schemas,
+        result_columns,
+        conds,
+        group_by,
+        having,
+        order_by,
+        distinct: stmt.distinct,
+        limit: stmt.limit.clone(),
+        offset: stmt.offset.clone(),
+        compound: None,
+    };
+    // Rows only have the columns the statement reads decoded
+    let read = read_columns(&query);
+    query.from.read_only(&read);
+    Ok(query)
+}
+
+/// Prepare each SELECT of a compound SELECT, which must have as many result columns as the
+/// first, and resolve the ORDER BY terms against the result columns.
+fn prepare_compound<'a>(
+    stmt: &Select,
+    db: &Database,
+    outer: Option<&'a Outer<'a>>,
+    with: Vec<With>,
+    materialize: Materialize,
+) -> Result<Query<'a>> {
+    let first = Select {
+        ctes: vec![],
+        compound: vec![],
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        ..stmt.clone()
+    };
+    let mut query = prepare(&first, db, outer, &with, materialize)?;
+    let width = query.result_columns.len();
+    let mut selects = vec![];
+    for (operator, select) in &stmt.compound {
+        let other = prepare(select, db, outer, &with, materialize)?;
+        if other.result_columns.len() != width {
+            bail!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                operator
+            );
+        }
+        selects.push((*operator, other));
+    }
+
+    let table = result_table(&query);
+    let mut order_by = vec![];
+    for (i, term) in stmt.order_by.iter().enumerate() {
+        // A COLLATE applies to the result column the term names
+        let (expr, collation) = match &term.expr {
+            Expr::Collate(expr, name) => (expr.as_ref(), Some(name)),
+            expr => (expr, None),
+        };
+        let idx = match expr {
+            Expr::Literal(Value::Integer(n)) => {
+                match usize::try_from(*n).ok().filter(|n| (1..=width).contains(n)) {
+                    Some(n) => Some(n - 1),
+                    None => bail!(
+                        "{} ORDER BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        width
+                    ),
+                }
+            }
+            Expr::Column(name) => query
+                .result_columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(name)),
+            _ => None,
+        };
+        // Otherwise the term has to be one of the first SELECT's result expressions
+        let idx = idx.or_else(|| {
+            let expr = query.from.resolve(expr).ok()?;
+            query
+                .result_columns
+                .iter()
+                .position(|column| column.expr == expr)
+        });
+        let column = match idx.and_then(|idx| table.columns.values().find(|c| c.idx == idx)) {
+            Some(column) => Expr::Column(column.name.clone()),
+            None => bail!(
+                "{} ORDER BY term does not match any column in the result set",
+                ordinal(i + 1)
+            ),
+        };
+        let expr = match collation {
+            Some(name) => Expr::Collate(Box::new(column), name.clone()),
+            None => column,
+        };
+        order_by.push(OrderingTerm {
+            expr,
+            ..term.clone()
+        });
+    }
+    if let Some(name) = order_by
+        .iter()
+        .filter_map(|term| match &term.expr {
+            Expr::Collate(_, name) => Some(name),
+            _ => None,
+        })
+        .find(|name| db.collations.get(name).is_none())
+    {
+        bail!("no such collation sequence: {}", name);
+    }
+
+    query.compound = Some(Box::new(Compound {
+        selects,
+        table,
+        order_by,
+        limit: stmt.limit.clone(),
+        offset: stmt.offset.clone(),
+    }));
+    Ok(query)
+}
+
+/// Run a FROM clause subquery, returning a table of its result columns and its rows, which
+/// are left empty when only the columns are materialized.
+fn derived_table(
+    select: &Select,
+    db: &Database,
+    outer: Option<&Outer>,
+    with: &[With],
+    materialize: Materialize,
+) -> Result<(Table, Vec<Row>)> {
+    let query = prepare(select, db, outer, with, materialize)?;
+    let table = result_table(&query);
+    let rows = match materialize {
+        Materialize::Rows => execute(query, db)?,
+        Materialize::Columns => vec![],
+    };
+    Ok((table, rows))
+}
+
+/// The result columns of a query as the columns of a table without a name.
+fn result_table(query: &Query) -> Table {
+    let mut columns: HashMap<String, Column> = HashMap::new();
+    for (idx, column) in query.result_columns.iter().enumerate() {
+        // Repeated names get a number, as SQLite does
+        let mut name = column.name.clone();
+        for n in 1.. {
+            if !columns.keys().any(|c| c.eq_ignore_ascii_case(&name)) {
+                break;
+            }
+            name = format!("{}:{}", column.name, n);
+        }
+        let collation = match column.expr.collation_name(&query.from.columns) {
+            BINARY => None,
+            collation => Some(collation.to_string()),
+        };
+        let column = Column {
+            idx,
+            name: name.clone(),
+            cell_type: column
+                .expr
+                .affinity(&query.from.columns)
+                .unwrap_or(CellType::Blob),
+            nullable: true,
+            pk: false,
+            collation,
+        };
+        columns.insert(name, column);
+    }
+    Table {
+        name: String::new(),
+        columns,
+        key: None,
+    }
+}
+
+/// The table and rows of a FROM clause name that isn't a table of the database: a table of
+/// a WITH clause in `with`, or a view, which only sees the tables of the database. `needed`
+/// is how many rows are read, when the query doesn't read them all.
+fn named_table(
+    name: &str,
+    db: &Database,
+    with: &[With],
+    schemas: &HashMap<String, Schema>,
+    needed: Option<usize>,
+    materialize: Materialize,
+) -> Option<Result<(Table, Vec<Row>)>> {
+    if let Some(i) = with
+        .iter()
+        .position(|w| w.name().eq_ignore_ascii_case(name))
+    {
+        return Some(match &with[i] {
+            With::Cte(cte) => cte_table(cte, db, &with[i + 1..], needed, materialize),
+            With::Working(table, row) => Ok((table.clone(), vec![row.clone()])),
+        });
+    }
+    let schema = schemas.get(name).filter(|s| s.stype == SchemaType::View)?;
+    Some(View::try_from(schema).and_then(|view| {
+        let (table, rows) = derived_table(&view.select, db, None, &[], materialize)?;
+        Ok((view.table(table)?, rows))
+    }))
+}
+
+/// Run the SELECTs of a WITH clause table, whose own tables are looked up in `with`,
+/// returning the table and its rows. A recursive table is filled from a queue as in SQLite:
+/// each row taken from it is added to the table, then the recursive SELECTs run with that
+/// row as the whole table and their rows are queued, in the ORDER BY order if there is one.
+/// Only the first `needed` rows are computed, when given, and none unless `materialize` asks
+/// for rows.
+fn cte_table(
+    cte: &Cte,
+    db: &Database,
+    with: &[With],
+    needed: Option<usize>,
+    materialize: Materialize,
+) -> Result<(Table, Vec<Row>)> {
+    let named = |table: Table| {
+        let (names, width) = (cte.view.columns.len(), table.columns.len());
+        if names > 0 && names != width {
+            bail!(
+                "table {} has {} values for {} columns",
+                cte.view.name,
+                width,
+                names
+            );
+        }
+        cte.view.table(table)
+    };
+    let select = &cte.view.select;
+    let (initial, steps) = match cte.recursion() {
+        Some(recursion) => recursion,
+        None => {
+            let (table, rows) = derived_table(select, db, None, with, materialize)?;
+            return Ok((named(table)?, rows));
+        }
+    };
+    let (table, rows) = derived_table(&initial, db, None, with, materialize)?;
+    let table = named(table)?;
+    if materialize == Materialize::Columns {
+        return Ok((table, rows));
+    }
+    let width = table.columns.len();
+    // UNION drops the rows equal to one already in the table, by the column collations
+    let mut distinct = match steps[0].0 {
+        CompoundOperator::UnionAll => None,
+        CompoundOperator::Union => Some(Distinct::new(column_keys(&table, db)?, false)),
+        _ => bail!("circular reference: {}", cte.view.name),
     };
+    let mut is_new = |row: &Row| distinct.as_mut().is_none_or(|d| d.insert(row));
 
-    match stmt.columns {
-        SelectColumns::Count => {
-            let page = match NonZeroU64::new(schema.rootpage) {
-                Some(pgno) => db.get_page(pgno)?,
-                None => bail!("invalid table rootpage: {}", schema.rootpage),
+    // The ORDER BY decides which queued row is taken next, and LIMIT and OFFSET count the
+    // rows taken
+    let order = Select {
+        ctes: vec![],
+        from: TableSource::Table(table.name.clone()),
+        alias: None,
+        joins: vec![],
+        distinct: false,
+        columns: vec![SelectColumn::All(None)],
+        conds: vec![],
+        group_by: vec![],
+        having: None,
+        compound: vec![],
+        order_by: select.order_by.clone(),
+        limit: None,
+        offset: None,
+    };
+    let nulls = With::Working(table.clone(), vec![Value::Null; width]);
+    let order = prepare(&order, db, None, &[nulls], Materialize::Rows)?;
+    let keys = order_keys(&order.order_by, &order.from.columns, db)?;
+    let key_exprs: Vec<_> = order
+        .order_by
+        .iter()
+        .map(|term| BoundExpr::new(&term.expr, &order.from.columns, &db.collations))
+        .collect();
+    let key =
+        |row: &Row| -> Result<Vec<Value>> { key_exprs.iter().map(|expr| expr.eval(row)).collect() };
+    let mut queue = VecDeque::new();
+    let mut enqueue = |queue: &mut VecDeque<(Vec<Value>, Row)>, row: Row| -> Result<()> {
+        if is_new(&row) {
+            let key = key(&row)?;
+            // Rows with equal keys are taken in the order they came
+            let at = queue.partition_point(|(other, _)| {
+                keys.iter()
+                    .zip(other.iter().zip(&key))
+                    .map(|(k, (a, b))| k.compare(a, b))
+                    .find(|ord| ord.is_ne())
+                    .is_none_or(|ord| ord.is_lt())
+            });
+            queue.insert(at, (key, row));
+        }
+        Ok(())
+    };
+    for row in rows {
+        enqueue(&mut queue, row)?;
+    }
+
+    let limit = row_count(select.limit.clone(), db)?.map_or(usize::MAX, |n| n as usize);
+    let limit = limit.min(needed.unwrap_or(usize::MAX));
+    let offset = row_count(select.offset.clone(), db)?.map_or(0, |n| n as usize);
+    let mut rows = vec![];
+    let mut taken = 0;
+    while rows.len() < limit {
+        let Some((_, row)) = queue.pop_front() else {
+            break;
+        };
+        taken += 1;
+        if taken > offset {
+            rows.push(row.clone());
+        }
+        let working: Vec<_> = iter::once(With::Working(table.clone(), row))
+            .chain(with.iter().cloned())
+            .collect();
+        for (operator, step) in steps {
+            let (other, more) = derived_table(step, db, None, &working, Materialize::Rows)?;
+            if !matches!(
+                operator,
+                CompoundOperator::Union | CompoundOperator::UnionAll
+            ) {
+                bail!("circular reference: {}", cte.view.name);
+            }
+            if other.columns.len() != width {
+                bail!(
+                    "SELECTs to the left and right of {} do not have the same number of result columns",
+                    operator
+                );
+            }
+            for row in more {
+                enqueue(&mut queue, row)?;
+            }
+        }
+    }
+    Ok((table, rows))
+}
+
+/// The plan of a SELECT, as EXPLAIN QUERY PLAN shows it: the queries materializing its FROM
+/// clause tables, how each table is read, the temporary b-trees its rows are sorted in, and
+/// the plans of its subqueries, numbered in the statement by `subqueries`.
+fn explain(
+    stmt: &Select,
+    db: &Database,
+    outer: Option<&Outer>,
+    with: &[With],
+    subqueries: &mut usize,
+) -> Result<Vec<PlanNode>> {
+    let query = prepare(stmt, db, outer, with, Materialize::Columns)?;
+    let mut plan = vec![];
+    if let Some(compound) = &query.compound {
+        let first = Select {
+            ctes: vec![],
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            ..stmt.clone()
+        };
+        let mut selects = vec![PlanNode::with_children(
+            "LEFT-MOST SUBQUERY",
+            explain(&first, db, outer, &query.with, subqueries)?,
+        )];
+        for (operator, select) in &stmt.compound {
+            // All but UNION ALL keep the rows seen in a temporary b-tree
+            let detail = match operator {
+                CompoundOperator::UnionAll => operator.to_string(),
+                _ => format!("{operator} USING TEMP B-TREE"),
             };
-            println!("{}", page.header.cell_count);
-        }
-        _ => {
-            let columns = table.select(&stmt);
-
-            let table_search =
-                match find_table_index(&stmt.conds, stmt.name.as_str(), &table, &schemas) {
-                    Some((table_index, search_key)) => {
-                        let pgno = match NonZeroU64::new(table_index) {
-                            Some(pgno) => pgno,
-                            None => bail!("invalid index rootpage: {}", schema.rootpage),
-                        };
-                        Search::new(pgno, Some(search_key), None, schema.to_owned(), stmt.conds)
-                    }
-                    None => {
-                        let pgno = match NonZeroU64::new(schema.rootpage) {
-                            Some(pgno) => pgno,
-                            None => bail!("invalid index rootpage: {}", schema.rootpage),
-                        };
-                        Search::new(pgno, None, None, schema.to_owned(), stmt.conds)
-                    }
+            let children = explain(select, db, outer, &query.with, subqueries)?;
+            selects.push(PlanNode::with_children(detail, children));
+        }
+        plan.push(PlanNode::with_children("COMPOUND QUERY", selects));
+        if !compound.order_by.is_empty() {
+            plan.push(PlanNode::new("USE TEMP B-TREE FOR ORDER BY"));
+        }
+        return Ok(plan);
+    }
+
+    let tables = iter::once(&stmt.from).chain(stmt.joins.iter().map(|join| &join.table));
+    for (source, table) in query.from.sources.iter().zip(tables) {
+        let materialized = match table {
+            TableSource::Subquery(select) => Some(PlanNode::with_children(
+                format!("MATERIALIZE {}", source.name),
+                explain(select, db, outer, &query.with, subqueries)?,
+            )),
+            TableSource::Table(name) => explain_named(name, db, &query, subqueries)?,
+            TableSource::Values(_) => None,
+        };
+        plan.extend(materialized);
+    }
+
+    let is_aggregate = query.is_aggregate();
+    let (sorted, grouped) = match query.from.single() {
+        Some((source, schema)) => {
+            let exprs: Vec<_> = query
+                .result_columns
+                .iter()
+                .map(|c| c.expr.clone())
+                .collect();
+            let used = used_columns(source);
+            let access = match is_aggregate {
+                true => table_access(
+                    db,
+                    (source, schema),
+                    query.conds.clone(),
+                    &query.schemas,
+                    &[],
+                    None,
+                    &used,
+                )?,
+                false => table_access(
+                    db,
+                    (source, schema),
+                    query.conds.clone(),
+                    &query.schemas,
+                    &query.order_by,
+                    query.distinct.then_some(exprs.as_slice()),
+                    &used,
+                )?,
+            };
+            let covering = covering_index(&access, source, schema, &query.schemas, &used);
+            let how = match (&access.search, &access.scan, covering) {
+                (search, _, _) if search.indeces.is_some() => Access::Rowid,
+                (search, _, Some(rootpage)) if search.key.is_some() => {
+                    Access::CoveringIndexSearch(rootpage, 1 + search.prefix.len())
+                }
+                (search, _, None) if search.key.is_some() => {
+                    Access::IndexSearch(search.pgno.get(), 1 + search.prefix.len())
+                }
+                (search, _, None) if search.has_range() => Access::RowidRange(
+                    search.range.0 != Bound::Unbounded,
+                    search.range.1 != Bound::Unbounded,
+                ),
+                (_, _, Some(rootpage)) => Access::CoveringIndexScan(rootpage),
+                (_, Some((OrderedScan::Index(rootpage), _)), None) => Access::IndexScan(*rootpage),
+                _ => Access::Scan,
+            };
+            let intersect = &access.search.intersect;
+            // Like SQLite's MULTI-INDEX OR, with each index searched on a line of its own
+            let node = match intersect.is_empty() {
+                true => PlanNode::new(access_detail(&source.name, &how, &query.schemas)),
+                false => PlanNode::with_children(
+                    "MULTI-INDEX AND",
+                    intersect
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (pgno, _, _))| {
+                            let how = Access::IndexSearch(pgno.get(), 1);
+                            let detail = access_detail(&source.name, &how, &query.schemas);
+                            PlanNode::with_children(
+                                format!("INDEX {}", i + 1),
+                                vec![PlanNode::new(detail)],
+                            )
+                        })
+                        .collect(),
+                ),
+            };
+            plan.push(node);
+            let sorted = covering_sorted(&access, covering, &query.schemas);
+            (sorted, access.grouped)
+        }
+        None => {
+            plan.extend(query.from.explain(db, &query.conds, &query.schemas)?);
+            (query.order_by.is_empty(), false)
+        }
+    };
+    if is_aggregate && !query.group_by.is_empty() {
+        plan.push(PlanNode::new("USE TEMP B-TREE FOR GROUP BY"));
+    }
+    if query.distinct && !grouped {
+        plan.push(PlanNode::new("USE TEMP B-TREE FOR DISTINCT"));
+    }
+    // Grouped rows are always sorted afterwards
+    if !query.order_by.is_empty() && (is_aggregate || !sorted) {
+        plan.push(PlanNode::new("USE TEMP B-TREE FOR ORDER BY"));
+    }
+
+    let mut exprs = vec![];
+    for expr in query
+        .conds
+        .iter()
+        .chain(query.from.conditions())
+        .chain(&query.group_by)
+        .chain(&query.having)
+        .chain(query.order_by.iter().map(|t| &t.expr))
+        .chain(query.result_columns.iter().map(|c| &c.expr))
+    {
+        expr.walk(&mut |expr| {
+            if expr.is_subquery() {
+                exprs.push(expr.clone());
+            }
+        });
+    }
+    // A subquery reading a column of the row it runs for is run again for each row
+    let nulls = vec![Value::Null; query.from.columns.len()];
+    for expr in exprs {
+        let (select, kind) = match &expr {
+            Expr::Subquery(select) | Expr::Exists(select) => (select, "SCALAR"),
+            Expr::InSelect { select, .. } => (select, "LIST"),
+            _ => continue,
+        };
+        *subqueries += 1;
+        let n = *subqueries;
+        let outer = Outer::new(&query.from, &nulls);
+        let children = explain(select, db, Some(&outer), &query.with, subqueries)?;
+        let detail = match outer.used.get() {
+            true => format!("CORRELATED {kind} SUBQUERY {n}"),
+            false => format!("{kind} SUBQUERY {n}"),
+        };
+        plan.push(PlanNode::with_children(detail, children));
+    }
+    Ok(plan)
+}
+
+/// The plan materializing a FROM clause name that isn't a table of the database, as
+/// [`named_table`] does: the SELECTs of a WITH clause table, or those of a view.
+fn explain_named(
+    name: &str,
+    db: &Database,
+    query: &Query,
+    subqueries: &mut usize,
+) -> Result<Option<PlanNode>> {
+    let with = &query.with;
+    if let Some(i) = with
+        .iter()
+        .position(|w| w.name().eq_ignore_ascii_case(name))
+    {
+        let cte = match &with[i] {
+            With::Cte(cte) => cte,
+            With::Working(..) => return Ok(None),
+        };
+        let with = &with[i + 1..];
+        let detail = format!("MATERIALIZE {}", cte.view.name);
+        let (initial, steps) = match cte.recursion() {
+            Some(recursion) => recursion,
+            None => {
+                let children = explain(&cte.view.select, db, None, with, subqueries)?;
+                return Ok(Some(PlanNode::with_children(detail, children)));
+            }
+        };
+        let setup = explain(&initial, db, None, with, subqueries)?;
+        // The recursive SELECTs read a single row of the table as the whole of it
+        let (table, _) = cte_table(cte, db, with, None, Materialize::Columns)?;
+        let width = table.columns.len();
+        let working: Vec<_> = iter::once(With::Working(table, vec![Value::Null; width]))
+            .chain(with.iter().cloned())
+            .collect();
+        let mut recursive = vec![];
+        for (_, step) in steps {
+            recursive.extend(explain(step, db, None, &working, subqueries)?);
+        }
+        let children = vec![
+            PlanNode::with_children("SETUP", setup),
+            PlanNode::with_children("RECURSIVE STEP", recursive),
+        ];
+        return Ok(Some(PlanNode::with_children(detail, children)));
+    }
+    match query
+        .schemas
+        .get(name)
+        .filter(|s| s.stype == SchemaType::View)
+    {
+        Some(schema) => {
+            let view = View::try_from(schema)?;
+            let children = explain(&view.select, db, None, &[], subqueries)?;
+            Ok(Some(PlanNode::with_children(
+                format!("MATERIALIZE {name}"),
+                children,
+            )))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Cursors of a compiled program: the table read, the index leading to its rows, and the
+/// sorter ordering the results.
+const TABLE_CURSOR: usize = 0;
+const INDEX_CURSOR: usize = 1;
+const SORTER_CURSOR: usize = 2;
+
+/// The columns of its tables that a SELECT reads, by their ordinals in the joined rows,
+/// counting every name its subqueries use that could stand for one of them.
+fn read_columns(query: &Query) -> HashSet<usize> {
+    let mut read = HashSet::new();
+    let exprs = query
+        .result_columns
+        .iter()
+        .map(|c| &c.expr)
+        .chain(&query.conds)
+        .chain(query.from.conditions())
+        .chain(&query.group_by)
+        .chain(&query.having)
+        .chain(query.order_by.iter().map(|t| &t.expr));
+    for expr in exprs {
+        expr.walk_nested(&mut |e| {
+            if let Expr::Column(name) = e {
+                // Resolved names are keys of the joined columns, those of nested subqueries
+                // aren't resolved yet
+                let column = match query.from.columns.get(name) {
+                    Some(column) => Some(column),
+                    None => query.from.find(name).ok().flatten(),
                 };
+                read.extend(column.map(|c| c.idx));
+            }
+        });
+    }
+    read
+}
+
+/// Names of the columns of a table that the statement reads.
+fn used_columns(source: &Source) -> HashSet<String> {
+    source
+        .table
+        .columns
+        .values()
+        .filter(|c| (source.read.as_ref()).is_none_or(|read| read.binary_search(&c.idx).is_ok()))
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+/// The columns of its lone table that a SELECT reads when it compiles to a bytecode
+/// program, or `None` when it has to run as a tree of operators instead.
+fn compiled_columns(query: &Query) -> Option<HashSet<String>> {
+    let (source, _) = query.from.single()?;
+    if query.compound.is_some()
+        || query.distinct
+        || !query.group_by.is_empty()
+        || query.having.is_some()
+    {
+        return None;
+    }
+    let mut exprs = query
+        .result_columns
+        .iter()
+        .map(|c| &c.expr)
+        .chain(&query.conds)
+        .chain(query.order_by.iter().map(|t| &t.expr));
+    if exprs.any(|expr| expr.has_subquery() || expr.has_window()) {
+        return None;
+    }
+    let columns = &query.from.columns;
+    // Columns outside aggregate calls would describe one of the rows, which the aggregate
+    // operator picks
+    if query.is_aggregate() {
+        let mut bare = false;
+        for column in &query.result_columns {
+            let expr = column.expr.extract_aggregates(&mut vec![]);
+            expr.walk(&mut |e| {
+                bare |= matches!(e, Expr::Column(name) if columns.contains_key(name))
+            });
+        }
+        if bare {
+            return None;
+        }
+    }
+    Some(used_columns(source))
+}
 
-            let rows = db.rows(table_search);
+/// Compile a prepared SELECT into a bytecode program, when it reads a lone table of the
+/// database and computes nothing but expressions, non-grouped aggregates and a sort. Joins,
+/// subqueries, GROUP BY, DISTINCT, window functions and compound SELECTs are left to the
+/// operators.
+fn compile(query: &Query, db: &Database) -> Result<Option<Program>> {
+    let (source, schema) = match query.from.single() {
+        Some(single) => single,
+        None => return Ok(None),
+    };
+    let used = match compiled_columns(query) {
+        Some(used) => used,
+        None => return Ok(None),
+    };
+    let columns = &query.from.columns;
+    let is_aggregate = query.is_aggregate();
+    let mut calls = vec![];
+    let result_exprs: Vec<_> = query
+        .result_columns
+        .iter()
+        .map(|c| match is_aggregate {
+            true => c.expr.extract_aggregates(&mut calls),
+            false => c.expr.clone(),
+        })
+        .collect();
 
-            print_rows(rows, columns);
+    // An aggregate without GROUP BY is a single row, with nothing to sort
+    let order_by = match is_aggregate {
+        true => &[][..],
+        false => &query.order_by[..],
+    };
+    let access = table_access(
+        db,
+        (source, schema),
+        query.conds.clone(),
+        &query.schemas,
+        order_by,
+        None,
+        &used,
+    )?;
+    // Intersecting the rowids of several indexes, seeking a range of rowids and reading the
+    // entries under an index key backwards are left to the operators
+    let entry_order = match access.scan {
+        Some((OrderedScan::Search, true)) => return Ok(None),
+        Some((OrderedScan::Search, false)) => true,
+        _ => false,
+    };
+    if !access.search.intersect.is_empty() || access.search.has_range() {
+        return Ok(None);
+    }
+    let search = &access.search;
+    // Rows found through an index key come in the index's order rather than by rowid, which
+    // only saves sorting them when the search was chosen for that order
+    let sort = !order_by.is_empty() && (!access.sorted || (search.key.is_some() && !entry_order));
+    let keys = match sort {
+        true => {
+            order_keys(order_by, columns, db)?;
+            order_by
+                .iter()
+                .map(|term| KeyColumn {
+                    collation: term.expr.collation_name(columns).to_uppercase(),
+                    desc: term.desc,
+                    nulls_first: term.nulls_first(),
+                })
+                .collect()
         }
+        false => vec![],
     };
+    let limit = row_count(query.limit.clone(), db)?;
+    let offset = row_count(query.offset.clone(), db)?.filter(|&n| n > 0);
 
+    let mut b = Builder::default();
+    let init = b.emit(Insn::Init { target: 0 });
+    let limit_reg = limit.map(|_| b.alloc(1));
+    let offset_reg = offset.map(|_| b.alloc(1));
+    let accumulators = b.alloc(calls.len());
+    // Jumps leaving the program, and those moving on to the next row
+    let mut done = vec![];
+    let mut skip = vec![];
+    // A LIMIT of zero halts before any row is read, or aggregated into a row of its own
+    let halt = limit_reg.map(|src| {
+        b.emit(Insn::IfNot {
+            src,
+            target: 0,
+            null: true,
+        })
+    });
+    if sort {
+        b.emit(Insn::SorterOpen {
+            cursor: SORTER_CURSOR,
+            keys,
+        });
+    }
+    let covering = covering_index(&access, source, schema, &query.schemas, &used);
+    if covering.is_none() {
+        b.emit(Insn::OpenRead {
+            cursor: TABLE_CURSOR,
+            root: schema.rootpage,
+            name: schema.name.clone(),
+            collation: None,
+        });
+    }
+    let index = |rootpage: u64| {
+        query
+            .schemas
+            .values()
+            .filter(|s| s.stype == SchemaType::Index && s.rootpage == rootpage)
+            .find_map(|s| Index::try_from(s).ok())
+            .ok_or_else(|| anyhow!("no index with rootpage {}", rootpage))
+    };
+    // Columns are read from the index entries when they hold all of them
+    let covered;
+    let scope = match covering {
+        Some(rootpage) => {
+            covered = index_columns(&index(rootpage)?, &source.table, columns);
+            Scope {
+                cursor: INDEX_CURSOR,
+                columns: &covered,
+                registers: HashMap::new(),
+            }
+        }
+        None => Scope {
+            cursor: TABLE_CURSOR,
+            columns,
+            registers: HashMap::new(),
+        },
+    };
+    // Open the index and move from its entries to the rows of the table they point to,
+    // unless the entries cover the columns
+    let open_index = |b: &mut Builder, rootpage: u64, collation: String| -> Result<()> {
+        b.emit(Insn::OpenRead {
+            cursor: INDEX_CURSOR,
+            root: rootpage,
+            name: index(rootpage)?.name,
+            collation: Some(collation.to_uppercase()),
+        });
+        Ok(())
+    };
+    let fetch_row = |b: &mut Builder, skip: &mut Vec<usize>| {
+        if covering.is_some() {
+            return;
+        }
+        let rowid = b.alloc(1);
+        b.emit(Insn::IdxRowid {
+            cursor: INDEX_CURSOR,
+            dest: rowid,
+        });
+        skip.push(b.emit(Insn::SeekRowid {
+            cursor: TABLE_CURSOR,
+            target: 0,
+            rowid,
+        }));
+    };
+
+    // The loop over the rows, and the instruction moving to the next one once it is emitted
+    let mut next = None;
+    let scan = match (access.scan, covering) {
+        (None, Some(rootpage)) => Some((OrderedScan::Index(rootpage), false)),
+        (scan, _) => scan,
+    };
+    match (&search.indeces, &search.key, scan) {
+        (Some(rowids), _, _) => {
+            let rowid = b.alloc(1);
+            let value = rowids.first().map_or(0, |&rowid| rowid as i64);
+            b.emit(Insn::Integer { value, dest: rowid });
+            done.push(b.emit(Insn::SeekRowid {
+                cursor: TABLE_CURSOR,
+                target: 0,
+                rowid,
+            }));
+        }
+        (None, Some(key), _) => {
+            open_index(&mut b, search.pgno.get(), search.collation.clone())?;
+            let key_reg = b.expr(&Expr::Literal(key.clone()), &scope)?;
+            done.push(b.emit(Insn::SeekGE {
+                cursor: INDEX_CURSOR,
+                target: 0,
+                key: key_reg,
+            }));
+            let top = b.here();
+            done.push(b.emit(Insn::IdxGT {
+                cursor: INDEX_CURSOR,
+                target: 0,
+                key: key_reg,
+            }));
+            fetch_row(&mut b, &mut skip);
+            next = Some(Insn::Next {
+                cursor: INDEX_CURSOR,
+                target: top,
+            });
+        }
+        (None, None, Some((OrderedScan::Index(rootpage), reverse))) => {
+            let collation = index(rootpage)?
+                .collation(0, &source.table)
+                .unwrap_or_else(|| BINARY.to_string());
+            open_index(&mut b, rootpage, collation)?;
+            done.push(b.emit(match reverse {
+                true => Insn::Last {
+                    cursor: INDEX_CURSOR,
+                    target: 0,
+                },
+                false => Insn::Rewind {
+                    cursor: INDEX_CURSOR,
+                    target: 0,
+                },
+            }));
+            let top = b.here();
+            fetch_row(&mut b, &mut skip);
+            next = Some(match reverse {
+                true => Insn::Prev {
+                    cursor: INDEX_CURSOR,
+                    target: top,
+                },
+                false => Insn::Next {
+                    cursor: INDEX_CURSOR,
+                    target: top,
+                },
+            });
+        }
+        (None, None, scan) => {
+            let reverse = matches!(scan, Some((OrderedScan::Table, true)));
+            done.push(b.emit(match reverse {
+                true => Insn::Last {
+                    cursor: TABLE_CURSOR,
+                    target: 0,
+                },
+                false => Insn::Rewind {
+                    cursor: TABLE_CURSOR,
+                    target: 0,
+                },
+            }));
+            let top = b.here();
+            next = Some(match reverse {
+                true => Insn::Prev {
+                    cursor: TABLE_CURSOR,
+                    target: top,
+                },
+                false => Insn::Next {
+                    cursor: TABLE_CURSOR,
+                    target: top,
+                },
+            });
+        }
+    }
+
+    for cond in &search.conds {
+        let src = b.expr(cond, &scope)?;
+        skip.push(b.emit(Insn::IfNot {
+            src,
+            target: 0,
+            null: true,
+        }));
+    }
+    if is_aggregate {
+        for (n, call) in calls.iter().enumerate() {
+            let (name, args, distinct) = match call {
+                Expr::Function {
+                    name,
+                    args,
+                    distinct,
+                } => (name, args, *distinct),
+                _ => bail!("not an aggregate: {:?}", call),
+            };
+            let first = b.alloc(args.len());
+            for (i, arg) in args.iter().enumerate() {
+                b.expr_into(arg, &scope, first + i)?;
+            }
+            let collation = args
+                .first()
+                .map_or(BINARY, |arg| arg.collation_name(columns));
+            if !collation.eq_ignore_ascii_case(BINARY) {
+                b.emit(Insn::CollSeq {
+                    collation: collation.to_uppercase(),
+                });
+            }
+            b.emit(Insn::AggStep {
+                name: name.to_lowercase(),
+                distinct,
+                args: first,
+                count: args.len(),
+                acc: accumulators + n,
+            });
+        }
+    } else if sort {
+        let start = b.alloc(order_by.len() + result_exprs.len());
+        let exprs = order_by.iter().map(|t| &t.expr).chain(&result_exprs);
+        for (i, expr) in exprs.enumerate() {
+            b.expr_into(expr, &scope, start + i)?;
+        }
+        b.emit(Insn::SorterInsert {
+            cursor: SORTER_CURSOR,
+            start,
+            count: order_by.len() + result_exprs.len(),
+        });
+    } else {
+        let exprs = |b: &mut Builder, start| {
+            for (i, expr) in result_exprs.iter().enumerate() {
+                b.expr_into(expr, &scope, start + i)?;
+            }
+            Ok(())
+        };
+        compile_output(
+            &mut b,
+            result_exprs.len(),
+            exprs,
+            offset_reg,
+            limit_reg,
+            &mut skip,
+            &mut done,
+        )?;
+    }
+    b.resolve(&skip);
+    if let Some(next) = next {
+        b.emit(next);
+    }
+    b.resolve(&done);
+    done.clear();
+    skip.clear();
+
+    if is_aggregate {
+        for (n, call) in calls.iter().enumerate() {
+            if let Expr::Function { name, args, .. } = call {
+                b.emit(Insn::AggFinal {
+                    name: name.to_lowercase(),
+                    count: args.len(),
+                    acc: accumulators + n,
+                });
+            }
+        }
+        let scope = Scope {
+            registers: (0..calls.len())
+                .map(|n| (Expr::aggregate_column(n), accumulators + n))
+                .collect(),
+            ..scope
+        };
+        let exprs = |b: &mut Builder, start| {
+            for (i, expr) in result_exprs.iter().enumerate() {
+                b.expr_into(expr, &scope, start + i)?;
+            }
+            Ok(())
+        };
+        compile_output(
+            &mut b,
+            result_exprs.len(),
+            exprs,
+            offset_reg,
+            limit_reg,
+            &mut skip,
+            &mut done,
+        )?;
+    } else if sort {
+        done.push(b.emit(Insn::SorterSort {
+            cursor: SORTER_CURSOR,
+            target: 0,
+        }));
+        let top = b.here();
+        // The sorted records hold the results after their keys, which the sorter drops
+        let columns = |b: &mut Builder, start| {
+            for (i, column) in query.result_columns.iter().enumerate() {
+                b.emit(Insn::Column {
+                    cursor: SORTER_CURSOR,
+                    column: i,
+                    dest: start + i,
+                    name: column.name.clone(),
+                });
+            }
+            Ok(())
+        };
+        compile_output(
+            &mut b,
+            result_exprs.len(),
+            columns,
+            offset_reg,
+            limit_reg,
+            &mut skip,
+            &mut done,
+        )?;
+        b.resolve(&skip);
+        skip.clear();
+        b.emit(Insn::SorterNext {
+            cursor: SORTER_CURSOR,
+            target: top,
+        });
+    }
+    b.resolve(&skip);
+    b.resolve(&done);
+    b.resolve(halt.as_slice());
+    b.emit(Insn::Halt);
+
+    // Constants are loaded once, before the program starts over from its second instruction
+    b.resolve(&[init]);
+    for (reg, value) in [(limit_reg, limit), (offset_reg, offset)] {
+        if let (Some(dest), Some(value)) = (reg, value) {
+            b.emit(Insn::Integer { value, dest });
+        }
+    }
+    b.emit(Insn::Goto { target: init + 1 });
+    Ok(Some(b.finish()))
+}
+
+/// Emit the code producing a result row of `count` values, which `values` computes into the
+/// registers from the one it is given. The rows of the OFFSET counted down in register
+/// `offset` are skipped first, and the loop is left once the LIMIT counted down in register
+/// `limit` is reached. The jumps to the next row go in `skip` and those leaving in `done`,
+/// for their targets to be set.
+fn compile_output(
+    b: &mut Builder,
+    count: usize,
+    values: impl FnOnce(&mut Builder, usize) -> Result<()>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    skip: &mut Vec<usize>,
+    done: &mut Vec<usize>,
+) -> Result<()> {
+    if let Some(reg) = offset {
+        skip.push(b.emit(Insn::IfPos { reg, target: 0 }));
+    }
+    let start = b.alloc(count);
+    values(b, start)?;
+    b.emit(Insn::ResultRow { start, count });
+    if let Some(reg) = limit {
+        done.push(b.emit(Insn::DecrJumpZero { reg, target: 0 }));
+    }
     Ok(())
 }
 
+/// Run a compound SELECT, combining the rows of each SELECT with those of the ones before it,
+/// then sorting and limiting them all.
+fn execute_compound(first: Query, compound: Compound, db: &Database) -> Result<Vec<Row>> {
+    let Compound {
+        selects,
+        table,
+        order_by,
+        limit,
+        offset,
+    } = compound;
+    // Rows are compared by the collations of the first SELECT's columns
+    let keys = column_keys(&table, db)?;
+    let mut rows = execute(first, db)?;
+    for (operator, query) in selects {
+        let more = execute(query, db)?;
+        let mut distinct = Distinct::new(keys.clone(), false);
+        rows = match operator {
+            CompoundOperator::UnionAll => rows.into_iter().chain(more).collect(),
+            CompoundOperator::Union => rows
+                .into_iter()
+                .chain(more)
+                .filter(|row| distinct.insert(row))
+                .collect(),
+            CompoundOperator::Intersect | CompoundOperator::Except => {
+                let mut found = Distinct::new(keys.clone(), false);
+                for row in &more {
+                    found.insert(row);
+                }
+                let keep = operator == CompoundOperator::Intersect;
+                rows.into_iter()
+                    .filter(|row| found.contains(row) == keep && distinct.insert(row))
+                    .collect()
+            }
+        };
+    }
+    if !order_by.is_empty() {
+        rows = sort_rows(rows, &order_by, &table.columns, db)?;
+    }
+    let limit = row_count(limit, db)?.map_or(usize::MAX, |n| n.try_into().unwrap_or(usize::MAX));
+    let offset = row_count(offset, db)?.map_or(0, |n| n.try_into().unwrap_or(0));
+    Ok(rows.into_iter().skip(offset).take(limit).collect())
+}
+
+/// Keys comparing each column of a table's rows by its collating sequence.
+fn column_keys(table: &Table, db: &Database) -> Result<Vec<SortKey>> {
+    let exprs: Vec<_> = table
+        .columns
+        .values()
+        .sorted()
+        .map(|column| Expr::Column(column.name.clone()))
+        .collect();
+    sort_keys(&exprs, &table.columns, db)
+}
+
+/// Run a prepared SELECT, returning the values of its result columns for each row.
+fn execute(mut query: Query, db: &Database) -> Result<Vec<Row>> {
+    if let Some(compound) = query.compound.take() {
+        return execute_compound(query, *compound, db);
+    }
+    let is_aggregate = query.is_aggregate();
+    let Query {
+        from,
+        with,
+        schemas,
+        result_columns,
+        conds,
+        group_by,
+        having,
+        order_by,
+        distinct,
+        limit,
+        offset,
+        compound: _,
+    } = query;
+
+    // Subqueries are run for each row and their values read like extra columns: those of
+    // WHERE and GROUP BY before rows are filtered and grouped, and the others afterwards
+    let (filters, conds): (Vec<_>, Vec<_>) = conds.into_iter().partition(Expr::has_subquery);
+    let mut subqueries = vec![];
+    let filters: Vec<_> = filters
+        .iter()
+        .map(|cond| cond.extract_subqueries(&mut subqueries))
+        .collect();
+    let group_by: Vec<_> = group_by
+        .iter()
+        .map(|expr| expr.extract_subqueries(&mut subqueries))
+        .collect();
+    let mut columns = from.columns.clone();
+    let before = Subqueries::new(&from, &with, subqueries, 0, &mut columns);
+    let row_columns = columns.clone();
+
+    // Aggregate calls are computed per group and then read like extra columns
+    let mut calls = vec![];
+    let mut extract = |expr: &Expr| match is_aggregate {
+        true => expr.extract_aggregates(&mut calls),
+        false => expr.clone(),
+    };
+    let result_exprs: Vec<_> = result_columns.iter().map(|c| extract(&c.expr)).collect();
+    let having = having.map(|h| extract(&h));
+    let order_by: Vec<_> = order_by
+        .into_iter()
+        .map(|t| OrderingTerm {
+            expr: extract(&t.expr),
+            ..t
+        })
+        .collect();
+    let width = columns.len();
+    for n in 0..calls.len() {
+        let name = Expr::aggregate_column(n);
+        let column = Column {
+            idx: width + n,
+            name: name.clone(),
+            cell_type: CellType::Blob,
+            nullable: true,
+            pk: false,
+            collation: None,
+        };
+        columns.insert(name, column);
+    }
+
+    let mut subqueries = vec![];
+    let mut extract = |expr: &Expr| expr.extract_subqueries(&mut subqueries);
+    let result_exprs: Vec<_> = result_exprs.iter().map(&mut extract).collect();
+    let having = having.map(|h| extract(&h));
+    let order_by: Vec<_> = order_by
+        .into_iter()
+        .map(|t| OrderingTerm {
+            expr: extract(&t.expr),
+            ..t
+        })
+        .collect();
+    let after = Subqueries::new(&from, &with, subqueries, before.exprs.len(), &mut columns);
+
+    // Window function calls are computed once all the rows are there, after HAVING, and
+    // then read like extra columns
+    let mut windows = vec![];
+    let result_exprs: Vec<_> = result_exprs
+        .iter()
+        .map(|expr| expr.extract_windows(&mut windows))
+        .collect();
+    let order_by: Vec<_> = order_by
+        .into_iter()
+        .map(|t| OrderingTerm {
+            expr: t.expr.extract_windows(&mut windows),
+            ..t
+        })
+        .collect();
+    let window_columns = columns.clone();
+    let width = columns.len();
+    for n in 0..windows.len() {
+        let name = Expr::window_column(n);
+        let column = Column {
+            idx: width + n,
+            name: name.clone(),
+            cell_type: CellType::Blob,
+            nullable: true,
+            pk: false,
+            collation: None,
+        };
+        columns.insert(name, column);
+    }
+
+    // A lone table can be searched through an index, or read in an index's order, while
+    // joined tables are read by nested loops
+    let single = match from.single() {
+        Some((source, schema)) => {
+            let used = used_columns(source);
+            let access = match is_aggregate {
+                true => table_access(
+                    db,
+                    (source, schema),
+                    conds.clone(),
+                    &schemas,
+                    &[],
+                    None,
+                    &used,
+                )?,
+                false => table_access(
+                    db,
+                    (source, schema),
+                    conds.clone(),
+                    &schemas,
+                    &order_by,
+                    distinct.then_some(result_exprs.as_slice()),
+                    &used,
+                )?,
+            };
+            let covering = covering_index(&access, source, schema, &schemas, &used);
+            let sorted = covering_sorted(&access, covering, &schemas);
+            Some((TableAccess { sorted, ..access }, covering))
+        }
+        None => None,
+    };
+    let collations = &db.collations;
+    let (mut rows, sorted, grouped): (BoxedOperator, _, _) = match single {
+        // Rows are made from the entries of an index holding every column read
+        Some((access, Some(rootpage))) => {
+            let index = schemas
+                .values()
+                .filter(|s| s.stype == SchemaType::Index && s.rootpage == rootpage)
+                .find_map(|s| Index::try_from(s).ok())
+                .ok_or_else(|| anyhow!("no index with rootpage {}", rootpage))?;
+            let pgno = match NonZeroU64::new(rootpage) {
+                Some(pgno) => pgno,
+                None => bail!("invalid index rootpage: {}", rootpage),
+            };
+            let TableAccess {
+                search,
+                scan,
+                sorted,
+                grouped,
+            } = access;
+            let reverse = scan.is_some_and(|(_, reverse)| reverse);
+            let entries: BoxedOperator = match &search.key {
+                Some(key) => {
+                    let seek = IndexSeek::new(db, pgno, key.clone(), &search.collation)
+                        .with_prefix(search.prefix.clone());
+                    match reverse {
+                        true => Box::new(seek.rev()),
+                        false => Box::new(seek),
+                    }
+                }
+                None => Box::new(IndexScan::new(db, pgno, reverse)),
+            };
+            let rows = CoveringRows::new(db, &search, &index, entries)?;
+            (Box::new(rows), sorted, grouped)
+        }
+        Some((
+            TableAccess {
+                search,
+                scan,
+                sorted,
+                grouped,
+            },
+            None,
+        )) => {
+            let rows = match scan {
+                // Table b-trees, and rows found through an index key, come in rowid order
+                Some((OrderedScan::Table, reverse)) => search_rows(db, search, reverse)?,
+                Some((OrderedScan::Search, reverse)) => ordered_search_rows(db, search, reverse)?,
+                Some((OrderedScan::Index(rootpage), reverse)) => {
+                    let pgno = match NonZeroU64::new(rootpage) {
+                        Some(pgno) => pgno,
+                        None => bail!("invalid index rootpage: {}", rootpage),
+                    };
+                    let entries = Box::new(IndexScan::new(db, pgno, reverse));
+                    Box::new(RowidFetch::new(db, search, entries))
+                }
+                None => search_rows(db, search, false)?,
+            };
+            (rows, sorted, grouped)
+        }
+        None => (from.rows(db, &schemas, &conds)?, order_by.is_empty(), false),
+    };
+
+    if !before.exprs.is_empty() {
+        let appended = Box::new(Map::new(rows, |row| before.append(row, &columns, db)));
+        rows = Box::new(Filter::new(appended, filters, &columns, collations));
+    }
+    if is_aggregate {
+        rows = Box::new(Aggregate::new(
+            rows,
+            group_by,
+            calls,
+            &row_columns,
+            collations,
+        ));
+    }
+    if !after.exprs.is_empty() {
+        rows = Box::new(Map::new(rows, |row| after.append(row, &columns, db)));
+    }
+    if let Some(having) = having {
+        rows = Box::new(Filter::new(rows, vec![having], &columns, collations));
+    }
+    if !windows.is_empty() {
+        rows = Box::new(Window::new(rows, windows, &window_columns, collations));
+    }
+    // Groups come in the order of their keys, so they are sorted again
+    if !order_by.is_empty() && (is_aggregate || !sorted) {
+        let keys = order_keys(&order_by, &columns, db)?;
+        rows = Box::new(Sort::new(rows, order_by, keys, &columns, collations));
+    }
+
+    // The limit stops pulling rows once enough are produced, and the scans reading pages
+    let limit = row_count(limit, db)?.map_or(usize::MAX, |n| n.try_into().unwrap_or(usize::MAX));
+    let offset = row_count(offset, db)?.map_or(0, |n| n.try_into().unwrap_or(0));
+    let keys = match distinct {
+        true => Some(sort_keys(&result_exprs, &columns, db)?),
+        false => None,
+    };
+    rows = Box::new(Project::new(rows, result_exprs, &columns, collations));
+    if let Some(keys) = keys {
+        rows = Box::new(operators::Distinct::new(rows, keys, grouped));
+    }
+    operators::collect(Limit::new(rows, offset, limit))
+}
+
+/// What a subquery returned for a row, as needed by the expression running it.
+enum SubqueryResult {
+    /// Value of a scalar or `EXISTS` subquery.
+    Value(Value),
+    /// The first column of the rows of an `IN` subquery.
+    In(InSet),
+}
+
+/// Values an `IN` subquery compares its left operand with, with the affinity and collation
+/// of the comparison.
+struct InSet {
+    affinity: Option<CellType>,
+    collation: String,
+    /// Keys of the values when the collation can be hashed, and otherwise the values.
+    keys: HashSet<HashKey>,
+    values: Vec<Value>,
+    has_null: bool,
+    is_empty: bool,
+}
+
+/// The subqueries of a statement's expressions, whose values are computed for each row and
+/// appended to it, to be read through the columns named by [`Expr::subquery_column`].
+struct Subqueries<'q> {
+    from: &'q JoinedTables<'q>,
+    with: &'q [With],
+    exprs: Vec<Expr>,
+    /// Position of the first subquery's column in the rows.
+    width: usize,
+    /// Results of the subqueries that don't refer to the row they run for, run only once.
+    cache: RefCell<Vec<Option<Rc<SubqueryResult>>>>,
+}
+
+impl<'q> Subqueries<'q> {
+    /// Take the subqueries extracted from expressions, numbered from `first`, and add their
+    /// columns to `columns`, after the columns already there.
+    fn new(
+        from: &'q JoinedTables<'q>,
+        with: &'q [With],
+        mut exprs: Vec<Expr>,
+        first: usize,
+        columns: &mut HashMap<String, Column>,
+    ) -> Self {
+        let width = columns.len();
+        for (n, expr) in exprs.iter_mut().enumerate() {
+            // Only the first row of a scalar or EXISTS subquery is ever needed
+            if let Expr::Subquery(select) | Expr::Exists(select) = expr {
+                select.limit.get_or_insert(Expr::Literal(Value::Integer(1)));
+            }
+            let name = Expr::subquery_column(first + n);
+            let column = Column {
+                idx: width + n,
+                name: name.clone(),
+                cell_type: CellType::Blob,
+                nullable: true,
+                pk: false,
+                collation: None,
+            };
+            columns.insert(name, column);
+        }
+        Subqueries {
+            from,
+            with,
+            cache: RefCell::new(exprs.iter().map(|_| None).collect()),
+            exprs,
+            width,
+        }
+    }
+
+    /// The row with the values of the subqueries appended.
+    fn append(
+        &self,
+        mut row: Row,
+        columns: &HashMap<String, Column>,
+        db: &Database,
+    ) -> Result<Row> {
+        // Rows written before columns were added are shorter than the table
+        row.resize(self.width, Value::Null);
+        for n in 0..self.exprs.len() {
+            let value = self.value(n, &row, columns, db)?;
+            row.push(value);
+        }
+        Ok(row)
+    }
+
+    /// Value of the `n`th subquery, with the operator applied to it, for a row.
+    fn value(
+        &self,
+        n: usize,
+        row: &[Value],
+        columns: &HashMap<String, Column>,
+        db: &Database,
+    ) -> Result<Value> {
+        let expr = &self.exprs[n];
+        let cached = self.cache.borrow()[n].clone();
+        let result = match cached {
+            Some(result) => result,
+            None => {
+                let outer = Outer::new(self.from, row);
+                let result = Rc::new(self.run(expr, &outer, columns, db)?);
+                if !outer.used.get() {
+                    self.cache.borrow_mut()[n] = Some(Rc::clone(&result));
+                }
+                result
+            }
+        };
+        let (lhs, negated, set) = match (expr, result.as_ref()) {
+            (_, SubqueryResult::Value(value)) => return Ok(value.clone()),
+            (Expr::InSelect { expr, negated, .. }, SubqueryResult::In(set)) => (expr, negated, set),
+            _ => bail!("{:?} is not an IN subquery", expr),
+        };
+        // Like `lhs = value` for each value, where NULL makes the result unknown unless
+        // another value matches, but nothing is in an empty set
+        let lhs = match lhs.eval(row, columns, &db.collations)? {
+            _ if set.is_empty => return Ok(Value::from(*negated)),
+            Value::Null => return Ok(Value::Null),
+            lhs => match set.affinity {
+                Some(affinity) => lhs.apply_affinity(affinity),
+                None => lhs,
+            },
+        };
+        let found = match (HashKey::new(lhs.clone(), &set.collation), &set.collation) {
+            (Some(key), collation) if HashKey::supports(collation) => set.keys.contains(&key),
+            _ => {
+                let cmp = db.collations.get(&set.collation);
+                set.values.iter().any(|value| match cmp {
+                    Some(cmp) => lhs.compare_collated(value, cmp.as_ref()).is_eq(),
+                    None => lhs.compare(value).is_eq(),
+                })
+            }
+        };
+        Ok(match (found, set.has_null) {
+            (true, _) => Value::from(!*negated),
+            (false, true) => Value::Null,
+            (false, false) => Value::from(*negated),
+        })
+    }
+
+    /// Run a subquery for the row of `outer`.
+    fn run(
+        &self,
+        expr: &Expr,
+        outer: &Outer,
+        columns: &HashMap<String, Column>,
+        db: &Database,
+    ) -> Result<SubqueryResult> {
+        let (select, lhs) = match expr {
+            Expr::Subquery(select) => {
+                let rows = execute(
+                    prepare(select, db, Some(outer), self.with, Materialize::Rows)?,
+                    db,
+                )?;
+                let value = rows
+                    .into_iter()
+                    .next()
+                    .and_then(|row| row.into_iter().next());
+                return Ok(SubqueryResult::Value(value.unwrap_or(Value::Null)));
+            }
+            Expr::Exists(select) => {
+                let rows = execute(
+                    prepare(select, db, Some(outer), self.with, Materialize::Rows)?,
+                    db,
+                )?;
+                return Ok(SubqueryResult::Value(Value::from(!rows.is_empty())));
+            }
+            Expr::InSelect { expr, select, .. } => (select, expr),
+            _ => bail!("{:?} is not a subquery", expr),
+        };
+        let query = prepare(select, db, Some(outer), self.with, Materialize::Rows)?;
+        let rhs = match query.result_columns.as_slice() {
+            [column] => &column.expr,
+            columns => bail!("sub-select returns {} columns - expected 1", columns.len()),
+        };
+        let affinity =
+            Expr::comparison_affinity(lhs.affinity(columns), rhs.affinity(&query.from.columns));
+        let collation =
+            Expr::joint_collation(lhs.collation(columns), rhs.collation(&query.from.columns))
+                .to_string();
+        let hashed = HashKey::supports(&collation);
+        let mut set = InSet {
+            affinity,
+            collation,
+            keys: HashSet::new(),
+            values: vec![],
+            has_null: false,
+            is_empty: true,
+        };
+        for row in execute(query, db)? {
+            set.is_empty = false;
+            let value = match (row.into_iter().next(), affinity) {
+                (Some(value), Some(affinity)) => value.apply_affinity(affinity),
+                (Some(value), None) => value,
+                (None, _) => Value::Null,
+            };
+            match HashKey::new(value.clone(), &set.collation) {
+                None => set.has_null = true,
+                Some(key) if hashed => {
+                    set.keys.insert(key);
+                }
+                Some(_) => set.values.push(value),
+            }
+        }
+        Ok(SubqueryResult::In(set))
+    }
+}
+
+/// How the rows of a lone table are read.
+struct TableAccess {
+    search: Search,
+    /// The b-tree whose order the rows are read in, and whether backwards.
+    scan: Option<(OrderedScan, bool)>,
+    /// Whether the rows come in the order the ORDER BY asks for.
+    sorted: bool,
+    /// Whether equal rows of a SELECT DISTINCT come next to each other.
+    grouped: bool,
+}
+
+/// Decide how to read the rows of a lone table satisfying `conds`: searched for through the
+/// rowid or an index when a condition allows it, and otherwise read in an order that saves
+/// sorting them by `order_by`, or that brings together the equal values of `distinct`, the
+/// result expressions of a SELECT DISTINCT. An index search saves sorting the rows too when
+/// its entries come in that order. The `used` columns are those the query reads.
+fn table_access(
+    db: &Database,
+    (source, schema): (&Source, &Schema),
+    conds: Vec<Expr>,
+    schemas: &HashMap<String, Schema>,
+    order_by: &[OrderingTerm],
+    distinct: Option<&[Expr]>,
+    used: &HashSet<String>,
+) -> Result<TableAccess> {
+    let mut search = table_search(db, source, schema, conds, schemas, order_by, used)?;
+    let seeks = search.key.is_some() || search.indeces.is_some() || !search.intersect.is_empty();
+    // Scanning an index on the distinct columns brings equal rows together
+    let distinct_scan = match distinct {
+        Some(exprs) if order_by.is_empty() && !seeks => {
+            find_distinct_index(exprs, &schema.name, &source.table, schemas)
+        }
+        _ => None,
+    };
+    let grouped = distinct_scan.is_some();
+    let scan = match distinct_scan {
+        Some(scan) => Some((scan, false)),
+        None => find_order_index(order_by, &schema.name, &source.table, schemas),
+    };
+    // Rows found through a key can't also be read in another index's order, but may come in
+    // the order of the entries under the key
+    let entry_order = || {
+        let index = schemas
+            .values()
+            .filter(|s| s.stype == SchemaType::Index && s.rootpage == search.pgno.get())
+            .find_map(|s| Index::try_from(s).ok())?;
+        let equal = 1 + search.prefix.len();
+        search_order(order_by, &index, equal, &source.table)
+            .map(|reverse| (OrderedScan::Search, reverse))
+    };
+    let scan = match scan {
+        Some((OrderedScan::Table, reverse)) => Some((OrderedScan::Table, reverse)),
+        _ if search.key.is_some() && !order_by.is_empty() => entry_order(),
+        scan => scan.filter(|_| !seeks),
+    };
+    // A range of rowids is read from the table, so reading an index instead leaves it to the
+    // conditions
+    if let Some((OrderedScan::Index(_), _)) = scan {
+        search = search.with_range((Bound::Unbounded, Bound::Unbounded));
+    }
+    Ok(TableAccess {
+        search,
+        sorted: scan.is_some() || order_by.is_empty(),
+        scan,
+        grouped,
+    })
+}
+
+/// The index whose entries hold every one of the `used` columns of a lone table, so they
+/// can be read without looking rows up in the table, returning its rootpage: the index
+/// `access` searches or scans for its order, or else the narrowest one, read instead of the
+/// whole table.
+fn covering_index(
+    access: &TableAccess,
+    source: &Source,
+    schema: &Schema,
+    schemas: &HashMap<String, Schema>,
+    used: &HashSet<String>,
+) -> Option<u64> {
+    let search = &access.search;
+    if !search.intersect.is_empty() {
+        return None;
+    }
+    let rootpage = match (&search.indeces, &search.key, &access.scan) {
+        (Some(_), _, _) | (None, None, Some((OrderedScan::Table | OrderedScan::Search, _))) => {
+            return None
+        }
+        (None, Some(_), _) => search.pgno.get(),
+        (None, None, Some((OrderedScan::Index(rootpage), _))) => *rootpage,
+        // Seeking a range of rowids reads the table
+        (None, None, None) if search.has_range() => return None,
+        (None, None, None) => {
+            return find_covering_index(used, &schema.name, &source.table, schemas)
+        }
+    };
+    schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.rootpage == rootpage)
+        .find_map(|s| Index::try_from(s).ok())
+        .filter(|index| index_covers(index, &source.table, used))
+        .map(|_| rootpage)
+}
+
+/// Whether the rows `access` reads still come sorted when they are made from the entries of
+/// the `covering` index: entries read for their own order keep it, while entries of equal
+/// keys only come in rowid order when the key, with the columns it is narrowed by, is all
+/// they hold.
+fn covering_sorted(
+    access: &TableAccess,
+    covering: Option<u64>,
+    schemas: &HashMap<String, Schema>,
+) -> bool {
+    let rootpage = match covering {
+        Some(_) if matches!(access.scan, Some((OrderedScan::Search, _))) => return access.sorted,
+        Some(rootpage) if access.search.key.is_some() => rootpage,
+        _ => return access.sorted,
+    };
+    let width = schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.rootpage == rootpage)
+        .find_map(|s| Index::try_from(s).ok())
+        .map(|index| index.columns.len());
+    access.sorted && width == Some(1 + access.search.prefix.len())
+}
+
+/// Search for the rows of a lone table satisfying `conds`, seeking the row an equality on
+/// the integer primary key names, or through the index, or the intersection of several,
+/// expected to find them most cheaply when the conditions include equalities that indexes
+/// can answer, or else the range of rowids the conditions on the integer primary key bound.
+/// An index holding all of the `used` columns is cheaper, as rows needn't be looked up in
+/// the table, and of indexes costing the same, one whose entries come in `order_by` order.
+fn table_search(
+    db: &Database,
+    source: &Source,
+    schema: &Schema,
+    conds: Vec<Expr>,
+    schemas: &HashMap<String, Schema>,
+    order_by: &[OrderingTerm],
+    used: &HashSet<String>,
+) -> Result<Search> {
+    // Rows only have the columns the statement reads decoded
+    let project = |search: Search| match &source.read {
+        Some(columns) => search.with_columns(columns.clone()),
+        None => search,
+    };
+    let rowid = conds.iter().find_map(|cond| {
+        let (name, value) = cond.equality_key()?;
+        let column = source.table.columns.get(name)?;
+        if source.table.key.as_ref() != Some(&column.name) || column.cell_type != CellType::Integer
+        {
+            return None;
+        }
+        match value.clone().apply_affinity(CellType::Integer) {
+            Value::Integer(rowid) => Some(rowid as u64),
+            _ => None,
+        }
+    });
+    if let Some(rowid) = rowid {
+        return match NonZeroU64::new(schema.rootpage) {
+            Some(pgno) => Ok(project(Search::new(
+                pgno,
+                None,
+                Some(vec![rowid]),
+                schema.to_owned(),
+                conds,
+            ))),
+            None => bail!("invalid index rootpage: {}", schema.rootpage),
+        };
+    }
+    let stats = db.statistics()?;
+    let table_pgno = match NonZeroU64::new(schema.rootpage) {
+        Some(pgno) => pgno,
+        None => bail!("invalid table rootpage: {}", schema.rootpage),
+    };
+    let intersect = find_index_intersection(
+        &conds,
+        &schema.name,
+        &source.table,
+        schemas,
+        &stats,
+        &db.collations,
+        Some(used),
+    );
+    if let Some(seeks) = intersect {
+        let seeks = seeks
+            .into_iter()
+            .map(
+                |(rootpage, key, collation)| match NonZeroU64::new(rootpage) {
+                    Some(pgno) => Ok((pgno, key, collation)),
+                    None => bail!("invalid index rootpage: {}", rootpage),
+                },
+            )
+            .collect::<Result<_>>()?;
+        let search = Search::new(table_pgno, None, None, schema.to_owned(), conds);
+        return Ok(project(search.with_intersection(seeks)));
+    }
+    let found = find_table_index(
+        &conds,
+        &schema.name,
+        &source.table,
+        schemas,
+        &stats,
+        &db.collations,
+        Some(used),
+        order_by,
+    );
+    let search = match found {
+        Some((table_index, search_key, collation, prefix)) => {
+            let pgno = match NonZeroU64::new(table_index) {
+                Some(pgno) => pgno,
+                None => bail!("invalid index rootpage: {}", schema.rootpage),
+            };
+            Search::new(pgno, Some(search_key), None, schema.to_owned(), conds)
+                .with_collation(&collation)
+                .with_prefix(prefix)
+        }
+        None => {
+            let range = find_rowid_range(&conds, &source.table);
+            Search::new(table_pgno, None, None, schema.to_owned(), conds).with_range(range)
+        }
+    };
+    Ok(project(search))
+}
+
+/// Replace names of result column aliases with the expressions they stand for. ORDER BY
+/// looks for an alias first, while other clauses only fall back to one when the tables have
+/// no column of that name.
+fn with_aliases(
+    expr: &Expr,
+    columns: &[ResultColumn],
+    from: &JoinedTables,
+    prefer_alias: bool,
+) -> Expr {
+    expr.rewrite(&mut |expr| match expr {
+        Expr::Column(name) if prefer_alias || !from.has_column(name) => columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+            .map(|c| c.expr.clone()),
+        _ => None,
+    })
+}
+
+/// Replace ORDER BY terms that are column numbers or aliases with the result column they
+/// refer to, and resolve the others against the tables.
+fn resolve_order_by(
+    terms: Vec<OrderingTerm>,
+    columns: &[ResultColumn],
+    from: &JoinedTables,
+) -> Result<Vec<OrderingTerm>> {
+    terms
+        .into_iter()
+        .enumerate()
+        .map(|(i, term)| match term.expr {
+            Expr::Literal(Value::Integer(n)) => match result_column(n, columns) {
+                Some(column) => Ok(OrderingTerm {
+                    expr: column.expr.clone(),
+                    ..term
+                }),
+                None => bail!(
+                    "{} ORDER BY term out of range - should be between 1 and {}",
+                    ordinal(i + 1),
+                    columns.len()
+                ),
+            },
+            _ => Ok(OrderingTerm {
+                expr: from.resolve(&with_aliases(&term.expr, columns, from, true))?,
+                ..term
+            }),
+        })
+        .collect()
+}
+
+/// The result column an integer ORDER BY or GROUP BY term names by its position, from 1.
+fn result_column(n: i64, columns: &[ResultColumn]) -> Option<&ResultColumn> {
+    let n = usize::try_from(n).ok()?;
+    columns.get(n.checked_sub(1)?)
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// Evaluate a LIMIT or OFFSET expression, which must be an integer. A negative LIMIT means
+/// no limit and a negative OFFSET is treated as zero.
+fn row_count(expr: Option<Expr>, db: &Database) -> Result<Option<i64>> {
+    let expr = match expr {
+        Some(expr) => expr,
+        None => return Ok(None),
+    };
+    match expr
+        .eval(&[], &HashMap::new(), &db.collations)?
+        .apply_affinity(CellType::Numeric)
+    {
+        Value::Integer(n) if n >= 0 => Ok(Some(n)),
+        Value::Integer(_) => Ok(None),
+        _ => bail!("datatype mismatch"),
+    }
+}
+
+/// Ascending keys comparing the values of `exprs` by their collating sequences.
+fn sort_keys(
+    exprs: &[Expr],
+    columns: &HashMap<String, Column>,
+    db: &Database,
+) -> Result<Vec<SortKey>> {
+    exprs
+        .iter()
+        .map(|expr| {
+            let name = expr.collation_name(columns);
+            match db.collations.get(name) {
+                Some(collation) => Ok(SortKey {
+                    desc: false,
+                    nulls_first: true,
+                    collation: collation.clone(),
+                }),
+                None => bail!("no such collation sequence: {}", name),
+            }
+        })
+        .collect()
+}
+
+/// Keys comparing the values of ORDER BY terms in the order the terms ask for.
+fn order_keys(
+    order_by: &[OrderingTerm],
+    columns: &HashMap<String, Column>,
+    db: &Database,
+) -> Result<Vec<SortKey>> {
+    let exprs: Vec<_> = order_by.iter().map(|term| term.expr.clone()).collect();
+    Ok(sort_keys(&exprs, columns, db)?
+        .into_iter()
+        .zip(order_by)
+        .map(|(key, term)| SortKey {
+            desc: term.desc,
+            nulls_first: term.nulls_first(),
+            ..key
+        })
+        .collect())
+}
+
+/// Sort rows by the ORDER BY terms, spilling to temporary files when they don't fit in memory.
+fn sort_rows(
+    rows: Vec<Row>,
+    order_by: &[OrderingTerm],
+    columns: &HashMap<String, Column>,
+    db: &Database,
+) -> Result<Vec<Row>> {
+    let keys = order_keys(order_by, columns, db)?;
+    let rows = Box::new(Values::new(rows.into_iter()));
+    operators::collect(Sort::new(
+        rows,
+        order_by.to_vec(),
+        keys,
+        columns,
+        &db.collations,
+    ))
+}
+
 pub fn start() -> Result<()> {
     let mut app = App::new();
     let mut input = String::new();
@@ -108,7 +2364,7 @@ pub fn start() -> Result<()> {
     while app.is_running {
         input.clear();
         next = false;
-        app.term.write("\n> ".as_bytes())?;
+        app.term.write_all("\n> ".as_bytes())?;
 
         while !next {
             match app.term.read_key()? {
@@ -128,11 +2384,9 @@ pub fn start() -> Result<()> {
                         app.write(&mut format!("> {input}"))?
                     };
                 }
-                Key::Char(key) => {
-                    if key != '\u{b}' {
-                        input.push(key);
-                        app.write(&mut input)?;
-                    }
+                Key::Char(key) if key != '\u{b}' => {
+                    input.push(key);
+                    app.write(&mut input)?;
                 }
                 _ => {}
             }
@@ -140,3 +2394,317 @@ pub fn start() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+fn select(db: &Database, sql: &str) -> Vec<Row> {
+    let stmt: Select = sql.parse().unwrap();
+    let query = prepare(&stmt, db, None, &[], Materialize::Rows).unwrap();
+    run_query(query, db).unwrap()
+}
+
+#[test]
+fn test_select_without_from() {
+    let db = crate::sqlite::db::test_database(&[]);
+    let ints = |ns: &[i64]| -> Vec<Row> { ns.iter().map(|&n| vec![Value::Integer(n)]).collect() };
+    assert_eq!(select(&db, "select 1 + 2"), ints(&[3]));
+    assert_eq!(select(&db, "select 1 where 0"), ints(&[]));
+    assert_eq!(
+        select(&db, "with recursive c(x) as (select 1 union all select x + 1 from c where x < 5) select x from c"),
+        ints(&[1, 2, 3, 4, 5])
+    );
+    assert_eq!(
+        select(&db, "with recursive c(x) as (values (1) union all select x * 2 from c where x < 8) select sum(x) from c"),
+        ints(&[15])
+    );
+    assert_eq!(
+        select(
+            &db,
+            "select column2 from (values (1, 'a'), (2, 'b')) where column1 = 2"
+        ),
+        vec![vec![Value::Text("b".to_string())]]
+    );
+}
+
+#[test]
+fn test_limit_zero() {
+    let db = crate::sqlite::db::test_database(&[(
+        "CREATE TABLE emp (id integer primary key, salary integer)",
+        vec![
+            vec![Value::Null, Value::Integer(10)],
+            vec![Value::Null, Value::Integer(20)],
+        ],
+    )]);
+    for sql in [
+        "select count(*) from emp limit 0",
+        "select max(salary) from emp limit 0",
+        "select salary from emp order by salary limit 0",
+    ] {
+        // Each compiles to bytecode, which halts before the aggregate or sorter makes a row
+        let query = prepare(&sql.parse().unwrap(), &db, None, &[], Materialize::Rows).unwrap();
+        assert!(compile(&query, &db).unwrap().is_some(), "{sql}");
+        assert_eq!(select(&db, sql), Vec::<Row>::new(), "{sql}");
+    }
+    assert_eq!(
+        select(&db, "select count(*) from emp limit 1"),
+        vec![vec![Value::Integer(2)]]
+    );
+}
+
+#[test]
+fn test_rowid_alias() {
+    let text = |s: &str| Value::Text(s.to_string());
+    let db = crate::sqlite::db::test_database(&[
+        (
+            "CREATE TABLE t (a integer, b text)",
+            vec![
+                vec![Value::Null, text("x")],
+                vec![Value::Integer(7), text("y")],
+            ],
+        ),
+        (
+            "CREATE TABLE u (name text, id integer primary key)",
+            vec![vec![text("x"), Value::Null], vec![text("y"), Value::Null]],
+        ),
+    ]);
+
+    // A NULL in a plain first column stays NULL, rather than reading as the rowid
+    assert_eq!(
+        select(&db, "select a, b from t"),
+        [
+            vec![Value::Null, text("x")],
+            vec![Value::Integer(7), text("y")]
+        ]
+    );
+    assert_eq!(
+        select(&db, "select b from t where a is null"),
+        [vec![text("x")]]
+    );
+    // The INTEGER PRIMARY KEY reads as the rowid wherever it is declared
+    assert_eq!(
+        select(&db, "select id, name from u"),
+        [
+            vec![Value::Integer(1), text("x")],
+            vec![Value::Integer(2), text("y")]
+        ]
+    );
+    assert_eq!(
+        select(&db, "select name, id from u where id = 2"),
+        [vec![text("y"), Value::Integer(2)]]
+    );
+}
+
+#[test]
+fn test_self_join() {
+    let int = |n: i64| Value::Integer(n);
+    let db = crate::sqlite::db::test_database(&[(
+        "CREATE TABLE t (id integer primary key, a integer)",
+        vec![
+            vec![Value::Null, int(1)],
+            vec![Value::Null, int(2)],
+            vec![Value::Null, Value::Null],
+        ],
+    )]);
+
+    // The second t has columns of its own, as if it had an alias, and rows with a NULL match none
+    assert_eq!(
+        select(&db, "select * from t natural join t"),
+        [vec![int(1), int(1)], vec![int(2), int(2)]]
+    );
+    assert_eq!(
+        select(&db, "select t.id from t join t using (id) where t.id = 2"),
+        [vec![int(2)]]
+    );
+    // Only the merged column can be told apart, as in sqlite
+    let stmt: Select = "select t.a from t join t using (id)".parse().unwrap();
+    let err = prepare(&stmt, &db, None, &[], Materialize::Rows)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("ambiguous column name: t.a"));
+    assert_eq!(select(&db, "select count(*) from t, t"), [vec![int(9)]]);
+}
+
+#[test]
+fn test_covering_index() {
+    let int = |n: i64| Value::Integer(n);
+    let db = crate::sqlite::db::test_database(&[
+        (
+            "CREATE TABLE t (id integer primary key, a integer, b integer, c text)",
+            [(1, 5), (2, 1), (1, 0), (1, 3)]
+                .into_iter()
+                .map(|(a, b)| vec![Value::Null, int(a), int(b), Value::Text("x".into())])
+                .collect(),
+        ),
+        (
+            "CREATE INDEX t_a ON t (a)",
+            [(1, 1), (1, 3), (1, 4), (2, 2)]
+                .into_iter()
+                .map(|(a, rowid)| vec![int(a), int(rowid)])
+                .collect(),
+        ),
+        (
+            "CREATE INDEX t_ab ON t (a, b)",
+            [(1, 0, 3), (1, 3, 4), (1, 5, 1), (2, 1, 2)]
+                .into_iter()
+                .map(|(a, b, rowid)| vec![int(a), int(b), int(rowid)])
+                .collect(),
+        ),
+    ]);
+    let plan = |sql: &str| {
+        let plan = explain(&sql.parse().unwrap(), &db, None, &[], &mut 0).unwrap();
+        render(&plan)
+    };
+
+    // Both indexes find the rows, but only one holds every column read
+    let sql = "select a, b from t where a = 1";
+    assert!(plan(sql).contains(&"`--SEARCH t USING COVERING INDEX t_ab (a=?)".to_string()));
+    assert_eq!(
+        select(&db, sql),
+        vec![
+            vec![int(1), int(0)],
+            vec![int(1), int(3)],
+            vec![int(1), int(5)]
+        ]
+    );
+
+    // Queries run by operators read the entries too
+    let sql = "select distinct a from t";
+    assert!(plan(sql).contains(&"`--SCAN t USING COVERING INDEX t_a".to_string()));
+    assert_eq!(select(&db, sql), vec![vec![int(1)], vec![int(2)]]);
+
+    // Entries of the same key come in the order of the other columns rather than of rowids
+    let sql = "select distinct id, b from t where a = 1 order by id desc";
+    assert!(plan(sql).contains(&"`--USE TEMP B-TREE FOR ORDER BY".to_string()));
+    assert_eq!(
+        select(&db, sql),
+        vec![
+            vec![int(4), int(3)],
+            vec![int(3), int(0)],
+            vec![int(1), int(5)]
+        ]
+    );
+}
+
+#[test]
+fn test_ordered_search() {
+    let int = |n: i64| Value::Integer(n);
+    let db = crate::sqlite::db::test_database(&[
+        (
+            "CREATE TABLE t (id integer primary key, a integer, b integer, c text)",
+            [(1, 5), (2, 1), (1, 0), (1, 3)]
+                .into_iter()
+                .map(|(a, b)| vec![Value::Null, int(a), int(b), Value::Text("x".into())])
+                .collect(),
+        ),
+        (
+            "CREATE INDEX t_a ON t (a)",
+            [(1, 1), (1, 3), (1, 4), (2, 2)]
+                .into_iter()
+                .map(|(a, rowid)| vec![int(a), int(rowid)])
+                .collect(),
+        ),
+        (
+            "CREATE INDEX t_ab ON t (a, b)",
+            [(1, 0, 3), (1, 3, 4), (1, 5, 1), (2, 1, 2)]
+                .into_iter()
+                .map(|(a, b, rowid)| vec![int(a), int(b), int(rowid)])
+                .collect(),
+        ),
+    ]);
+    let plan = |sql: &str| {
+        let plan = explain(&sql.parse().unwrap(), &db, None, &[], &mut 0).unwrap();
+        render(&plan)[1..].to_vec()
+    };
+    let ids = |sql: &str| -> Vec<Row> {
+        select(&db, sql)
+            .into_iter()
+            .map(|row| row[..1].to_vec())
+            .collect()
+    };
+
+    // The index whose entries come in the order asked for is searched, and nothing is sorted
+    let sql = "select * from t where a = 1 order by b";
+    assert_eq!(plan(sql), ["`--SEARCH t USING INDEX t_ab (a=?)"]);
+    assert_eq!(ids(sql), [vec![int(3)], vec![int(4)], vec![int(1)]]);
+    let sql = "select * from t where a = 1 order by b desc";
+    assert_eq!(plan(sql), ["`--SEARCH t USING INDEX t_ab (a=?)"]);
+    assert_eq!(ids(sql), [vec![int(1)], vec![int(4)], vec![int(3)]]);
+
+    // Bounds on the rowid alias only read the rows between them
+    let sql = "select id from t where id > 2";
+    assert_eq!(
+        plan(sql),
+        ["`--SEARCH t USING INTEGER PRIMARY KEY (rowid>?)"]
+    );
+    assert_eq!(select(&db, sql), [vec![int(3)], vec![int(4)]]);
+    let sql = "select id from t where id between 2 and 3 order by id desc";
+    assert_eq!(
+        plan(sql),
+        ["`--SEARCH t USING INTEGER PRIMARY KEY (rowid>? AND rowid<?)"]
+    );
+    assert_eq!(select(&db, sql), [vec![int(3)], vec![int(2)]]);
+}
+
+#[test]
+fn test_read_columns() {
+    let db = crate::sqlite::db::test_database(&[
+        (
+            "CREATE TABLE emp (id integer primary key, name text, dept text, salary integer)",
+            vec![],
+        ),
+        (
+            "CREATE TABLE projects (id integer primary key, emp_id integer, title text)",
+            vec![],
+        ),
+    ]);
+    let read = |sql: &str| -> Vec<Option<Vec<usize>>> {
+        let query = prepare(&sql.parse().unwrap(), &db, None, &[], Materialize::Rows).unwrap();
+        query.from.sources.iter().map(|s| s.read.clone()).collect()
+    };
+
+    assert_eq!(
+        read("select e.name from emp e join projects p on p.emp_id = e.id"),
+        vec![Some(vec![0, 1]), Some(vec![1])]
+    );
+    assert_eq!(
+        read("select dept, max(salary) from emp group by dept having count(*) > 1"),
+        vec![Some(vec![2, 3])]
+    );
+    // Subqueries may read any column of the row they run for that they name
+    assert_eq!(
+        read("select name from emp e where exists (select 1 from projects where emp_id = e.id)"),
+        vec![Some(vec![0, 1])]
+    );
+    assert_eq!(read("select * from projects"), vec![Some(vec![0, 1, 2])]);
+}
+
+#[test]
+fn test_group_by_position() {
+    let text = |s: &str| Value::Text(s.to_string());
+    let db = crate::sqlite::db::test_database(&[(
+        "CREATE TABLE emp (id integer primary key, dept text)",
+        ["eng", "sales", "eng"]
+            .into_iter()
+            .map(|dept| vec![Value::Null, text(dept)])
+            .collect(),
+    )]);
+    let by_name = select(&db, "select dept, count(*) from emp group by dept");
+    assert_eq!(by_name.len(), 2);
+    assert_eq!(
+        select(&db, "select dept, count(*) from emp group by 1"),
+        by_name
+    );
+
+    let error = |sql: &str| {
+        let stmt: Select = sql.parse().unwrap();
+        let result = prepare(&stmt, &db, None, &[], Materialize::Rows);
+        result.err().map(|e| e.to_string())
+    };
+    assert_eq!(
+        error("select dept, count(*) from emp group by 2").as_deref(),
+        Some("aggregate functions are not allowed in the GROUP BY clause")
+    );
+    assert_eq!(
+        error("select dept, count(*) from emp group by 3").as_deref(),
+        Some("1st GROUP BY term out of range - should be between 1 and 2")
+    );
+}
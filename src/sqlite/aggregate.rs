@@ -0,0 +1,383 @@
+use anyhow::{anyhow, bail, Result};
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::{
+    collation::{CollationFn, Collations, BINARY},
+    db::Row,
+    predicate::BoundExpr,
+    sorter::{SortKey, Sorter, SORTER_MEMORY_BUDGET},
+    tables::Column,
+};
+use crate::parsers::{
+    sql::Expr,
+    value::{parse_numeric, text_to_numeric, Value},
+};
+
+/// The aggregate functions, recognised by name and number of arguments.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AggregateKind {
+    /// `count(*)`, counting rows rather than non-NULL values.
+    CountRows,
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl AggregateKind {
+    /// The aggregate a call refers to, if any. With two or more arguments `min` and `max`
+    /// are the scalar functions instead.
+    pub fn from_call(name: &str, args: usize) -> Option<Self> {
+        let kind = match (name.to_ascii_lowercase().as_str(), args) {
+            ("count", 0) => AggregateKind::CountRows,
+            ("count", 1) => AggregateKind::Count,
+            ("sum", 1) => AggregateKind::Sum,
+            ("total", 1) => AggregateKind::Total,
+            ("avg", 1) => AggregateKind::Avg,
+            ("min", 1) => AggregateKind::Min,
+            ("max", 1) => AggregateKind::Max,
+            ("group_concat", 1 | 2) => AggregateKind::GroupConcat,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Real value of a number, or of text read as a number, for SUM, TOTAL and AVG.
+pub fn as_real(value: &Value) -> f64 {
+    match value {
+        Value::Integer(n) => *n as f64,
+        Value::Float(f) => *f,
+        Value::Text(s) => as_real(&text_to_numeric(s)),
+        Value::Blob(b) => as_real(&text_to_numeric(&String::from_utf8_lossy(b))),
+        Value::Null => 0.0,
+    }
+}
+
+/// Running state of one aggregate call over the rows of a group.
+pub struct Accumulator {
+    kind: AggregateKind,
+    collation: CollationFn,
+    /// Values already seen by a `DISTINCT` aggregate, kept sorted for lookups.
+    distinct: Option<Vec<Value>>,
+    count: i64,
+    int_sum: Option<i64>,
+    real_sum: f64,
+    best: Option<Value>,
+    concat: Option<String>,
+}
+
+impl Accumulator {
+    /// Start an aggregate, comparing text by `collation` for MIN, MAX and DISTINCT.
+    pub fn new(kind: AggregateKind, distinct: bool, collation: CollationFn) -> Self {
+        Accumulator {
+            kind,
+            collation,
+            distinct: distinct.then(Vec::new),
+            count: 0,
+            int_sum: Some(0),
+            real_sum: 0.0,
+            best: None,
+            concat: None,
+        }
+    }
+
+    /// Feed the argument values of the call for one row, returning whether they became the
+    /// new result of a MIN or MAX.
+    pub fn step(&mut self, args: &[Value]) -> Result<bool> {
+        if self.kind == AggregateKind::CountRows {
+            self.count += 1;
+            return Ok(false);
+        }
+        let value = match args.first() {
+            Some(Value::Null) | None => return Ok(false),
+            Some(value) => value,
+        };
+        if let Some(seen) = &mut self.distinct {
+            let collation = self.collation.as_ref();
+            match seen.binary_search_by(|v| v.compare_collated(value, collation)) {
+                Ok(_) => return Ok(false),
+                Err(pos) => seen.insert(pos, value.clone()),
+            }
+        }
+        self.count += 1;
+
+        match self.kind {
+            AggregateKind::Sum | AggregateKind::Total | AggregateKind::Avg => {
+                let number = match value {
+                    Value::Text(s) => parse_numeric(s),
+                    value => Some(value.clone()),
+                };
+                match number {
+                    Some(Value::Integer(n)) => {
+                        if let Some(sum) = self.int_sum {
+                            match sum.checked_add(n) {
+                                Some(sum) => self.int_sum = Some(sum),
+                                None if self.kind == AggregateKind::Sum => {
+                                    bail!("integer overflow")
+                                }
+                                None => self.int_sum = None,
+                            }
+                        }
+                    }
+                    // Once anything but an integer is added the sum is approximate
+                    _ => self.int_sum = None,
+                }
+                self.real_sum += as_real(value);
+            }
+            AggregateKind::Min | AggregateKind::Max => {
+                let replace = match &self.best {
+                    Some(best) => {
+                        let ord = value.compare_collated(best, self.collation.as_ref());
+                        match self.kind {
+                            AggregateKind::Min => ord == Ordering::Less,
+                            _ => ord == Ordering::Greater,
+                        }
+                    }
+                    None => true,
+                };
+                if replace {
+                    self.best = Some(value.clone());
+                }
+                return Ok(replace);
+            }
+            AggregateKind::GroupConcat => {
+                let text = match value {
+                    Value::Blob(b) => String::from_utf8_lossy(b).to_string(),
+                    value => value.to_string(),
+                };
+                match &mut self.concat {
+                    Some(concat) => {
+                        let separator = match args.get(1) {
+                            Some(Value::Null) => String::new(),
+                            Some(separator) => separator.to_string(),
+                            None => ",".to_string(),
+                        };
+                        concat.push_str(&separator);
+                        concat.push_str(&text);
+                    }
+                    None => self.concat = Some(text),
+                }
+            }
+            AggregateKind::CountRows | AggregateKind::Count => {}
+        }
+        Ok(false)
+    }
+
+    /// The aggregate's value over every row stepped so far.
+    pub fn finish(&self) -> Value {
+        match self.kind {
+            AggregateKind::CountRows | AggregateKind::Count => Value::Integer(self.count),
+            AggregateKind::Sum if self.count == 0 => Value::Null,
+            AggregateKind::Sum => match self.int_sum {
+                Some(sum) => Value::Integer(sum),
+                None => Value::Float(self.real_sum),
+            },
+            AggregateKind::Total => Value::Float(self.real_sum),
+            AggregateKind::Avg if self.count == 0 => Value::Null,
+            AggregateKind::Avg => Value::Float(self.real_sum / self.count as f64),
+            AggregateKind::Min | AggregateKind::Max => self.best.clone().unwrap_or(Value::Null),
+            AggregateKind::GroupConcat => self.concat.clone().map_or(Value::Null, Value::Text),
+        }
+    }
+}
+
+/// Group `rows` by the values of `group_by` and compute the aggregate `calls` over each group.
+///
+/// Groups are formed by sorting on their keys, so they come out in key order. Each output row
+/// is the last row of its group, padded to the table's `columns`, followed by the result of
+/// every call. Like SQLite, when the only MIN or MAX is a single call the row it picked is used
+/// instead, so bare columns describe it. Without GROUP BY all rows form a single group, even
+/// when there are none.
+pub fn group_rows(
+    rows: impl Iterator<Item = Result<Row>>,
+    group_by: &[Expr],
+    calls: &[Expr],
+    columns: &HashMap<String, Column>,
+    collations: &Collations,
+) -> Result<Vec<Row>> {
+    let collation = |expr: Option<&Expr>| {
+        let name = expr.map_or(BINARY, |expr| expr.collation_name(columns));
+        collations
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no such collation sequence: {}", name))
+    };
+    let keys = group_by
+        .iter()
+        .map(|expr| {
+            Ok(SortKey {
+                desc: false,
+                nulls_first: true,
+                collation: collation(Some(expr))?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let accumulators = || {
+        calls
+            .iter()
+            .map(|call| match (call, call.aggregate()) {
+                (Expr::Function { args, distinct, .. }, Some(kind)) => {
+                    Ok(Accumulator::new(kind, *distinct, collation(args.first())?))
+                }
+                _ => bail!("not an aggregate: {:?}", call),
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    // The keys and arguments are bound once, rather than looked up by name for every row
+    let bind = |expr: &Expr| BoundExpr::new(expr, columns, collations);
+    let group_key: Vec<_> = group_by.iter().map(bind).collect();
+    let call_args: Vec<Vec<_>> = calls
+        .iter()
+        .map(|call| match call {
+            Expr::Function { args, .. } => args.iter().map(bind).collect(),
+            _ => vec![],
+        })
+        .collect();
+    let key_of =
+        |row: &Row| -> Result<Vec<Value>> { group_key.iter().map(|expr| expr.eval(row)).collect() };
+    let finish = |mut row: Row, accumulators: Vec<Accumulator>| {
+        row.resize(columns.len(), Value::Null);
+        row.extend(accumulators.iter().map(Accumulator::finish));
+        row
+    };
+
+    let sorted: Box<dyn Iterator<Item = Result<Row>>> = if group_by.is_empty() {
+        Box::new(rows)
+    } else {
+        let mut sorter = Sorter::new(keys.clone(), SORTER_MEMORY_BUDGET);
+        for row in rows {
+            let row = row?;
+            sorter.push(key_of(&row)?, row)?;
+        }
+        Box::new(sorter.finish()?)
+    };
+
+    let extremes: Vec<_> = calls
+        .iter()
+        .enumerate()
+        .filter(|(_, call)| {
+            matches!(
+                call.aggregate(),
+                Some(AggregateKind::Min | AggregateKind::Max)
+            )
+        })
+        .collect();
+    let picking_call = match extremes[..] {
+        [(n, _)] => Some(n),
+        _ => None,
+    };
+
+    let mut groups = vec![];
+    let mut current: Option<(Vec<Value>, Row, Vec<Accumulator>)> = None;
+    for row in sorted {
+        let row = row?;
+        let key = key_of(&row)?;
+        let same_group = current.as_ref().is_some_and(|(current_key, _, _)| {
+            keys.iter()
+                .zip(current_key.iter().zip(&key))
+                .all(|(sort_key, (a, b))| sort_key.compare(a, b).is_eq())
+        });
+        if !same_group {
+            if let Some((_, last, accs)) = current.take() {
+                groups.push(finish(last, accs));
+            }
+            current = Some((key, vec![], accumulators()?));
+        }
+
+        if let Some((_, last, accs)) = current.as_mut() {
+            let mut picked = false;
+            for (n, (acc, args)) in accs.iter_mut().zip(&call_args).enumerate() {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(&row))
+                    .collect::<Result<Vec<_>>>()?;
+                let improved = acc.step(&args)?;
+                picked |= improved && picking_call == Some(n);
+            }
+            if picked || picking_call.is_none() || last.is_empty() {
+                *last = row;
+            }
+        }
+    }
+
+    match current {
+        Some((_, last, accs)) => groups.push(finish(last, accs)),
+        None if group_by.is_empty() => groups.push(finish(vec![], accumulators()?)),
+        None => {}
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+fn aggregate(name: &str, distinct: bool, values: &[Value]) -> Result<Value> {
+    use super::collation::{Collations, BINARY};
+    let kind = AggregateKind::from_call(name, 1).unwrap();
+    let binary = Collations::default().get(BINARY).unwrap().clone();
+    let mut acc = Accumulator::new(kind, distinct, binary);
+    for value in values {
+        acc.step(std::slice::from_ref(value))?;
+    }
+    Ok(acc.finish())
+}
+
+#[test]
+fn test_aggregates() {
+    use Value::*;
+    let values = [
+        Integer(3),
+        Null,
+        Integer(1),
+        Integer(3),
+        Text("2".to_string()),
+    ];
+    assert_eq!(aggregate("count", false, &values).unwrap(), Integer(4));
+    assert_eq!(aggregate("count", true, &values).unwrap(), Integer(3));
+    assert_eq!(aggregate("sum", false, &values).unwrap(), Integer(9));
+    assert_eq!(aggregate("sum", true, &values).unwrap(), Integer(6));
+    assert_eq!(aggregate("total", false, &values).unwrap(), Float(9.0));
+    assert_eq!(aggregate("avg", false, &values).unwrap(), Float(2.25));
+    assert_eq!(aggregate("min", false, &values).unwrap(), Integer(1));
+    assert_eq!(
+        aggregate("max", false, &values).unwrap(),
+        Text("2".to_string())
+    );
+    assert_eq!(
+        aggregate("group_concat", false, &values).unwrap(),
+        Text("3,1,3,2".to_string())
+    );
+}
+
+#[test]
+fn test_aggregates_over_nothing() {
+    use Value::*;
+    let values = [Null, Null];
+    assert_eq!(aggregate("count", false, &values).unwrap(), Integer(0));
+    assert_eq!(aggregate("sum", false, &values).unwrap(), Null);
+    assert_eq!(aggregate("total", false, &values).unwrap(), Float(0.0));
+    assert_eq!(aggregate("avg", false, &values).unwrap(), Null);
+    assert_eq!(aggregate("max", false, &values).unwrap(), Null);
+    assert_eq!(aggregate("group_concat", false, &values).unwrap(), Null);
+}
+
+#[test]
+fn test_sum_overflow_and_reals() {
+    use Value::*;
+    assert!(aggregate("sum", false, &[Integer(i64::MAX), Integer(1)]).is_err());
+    assert_eq!(
+        aggregate("total", false, &[Integer(i64::MAX), Integer(1)]).unwrap(),
+        Float(9.223372036854776e18)
+    );
+    assert_eq!(
+        aggregate(
+            "sum",
+            false,
+            &[Integer(1), Float(0.5), Text("x".to_string())]
+        )
+        .unwrap(),
+        Float(1.5)
+    );
+}
@@ -0,0 +1,84 @@
+use std::{cmp::Ordering, collections::HashMap, fmt, rc::Rc};
+
+pub type CollationFn = Rc<dyn Fn(&str, &str) -> Ordering>;
+
+/// Name of the collating sequence used when neither operand asks for another one.
+pub const BINARY: &str = "BINARY";
+
+/// Registry of collating sequences available to queries, keyed by case-insensitive name.
+#[derive(Clone)]
+pub struct Collations {
+    sequences: HashMap<String, CollationFn>,
+}
+
+impl Collations {
+    /// Register an application-defined collation, replacing any previous one with that name.
+    pub fn register<F>(&mut self, name: &str, cmp: F)
+    where
+        F: Fn(&str, &str) -> Ordering + 'static,
+    {
+        self.sequences.insert(name.to_uppercase(), Rc::new(cmp));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CollationFn> {
+        self.sequences.get(&name.to_uppercase())
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<_> = self.sequences.keys().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Collations {
+    fn default() -> Self {
+        let mut collations = Collations {
+            sequences: HashMap::new(),
+        };
+        collations.register(BINARY, |a, b| a.as_bytes().cmp(b.as_bytes()));
+        collations.register("NOCASE", nocase);
+        collations.register("RTRIM", |a, b| {
+            a.trim_end_matches(' ').cmp(b.trim_end_matches(' '))
+        });
+        collations
+    }
+}
+
+impl fmt::Debug for Collations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Compare text folding only the 26 ASCII letters, exactly like SQLite's NOCASE.
+fn nocase(a: &str, b: &str) -> Ordering {
+    let fold = |s: &str| {
+        s.bytes()
+            .map(|b| b.to_ascii_lowercase())
+            .collect::<Vec<_>>()
+    };
+    fold(a).cmp(&fold(b))
+}
+
+#[test]
+fn test_builtin_collations() {
+    let collations = Collations::default();
+    let cmp = |name: &str, a: &str, b: &str| collations.get(name).unwrap()(a, b);
+    assert_eq!(cmp("binary", "a", "B"), Ordering::Greater);
+    assert_eq!(cmp("NOCASE", "Apple", "aPPLE"), Ordering::Equal);
+    assert_eq!(cmp("nocase", "É", "é"), Ordering::Less);
+    assert_eq!(cmp("RTRIM", "red  ", "red"), Ordering::Equal);
+    assert_eq!(cmp("RTRIM", " red", "red"), Ordering::Less);
+}
+
+#[test]
+fn test_register_collation() {
+    let mut collations = Collations::default();
+    collations.register("reverse", |a, b| b.cmp(a));
+    assert_eq!(
+        collations.get("REVERSE").unwrap()("a", "b"),
+        Ordering::Greater
+    );
+    assert!(collations.get("missing").is_none());
+}
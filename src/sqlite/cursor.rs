@@ -0,0 +1,329 @@
+use anyhow::{bail, Result};
+use std::{cmp::Ordering, num::NonZeroU64, ops::RangeBounds, rc::Rc};
+
+use super::{
+    db::{apply_reals, Database, Row, Search},
+    pages::{Page, PageKind},
+    predicate::Predicate,
+};
+use crate::parsers::{
+    cells::{alias_rowid, Cell},
+    value::Value,
+};
+
+/// Steps to take through an interior page, left to right or right to left when `reverse`,
+/// skipping children that aren't `wanted`. The rightmost child is asked about as `None`.
+fn child_pages(
+    page: &Page,
+    reverse: bool,
+    mut wanted: impl FnMut(Option<&Cell>) -> bool,
+) -> Vec<Step> {
+    let mut steps = vec![];
+    for cell in page.cells() {
+        if let Some(child) = cell.next_page().filter(|_| wanted(Some(&cell))) {
+            steps.push(Step::Page(child));
+        }
+        // Interior index cells hold an entry that sorts after their left child
+        if let Cell::IndexInterior { .. } = cell {
+            if let Ok(entry) = cell.try_into() {
+                steps.push(Step::Entry(entry));
+            }
+        }
+    }
+    steps.extend(
+        page.header
+            .rightmost_pointer
+            .and_then(|pgno| NonZeroU64::new(pgno.into()))
+            .filter(|_| wanted(None))
+            .map(Step::Page),
+    );
+    // The stack pops from the end, so the first step to take goes last
+    if !reverse {
+        steps.reverse();
+    }
+    steps
+}
+
+#[derive(Debug)]
+enum Step {
+    Page(NonZeroU64),
+    Entry(Row),
+}
+
+/// Lazily walks a table b-tree in rowid order, yielding the rows that satisfy a [`Search`]
+/// and reading pages only as rows are asked for.
+pub struct TableScan<'db> {
+    db: &'db Database,
+    search: Search,
+    /// The search conditions, bound to the table's columns before the first row is read.
+    filter: Predicate,
+    /// Columns the filter reads, decoded first so that rows it rejects decode nothing else.
+    filtered: Vec<usize>,
+    /// The other columns the search reads, decoded for rows the filter keeps. Every other
+    /// column is when `None`.
+    projected: Option<Vec<usize>>,
+    /// Positions of the table's REAL columns.
+    reals: Rc<[usize]>,
+    /// Position of the table's INTEGER PRIMARY KEY column, read as the rowid.
+    alias: Option<usize>,
+    reverse: bool,
+    pending: Vec<Step>,
+    rows: std::vec::IntoIter<Row>,
+}
+
+impl<'db> TableScan<'db> {
+    /// Scan from the search page, in descending rowid order when `reverse`.
+    pub fn new(db: &'db Database, search: Search, reverse: bool) -> Self {
+        let filter = Predicate::for_search(&search, &db.collations);
+        TableScan::with_filter(db, search, filter, reverse)
+    }
+
+    /// Scan like [`TableScan::new`], keeping the rows that satisfy `filter`, the conditions
+    /// of the search already bound to the table's columns.
+    pub fn with_filter(
+        db: &'db Database,
+        search: Search,
+        filter: Predicate,
+        reverse: bool,
+    ) -> Self {
+        let filtered = filter.columns();
+        let projected = search.columns.as_ref().map(|columns| {
+            columns
+                .iter()
+                .copied()
+                .filter(|idx| filtered.binary_search(idx).is_err())
+                .collect()
+        });
+        TableScan {
+            db,
+            pending: vec![Step::Page(search.pgno)],
+            filter,
+            filtered,
+            projected,
+            reals: db.real_columns(search.pgno),
+            alias: db.rowid_alias(search.pgno),
+            search,
+            reverse,
+            rows: vec![].into_iter(),
+        }
+    }
+
+    fn visit(&mut self, page: Page) -> Result<()> {
+        match page.header.kind {
+            PageKind::TableInterior => {
+                // Every rowid in a left child is greater than the previous cell's key
+                let mut lower_bound = None;
+                let search = &self.search;
+                let steps = child_pages(&page, self.reverse, |cell| match cell {
+                    Some(Cell::TableInterior { row_id, .. }) => {
+                        let wanted = search.wants_rowids(lower_bound, Some(*row_id));
+                        lower_bound = Some(*row_id);
+                        wanted
+                    }
+                    Some(_) => false,
+                    None => search.wants_rowids(lower_bound, None),
+                });
+                self.pending.extend(steps);
+            }
+            PageKind::TableLeaf => {
+                let mut rows: Vec<Row> = vec![];
+                for cell in page.cells() {
+                    let Cell::TableLeaf { row_id, .. } = cell else {
+                        continue;
+                    };
+                    if !self.search.range.contains(&(row_id as i64)) {
+                        continue;
+                    }
+                    // Check if it matches searched index
+                    if let Some(indeces) = &self.search.indeces {
+                        if indeces.binary_search(&row_id).is_err() {
+                            continue;
+                        }
+                    }
+                    let record = cell.record()?;
+                    let mut row = record.row(Some(&self.filtered));
+                    alias_rowid(&mut row, row_id, self.alias);
+                    apply_reals(&mut row, &self.reals);
+                    if !self.filter.matches(&row)? {
+                        continue;
+                    }
+                    match &self.projected {
+                        Some(columns) => record.decode(columns.iter().copied(), &mut row),
+                        None => record.decode(
+                            (0..record.len())
+                                .filter(|idx| self.filtered.binary_search(idx).is_err()),
+                            &mut row,
+                        ),
+                    }
+                    alias_rowid(&mut row, row_id, self.alias);
+                    apply_reals(&mut row, &self.reals);
+                    rows.push(row);
+                }
+                if self.reverse {
+                    rows.reverse();
+                }
+                self.rows = rows.into_iter();
+            }
+            // Indexes are read by their own scans
+            PageKind::IndexInterior | PageKind::IndexLeaf => {}
+        }
+        Ok(())
+    }
+}
+
+/// Rows that can't be read, or that sit on pages that can't be, end the scan with the error.
+impl<'db> Iterator for TableScan<'db> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row));
+            }
+            let pgno = match self.pending.pop()? {
+                Step::Page(pgno) => pgno,
+                Step::Entry(_) => continue,
+            };
+            let visited = self.db.get_page(pgno).and_then(|page| self.visit(page));
+            if let Err(e) = visited {
+                self.pending.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Lazily walks an index b-tree in key order, yielding each entry, whose last column is the
+/// rowid of its row.
+pub struct IndexScan<'db> {
+    db: &'db Database,
+    /// Positions of the index columns holding a REAL table column.
+    reals: Rc<[usize]>,
+    reverse: bool,
+    pending: Vec<Step>,
+}
+
+impl<'db> IndexScan<'db> {
+    /// Scan the whole index rooted at `pgno`, in descending key order when `reverse`.
+    pub fn new(db: &'db Database, pgno: NonZeroU64, reverse: bool) -> Self {
+        IndexScan {
+            db,
+            reals: db.real_columns(pgno),
+            reverse,
+            pending: vec![Step::Page(pgno)],
+        }
+    }
+
+    /// Scan the index rooted at `pgno` in key order from the first entry whose first column
+    /// is not below `key`, with keys ordered by the `collation` the index was built with.
+    pub fn seek(
+        db: &'db Database,
+        pgno: NonZeroU64,
+        key: &Value,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> Result<Self> {
+        let mut pending = vec![];
+        let mut page = db.get_page(pgno)?;
+        loop {
+            let cells: Vec<_> = page
+                .cells()
+                .map(|cell| (cell.next_page(), Row::try_from(cell).ok()))
+                .collect();
+            let first = cells.iter().position(|(_, entry)| {
+                entry
+                    .as_ref()
+                    .and_then(|entry| entry.first())
+                    .is_some_and(|k| k.compare_collated(key, collation).is_ge())
+            });
+            let from = first.unwrap_or(cells.len());
+            match page.header.kind {
+                PageKind::IndexInterior => {
+                    // Descend into the left child of the first entry not below the key, and
+                    // take every step to its right once that child is read
+                    let rightmost = page
+                        .header
+                        .rightmost_pointer
+                        .and_then(|pgno| NonZeroU64::new(pgno.into()));
+                    let child = match first {
+                        Some(first) => cells[first].0,
+                        None => rightmost,
+                    };
+                    let mut steps = vec![];
+                    for (i, (child, entry)) in cells.into_iter().skip(from).enumerate() {
+                        if i > 0 {
+                            steps.extend(child.map(Step::Page));
+                        }
+                        steps.extend(entry.map(Step::Entry));
+                    }
+                    if first.is_some() {
+                        steps.extend(rightmost.map(Step::Page));
+                    }
+                    pending.extend(steps.into_iter().rev());
+                    match child {
+                        Some(child) => page = db.get_page(child)?,
+                        None => break,
+                    }
+                }
+                PageKind::IndexLeaf => {
+                    let entries = cells.into_iter().skip(from).rev();
+                    pending.extend(entries.flat_map(|(_, entry)| entry.map(Step::Entry)));
+                    break;
+                }
+                _ => bail!("page {} is not an index page", page.page_id),
+            }
+        }
+        Ok(IndexScan {
+            db,
+            reals: db.real_columns(pgno),
+            reverse: false,
+            pending,
+        })
+    }
+}
+
+/// Entries that can't be read, or that sit on pages that can't be, end the scan with the
+/// error.
+impl<'db> Iterator for IndexScan<'db> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pgno = match self.pending.pop()? {
+                Step::Entry(mut entry) => {
+                    apply_reals(&mut entry, &self.reals);
+                    return Some(Ok(entry));
+                }
+                Step::Page(pgno) => pgno,
+            };
+            let page = match self.db.get_page(pgno) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.pending.clear();
+                    return Some(Err(e));
+                }
+            };
+            match page.header.kind {
+                PageKind::IndexInterior => {
+                    self.pending
+                        .extend(child_pages(&page, self.reverse, |_| true));
+                }
+                PageKind::IndexLeaf => {
+                    let entries: Result<Vec<Row>> =
+                        page.cells().map(|cell| cell.try_into()).collect();
+                    let mut entries: Vec<_> = match entries {
+                        Ok(entries) => entries.into_iter().map(Step::Entry).collect(),
+                        Err(e) => {
+                            self.pending.clear();
+                            return Some(Err(e));
+                        }
+                    };
+                    if !self.reverse {
+                        entries.reverse();
+                    }
+                    self.pending.extend(entries);
+                }
+                _ => {}
+            }
+        }
+    }
+}
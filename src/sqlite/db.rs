@@ -2,63 +2,156 @@ use anyhow::{anyhow, Result};
 use core::fmt;
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     collections::HashMap,
     fs::File,
     io::{Read, Seek, SeekFrom},
     num::NonZeroU64,
+    ops::Bound,
+    rc::Rc,
 };
 
 use super::{
+    collation::{Collations, BINARY},
     db_header::DatabaseHeader,
+    indexes::Index,
     pages::{BtreeHeader, Page, PageKind},
-    schemas::Schema,
-    tables::Table,
+    schemas::{Schema, SchemaType},
+    stats::{Analysis, Statistics},
+    tables::{CellType, Column, Table},
 };
-use crate::parsers::{cells::Cell, sql::Condition, value::Value};
+use crate::parsers::{cells::Cell, sql::Expr, value::Value};
 
 pub type Row = Vec<Value>;
 
-#[derive(Debug)]
+/// Read the whole numbers stored in the REAL columns at `reals` back as reals, as SQLite
+/// does when it reads a column with REAL affinity.
+pub fn apply_reals(row: &mut [Value], reals: &[usize]) {
+    for &idx in reals {
+        if let Some(Value::Integer(n)) = row.get(idx) {
+            row[idx] = Value::Float(*n as f64);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Search {
     pub pgno: NonZeroU64,
-    pub key: Option<String>,
+    pub key: Option<Value>,
+    /// Collating sequence the index orders `key` by.
+    pub collation: String,
+    /// Keys the index columns after the first are equal to, each with its collation, which
+    /// narrow the entries found under `key` before their rows are read.
+    pub prefix: Vec<(Value, String)>,
     pub indeces: Option<Vec<u64>>,
+    /// Bounds of the rowids of the rows searched for, compared as the signed integers they
+    /// are, which keep the table b-tree from being read outside of them.
+    pub range: (Bound<i64>, Bound<i64>),
     pub schema: Schema,
-    pub conds: Vec<Condition>,
+    pub conds: Vec<Expr>,
+    /// Keys of several indexes, each with the rootpage and collation of its index, whose
+    /// rowids are intersected to find the rows. Empty unless indexes are searched together.
+    pub intersect: Vec<(NonZeroU64, Value, String)>,
+    /// Ordinals of the columns read from the rows found, the only ones decoded besides those
+    /// the conditions need. Every column is when `None`.
+    pub columns: Option<Vec<usize>>,
 }
 
 impl Search {
     pub fn new(
         pgno: NonZeroU64,
-        key: Option<String>,
+        key: Option<Value>,
         indeces: Option<Vec<u64>>,
         schema: Schema,
-        conds: Vec<Condition>,
+        conds: Vec<Expr>,
     ) -> Self {
         Search {
             pgno,
             key,
+            collation: BINARY.to_string(),
+            prefix: vec![],
             indeces,
+            range: (Bound::Unbounded, Bound::Unbounded),
             schema,
             conds,
+            intersect: vec![],
+            columns: None,
         }
     }
-    pub fn next_page(&self, pgno: NonZeroU64) -> Self {
+
+    pub fn with_collation(self, collation: &str) -> Self {
         Self {
-            pgno,
-            key: self.key.clone(),
-            indeces: self.indeces.clone(),
-            schema: self.schema.clone(),
-            conds: self.conds.clone(),
+            collation: collation.to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_prefix(self, prefix: Vec<(Value, String)>) -> Self {
+        Self { prefix, ..self }
+    }
+
+    pub fn with_intersection(self, intersect: Vec<(NonZeroU64, Value, String)>) -> Self {
+        Self { intersect, ..self }
+    }
+
+    pub fn with_range(self, range: (Bound<i64>, Bound<i64>)) -> Self {
+        Self { range, ..self }
+    }
+
+    /// Whether the search only reads the rows of a range of rowids.
+    pub fn has_range(&self) -> bool {
+        self.range != (Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn with_columns(self, columns: Vec<usize>) -> Self {
+        Self {
+            columns: Some(columns),
+            ..self
+        }
+    }
+
+    /// Whether the rowids in `(lower, upper]` may contain a row this search is looking for.
+    pub fn wants_rowids(&self, lower: Option<u64>, upper: Option<u64>) -> bool {
+        let (lower_signed, upper_signed) = (lower.map(|l| l as i64), upper.map(|u| u as i64));
+        let reaches_start = match (self.range.0, upper_signed) {
+            (Bound::Included(start), Some(upper)) => start <= upper,
+            (Bound::Excluded(start), Some(upper)) => start < upper,
+            _ => true,
+        };
+        let reaches_end = match (self.range.1, lower_signed) {
+            (Bound::Included(end), Some(lower)) => end > lower,
+            (Bound::Excluded(end), Some(lower)) => lower.checked_add(1).is_some_and(|l| end > l),
+            _ => true,
+        };
+        if !reaches_start || !reaches_end {
+            return false;
+        }
+        match &self.indeces {
+            Some(indeces) => {
+                let first = indeces.partition_point(|&id| lower.is_some_and(|lower| id <= lower));
+                match (indeces.get(first), upper) {
+                    (Some(&id), Some(upper)) => id <= upper,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            }
+            None => true,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Database {
+    pub collations: Collations,
     file: RefCell<File>,
     header: DatabaseHeader,
     first_page: Page,
+    /// Statistics for the planner, read from `sqlite_stat1` when first asked for.
+    statistics: RefCell<Option<Rc<Statistics>>>,
+    /// REAL column positions of the b-trees read so far, by rootpage.
+    reals: RefCell<HashMap<NonZeroU64, Rc<[usize]>>>,
+    /// Position of the INTEGER PRIMARY KEY column of the tables read so far, by rootpage.
+    aliases: RefCell<HashMap<NonZeroU64, Option<usize>>>,
 }
 
 impl Database {
@@ -77,6 +170,7 @@ impl Database {
             .map_err(|e| anyhow!("parsing btree header at Database::new:\n{}", e))?;
 
         Ok(Self {
+            collations: Collations::default(),
             file: RefCell::new(file),
             header: db_header,
             first_page: Page {
@@ -84,25 +178,43 @@ impl Database {
                 data,
                 header,
             },
+            statistics: RefCell::new(None),
+            reals: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(HashMap::new()),
         })
     }
 
+    /// The statistics `ANALYZE` left for the planner, empty when it was never run.
+    pub fn statistics(&self) -> Result<Rc<Statistics>> {
+        if let Some(stats) = self.statistics.borrow().as_ref() {
+            return Ok(stats.clone());
+        }
+        let stats = Rc::new(Statistics::load(self)?);
+        *self.statistics.borrow_mut() = Some(stats.clone());
+        Ok(stats)
+    }
+
+    /// Keep the statistics of `ANALYZE` for the rest of the session. The file is opened
+    /// read-only, so they are held in memory rather than written to `sqlite_stat1`.
+    pub fn set_statistics(&self, analysis: &Analysis) -> Result<()> {
+        let mut stats = Statistics::clone(&*self.statistics()?);
+        stats.extend(analysis);
+        *self.statistics.borrow_mut() = Some(Rc::new(stats));
+        Ok(())
+    }
+
     pub fn get_page(&self, pgno: NonZeroU64) -> Result<Page> {
         let pgno = pgno.get();
         let mut data = vec![0u8; self.header.page_size as usize];
-        self.file
-            .borrow_mut()
-            .seek(SeekFrom::Start(
-                ((pgno - 1) * self.header.page_size as u64) as u64,
-            ))
-            .unwrap();
-        self.file.borrow_mut().read_exact(&mut data[..]).unwrap();
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((pgno - 1) * self.header.page_size as u64))?;
+        file.read_exact(&mut data[..])
+            .map_err(|e| anyhow!("reading page {}: {}", pgno, e))?;
 
         let hdata = if pgno == 1 { &data[100..] } else { &data[..] };
 
         let (_, header) = BtreeHeader::new(hdata)
-            .map_err(|e| anyhow!("parsing header at Database::get_page:\n{}", e))
-            .unwrap();
+            .map_err(|e| anyhow!("parsing header at Database::get_page:\n{}", e))?;
 
         Ok(Page {
             page_id: pgno,
@@ -111,166 +223,171 @@ impl Database {
         })
     }
 
-    pub fn rows(&self, search: Search) -> Vec<Row> {
-        match self.get_page(search.pgno) {
-            Ok(page) => match &page.header.kind {
-                PageKind::TableInterior => {
-                    let mut rows: Vec<Row> = vec![];
-                    for cell in page.cells() {
-                        let cell_rows = match cell.next_page().map(|pgno| search.next_page(pgno)) {
-                            Some(next_search) => {
-                                if let Some(indeces) = &search.indeces {
-                                    if let Cell::TableInterior { row_id, .. } = cell {
-                                        if indeces.binary_search(&row_id).is_ok() {
-                                            return self.rows(next_search);
-                                        }
-                                    }
-                                }
-                                self.rows(next_search)
-                            }
-                            None => vec![],
-                        };
-                        rows.extend(cell_rows);
-                    }
+    /// Positions of the REAL columns in the records of the b-tree rooted at `pgno`, whose
+    /// whole numbers SQLite stores as integers.
+    pub fn real_columns(&self, pgno: NonZeroU64) -> Rc<[usize]> {
+        if let Some(reals) = self.reals.borrow().get(&pgno) {
+            return reals.clone();
+        }
+        let schemas = self.get_schemas_vec();
+        let table = |name: &str| {
+            schemas
+                .iter()
+                .find(|s| s.stype == SchemaType::Table && s.name == name)
+                .and_then(|s| Table::try_from(s).ok())
+        };
+        let is_real = |column: &Column| column.cell_type == CellType::Float;
+        let reals: Rc<[usize]> = match schemas.iter().find(|s| s.rootpage == pgno.get()) {
+            Some(schema) if schema.stype == SchemaType::Table => table(&schema.name)
+                .map(|table| {
+                    let mut reals: Vec<_> = table
+                        .columns
+                        .values()
+                        .filter(|c| is_real(c))
+                        .map(|c| c.idx)
+                        .collect();
+                    reals.sort_unstable();
+                    reals
+                })
+                .unwrap_or_default()
+                .into(),
+            Some(schema) if schema.stype == SchemaType::Index => {
+                match (Index::try_from(schema), table(&schema.table_name)) {
+                    (Ok(index), Some(table)) => index
+                        .columns
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, c)| table.column(&c.name).is_some_and(is_real))
+                        .map(|(pos, _)| pos)
+                        .collect(),
+                    _ => Rc::new([]),
+                }
+            }
+            _ => Rc::new([]),
+        };
+        self.reals.borrow_mut().insert(pgno, reals.clone());
+        reals
+    }
 
-                    if let Some(rightmost_pointer) = page.header.rightmost_pointer {
-                        if let Some(rightmost_pointer) = NonZeroU64::new(rightmost_pointer.into()) {
-                            rows.extend(self.rows(search.next_page(rightmost_pointer)));
-                        }
-                    }
+    /// Position of the INTEGER PRIMARY KEY column of the table b-tree rooted at `pgno`, whose
+    /// records store NULL for it in place of the rowid.
+    pub fn rowid_alias(&self, pgno: NonZeroU64) -> Option<usize> {
+        if let Some(alias) = self.aliases.borrow().get(&pgno) {
+            return *alias;
+        }
+        let alias = self
+            .get_schemas_vec()
+            .iter()
+            .find(|s| s.stype == SchemaType::Table && s.rootpage == pgno.get())
+            .and_then(|s| Table::try_from(s).ok())
+            .and_then(|table| table.rowid_alias());
+        self.aliases.borrow_mut().insert(pgno, alias);
+        alias
+    }
 
-                    rows
+    /// Descend the table b-tree to the row with `rowid`, decoding only its `columns` when
+    /// given.
+    pub fn seek_rowid(
+        &self,
+        pgno: NonZeroU64,
+        rowid: u64,
+        columns: Option<&[usize]>,
+    ) -> Option<Row> {
+        let reals = self.real_columns(pgno);
+        let alias = self.rowid_alias(pgno);
+        let mut pgno = pgno;
+        loop {
+            let page = match self.get_page(pgno) {
+                Ok(page) => page,
+                Err(e) => {
+                    println!("Error getting page: {}", e);
+                    return None;
                 }
-                PageKind::IndexInterior => {
-                    let mut indices = vec![];
-                    let mut rows = vec![];
-
-                    let (search_key, pgno) =
-                        match (search.key.clone(), NonZeroU64::new(search.schema.rootpage)) {
-                            (Some(key), Some(pgno)) => (key, pgno),
-                            _ => return vec![],
-                        };
-
-                    let mut left_key: Option<String> = None;
-
-                    for cell in page.cells() {
-                        let row = match TryInto::<Row>::try_into(cell) {
-                            Ok(row) => row,
-                            Err(_) => continue,
-                        };
-                        let row_key = match row.first() {
-                            Some(row_key) => row_key.to_string(),
-                            None => continue,
-                        };
-
-                        left_key = match left_key {
-                            Some(ref lk) => {
-                                if lk <= &search_key && search_key <= row_key {
-                                    match cell.next_page().map(|pgno| search.next_page(pgno)) {
-                                        Some(next_search) => {
-                                            let mut index_rows = self.rows(next_search);
-
-                                            if row_key == search_key {
-                                                index_rows.push(row);
-                                            }
-
-                                            let row_indeces = index_rows
-                                                .iter()
-                                                .flat_map(|r| {
-                                                    let row_id = r.get(1)?.clone().into();
-                                                    Some(row_id)
-                                                })
-                                                .collect::<Vec<u64>>();
-
-                                            indices.extend(row_indeces);
-                                        }
-                                        None => continue,
-                                    };
-                                }
-
-                                if *lk == search_key {
-                                    break;
-                                }
-                                Some(row_key.clone())
-                            }
-                            None => {
-                                if search_key <= row_key {
-                                    match cell.next_page().map(|pgno| search.next_page(pgno)) {
-                                        Some(next_search) => {
-                                            rows.extend(self.rows(next_search));
-                                        }
-                                        None => continue,
-                                    };
-                                }
-                                Some(row_key.clone())
-                            }
-                        };
-                    }
-
-                    if indices.len() > 0 {
-                        let indexed_rows = self.rows(Search {
-                            pgno,
-                            key: None,
-                            indeces: Some(indices),
-                            schema: search.schema.clone(),
-                            conds: search.conds.clone(),
-                        });
+            };
+            match page.header.kind {
+                PageKind::TableInterior => {
+                    // The first cell whose key is not below the rowid holds it in its left child
+                    pgno = page
+                        .cells()
+                        .find(|cell| matches!(cell, Cell::TableInterior { row_id, .. } if *row_id >= rowid))
+                        .and_then(|cell| cell.next_page())
+                        .or_else(|| NonZeroU64::new(page.header.rightmost_pointer?.into()))?;
+                }
+                PageKind::TableLeaf => {
+                    let mut row = page
+                        .cells()
+                        .find(|cell| matches!(cell, Cell::TableLeaf { row_id, .. } if *row_id == rowid))
+                        .and_then(|cell| cell.row(columns, alias).ok())?;
+                    apply_reals(&mut row, &reals);
+                    return Some(row);
+                }
+                _ => return None,
+            }
+        }
+    }
 
-                        rows.extend(indexed_rows);
-                    }
+    /// Collect every index entry whose first column equals `key`, with the keys ordered by
+    /// the `collation` the index was built with.
+    pub fn index_entries(
+        &self,
+        pgno: NonZeroU64,
+        key: &Value,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> Vec<Row> {
+        let reals = self.real_columns(pgno);
+        let mut entries = self.entries_under(pgno, key, collation);
+        for entry in &mut entries {
+            apply_reals(entry, &reals);
+        }
+        entries
+    }
 
-                    rows
-                }
-                PageKind::TableLeaf | PageKind::IndexLeaf => page
-                    .cells()
-                    .flat_map(|cell| {
-                        let row = TryInto::<Row>::try_into(cell).ok()?;
-
-                        // Check if it matches searched index
-                        if let Some(indeces) = &search.indeces {
-                            if let Cell::TableLeaf { row_id, .. } = cell {
-                                if !indeces.contains(&row_id) {
-                                    return None;
-                                }
-                            }
-                        }
-
-                        // Check if it matches index key
-                        if let Some(ref search_key) = search.key {
-                            let row_key = row.first()?.to_string();
-                            if row_key == search_key.to_string() {
-                                return Some(row);
-                            } else {
-                                return None;
-                            }
-                        }
-
-                        // If there are conditions check that it passes at least 1
-                        if search.conds.len() > 0 {
-                            let match_cnt = search
-                                .conds
-                                .iter()
-                                .filter(|cond| {
-                                    (&search.schema)
-                                        .try_into()
-                                        .map(|table: Table| cond.eval(&row, &table.columns))
-                                        .unwrap_or(false)
-                                })
-                                .count();
-                            if match_cnt != search.conds.len() {
-                                return None;
-                            }
-                        }
-
-                        Some(row)
-                    })
-                    .collect(),
-            },
+    /// The entries equal to `key` in the index subtree rooted at `pgno`, as stored.
+    fn entries_under(
+        &self,
+        pgno: NonZeroU64,
+        key: &Value,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> Vec<Row> {
+        let page = match self.get_page(pgno) {
+            Ok(page) => page,
             Err(e) => {
                 println!("Error getting page: {}", e);
-                vec![]
+                return vec![];
+            }
+        };
+
+        let mut entries = vec![];
+        for cell in page.cells() {
+            let row = match TryInto::<Row>::try_into(cell) {
+                Ok(row) => row,
+                Err(_) => continue,
+            };
+            let ordering = match row.first() {
+                Some(row_key) => row_key.compare_collated(key, collation),
+                None => continue,
+            };
+
+            // Entries equal to the key can continue in the left child of this cell
+            if ordering != Ordering::Less {
+                if let Some(child) = cell.next_page() {
+                    entries.extend(self.entries_under(child, key, collation));
+                }
+            }
+            match ordering {
+                Ordering::Equal => entries.push(row),
+                Ordering::Greater => return entries,
+                Ordering::Less => {}
+            }
+        }
+
+        if let Some(rightmost_pointer) = page.header.rightmost_pointer {
+            if let Some(rightmost_pointer) = NonZeroU64::new(rightmost_pointer.into()) {
+                entries.extend(self.entries_under(rightmost_pointer, key, collation));
             }
         }
+
+        entries
     }
 
     pub fn get_schemas_vec(&self) -> Vec<Schema> {
@@ -366,3 +483,146 @@ impl fmt::Display for Database {
         )
     }
 }
+
+#[test]
+fn test_apply_reals() {
+    let mut row = vec![
+        Value::Integer(1),
+        Value::Integer(80),
+        Value::Null,
+        Value::Float(72.5),
+        Value::Text("80".into()),
+    ];
+    apply_reals(&mut row, &[1, 2, 3, 4, 9]);
+    assert_eq!(
+        row,
+        vec![
+            Value::Integer(1),
+            Value::Float(80.0),
+            Value::Null,
+            Value::Float(72.5),
+            Value::Text("80".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_wants_rowids_range() {
+    let schema = Schema {
+        stype: SchemaType::Table,
+        name: "t".to_string(),
+        table_name: "t".to_string(),
+        rootpage: 2,
+        sql: "CREATE TABLE t (id integer primary key)".to_string(),
+    };
+    let pgno = NonZeroU64::new(2).unwrap();
+    let search = Search::new(pgno, None, None, schema, vec![])
+        .with_range((Bound::Excluded(10), Bound::Included(20)));
+    let wants = |lower: Option<i64>, upper: Option<i64>| {
+        search.wants_rowids(lower.map(|l| l as u64), upper.map(|u| u as u64))
+    };
+
+    // Children hold the rowids above the key before them, up to their own key
+    assert!(!wants(None, Some(10)));
+    assert!(wants(Some(10), Some(11)));
+    assert!(wants(Some(19), Some(30)));
+    assert!(!wants(Some(20), None));
+    // Negative rowids sort first
+    assert!(!wants(None, Some(-1)));
+    assert!(wants(Some(-1), None));
+    assert!(!Search::new(pgno, None, None, search.schema.clone(), vec![]).has_range());
+}
+
+/// A database of the tables and indexes the `objects` statements create, each holding its
+/// rows in a single leaf page. Table rows get the rowids 1, 2 and so on, holding NULL where
+/// an INTEGER PRIMARY KEY stands for the rowid; index rows are entries in key order, each
+/// ending with the rowid of its row.
+#[cfg(test)]
+pub fn test_database(objects: &[(&str, Vec<Row>)]) -> Database {
+    use crate::parsers::{payload::encode_record, varint::encode_varint};
+    use std::{
+        fs,
+        io::Write,
+        process,
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    static DATABASES: AtomicUsize = AtomicUsize::new(0);
+    const PAGE_SIZE: usize = 4096;
+
+    let cell = |rowid: Option<u64>, record: Vec<u8>| {
+        let mut cell = vec![];
+        encode_varint(record.len() as u64, &mut cell);
+        if let Some(rowid) = rowid {
+            encode_varint(rowid, &mut cell);
+        }
+        cell.extend(record);
+        cell
+    };
+    // Cells are laid out from the end of the page, their pointers after the b-tree header
+    let page = |kind: u8, cells: Vec<Vec<u8>>, header: usize| {
+        let mut page = vec![0u8; PAGE_SIZE];
+        let mut content = PAGE_SIZE;
+        for (i, cell) in cells.iter().enumerate() {
+            content -= cell.len();
+            page[content..content + cell.len()].copy_from_slice(cell);
+            let pointer = header + 8 + 2 * i;
+            page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+        }
+        page[header] = kind;
+        page[header + 3..header + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+        page[header + 5..header + 7].copy_from_slice(&(content as u16).to_be_bytes());
+        page
+    };
+
+    let mut schema = vec![];
+    let mut pages = vec![];
+    for (i, (sql, rows)) in objects.iter().enumerate() {
+        let rootpage = i as i64 + 2;
+        let (kind, name, table, leaf) = match Index::from_str(sql) {
+            Ok(index) => ("index", index.name, index.table_name, 0x0A),
+            Err(_) => {
+                let table = Table::from_str(sql).unwrap();
+                ("table", table.name.clone(), table.name, 0x0D)
+            }
+        };
+        let cells = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| cell((leaf == 0x0D).then_some(i as u64 + 1), encode_record(row)))
+            .collect();
+        pages.push(page(leaf, cells, 0));
+        let entry = [
+            Value::Text(kind.to_string()),
+            Value::Text(name.clone()),
+            Value::Text(table),
+            Value::Integer(rootpage),
+            Value::Text(sql.to_string()),
+        ];
+        schema.push(cell(Some(i as u64 + 1), encode_record(&entry)));
+    }
+
+    let mut first = page(0x0D, schema, 100);
+    first[..16].copy_from_slice(b"SQLite format 3\0");
+    first[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    first[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    first[28..32].copy_from_slice(&(pages.len() as u32 + 1).to_be_bytes());
+    first[44..48].copy_from_slice(&4u32.to_be_bytes());
+    first[56..60].copy_from_slice(&1u32.to_be_bytes());
+
+    let path = std::env::temp_dir().join(format!(
+        "esquilait-test-{}-{}.db",
+        process::id(),
+        DATABASES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = File::create(&path).unwrap();
+    file.write_all(&first).unwrap();
+    for page in pages {
+        file.write_all(&page).unwrap();
+    }
+    let db = Database::new(File::open(&path).unwrap()).unwrap();
+    // The open file keeps its pages readable once its name is gone
+    let _ = fs::remove_file(&path);
+    db
+}
@@ -3,6 +3,7 @@ use nom::{
     IResult,
 };
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct DatabaseHeader {
     pub header_string: String,
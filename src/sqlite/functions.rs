@@ -0,0 +1,817 @@
+use anyhow::{bail, Result};
+use std::{
+    cmp::Ordering,
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::aggregate::{as_real, AggregateKind};
+use crate::parsers::value::{real_to_text, text_to_numeric, Value};
+
+/// Most significant digits `printf` renders a real with, as SQLite does without the `!` flag.
+const PRINTF_DIGITS: i64 = 16;
+
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// The built-in scalar functions.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScalarFunction {
+    Abs,
+    Char,
+    Coalesce,
+    Hex,
+    Ifnull,
+    Instr,
+    Length,
+    Lower,
+    Ltrim,
+    Max,
+    Min,
+    Nullif,
+    Printf,
+    Quote,
+    Random,
+    Replace,
+    Round,
+    Rtrim,
+    Substr,
+    Trim,
+    Typeof,
+    Unicode,
+    Upper,
+}
+
+impl ScalarFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let function = match name.to_ascii_lowercase().as_str() {
+            "abs" => ScalarFunction::Abs,
+            "char" => ScalarFunction::Char,
+            "coalesce" => ScalarFunction::Coalesce,
+            "hex" => ScalarFunction::Hex,
+            "ifnull" => ScalarFunction::Ifnull,
+            "instr" => ScalarFunction::Instr,
+            "length" => ScalarFunction::Length,
+            "lower" => ScalarFunction::Lower,
+            "ltrim" => ScalarFunction::Ltrim,
+            "max" => ScalarFunction::Max,
+            "min" => ScalarFunction::Min,
+            "nullif" => ScalarFunction::Nullif,
+            "printf" | "format" => ScalarFunction::Printf,
+            "quote" => ScalarFunction::Quote,
+            "random" => ScalarFunction::Random,
+            "replace" => ScalarFunction::Replace,
+            "round" => ScalarFunction::Round,
+            "rtrim" => ScalarFunction::Rtrim,
+            "substr" | "substring" => ScalarFunction::Substr,
+            "trim" => ScalarFunction::Trim,
+            "typeof" => ScalarFunction::Typeof,
+            "unicode" => ScalarFunction::Unicode,
+            "upper" => ScalarFunction::Upper,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    /// Whether the function can be called with `args` arguments. With a single argument
+    /// `min` and `max` are the aggregates instead.
+    pub fn accepts(self, args: usize) -> bool {
+        use ScalarFunction::*;
+        match self {
+            Abs | Hex | Length | Lower | Quote | Typeof | Unicode | Upper => args == 1,
+            Ifnull | Instr | Nullif => args == 2,
+            Replace => args == 3,
+            Ltrim | Rtrim | Trim | Round => (1..=2).contains(&args),
+            Substr => (2..=3).contains(&args),
+            Coalesce | Max | Min => args >= 2,
+            Printf => args >= 1,
+            Random => args == 0,
+            Char => true,
+        }
+    }
+
+    /// Apply the function to its argument values. Text is compared by `collation` in `min`,
+    /// `max` and `nullif`. Fails where SQLite raises an error, as `abs` of the smallest
+    /// integer does.
+    pub fn call(self, args: &[Value], collation: &dyn Fn(&str, &str) -> Ordering) -> Result<Value> {
+        use ScalarFunction::*;
+        let arg = |i: usize| args.get(i).unwrap_or(&Value::Null);
+        // Most functions are NULL as soon as any argument is
+        let has_null = args.contains(&Value::Null);
+        Ok(match self {
+            Coalesce | Ifnull => args
+                .iter()
+                .find(|arg| **arg != Value::Null)
+                .cloned()
+                .unwrap_or(Value::Null),
+            Nullif => match arg(0).compare_collated(arg(1), collation) {
+                Ordering::Equal => Value::Null,
+                _ => arg(0).clone(),
+            },
+            Typeof => Value::Text(type_name(arg(0)).to_string()),
+            Hex => Value::Text(match arg(0) {
+                Value::Blob(bytes) => hex(bytes),
+                value => hex(as_text(value).unwrap_or_default().as_bytes()),
+            }),
+            Quote => Value::Text(quote(arg(0))),
+            Random => Value::Integer(random()),
+            Char => Value::Text(
+                args.iter()
+                    .map(|arg| {
+                        u32::try_from(as_integer(arg))
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or(char::REPLACEMENT_CHARACTER)
+                    })
+                    .collect(),
+            ),
+            Printf => match as_text(arg(0)) {
+                Some(format) => Value::Text(printf(&format, &args[1..])),
+                None => Value::Null,
+            },
+            _ if has_null => Value::Null,
+            Length => match arg(0) {
+                Value::Blob(bytes) => Value::Integer(bytes.len() as i64),
+                // Text ends at its first NUL character
+                value => Value::Integer(
+                    as_text(value)
+                        .unwrap_or_default()
+                        .chars()
+                        .take_while(|&c| c != '\0')
+                        .count() as i64,
+                ),
+            },
+            Lower => Value::Text(as_text(arg(0)).unwrap_or_default().to_ascii_lowercase()),
+            Upper => Value::Text(as_text(arg(0)).unwrap_or_default().to_ascii_uppercase()),
+            Substr => {
+                let start = as_integer(arg(1));
+                let len = args.get(2).map(as_integer);
+                match arg(0) {
+                    Value::Blob(bytes) => {
+                        let (from, to) = substr_range(bytes.len(), start, len);
+                        Value::Blob(bytes[from..to].to_vec())
+                    }
+                    value => {
+                        let chars: Vec<_> = as_text(value).unwrap_or_default().chars().collect();
+                        let (from, to) = substr_range(chars.len(), start, len);
+                        Value::Text(chars[from..to].iter().collect())
+                    }
+                }
+            }
+            Trim | Ltrim | Rtrim => {
+                let text = as_text(arg(0)).unwrap_or_default();
+                let chars = args.get(1).and_then(as_text);
+                let chars = chars.as_deref().unwrap_or(" ");
+                let trimmed = |c: char| chars.contains(c);
+                Value::Text(
+                    match self {
+                        Ltrim => text.trim_start_matches(trimmed),
+                        Rtrim => text.trim_end_matches(trimmed),
+                        _ => text.trim_matches(trimmed),
+                    }
+                    .to_string(),
+                )
+            }
+            Replace => {
+                let text = as_text(arg(0)).unwrap_or_default();
+                let pattern = as_text(arg(1)).unwrap_or_default();
+                match pattern.is_empty() {
+                    true => Value::Text(text),
+                    false => {
+                        Value::Text(text.replace(&pattern, &as_text(arg(2)).unwrap_or_default()))
+                    }
+                }
+            }
+            Instr => match (arg(0), arg(1)) {
+                (Value::Blob(haystack), Value::Blob(needle)) => Value::Integer(
+                    haystack
+                        .windows(needle.len().max(1))
+                        .position(|window| needle.is_empty() || window == &needle[..])
+                        .map_or(0, |i| i as i64 + 1),
+                ),
+                (haystack, needle) => {
+                    let haystack = as_text(haystack).unwrap_or_default();
+                    let needle = as_text(needle).unwrap_or_default();
+                    Value::Integer(
+                        haystack
+                            .find(&needle)
+                            .map_or(0, |i| haystack[..i].chars().count() as i64 + 1),
+                    )
+                }
+            },
+            Abs => match arg(0) {
+                Value::Integer(n) => match n.checked_abs() {
+                    Some(n) => Value::Integer(n),
+                    None => bail!("integer overflow"),
+                },
+                Value::Float(f) => Value::Float(f.abs()),
+                value => Value::Float(as_real(value).abs()),
+            },
+            Round => {
+                let digits = args.get(1).map_or(0, as_integer).clamp(0, 30);
+                Value::Float(round(as_real(arg(0)), digits))
+            }
+            Unicode => as_text(arg(0))
+                .and_then(|text| text.chars().next())
+                .map_or(Value::Null, |c| Value::Integer(c as i64)),
+            Min | Max => {
+                let ordering = match self {
+                    Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                args.iter()
+                    .reduce(
+                        |best, arg| match arg.compare_collated(best, collation) == ordering {
+                            true => arg,
+                            false => best,
+                        },
+                    )
+                    .cloned()
+                    .unwrap_or(Value::Null)
+            }
+        })
+    }
+}
+
+/// Check that a call names a known function and passes it an acceptable number of arguments.
+pub fn check_call(name: &str, args: usize) -> Result<()> {
+    if AggregateKind::from_call(name, args).is_some() {
+        return Ok(());
+    }
+    match ScalarFunction::from_name(name) {
+        Some(function) if function.accepts(args) => Ok(()),
+        Some(_) => bail!("wrong number of arguments to function {}()", name),
+        None if AggregateKind::from_call(name, 1).is_some() => {
+            bail!("wrong number of arguments to function {}()", name)
+        }
+        None => bail!("no such function: {}", name),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Integer(_) => "integer",
+        Value::Float(_) => "real",
+        Value::Text(_) => "text",
+        Value::Blob(_) => "blob",
+    }
+}
+
+/// The value as text the way string functions see it, or `None` when it is NULL.
+pub fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Blob(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// The value as an integer, reading text by its numeric prefix and truncating reals.
+pub fn as_integer(value: &Value) -> i64 {
+    match value {
+        Value::Null => 0,
+        Value::Integer(n) => *n,
+        Value::Float(f) => *f as i64,
+        Value::Text(s) => as_integer(&text_to_numeric(s)),
+        Value::Blob(b) => as_integer(&text_to_numeric(&String::from_utf8_lossy(b))),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// The value as an SQL literal.
+fn quote(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Float(f) => {
+            // Fall back to more digits when the short form would read back as another real
+            let text = real_to_text(*f);
+            if text.parse::<f64>().ok() == Some(*f) {
+                return text;
+            }
+            let (digits, exp) = decimal_digits(*f, 19);
+            format!(
+                "{}{}",
+                if *f < 0.0 { "-" } else { "" },
+                scientific(&digits, exp, 18, false)
+            )
+        }
+        Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(bytes) => format!("X'{}'", hex(bytes)),
+    }
+}
+
+/// Range of the characters or bytes that `substr` takes from `len` of them, with SQLite's
+/// handling of a zero or negative start and a negative length.
+fn substr_range(len: usize, start: i64, count: Option<i64>) -> (usize, usize) {
+    let len = len as i64;
+    let mut p1 = start;
+    let mut p2 = count.map_or(len, |n| n.saturating_abs());
+    if p1 < 0 {
+        p1 += len;
+        if p1 < 0 {
+            p2 = (p2 + p1).max(0);
+            p1 = 0;
+        }
+    } else if p1 > 0 {
+        p1 -= 1;
+    } else if p2 > 0 {
+        p2 -= 1;
+    }
+    // A negative length takes the characters before the start instead
+    if count.is_some_and(|n| n < 0) {
+        p1 -= p2;
+        if p1 < 0 {
+            p2 += p1;
+            p1 = 0;
+        }
+    }
+    let from = p1.min(len);
+    (
+        from as usize,
+        p1.saturating_add(p2).clamp(from, len) as usize,
+    )
+}
+
+/// Round a real to `digits` decimal places, with halves rounded away from zero.
+fn round(f: f64, digits: i64) -> f64 {
+    // Reals this large have no fractional part
+    if f.abs() >= 4_503_599_627_370_496.0 {
+        return f;
+    }
+    if digits == 0 {
+        return (f + 0.5f64.copysign(f)) as i64 as f64;
+    }
+    let (significant, exp) = decimal_digits(f, 17);
+    let (significant, exp) = round_digits(significant, exp, digits + exp + 1);
+    let mantissa: String = significant.iter().map(|d| char::from(b'0' + d)).collect();
+    let rounded: f64 = format!("0.{}e{}", mantissa, exp + 1).parse().unwrap_or(0.0);
+    match rounded {
+        0.0 => 0.0,
+        _ => rounded.copysign(f),
+    }
+}
+
+/// The first `count` significant decimal digits of `|f|`, with the power of ten of the first.
+fn decimal_digits(f: f64, count: usize) -> (Vec<u8>, i64) {
+    let sci = format!("{:.*e}", count - 1, f.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|b| b - b'0')
+        .collect();
+    (digits, exp.parse().unwrap_or(0))
+}
+
+/// Round decimal digits half up to `keep` significant digits, which may carry into a new
+/// leading digit.
+fn round_digits(mut digits: Vec<u8>, mut exp: i64, keep: i64) -> (Vec<u8>, i64) {
+    let keep = match usize::try_from(keep) {
+        Ok(keep) if keep < digits.len() => keep,
+        Ok(_) => return (digits, exp),
+        Err(_) => return (vec![0], exp),
+    };
+    let round_up = digits[keep] >= 5;
+    digits.truncate(keep);
+    if round_up {
+        let mut i = keep;
+        loop {
+            if i == 0 {
+                digits.insert(0, 1);
+                exp += 1;
+                break;
+            }
+            i -= 1;
+            if digits[i] == 9 {
+                digits[i] = 0;
+            } else {
+                digits[i] += 1;
+                break;
+            }
+        }
+    }
+    if digits.is_empty() {
+        digits.push(0);
+    }
+    (digits, exp)
+}
+
+/// Digits as fixed-point notation with `precision` decimals.
+fn fixed(digits: &[u8], exp: i64, precision: usize) -> String {
+    let digit = |pos: i64| -> char {
+        let digit = usize::try_from(exp - pos)
+            .ok()
+            .and_then(|i| digits.get(i))
+            .unwrap_or(&0);
+        char::from(b'0' + digit)
+    };
+    let mut text: String = (0..=exp.max(0)).rev().map(digit).collect();
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision as i64).map(|pos| digit(-pos)));
+    }
+    text
+}
+
+/// Digits as scientific notation with `precision` decimals, like `1.50e+03`.
+fn scientific(digits: &[u8], exp: i64, precision: usize, upper: bool) -> String {
+    let digit = |i: usize| char::from(b'0' + digits.get(i).unwrap_or(&0));
+    let mut text = String::from(digit(0));
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision).map(digit));
+    }
+    let sign = if exp < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    text + &format!("{}{}{:02}", e, sign, exp.abs())
+}
+
+/// A pseudo-random 64-bit integer.
+fn random() -> i64 {
+    let mut x = RANDOM_STATE.load(AtomicOrdering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+            | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RANDOM_STATE.store(x, AtomicOrdering::Relaxed);
+    x as i64
+}
+
+/// Flags, width and precision of one `printf` conversion.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    thousands: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad a formatted conversion to the field width, putting zeros after any sign or prefix.
+    fn pad(&self, text: String, numeric: bool) -> String {
+        let len = text.chars().count();
+        if len >= self.width {
+            return text;
+        }
+        let fill = self.width - len;
+        if self.left {
+            text + &" ".repeat(fill)
+        } else if self.zero && numeric {
+            let sign_len =
+                text.find(|c: char| c.is_ascii_digit())
+                    .map_or(0, |i| match &text[i..] {
+                        rest if rest.starts_with("0x") || rest.starts_with("0X") => i + 2,
+                        _ => i,
+                    });
+            format!(
+                "{}{}{}",
+                &text[..sign_len],
+                "0".repeat(fill),
+                &text[sign_len..]
+            )
+        } else {
+            " ".repeat(fill) + &text
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+}
+
+/// Format arguments like SQLite's `printf`, with missing arguments read as NULL.
+fn printf(format: &str, args: &[Value]) -> String {
+    let mut args = args.iter();
+    let mut next_arg = || args.next().unwrap_or(&Value::Null);
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                '#' => spec.alternate = true,
+                ',' => spec.thousands = true,
+                '!' => {}
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            if chars.next_if_eq(&'*').is_some() {
+                let n = as_integer(next_arg());
+                return Some(n);
+            }
+            let mut digits = String::new();
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                digits.push(d);
+            }
+            digits.parse().ok()
+        };
+        if let Some(width) = number(&mut chars) {
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = Some(number(&mut chars).unwrap_or(0).max(0) as usize);
+        }
+        while chars.next_if(|&c| c == 'l').is_some() {}
+
+        let conversion = match chars.next() {
+            Some(conversion) => conversion,
+            None => break,
+        };
+        let text = match conversion {
+            '%' => "%".to_string(),
+            'd' | 'i' => {
+                let n = as_integer(next_arg());
+                let mut digits = n.unsigned_abs().to_string();
+                if let Some(precision) = spec.precision {
+                    digits = format!("{:0>width$}", digits, width = precision);
+                }
+                if spec.thousands {
+                    digits = group_thousands(&digits);
+                }
+                spec.pad(format!("{}{}", spec.sign(n < 0), digits), true)
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let n = as_integer(next_arg()) as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (n.to_string(), ""),
+                    'x' => (format!("{:x}", n), "0x"),
+                    'X' => (format!("{:X}", n), "0X"),
+                    _ => (format!("{:o}", n), "0"),
+                };
+                let prefix = if spec.alternate && n != 0 { prefix } else { "" };
+                spec.pad(format!("{}{}", prefix, digits), true)
+            }
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                let f = as_real(next_arg());
+                let text = format_real(f, conversion, &spec);
+                spec.pad(
+                    format!("{}{}", spec.sign(f.is_sign_negative() && f != 0.0), text),
+                    true,
+                )
+            }
+            's' | 'z' => {
+                let text = as_text(next_arg()).unwrap_or_default();
+                spec.pad(truncate(text, spec.precision), false)
+            }
+            'c' => {
+                let text = as_text(next_arg()).unwrap_or_default();
+                spec.pad(
+                    text.chars().next().map(String::from).unwrap_or_default(),
+                    false,
+                )
+            }
+            'q' | 'Q' | 'w' => {
+                let text = match (next_arg(), conversion) {
+                    (Value::Null, 'Q') => "NULL".to_string(),
+                    (Value::Null, _) => "(NULL)".to_string(),
+                    (value, 'w') => as_text(value).unwrap_or_default().replace('"', "\"\""),
+                    (value, 'Q') => quote(&Value::Text(as_text(value).unwrap_or_default())),
+                    (value, _) => as_text(value).unwrap_or_default().replace('\'', "''"),
+                };
+                spec.pad(truncate(text, spec.precision), false)
+            }
+            _ => String::new(),
+        };
+        out.push_str(&text);
+    }
+    out
+}
+
+/// Render `|f|` for a `%f`, `%e` or `%g` conversion.
+fn format_real(f: f64, conversion: char, spec: &Spec) -> String {
+    if f.is_nan() {
+        return "NaN".to_string();
+    }
+    if f.is_infinite() {
+        return "Inf".to_string();
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let (digits, exp) = decimal_digits(f, 17);
+    match conversion {
+        'f' => {
+            let keep = (precision as i64 + exp + 1).min(PRINTF_DIGITS);
+            let (digits, exp) = round_digits(digits, exp, keep);
+            let text = fixed(&digits, exp, precision);
+            match spec.thousands {
+                true => match text.split_once('.') {
+                    Some((int, frac)) => format!("{}.{}", group_thousands(int), frac),
+                    None => group_thousands(&text),
+                },
+                false => text,
+            }
+        }
+        'e' | 'E' => {
+            let keep = (precision as i64 + 1).min(PRINTF_DIGITS);
+            let (digits, exp) = round_digits(digits, exp, keep);
+            scientific(&digits, exp, precision, conversion == 'E')
+        }
+        _ => {
+            // %g picks the shorter notation for the number of significant digits asked for
+            let significant = precision.max(1);
+            let (digits, exp) = round_digits(digits, exp, (significant as i64).min(PRINTF_DIGITS));
+            let text = if exp < -4 || exp >= significant as i64 {
+                scientific(&digits, exp, significant - 1, conversion == 'G')
+            } else {
+                fixed(&digits, exp, (significant as i64 - 1 - exp) as usize)
+            };
+            match spec.alternate {
+                true => text,
+                false => strip_zeros(&text),
+            }
+        }
+    }
+}
+
+/// Drop trailing zeros from the fraction of a `%g` result, and the point if nothing is left.
+fn strip_zeros(text: &str) -> String {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    let mantissa = match mantissa.contains('.') {
+        true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+        false => mantissa,
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+fn truncate(text: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+fn call(name: &str, args: Vec<Value>) -> Value {
+    ScalarFunction::from_name(name)
+        .unwrap()
+        .call(&args, &|a: &str, b: &str| a.cmp(b))
+        .unwrap()
+}
+
+#[test]
+fn test_string_functions() {
+    use Value::*;
+    let text = |s: &str| Text(s.to_string());
+    assert_eq!(call("length", vec![text("héllo")]), Integer(5));
+    assert_eq!(call("length", vec![Float(12.0)]), Integer(4));
+    assert_eq!(call("length", vec![Null]), Null);
+    assert_eq!(call("upper", vec![text("àbc")]), text("àBC"));
+    assert_eq!(
+        call("substr", vec![text("hello"), Integer(2), Integer(3)]),
+        text("ell")
+    );
+    assert_eq!(call("substr", vec![text("hello"), Integer(-2)]), text("lo"));
+    assert_eq!(
+        call("substr", vec![text("hello"), Integer(0), Integer(2)]),
+        text("h")
+    );
+    assert_eq!(
+        call("substr", vec![text("hello"), Integer(3), Integer(-2)]),
+        text("he")
+    );
+    assert_eq!(
+        call("substr", vec![text("hello"), Integer(-7), Integer(3)]),
+        text("h")
+    );
+    assert_eq!(call("trim", vec![text("xxhixx"), text("x")]), text("hi"));
+    assert_eq!(call("ltrim", vec![text("  hi  ")]), text("hi  "));
+    assert_eq!(
+        call("replace", vec![Integer(5), text(""), text("a")]),
+        text("5")
+    );
+    assert_eq!(call("instr", vec![text("héllo"), text("l")]), Integer(3));
+    assert_eq!(call("instr", vec![text("abc"), text("z")]), Integer(0));
+    assert_eq!(call("hex", vec![Blob(vec![1, 0xab])]), text("01AB"));
+    assert_eq!(call("hex", vec![Null]), text(""));
+    assert_eq!(call("quote", vec![text("it's")]), text("'it''s'"));
+    assert_eq!(
+        call("quote", vec![Float(1.0 / 3.0)]),
+        text("3.333333333333333148e-01")
+    );
+    assert_eq!(call("unicode", vec![text("é")]), Integer(233));
+    assert_eq!(call("char", vec![Integer(72), Integer(105)]), text("Hi"));
+    assert_eq!(call("typeof", vec![Float(1.0)]), text("real"));
+}
+
+#[test]
+fn test_numeric_functions() {
+    use Value::*;
+    assert_eq!(call("abs", vec![Integer(-3)]), Integer(3));
+    assert_eq!(call("abs", vec![Text("-5".to_string())]), Float(5.0));
+    let abs = ScalarFunction::from_name("abs").unwrap();
+    let overflow = abs.call(&[Integer(i64::MIN)], &|a: &str, b: &str| a.cmp(b));
+    assert_eq!(overflow.unwrap_err().to_string(), "integer overflow");
+    assert_eq!(call("round", vec![Float(2.5)]), Float(3.0));
+    assert_eq!(call("round", vec![Float(-2.5)]), Float(-3.0));
+    assert_eq!(call("round", vec![Float(2.675), Integer(2)]), Float(2.67));
+    assert_eq!(call("round", vec![Float(0.125), Integer(2)]), Float(0.13));
+    assert_eq!(call("round", vec![Float(-0.001), Integer(2)]), Float(0.0));
+    assert_eq!(call("round", vec![Float(1.5), Null]), Null);
+    assert_eq!(
+        call("min", vec![Integer(1), Text("a".to_string()), Float(0.5)]),
+        Float(0.5)
+    );
+    assert_eq!(call("max", vec![Integer(1), Null]), Null);
+    assert_eq!(call("coalesce", vec![Null, Null, Integer(3)]), Integer(3));
+    assert_eq!(call("nullif", vec![Integer(1), Float(1.0)]), Null);
+    assert_eq!(call("nullif", vec![Integer(1), Null]), Integer(1));
+    assert_ne!(call("random", vec![]), call("random", vec![]));
+}
+
+#[test]
+fn test_printf() {
+    let printf = |format: &str, args: Vec<Value>| printf(format, &args);
+    use Value::*;
+    assert_eq!(printf("%d items, %s", vec![Integer(3), Null]), "3 items, ");
+    assert_eq!(
+        printf("%05d|%-5d|%+d", vec![Integer(-42), Integer(42), Integer(5)]),
+        "-0042|42   |+5"
+    );
+    assert_eq!(
+        printf(
+            "%,d|%.3d|%x|%#X|%o",
+            vec![
+                Integer(1234567),
+                Integer(5),
+                Integer(255),
+                Integer(255),
+                Integer(8)
+            ]
+        ),
+        "1,234,567|005|ff|0XFF|10"
+    );
+    assert_eq!(
+        printf("%.2f|%.20f", vec![Float(2.675), Float(0.1)]),
+        "2.67|0.10000000000000000000"
+    );
+    assert_eq!(
+        printf(
+            "%e|%.1e|%G",
+            vec![Float(0.000123), Float(12345.678), Float(1e-10)]
+        ),
+        "1.230000e-04|1.2e+04|1E-10"
+    );
+    assert_eq!(
+        printf(
+            "%g|%g|%10.3g",
+            vec![Float(1234567.0), Float(100000.0), Float(1.23456)]
+        ),
+        "1.23457e+06|100000|      1.23"
+    );
+    assert_eq!(
+        printf(
+            "%q|%Q|%Q|%w|%5.2s|%%",
+            vec![
+                Text("it's".to_string()),
+                Null,
+                Text("x".to_string()),
+                Text("a\"b".to_string()),
+                Text("hello".to_string())
+            ]
+        ),
+        "it''s|NULL|'x'|a\"\"b|   he|%"
+    );
+}
@@ -0,0 +1,300 @@
+use anyhow::Result;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    iter,
+};
+
+use super::{
+    db::Row,
+    operators::{BoxedOperator, Operator},
+    spill::{entry_size, Run, SpillFile, SpillWriter},
+};
+use crate::parsers::value::Value;
+
+/// Bytes of build rows a [`HashJoin`] keeps in memory before it partitions them to disk.
+pub const HASH_JOIN_MEMORY_BUDGET: usize = 32 * 1024 * 1024;
+
+/// Number of partitions both sides are split into once the build rows outgrow the budget.
+const PARTITIONS: usize = 16;
+
+/// A join key reduced so that values comparing equal under the join's collation are equal.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl HashKey {
+    /// Whether values can be hashed for a collation. The built-in ones only fold ASCII case
+    /// or trailing spaces, while an application-defined one could equate anything.
+    pub fn supports(collation: &str) -> bool {
+        ["BINARY", "NOCASE", "RTRIM"]
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(collation))
+    }
+
+    /// Key of a value, or `None` for NULL, which is equal to nothing.
+    pub fn new(value: Value, collation: &str) -> Option<Self> {
+        let key = match value {
+            Value::Null => return None,
+            Value::Integer(n) => HashKey::Integer(n),
+            // Reals holding an integer compare equal to it
+            Value::Float(f)
+                if f.fract() == 0.0
+                    && (-9.223_372_036_854_776e18..9.223_372_036_854_776e18).contains(&f) =>
+            {
+                HashKey::Integer(f as i64)
+            }
+            Value::Float(f) => HashKey::Real(f.to_bits()),
+            Value::Text(s) if collation.eq_ignore_ascii_case("NOCASE") => {
+                HashKey::Text(s.to_ascii_lowercase())
+            }
+            Value::Text(s) if collation.eq_ignore_ascii_case("RTRIM") => {
+                HashKey::Text(s.trim_end_matches(' ').to_string())
+            }
+            Value::Text(s) => HashKey::Text(s),
+            Value::Blob(b) => HashKey::Blob(b),
+        };
+        Some(key)
+    }
+
+    fn partition(&self) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish() as usize % PARTITIONS
+    }
+}
+
+type KeyFn<'a> = Box<dyn Fn(&Row) -> Result<Option<HashKey>> + 'a>;
+type JoinFn<'a> = Box<dyn Fn(&Row, &[Row]) -> Result<Vec<Row>> + 'a>;
+
+/// Joins each probe row with the build rows of equal key, through `join`, which gets the
+/// probe row and its candidate matches and returns the joined rows.
+///
+/// Build rows are hashed in memory until they outgrow the budget. Then both sides are split
+/// by key into partitions on disk, and the partitions are joined one after another; a single
+/// partition is still loaded whole, however large it is.
+pub struct HashJoin<'a> {
+    probe_key: KeyFn<'a>,
+    build_key: KeyFn<'a>,
+    join: JoinFn<'a>,
+    table: HashMap<HashKey, Vec<Row>>,
+    probe: BoxedOperator<'a>,
+    /// Spilled build and probe rows of the partitions left to join.
+    partitions: Vec<(Option<SpillFile>, SpillFile)>,
+    output: std::vec::IntoIter<Row>,
+}
+
+impl<'a> HashJoin<'a> {
+    pub fn new(
+        probe: BoxedOperator<'a>,
+        mut build: impl Operator,
+        probe_key: impl Fn(&Row) -> Result<Option<HashKey>> + 'a,
+        build_key: impl Fn(&Row) -> Result<Option<HashKey>> + 'a,
+        join: impl Fn(&Row, &[Row]) -> Result<Vec<Row>> + 'a,
+        budget: usize,
+    ) -> Result<Self> {
+        let mut table: HashMap<HashKey, Vec<Row>> = HashMap::new();
+        let mut size = 0;
+        let mut spilled: Option<Vec<SpillWriter>> = None;
+        while let Some(row) = build.next()? {
+            let key = match build_key(&row)? {
+                Some(key) => key,
+                None => continue,
+            };
+            if let Some(writers) = &mut spilled {
+                writers[key.partition()].write(&row)?;
+                continue;
+            }
+            size += entry_size(&row);
+            table.entry(key).or_default().push(row);
+            if size > budget {
+                let mut writers = partition_writers(PARTITIONS)?;
+                for (key, rows) in table.drain() {
+                    for row in rows {
+                        writers[key.partition()].write(&row)?;
+                    }
+                }
+                spilled = Some(writers);
+            }
+        }
+
+        let mut join = HashJoin {
+            probe_key: Box::new(probe_key),
+            build_key: Box::new(build_key),
+            join: Box::new(join),
+            table,
+            probe: Box::new(Spilled(None)),
+            partitions: vec![],
+            output: vec![].into_iter(),
+        };
+        let build_writers = match spilled {
+            Some(writers) => writers,
+            None => {
+                join.probe = probe;
+                return Ok(join);
+            }
+        };
+
+        // Probe rows without a key match nothing, and get a partition of their own
+        let mut probe = probe;
+        let mut probe_writers = partition_writers(PARTITIONS + 1)?;
+        while let Some(row) = probe.next()? {
+            let partition = (join.probe_key)(&row)?.map_or(PARTITIONS, |key| key.partition());
+            probe_writers[partition].write(&row)?;
+        }
+        let builds = build_writers
+            .into_iter()
+            .map(|writer| writer.finish().map(Some))
+            .chain(iter::once(Ok(None)));
+        for (build, probe) in builds.zip(probe_writers) {
+            join.partitions.push((build?, probe.finish()?));
+        }
+        Ok(join)
+    }
+
+    /// Whether the build rows had to be partitioned to disk.
+    #[cfg(test)]
+    fn spilled(&self) -> bool {
+        !self.partitions.is_empty()
+    }
+
+    /// Hash the build rows of the next partition and start reading its probe rows.
+    fn next_partition(&mut self) -> Result<bool> {
+        let (build, probe) = match self.partitions.pop() {
+            Some(partition) => partition,
+            None => return Ok(false),
+        };
+        self.table.clear();
+        if let Some(build) = build {
+            let mut run = Run::open(build)?;
+            while let Some(row) = run.next_entry()? {
+                if let Some(key) = (self.build_key)(&row)? {
+                    self.table.entry(key).or_default().push(row);
+                }
+            }
+        }
+        self.probe = Box::new(Spilled(Some(Run::open(probe)?)));
+        Ok(true)
+    }
+}
+
+/// The rows of a partition spilled to disk.
+struct Spilled(Option<Run>);
+
+impl Operator for Spilled {
+    fn next(&mut self) -> Result<Option<Row>> {
+        match &mut self.0 {
+            Some(run) => run.next_entry(),
+            None => Ok(None),
+        }
+    }
+}
+
+fn partition_writers(n: usize) -> Result<Vec<SpillWriter>> {
+    (0..n).map(|_| SpillWriter::create("join")).collect()
+}
+
+impl Operator for HashJoin<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        loop {
+            if let Some(row) = self.output.next() {
+                return Ok(Some(row));
+            }
+            match self.probe.next()? {
+                Some(row) => {
+                    let matches = (self.probe_key)(&row)?
+                        .and_then(|key| self.table.get(&key))
+                        .map_or(&[][..], Vec::as_slice);
+                    self.output = (self.join)(&row, matches)?.into_iter();
+                }
+                None if self.next_partition()? => {}
+                None => {
+                    self.table = HashMap::new();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hash_keys() {
+    use Value::*;
+    let key = |value, collation| HashKey::new(value, collation);
+    assert_eq!(key(Float(3.0), "BINARY"), key(Integer(3), "BINARY"));
+    assert_ne!(key(Float(3.5), "BINARY"), key(Integer(3), "BINARY"));
+    assert_ne!(
+        key(Text("3".to_string()), "BINARY"),
+        key(Integer(3), "BINARY")
+    );
+    assert_eq!(
+        key(Text("ABC".to_string()), "nocase"),
+        key(Text("abc".to_string()), "NOCASE")
+    );
+    assert_ne!(
+        key(Text("ABC".to_string()), "BINARY"),
+        key(Text("abc".to_string()), "BINARY")
+    );
+    assert_eq!(
+        key(Text("a  ".to_string()), "RTRIM"),
+        key(Text("a".to_string()), "RTRIM")
+    );
+    assert_eq!(key(Null, "BINARY"), None);
+    assert!(!HashKey::supports("reverse"));
+}
+
+#[test]
+fn test_hash_join_spills() {
+    use super::operators::{collect, Values};
+    use Value::*;
+    let build: Vec<Row> = (0..300)
+        .map(|i| vec![Integer(i % 50), Text(format!("build {}", i))])
+        .collect();
+    let probe: Vec<Row> = (0..120)
+        .map(|i| match i % 7 {
+            0 => vec![Null],
+            _ => vec![Integer(i % 60)],
+        })
+        .collect();
+    let join = |budget| {
+        let key = |row: &Row| Ok(HashKey::new(row[0].clone(), "BINARY"));
+        let join = HashJoin::new(
+            Box::new(Values::new(probe.clone().into_iter())),
+            Values::new(build.clone().into_iter()),
+            key,
+            key,
+            // Like a LEFT JOIN, keep probe rows without a match
+            |row: &Row, matches: &[Row]| {
+                Ok(match matches {
+                    [] => vec![[row.as_slice(), &[Null, Null]].concat()],
+                    _ => matches
+                        .iter()
+                        .map(|m| [row.as_slice(), m].concat())
+                        .collect(),
+                })
+            },
+            budget,
+        )
+        .unwrap();
+        let spilled = join.spilled();
+        let mut rows = collect(join).unwrap();
+        rows.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        (rows, spilled)
+    };
+
+    let (in_memory, spilled) = join(usize::MAX);
+    assert!(!spilled);
+    let (partitioned, spilled) = join(1024);
+    assert!(spilled);
+    assert_eq!(partitioned, in_memory);
+    // Each key below 50 matches 6 build rows; NULLs and keys of 50 and up match none
+    let matched = probe
+        .iter()
+        .filter(|row| matches!(row[0], Integer(n) if n < 50))
+        .count();
+    assert_eq!(in_memory.len(), matched * 6 + (probe.len() - matched));
+}
@@ -0,0 +1,52 @@
+use anyhow::Error;
+use std::str::FromStr;
+
+use crate::parsers::sql::create_idx_sql;
+
+use super::{schemas::Schema, tables::Table};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndexedColumn {
+    pub name: String,
+    /// Collating sequence given in the index definition, if any.
+    pub collation: Option<String>,
+    pub desc: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Index {
+    pub name: String,
+    pub table_name: String,
+    pub columns: Vec<IndexedColumn>,
+    pub unique: bool,
+    /// Partial indexes only hold the rows matching their `WHERE` clause.
+    pub partial: bool,
+}
+
+impl Index {
+    /// Collation the index keys of column `pos` are sorted by: the one named in the index
+    /// definition, else the one declared on the table column, else BINARY.
+    pub fn collation(&self, pos: usize, table: &Table) -> Option<String> {
+        let column = self.columns.get(pos)?;
+        column.collation.clone().or_else(|| {
+            table
+                .columns
+                .get(&column.name)
+                .and_then(|c| c.collation.clone())
+        })
+    }
+}
+
+impl TryFrom<&Schema> for Index {
+    type Error = Error;
+    fn try_from(value: &Schema) -> std::result::Result<Self, Self::Error> {
+        value.sql.parse()
+    }
+}
+
+impl FromStr for Index {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        create_idx_sql(s)
+    }
+}
@@ -0,0 +1,1037 @@
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    iter,
+    num::NonZeroU64,
+    rc::Rc,
+};
+
+use super::{
+    collation::{Collations, BINARY},
+    db::{Database, Row, Search},
+    hash_join::{HashJoin, HashKey, HASH_JOIN_MEMORY_BUDGET},
+    indexes::Index,
+    operators::{filtered_search_rows, BoxedOperator, Filter, Join, Map, Values},
+    plan::{access_detail, Access, PlanNode},
+    predicate::{BoundExpr, Predicate},
+    schemas::{Schema, SchemaType},
+    tables::{CellType, Column, Table},
+};
+use crate::parsers::{
+    sql::{
+        Expr, JoinConstraint, JoinKind, Operator, ResultColumn, Select, SelectColumn, TableSource,
+    },
+    value::Value,
+};
+use crate::utils::find_table_index;
+
+/// A table of the FROM clause.
+#[derive(Debug)]
+pub struct Source {
+    /// Name its columns are qualified by: the alias, or else the table name.
+    pub name: String,
+    pub table: Table,
+    pub origin: Origin,
+    pub kind: JoinKind,
+    /// What the keys of its columns in the joined columns are qualified by: its name, made
+    /// unique by its position when a table before it goes by the same name.
+    qualifier: String,
+    /// Position of the table's first column in a joined row.
+    offset: usize,
+    /// Terms of the ON or USING clause joining the table.
+    on: Vec<Expr>,
+    /// Ordinals of the table's columns the statement reads, the only ones decoded from the
+    /// rows of a database table. Every column is when `None`.
+    pub read: Option<Vec<usize>>,
+}
+
+/// Where the rows of a table of the FROM clause come from.
+#[derive(Debug)]
+pub enum Origin {
+    /// A table of the database, read through its b-tree or its indexes.
+    Table(Schema),
+    /// The rows of a subquery, run when the statement is prepared.
+    Subquery(Vec<Row>),
+    /// The rows of a VALUES clause, or the row of no columns a SELECT without FROM reads.
+    Values(Vec<Row>),
+}
+
+impl Source {
+    /// Schema of the database table the rows are read from, unless they come from a subquery.
+    pub fn schema(&self) -> Option<&Schema> {
+        match &self.origin {
+            Origin::Table(schema) => Some(schema),
+            Origin::Subquery(_) | Origin::Values(_) => None,
+        }
+    }
+}
+
+/// The row of an enclosing query a correlated subquery is run for. Names the subquery's own
+/// tables don't have are looked up in the enclosing query's tables, and stand for the
+/// values they have in the row.
+#[derive(Debug)]
+pub struct Outer<'a> {
+    pub from: &'a JoinedTables<'a>,
+    pub row: &'a [Value],
+    /// Whether the subquery referred to a column of the row.
+    pub used: Cell<bool>,
+}
+
+impl<'a> Outer<'a> {
+    pub fn new(from: &'a JoinedTables<'a>, row: &'a [Value]) -> Self {
+        Outer {
+            from,
+            row,
+            used: Cell::new(false),
+        }
+    }
+}
+
+/// The tables of a FROM clause, joined into rows holding the columns of each table in turn.
+/// Columns keep their names when there is a single table and are named `table.column` when
+/// there are several.
+#[derive(Debug)]
+pub struct JoinedTables<'a> {
+    pub sources: Vec<Source>,
+    /// Columns of the joined rows, by the name resolved expressions refer to them with.
+    pub columns: HashMap<String, Column>,
+    /// Right-hand columns of USING and NATURAL joins, only found by their qualified name.
+    merged: Vec<String>,
+    qualified: bool,
+    outer: Option<&'a Outer<'a>>,
+}
+
+/// How the rows of a table are found for each row of the tables joined before it.
+#[derive(Debug)]
+enum Lookup {
+    /// Read the rows of a subquery.
+    Subquery,
+    /// Read the whole table, or the rows an index holds under a constant key.
+    Scan {
+        pgno: NonZeroU64,
+        key: Option<Value>,
+        collation: String,
+        /// Keys the next columns of the index are equal to, with their collations.
+        prefix: Vec<(Value, String)>,
+    },
+    /// Seek the row whose rowid is the value of the expression.
+    Rowid(Expr),
+    /// Seek the index entries equal to the value of the expression.
+    Index {
+        pgno: NonZeroU64,
+        key: Expr,
+        affinity: CellType,
+        collation: String,
+    },
+    /// Read the table once into a hash table keyed by `build`, an expression on its own
+    /// columns, and look up the value of `probe` for each row joined so far.
+    Hash {
+        build: Expr,
+        probe: Expr,
+        /// Affinity the comparison applies to both keys, if any.
+        affinity: Option<CellType>,
+        collation: String,
+    },
+}
+
+/// Joining one table: how its rows are found and the conditions the joined rows must meet,
+/// bound to the columns they read once the join is planned.
+struct Step {
+    source: usize,
+    lookup: Lookup,
+    /// Terms on the table's own columns, checked while reading it.
+    pushed: Predicate,
+    /// Key the rowid or index lookup seeks, evaluated for each row of the tables before.
+    key: Option<BoundExpr>,
+    /// Terms deciding whether a row of the table matches the rows before it.
+    on: Predicate,
+    /// WHERE terms checked after a LEFT JOIN has filled in unmatched rows.
+    after: Predicate,
+}
+
+impl<'a> JoinedTables<'a> {
+    /// Look up the tables of a statement's FROM clause, running its subqueries with
+    /// `subquery`, which returns the table their result columns make up and their rows.
+    /// `named` gives the same for names that aren't tables of the database, such as views.
+    /// Column names the tables don't have are looked up in `outer`, the row of the enclosing
+    /// query of a subquery.
+    pub fn new(
+        stmt: &Select,
+        schemas: &HashMap<String, Schema>,
+        outer: Option<&'a Outer<'a>>,
+        named: impl Fn(&str) -> Option<Result<(Table, Vec<Row>)>>,
+        subquery: impl Fn(&Select, Option<&'a Outer<'a>>) -> Result<(Table, Vec<Row>)>,
+    ) -> Result<Self> {
+        let mut joined = JoinedTables {
+            sources: vec![],
+            columns: HashMap::new(),
+            merged: vec![],
+            qualified: !stmt.joins.is_empty(),
+            outer,
+        };
+        let first = (
+            JoinKind::Inner,
+            &stmt.from,
+            &stmt.alias,
+            &JoinConstraint::None,
+        );
+        let joins = stmt
+            .joins
+            .iter()
+            .map(|join| (join.kind, &join.table, &join.alias, &join.constraint));
+        for (i, (kind, from, alias, constraint)) in iter::once(first).chain(joins).enumerate() {
+            let (name, table, origin) = match from {
+                // Tables of a WITH clause hide those of the database
+                TableSource::Table(name) => match (named(name), schemas.get(name)) {
+                    (Some(rows), _) => {
+                        let (table, rows) = rows?;
+                        (name.clone(), table, Origin::Subquery(rows))
+                    }
+                    (None, Some(s)) if s.stype == SchemaType::Table => {
+                        (name.clone(), s.try_into()?, Origin::Table(s.clone()))
+                    }
+                    _ => bail!("no such table: {}", name),
+                },
+                TableSource::Values(rows) => {
+                    let width = rows.first().map_or(0, Vec::len);
+                    if rows.iter().any(|row| row.len() != width) {
+                        bail!("all VALUES must have the same number of terms");
+                    }
+                    let columns = (0..width)
+                        .map(|idx| {
+                            let name = format!("column{}", idx + 1);
+                            let column = Column {
+                                idx,
+                                name: name.clone(),
+                                cell_type: CellType::Blob,
+                                nullable: true,
+                                pk: false,
+                                collation: None,
+                            };
+                            (name, column)
+                        })
+                        .collect();
+                    let values = rows
+                        .iter()
+                        .map(|row| {
+                            row.iter()
+                                .map(|expr| {
+                                    let expr = joined.resolve(expr)?;
+                                    expr.eval(&[], &HashMap::new(), &Collations::default())
+                                })
+                                .collect()
+                        })
+                        .collect::<Result<_>>()?;
+                    let name = "(values)".to_string();
+                    let table = Table {
+                        name: name.clone(),
+                        columns,
+                        key: None,
+                    };
+                    (name, table, Origin::Values(values))
+                }
+                TableSource::Subquery(select) => {
+                    let (mut table, rows) = subquery(select, outer)?;
+                    // SQLite's name for a subquery without an alias
+                    let name = format!("(subquery-{})", i + 1);
+                    table.name = name.clone();
+                    (name, table, Origin::Subquery(rows))
+                }
+            };
+            // A table joined to itself without an alias is told apart as if it had one
+            let name = alias.clone().unwrap_or(name);
+            let qualifier = match joined.sources.iter().any(|s| s.name == name) {
+                true => format!("{}:{}", name, i + 1),
+                false => name.clone(),
+            };
+            let source = Source {
+                name,
+                qualifier,
+                offset: joined.columns.len(),
+                table,
+                origin,
+                kind,
+                on: vec![],
+                read: None,
+            };
+            for column in source.table.columns.values() {
+                let name = joined.key(&source, column);
+                let column = Column {
+                    idx: source.offset + column.idx,
+                    name: name.clone(),
+                    ..column.clone()
+                };
+                joined.columns.insert(name, column);
+            }
+
+            let using = match constraint {
+                JoinConstraint::Using(names) => names.clone(),
+                // The columns the table has in common with the ones before it
+                JoinConstraint::Natural => source
+                    .table
+                    .columns
+                    .values()
+                    .sorted()
+                    .filter(|c| joined.find(&c.name).is_ok_and(|found| found.is_some()))
+                    .map(|c| c.name.clone())
+                    .collect(),
+                _ => vec![],
+            };
+            let mut on = vec![];
+            for name in using {
+                let left = joined.find(&name)?;
+                let right = source.table.column(&name);
+                let (left, right) = match (left, right) {
+                    (Some(left), Some(right)) => (left.name.clone(), joined.key(&source, right)),
+                    _ => bail!(
+                        "cannot join using column {} - column not present in both tables",
+                        name
+                    ),
+                };
+                on.push(Expr::binary(
+                    Expr::Column(left),
+                    Operator::Eq,
+                    Expr::Column(right.clone()),
+                ));
+                joined.merged.push(right);
+            }
+            joined.sources.push(source);
+            if let JoinConstraint::On(expr) = constraint {
+                on.extend(joined.resolve(expr)?.conjuncts());
+            }
+            if let Some(source) = joined.sources.last_mut() {
+                source.on = on;
+            }
+        }
+        Ok(joined)
+    }
+
+    /// The only table of the FROM clause and its schema, when nothing is joined to it and
+    /// it is a table of the database.
+    pub fn single(&self) -> Option<(&Source, &Schema)> {
+        match self.sources.as_slice() {
+            [source] => Some((source, source.schema()?)),
+            _ => None,
+        }
+    }
+
+    /// Only decode, from the rows of each table, the columns whose ordinals in the joined
+    /// rows are among `columns`.
+    pub fn read_only(&mut self, columns: &HashSet<usize>) {
+        for source in &mut self.sources {
+            let range = source.offset..source.offset + source.table.columns.len();
+            let mut read: Vec<_> = columns
+                .iter()
+                .filter(|idx| range.contains(idx))
+                .map(|idx| idx - source.offset)
+                .collect();
+            read.sort_unstable();
+            source.read = Some(read);
+        }
+    }
+
+    /// Terms of the ON and USING clauses of every join.
+    pub fn conditions(&self) -> impl Iterator<Item = &Expr> {
+        self.sources.iter().flat_map(|source| &source.on)
+    }
+
+    fn key(&self, source: &Source, column: &Column) -> String {
+        match self.qualified {
+            true => format!("{}.{}", source.qualifier, column.name),
+            false => column.name.clone(),
+        }
+    }
+
+    /// The joined column a possibly qualified name refers to, which is an error when more
+    /// than one table has a column of that name.
+    pub fn find(&self, name: &str) -> Result<Option<&Column>> {
+        let column = |source: &Source, name: &str| {
+            let column = source.table.column(name)?;
+            self.columns.get(&self.key(source, column))
+        };
+        let found = match name.rsplit_once('.') {
+            Some((table, column_name)) => {
+                let mut found = self
+                    .sources
+                    .iter()
+                    .filter(|s| s.name.eq_ignore_ascii_case(table))
+                    .filter_map(|s| column(s, column_name))
+                    .collect_vec();
+                // Of a table joined to itself by a column, the name means the first one's
+                if found.len() > 1 {
+                    found.retain(|c| !self.merged.contains(&c.name));
+                }
+                found
+            }
+            None => self
+                .sources
+                .iter()
+                .filter_map(|s| column(s, name))
+                .filter(|c| !self.merged.contains(&c.name))
+                .collect_vec(),
+        };
+        match found.as_slice() {
+            [] => Ok(None),
+            [column] => Ok(Some(column)),
+            _ => bail!("ambiguous column name: {}", name),
+        }
+    }
+
+    /// Whether a name refers to a column, even if ambiguously.
+    pub fn has_column(&self, name: &str) -> bool {
+        !matches!(self.find(name), Ok(None))
+    }
+
+    /// Get the [`ResultColumn`]s of a [`Select`], expanding `*` to the columns of every table
+    /// in order and checking that the expressions only refer to columns of the tables.
+    pub fn select(&self, sel: &Select) -> Result<Vec<ResultColumn>> {
+        let mut columns = vec![];
+        for column in &sel.columns {
+            // A bare `*` shows the columns joined by USING once, `table.*` shows them all
+            let (sources, all) = match column {
+                SelectColumn::All(Some(table)) => {
+                    match self
+                        .sources
+                        .iter()
+                        .find(|s| s.name.eq_ignore_ascii_case(table))
+                    {
+                        Some(source) => (vec![source], true),
+                        None => bail!("no such table: {}", table),
+                    }
+                }
+                SelectColumn::All(None) if self.columns.is_empty() => bail!("no tables specified"),
+                SelectColumn::All(None) => (self.sources.iter().collect(), false),
+                SelectColumn::Expr(column) => {
+                    columns.push(ResultColumn {
+                        expr: self.resolve(&column.expr)?,
+                        name: column.name.clone(),
+                    });
+                    continue;
+                }
+            };
+            for source in sources {
+                for column in source.table.columns.values().sorted() {
+                    let key = self.key(source, column);
+                    if all || !self.merged.contains(&key) {
+                        columns.push(ResultColumn {
+                            expr: Expr::Column(key),
+                            name: column.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Point the column references of an expression at the joined columns, or replace them
+    /// with the values of the enclosing query's row.
+    pub fn resolve(&self, expr: &Expr) -> Result<Expr> {
+        let mut error = None;
+        let resolved = expr.rewrite(&mut |expr| match expr {
+            Expr::Column(name) => match self.find(name) {
+                Ok(Some(column)) => Some(Expr::Column(column.name.clone())),
+                Ok(None) => match self.outer_value(name) {
+                    Ok(Some(value)) => Some(value),
+                    Ok(None) => {
+                        error.get_or_insert_with(|| anyhow!("no such column: {}", name));
+                        None
+                    }
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    error.get_or_insert(e);
+                    None
+                }
+            },
+            _ => None,
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(resolved),
+        }
+    }
+
+    /// The value a column of an enclosing query has in the row a subquery is run for,
+    /// keeping the column's collation.
+    fn outer_value(&self, name: &str) -> Result<Option<Expr>> {
+        let outer = match self.outer {
+            Some(outer) => outer,
+            None => return Ok(None),
+        };
+        let column = match outer.from.find(name)? {
+            Some(column) => column,
+            None => return outer.from.outer_value(name),
+        };
+        outer.used.set(true);
+        let value = Expr::Literal(outer.row.get(column.idx).cloned().unwrap_or(Value::Null));
+        Ok(Some(match &column.collation {
+            Some(collation) => Expr::Collate(Box::new(value), collation.clone()),
+            None => value,
+        }))
+    }
+
+    /// The operators joining the rows of the tables with nested loops, in FROM clause order,
+    /// keeping those that satisfy the WHERE terms `conds`.
+    pub fn rows<'r>(
+        &'r self,
+        db: &'r Database,
+        schemas: &HashMap<String, Schema>,
+        conds: &[Expr],
+    ) -> Result<BoxedOperator<'r>> {
+        let mut rows: BoxedOperator<'r> = Box::new(Values::new(iter::once(vec![])));
+        for step in self.plan(db, conds, schemas)? {
+            let step = Rc::new(step);
+            rows = match step.lookup {
+                Lookup::Hash { .. } => Box::new(self.hash_join(db, step, rows)?),
+                _ => {
+                    let source = &self.sources[step.source];
+                    let left = match source.kind {
+                        JoinKind::Left => Some(source.offset + source.table.columns.len()),
+                        _ => None,
+                    };
+                    let (on, after) = (step.on.clone(), step.after.clone());
+                    Box::new(Join::new(
+                        rows,
+                        move |row| self.inner_rows(db, &step, row),
+                        on,
+                        after,
+                        left,
+                    ))
+                }
+            };
+        }
+        Ok(rows)
+    }
+
+    /// The plan lines of the nested loops [`JoinedTables::rows`] runs, one for each table in
+    /// the order they are joined.
+    pub fn explain(
+        &self,
+        db: &Database,
+        conds: &[Expr],
+        schemas: &HashMap<String, Schema>,
+    ) -> Result<Vec<PlanNode>> {
+        let plan = self.plan(db, conds, schemas)?;
+        Ok(plan
+            .iter()
+            .map(|step| {
+                let source = &self.sources[step.source];
+                let access = match &step.lookup {
+                    Lookup::Subquery => match &source.origin {
+                        Origin::Values(rows) => Access::Values(rows.len()),
+                        _ => Access::Scan,
+                    },
+                    Lookup::Scan { key: None, .. } => Access::Scan,
+                    Lookup::Scan { pgno, prefix, .. } => {
+                        Access::IndexSearch(pgno.get(), 1 + prefix.len())
+                    }
+                    Lookup::Rowid(_) => Access::Rowid,
+                    Lookup::Index { pgno, .. } => Access::IndexSearch(pgno.get(), 1),
+                    Lookup::Hash { build, .. } => Access::Hash(match build {
+                        Expr::Column(name) => name.clone(),
+                        _ => "<expr>".to_string(),
+                    }),
+                };
+                PlanNode::new(access_detail(&source.name, &access, schemas))
+            })
+            .collect())
+    }
+
+    /// Indexes of the tables whose columns an expression refers to.
+    fn sources_of(&self, expr: &Expr) -> Vec<usize> {
+        let mut sources = vec![];
+        expr.walk(&mut |expr| {
+            if let Expr::Column(name) = expr {
+                let idx = self.columns.get(name).map_or(0, |c| c.idx);
+                sources.extend(self.sources.iter().rposition(|s| s.offset <= idx));
+            }
+        });
+        sources
+    }
+
+    /// The expression with the joined column names replaced by the names of `source`'s own
+    /// columns, for conditions checked while reading the table.
+    fn unqualified(&self, source: &Source, expr: &Expr) -> Expr {
+        expr.rewrite(&mut |expr| match expr {
+            Expr::Column(name) => {
+                let idx = self.columns.get(name)?.idx - source.offset;
+                let column = source.table.columns.values().find(|c| c.idx == idx)?;
+                Some(Expr::Column(column.name.clone()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Decide, for each table, how its rows are read and where each condition is checked:
+    /// as soon as the tables it refers to are joined, except that WHERE terms on a LEFT
+    /// JOINed table wait for its unmatched rows to be filled in.
+    fn plan(
+        &self,
+        db: &Database,
+        conds: &[Expr],
+        schemas: &HashMap<String, Schema>,
+    ) -> Result<Vec<Step>> {
+        let stats = db.statistics()?;
+        let mut terms = vec![(vec![], vec![], vec![]); self.sources.len()];
+        // ON terms belong to their join even when they only refer to earlier tables
+        let where_terms = conds.iter().map(|cond| (cond, None));
+        let on_terms = self
+            .sources
+            .iter()
+            .enumerate()
+            .flat_map(|(i, source)| source.on.iter().map(move |cond| (cond, Some(i))));
+        for (cond, join) in where_terms.chain(on_terms) {
+            let sources = self.sources_of(cond);
+            let last = sources.iter().copied().chain(join).max().unwrap_or(0);
+            let (pushed, on, after) = &mut terms[last];
+            match self.sources[last].kind {
+                JoinKind::Left if join.is_none() => after.push(cond.clone()),
+                _ if sources.iter().all(|&i| i == last) => {
+                    pushed.push(self.unqualified(&self.sources[last], cond))
+                }
+                _ => on.push(cond.clone()),
+            }
+        }
+
+        let mut steps = vec![];
+        for (i, (pushed, on, after)) in terms.into_iter().enumerate() {
+            let source = &self.sources[i];
+            let collations = &db.collations;
+            let step = |lookup: Lookup| {
+                let key = match &lookup {
+                    Lookup::Rowid(key) | Lookup::Index { key, .. } => {
+                        Some(BoundExpr::new(key, &self.columns, collations))
+                    }
+                    _ => None,
+                };
+                Step {
+                    source: i,
+                    lookup,
+                    pushed: Predicate::new(&pushed, &source.table.columns, collations),
+                    key,
+                    on: Predicate::new(&on, &self.columns, collations),
+                    after: Predicate::new(&after, &self.columns, collations),
+                }
+            };
+            // Without an index to seek, hashing the table beats reading it for every row
+            let lookup = match self
+                .join_key(i, &on, schemas)
+                .or_else(|| self.hash_key(i, &on))
+            {
+                Some(lookup) => lookup,
+                None => {
+                    let schema = match &source.origin {
+                        Origin::Table(schema) => schema,
+                        Origin::Subquery(_) | Origin::Values(_) => {
+                            steps.push(step(Lookup::Subquery));
+                            continue;
+                        }
+                    };
+                    let found = find_table_index(
+                        &pushed,
+                        &schema.name,
+                        &source.table,
+                        schemas,
+                        &stats,
+                        &db.collations,
+                        None,
+                        &[],
+                    );
+                    let (rootpage, key, collation, prefix) = match found {
+                        Some((rootpage, key, collation, prefix)) => {
+                            (rootpage, Some(key), collation, prefix)
+                        }
+                        None => (schema.rootpage, None, BINARY.to_string(), vec![]),
+                    };
+                    match NonZeroU64::new(rootpage) {
+                        Some(pgno) => Lookup::Scan {
+                            pgno,
+                            key,
+                            collation,
+                            prefix,
+                        },
+                        None => bail!("invalid index rootpage: {}", rootpage),
+                    }
+                }
+            };
+            steps.push(step(lookup));
+        }
+        Ok(steps)
+    }
+
+    /// A seek for the rows of the `i`th table by one of its `column = expr` join terms,
+    /// where `expr` only depends on the tables joined before it.
+    fn join_key(&self, i: usize, on: &[Expr], schemas: &HashMap<String, Schema>) -> Option<Lookup> {
+        let source = &self.sources[i];
+        let schema = source.schema()?;
+        on.iter().find_map(|cond| {
+            let (lhs, rhs) = match cond {
+                Expr::Binary(lhs, Operator::Eq, rhs) => (lhs, rhs),
+                _ => return None,
+            };
+            [(lhs, rhs), (rhs, lhs)]
+                .into_iter()
+                .find_map(|(column, key)| {
+                    let column = match column.uncollated() {
+                        Expr::Column(name) => self.columns.get(name)?,
+                        _ => return None,
+                    };
+                    let idx = column.idx.checked_sub(source.offset)?;
+                    let column = source.table.columns.values().find(|c| c.idx == idx)?;
+                    if self.sources_of(key).iter().any(|&j| j >= i) {
+                        return None;
+                    }
+                    // The key takes the column's affinity, unless the comparison converts the
+                    // column's text to a number instead
+                    let numeric = |t: CellType| !matches!(t, CellType::Text | CellType::Blob);
+                    if !numeric(column.cell_type)
+                        && key.affinity(&self.columns).is_some_and(numeric)
+                    {
+                        return None;
+                    }
+                    if source.table.key.as_ref() == Some(&column.name)
+                        && column.cell_type == CellType::Integer
+                    {
+                        return Some(Lookup::Rowid((**key).clone()));
+                    }
+                    let collation = Expr::comparison_collation(lhs, rhs, &self.columns);
+                    let (rootpage, collation) = schemas
+                        .values()
+                        .filter(|s| s.stype == SchemaType::Index && s.table_name == schema.name)
+                        .find_map(|s| {
+                            let index = Index::try_from(s).ok()?;
+                            let first = index.columns.first()?;
+                            let index_collation = index
+                                .collation(0, &source.table)
+                                .unwrap_or_else(|| BINARY.to_string());
+                            let usable = !index.partial
+                                && !first.desc
+                                && first.name == column.name
+                                && index_collation.eq_ignore_ascii_case(collation);
+                            usable.then_some((s.rootpage, index_collation))
+                        })?;
+                    Some(Lookup::Index {
+                        pgno: NonZeroU64::new(rootpage)?,
+                        key: (**key).clone(),
+                        affinity: column.cell_type,
+                        collation,
+                    })
+                })
+        })
+    }
+
+    /// Hash keys for the rows of the `i`th table from one of its `expr = expr` join terms,
+    /// where one side only depends on the table and the other on the tables before it.
+    fn hash_key(&self, i: usize, on: &[Expr]) -> Option<Lookup> {
+        let source = &self.sources[i];
+        on.iter().find_map(|cond| {
+            let (lhs, rhs) = match cond {
+                Expr::Binary(lhs, Operator::Eq, rhs) => (lhs, rhs),
+                _ => return None,
+            };
+            let collation = Expr::comparison_collation(lhs, rhs, &self.columns);
+            if !HashKey::supports(collation) {
+                return None;
+            }
+            let (build, probe) = [(lhs, rhs), (rhs, lhs)]
+                .into_iter()
+                .find(|(build, probe)| {
+                    let build = self.sources_of(build);
+                    let probe = self.sources_of(probe);
+                    !build.is_empty()
+                        && build.iter().all(|&j| j == i)
+                        && !probe.is_empty()
+                        && probe.iter().all(|&j| j < i)
+                })?;
+            Some(Lookup::Hash {
+                build: self.unqualified(source, build),
+                probe: (**probe).clone(),
+                affinity: Expr::comparison_affinity(
+                    lhs.affinity(&self.columns),
+                    rhs.affinity(&self.columns),
+                ),
+                collation: collation.to_string(),
+            })
+        })
+    }
+
+    /// Join `rows` with the step's table by hashing the table on its join key.
+    fn hash_join<'r>(
+        &'r self,
+        db: &'r Database,
+        step: Rc<Step>,
+        rows: BoxedOperator<'r>,
+    ) -> Result<HashJoin<'r>> {
+        let source = &self.sources[step.source];
+        let (build, probe, affinity, collation) = match &step.lookup {
+            Lookup::Hash {
+                build,
+                probe,
+                affinity,
+                collation,
+            } => (build.clone(), probe.clone(), *affinity, collation.clone()),
+            _ => bail!("{:?} is not a hash join", step.lookup),
+        };
+        let key = move |value: Value| {
+            let value = match affinity {
+                Some(affinity) => value.apply_affinity(affinity),
+                None => value,
+            };
+            HashKey::new(value, &collation)
+        };
+        let build_key = key.clone();
+        let probe = BoundExpr::new(&probe, &self.columns, &db.collations);
+        let build = BoundExpr::new(&build, &source.table.columns, &db.collations);
+        let table = self.scan(db, source, None, step.pushed.clone())?;
+
+        let join_step = Rc::clone(&step);
+        HashJoin::new(
+            rows,
+            table,
+            move |row| Ok(key(probe.eval(row)?)),
+            move |row| Ok(build_key(build.eval(row)?)),
+            move |row, matches| {
+                let mut joined = vec![];
+                for inner in matches {
+                    let row = [row.as_slice(), inner].concat();
+                    if join_step.on.matches(&row)? {
+                        joined.push(row);
+                    }
+                }
+                match joined.is_empty() {
+                    true => self.unmatched(&join_step, row.clone()),
+                    false => joined
+                        .into_iter()
+                        .filter_map(|joined| match join_step.after.matches(&joined) {
+                            Ok(true) => Some(Ok(joined)),
+                            Ok(false) => None,
+                            Err(err) => Some(Err(err)),
+                        })
+                        .collect(),
+                }
+            },
+            HASH_JOIN_MEMORY_BUDGET,
+        )
+    }
+
+    /// The rows of a table that satisfy `filter`, its terms bound to the table's own columns,
+    /// found through `search` when it is read from the database and given.
+    fn scan<'r>(
+        &'r self,
+        db: &'r Database,
+        source: &'r Source,
+        search: Option<Search>,
+        filter: Predicate,
+    ) -> Result<BoxedOperator<'r>> {
+        let schema = match &source.origin {
+            Origin::Table(schema) => schema,
+            Origin::Subquery(rows) | Origin::Values(rows) => {
+                let rows = Box::new(Values::new(rows.iter().cloned()));
+                return Ok(Box::new(Filter::with_predicate(rows, filter)));
+            }
+        };
+        let search = match search {
+            Some(search) => search,
+            None => match NonZeroU64::new(schema.rootpage) {
+                Some(pgno) => Search::new(pgno, None, None, schema.clone(), vec![]),
+                None => bail!("invalid index rootpage: {}", schema.rootpage),
+            },
+        };
+        let search = match &source.read {
+            Some(columns) => search.with_columns(columns.clone()),
+            None => search,
+        };
+        let width = source.table.columns.len();
+        Ok(Box::new(Map::new(
+            filtered_search_rows(db, search, filter, false)?,
+            move |mut row| {
+                // Rows written before columns were added are shorter than the table
+                row.resize(width, Value::Null);
+                Ok(row)
+            },
+        )))
+    }
+
+    /// The rows of the step's table to join with `row`, the columns of the tables before
+    /// it, found through the step's lookup.
+    fn inner_rows<'r>(
+        &'r self,
+        db: &'r Database,
+        step: &Step,
+        row: &Row,
+    ) -> Result<BoxedOperator<'r>> {
+        let source = &self.sources[step.source];
+        let key = || {
+            step.key
+                .as_ref()
+                .map_or(Ok(Value::Null), |key| key.eval(row))
+        };
+        let nothing = || -> Result<BoxedOperator<'r>> { Ok(Box::new(Values::new(iter::empty()))) };
+        // Subqueries only have a lookup of their own, the other ones read tables. The search's
+        // conditions are left to the step's filter, bound when the join was planned
+        let search = match (&step.lookup, source.schema().cloned()) {
+            (Lookup::Hash { .. }, _) => bail!("hash joins read the table once"),
+            (Lookup::Subquery, _) | (_, None) => None,
+            (
+                Lookup::Scan {
+                    pgno,
+                    key,
+                    collation,
+                    prefix,
+                },
+                Some(schema),
+            ) => Some(
+                Search::new(*pgno, key.clone(), None, schema, vec![])
+                    .with_collation(collation)
+                    .with_prefix(prefix.clone()),
+            ),
+            (Lookup::Rowid(_), Some(schema)) => match (
+                key()?.apply_affinity(CellType::Integer),
+                NonZeroU64::new(schema.rootpage),
+            ) {
+                (Value::Integer(rowid), Some(pgno)) => Some(Search::new(
+                    pgno,
+                    None,
+                    Some(vec![rowid as u64]),
+                    schema,
+                    vec![],
+                )),
+                _ => return nothing(),
+            },
+            (
+                Lookup::Index {
+                    pgno,
+                    affinity,
+                    collation,
+                    ..
+                },
+                Some(schema),
+            ) => match key()? {
+                Value::Null => return nothing(),
+                key => {
+                    let key = Some(key.apply_affinity(*affinity));
+                    Some(Search::new(*pgno, key, None, schema, vec![]).with_collation(collation))
+                }
+            },
+        };
+        self.scan(db, source, search, step.pushed.clone())
+    }
+
+    /// The row of a LEFT JOIN for a row no row of the joined table matched, with NULL for
+    /// each of its columns, or no row for other joins.
+    fn unmatched(&self, step: &Step, mut row: Row) -> Result<Vec<Row>> {
+        let source = &self.sources[step.source];
+        if source.kind != JoinKind::Left {
+            return Ok(vec![]);
+        }
+        row.resize(source.offset + source.table.columns.len(), Value::Null);
+        Ok(match step.after.matches(&row)? {
+            true => vec![row],
+            false => vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+fn join(stmt: &str) -> Result<(JoinedTables<'static>, Vec<ResultColumn>)> {
+    let tables = [
+        (
+            "emp",
+            "create table emp (id integer primary key, name text, dept text)",
+        ),
+        ("depts", "create table depts (dept text, name text)"),
+    ];
+    let schemas = tables
+        .into_iter()
+        .map(|(name, sql)| {
+            let schema = Schema {
+                stype: SchemaType::Table,
+                name: name.to_string(),
+                table_name: name.to_string(),
+                rootpage: 2,
+                sql: sql.to_string(),
+            };
+            (name.to_string(), schema)
+        })
+        .collect();
+    let stmt: Select = stmt.parse()?;
+    let joined = JoinedTables::new(
+        &stmt,
+        &schemas,
+        None,
+        |_| None,
+        |_, _| bail!("no subqueries"),
+    )?;
+    let columns = joined.select(&stmt)?;
+    Ok((joined, columns))
+}
+
+#[test]
+fn test_join_columns() {
+    let column = |name: &str| Expr::Column(name.to_string());
+    let (joined, columns) = join("select *, E.Name from emp e join depts using (dept)").unwrap();
+    let exprs: Vec<_> = columns.iter().map(|c| c.expr.clone()).collect();
+    assert_eq!(
+        exprs,
+        ["e.id", "e.name", "e.dept", "depts.name", "e.name"].map(column)
+    );
+    assert_eq!(joined.columns["depts.name"].idx, 4);
+    assert_eq!(
+        joined.conditions().collect_vec(),
+        [&Expr::binary(
+            column("e.dept"),
+            Operator::Eq,
+            column("depts.dept")
+        )]
+    );
+    // The USING column is only ambiguous when qualified with neither table
+    assert_eq!(joined.resolve(&column("DEPT")).unwrap(), column("e.dept"));
+    assert_eq!(
+        joined.resolve(&column("depts.dept")).unwrap(),
+        column("depts.dept")
+    );
+    assert!(joined.resolve(&column("name")).is_err());
+    assert!(joined.resolve(&column("emp.id")).is_err());
+
+    let (joined, columns) = join("select depts.* from emp natural join depts").unwrap();
+    assert_eq!(columns.len(), 2);
+    assert_eq!(joined.conditions().count(), 2);
+
+    let (joined, _) = join("select name from emp").unwrap();
+    assert!(joined.single().is_some());
+    assert_eq!(joined.resolve(&column("emp.name")).unwrap(), column("name"));
+    assert!(join("select * from emp join depts using (id)").is_err());
+}
+
+#[test]
+fn test_outer_columns() {
+    let (outer_tables, _) = join("select * from emp e join depts d using (dept)").unwrap();
+    let row = [3, 4, 5, 6, 7].map(Value::Integer);
+    let outer = Outer::new(&outer_tables, &row);
+    let (joined, _) = join("select name from depts").unwrap();
+    let joined = JoinedTables {
+        outer: Some(&outer),
+        ..joined
+    };
+    let column = |name: &str| Expr::Column(name.to_string());
+
+    // The subquery's own columns come first, and only the others are the row's values
+    assert_eq!(joined.resolve(&column("name")).unwrap(), column("name"));
+    assert!(!outer.used.get());
+    assert_eq!(
+        joined.resolve(&column("e.id")).unwrap(),
+        Expr::Literal(Value::Integer(3))
+    );
+    assert!(outer.used.get());
+    assert_eq!(
+        joined.resolve(&column("d.name")).unwrap(),
+        Expr::Literal(Value::Integer(7))
+    );
+    assert!(joined.resolve(&column("e.nosuch")).is_err());
+}
@@ -1,5 +1,21 @@
+pub mod aggregate;
+pub mod collation;
+pub mod cursor;
 pub mod db;
 pub mod db_header;
+pub mod functions;
+pub mod hash_join;
+pub mod indexes;
+pub mod join;
+pub mod operators;
 pub mod pages;
+pub mod plan;
+pub mod predicate;
 pub mod schemas;
+pub mod sorter;
+pub mod spill;
+pub mod stats;
 pub mod tables;
+pub mod vdbe;
+pub mod views;
+pub mod window;
@@ -0,0 +1,911 @@
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, iter, mem, num::NonZeroU64, vec};
+
+use super::{
+    aggregate::group_rows,
+    collation::Collations,
+    cursor::{IndexScan, TableScan},
+    db::{Database, Row, Search},
+    indexes::Index,
+    predicate::{BoundExpr, Predicate},
+    sorter::{self, SortKey, SortedRows, Sorter, SORTER_MEMORY_BUDGET},
+    tables::{Column, Table},
+    window::window_rows,
+};
+use crate::{
+    parsers::{
+        sql::{Expr, OrderingTerm},
+        value::Value,
+    },
+    utils::index_columns,
+};
+
+/// A step of running a query, which yields its rows one at a time as they are pulled, and
+/// pulls the rows it needs from the operators under it.
+pub trait Operator {
+    /// The next row, or `None` once there are none left.
+    fn next(&mut self) -> Result<Option<Row>>;
+}
+
+/// An operator of any kind, as the operator above it holds it.
+pub type BoxedOperator<'a> = Box<dyn Operator + 'a>;
+
+impl<O: Operator + ?Sized> Operator for Box<O> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        self.as_mut().next()
+    }
+}
+
+/// The rows of an operator as an iterator, for the code that consumes iterators.
+pub fn rows(op: &mut (impl Operator + ?Sized)) -> impl Iterator<Item = Result<Row>> + '_ {
+    iter::from_fn(move || op.next().transpose())
+}
+
+/// Pull every row of an operator.
+pub fn collect(mut op: impl Operator) -> Result<Vec<Row>> {
+    rows(&mut op).collect()
+}
+
+/// Rows that are already at hand, such as those of a subquery run beforehand.
+pub struct Values<I> {
+    rows: I,
+}
+
+impl<I: Iterator<Item = Row>> Values<I> {
+    pub fn new(rows: I) -> Self {
+        Values { rows }
+    }
+}
+
+impl<I: Iterator<Item = Row>> Operator for Values<I> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        Ok(self.rows.next())
+    }
+}
+
+impl Operator for TableScan<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        Iterator::next(self).transpose()
+    }
+}
+
+impl Operator for IndexScan<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        Iterator::next(self).transpose()
+    }
+}
+
+/// The entries of an index whose first column equals a key, under the collation the index
+/// is sorted by. The index is searched when the first entry is pulled.
+pub struct IndexSeek<'db> {
+    db: &'db Database,
+    pgno: NonZeroU64,
+    key: Value,
+    collation: String,
+    /// Keys the columns after the first must equal, each compared by its own collation.
+    prefix: Vec<(Value, String)>,
+    reverse: bool,
+    entries: Option<vec::IntoIter<Row>>,
+}
+
+impl<'db> IndexSeek<'db> {
+    pub fn new(db: &'db Database, pgno: NonZeroU64, key: Value, collation: &str) -> Self {
+        IndexSeek {
+            db,
+            pgno,
+            key,
+            collation: collation.to_string(),
+            prefix: vec![],
+            reverse: false,
+            entries: None,
+        }
+    }
+
+    /// Only yield the entries whose next columns also equal the keys of `prefix`.
+    pub fn with_prefix(self, prefix: Vec<(Value, String)>) -> Self {
+        IndexSeek { prefix, ..self }
+    }
+
+    /// Yield the entries last first, which puts those of equal keys in descending rowid order.
+    pub fn rev(self) -> Self {
+        IndexSeek {
+            reverse: true,
+            ..self
+        }
+    }
+}
+
+impl Operator for IndexSeek<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.entries.is_none() {
+            let collation = self
+                .db
+                .collations
+                .get(&self.collation)
+                .ok_or_else(|| anyhow!("no such collation sequence: {}", self.collation))?;
+            let mut entries = self
+                .db
+                .index_entries(self.pgno, &self.key, collation.as_ref());
+            for (i, (key, collation)) in self.prefix.iter().enumerate() {
+                let cmp = self
+                    .db
+                    .collations
+                    .get(collation)
+                    .ok_or_else(|| anyhow!("no such collation sequence: {}", collation))?;
+                entries.retain(|entry| {
+                    entry
+                        .get(i + 1)
+                        .is_some_and(|value| value.compare_collated(key, cmp.as_ref()).is_eq())
+                });
+            }
+            self.entries = Some(entries.into_iter());
+        }
+        let entries = self.entries.as_mut();
+        Ok(match self.reverse {
+            true => entries.and_then(DoubleEndedIterator::next_back),
+            false => entries.and_then(Iterator::next),
+        })
+    }
+}
+
+/// The rowids found under every key of several index seeks, in ascending order, as entries
+/// of one column. The indexes are searched when the first entry is pulled.
+pub struct IndexIntersect<'db> {
+    seeks: Vec<IndexSeek<'db>>,
+    rowids: Option<vec::IntoIter<u64>>,
+}
+
+impl<'db> IndexIntersect<'db> {
+    pub fn new(seeks: Vec<IndexSeek<'db>>) -> Self {
+        IndexIntersect {
+            seeks,
+            rowids: None,
+        }
+    }
+}
+
+impl Operator for IndexIntersect<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.rowids.is_none() {
+            let mut found: Option<Vec<u64>> = None;
+            for seek in &mut self.seeks {
+                let mut rowids = vec![];
+                while let Some(entry) = seek.next()? {
+                    rowids.extend(entry_rowid(&entry));
+                }
+                rowids.sort_unstable();
+                rowids.dedup();
+                found = Some(match found {
+                    Some(found) => rowids
+                        .into_iter()
+                        .filter(|rowid| found.binary_search(rowid).is_ok())
+                        .collect(),
+                    None => rowids,
+                });
+            }
+            self.rowids = Some(found.unwrap_or_default().into_iter());
+        }
+        let rowid = self.rowids.as_mut().and_then(Iterator::next);
+        Ok(rowid.map(|rowid| vec![Value::Integer(rowid as i64)]))
+    }
+}
+
+/// The order [`RowidFetch`] gives its rows in.
+enum FetchOrder {
+    /// That of the index entries, seeking the row of each in turn.
+    Entries,
+    /// That of the rowids, descending when `reverse`, walking the table once over the pages
+    /// holding them.
+    Rowids { reverse: bool },
+}
+
+/// The rows of the table a [`Search`] reads that the index entries of `entries` point to,
+/// keeping those that satisfy the search conditions.
+pub struct RowidFetch<'db> {
+    db: &'db Database,
+    search: Search,
+    filter: Predicate,
+    /// Columns decoded from the rows fetched, those the search and its filter read.
+    columns: Option<Vec<usize>>,
+    entries: BoxedOperator<'db>,
+    order: FetchOrder,
+    scan: Option<TableScan<'db>>,
+}
+
+impl<'db> RowidFetch<'db> {
+    /// Fetch the rows in the order of the entries, which are only pulled as rows are.
+    pub fn new(db: &'db Database, search: Search, entries: BoxedOperator<'db>) -> Self {
+        let filter = Predicate::for_search(&search, &db.collations);
+        RowidFetch::with_filter(db, search, filter, entries)
+    }
+
+    /// Fetch like [`RowidFetch::new`], keeping the rows that satisfy `filter`, the conditions
+    /// of the search already bound to the table's columns.
+    pub fn with_filter(
+        db: &'db Database,
+        search: Search,
+        filter: Predicate,
+        entries: BoxedOperator<'db>,
+    ) -> Self {
+        let columns = search.columns.as_ref().map(|columns| {
+            let mut columns = [columns.as_slice(), &filter.columns()].concat();
+            columns.sort_unstable();
+            columns.dedup();
+            columns
+        });
+        RowidFetch {
+            db,
+            filter,
+            columns,
+            search,
+            entries,
+            order: FetchOrder::Entries,
+            scan: None,
+        }
+    }
+
+    /// Fetch the rows in rowid order, pulling all of the entries first.
+    pub fn sorted(
+        db: &'db Database,
+        search: Search,
+        filter: Predicate,
+        entries: BoxedOperator<'db>,
+        reverse: bool,
+    ) -> Self {
+        RowidFetch {
+            order: FetchOrder::Rowids { reverse },
+            ..RowidFetch::with_filter(db, search, filter, entries)
+        }
+    }
+}
+
+/// Rowid an index entry points to, stored as its last column.
+fn entry_rowid(entry: &Row) -> Option<u64> {
+    entry.last().map(|rowid| rowid.clone().into())
+}
+
+impl Operator for RowidFetch<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        let reverse = match self.order {
+            FetchOrder::Entries => {
+                while let Some(entry) = self.entries.next()? {
+                    let row = entry_rowid(&entry).and_then(|rowid| {
+                        self.db
+                            .seek_rowid(self.search.pgno, rowid, self.columns.as_deref())
+                    });
+                    if let Some(row) = row {
+                        if self.filter.matches(&row)? {
+                            return Ok(Some(row));
+                        }
+                    }
+                }
+                return Ok(None);
+            }
+            FetchOrder::Rowids { reverse } => reverse,
+        };
+        if self.scan.is_none() {
+            let mut rowids = vec![];
+            while let Some(entry) = self.entries.next()? {
+                rowids.extend(entry_rowid(&entry));
+            }
+            rowids.sort_unstable();
+            rowids.dedup();
+            let search = Search {
+                indeces: Some(rowids),
+                ..self.search.clone()
+            };
+            self.scan = Some(TableScan::with_filter(
+                self.db,
+                search,
+                self.filter.clone(),
+                reverse,
+            ));
+        }
+        self.scan.as_mut().and_then(Iterator::next).transpose()
+    }
+}
+
+/// The operator reading the rows a [`Search`] asks for: those under its key in the index it
+/// starts at, or under every key of the indexes it intersects, fetched from the table in
+/// rowid order, or else the rows of the table b-tree, in descending rowid order when
+/// `reverse`.
+pub fn search_rows(db: &Database, search: Search, reverse: bool) -> Result<BoxedOperator<'_>> {
+    let filter = Predicate::for_search(&search, &db.collations);
+    filtered_search_rows(db, search, filter, reverse)
+}
+
+/// The operator reading the rows a [`Search`] asks for like [`search_rows`], keeping those
+/// that satisfy `filter`, the conditions of the search already bound to the table's columns.
+pub fn filtered_search_rows(
+    db: &Database,
+    search: Search,
+    filter: Predicate,
+    reverse: bool,
+) -> Result<BoxedOperator<'_>> {
+    if !search.intersect.is_empty() {
+        let seeks = search
+            .intersect
+            .iter()
+            .map(|(pgno, key, collation)| IndexSeek::new(db, *pgno, key.clone(), collation))
+            .collect();
+        let entries = Box::new(IndexIntersect::new(seeks));
+        return Ok(Box::new(RowidFetch::sorted(
+            db, search, filter, entries, reverse,
+        )));
+    }
+    let key = match &search.key {
+        Some(key) => key.clone(),
+        None => {
+            return Ok(Box::new(TableScan::with_filter(
+                db, search, filter, reverse,
+            )))
+        }
+    };
+    let (entries, search) = index_seek(db, search, key)?;
+    Ok(Box::new(RowidFetch::sorted(
+        db,
+        search,
+        filter,
+        Box::new(entries),
+        reverse,
+    )))
+}
+
+/// The rows under the key of the index a [`Search`] starts at, in the order of its entries,
+/// last first when `reverse`, rather than in rowid order, keeping those that satisfy the
+/// search conditions.
+pub fn ordered_search_rows(
+    db: &Database,
+    search: Search,
+    reverse: bool,
+) -> Result<BoxedOperator<'_>> {
+    let key = match &search.key {
+        Some(key) => key.clone(),
+        None => bail!("only index searches read rows in the order of their entries"),
+    };
+    let (entries, search) = index_seek(db, search, key)?;
+    let entries = match reverse {
+        true => entries.rev(),
+        false => entries,
+    };
+    Ok(Box::new(RowidFetch::new(db, search, Box::new(entries))))
+}
+
+/// The seek of the entries under `key` in the index a [`Search`] starts at, and the search
+/// of the table rows they point to.
+fn index_seek(db: &Database, search: Search, key: Value) -> Result<(IndexSeek<'_>, Search)> {
+    let pgno = NonZeroU64::new(search.schema.rootpage)
+        .ok_or_else(|| anyhow!("invalid table rootpage: {}", search.schema.rootpage))?;
+    let entries =
+        IndexSeek::new(db, search.pgno, key, &search.collation).with_prefix(search.prefix.clone());
+    let search = Search {
+        pgno,
+        key: None,
+        prefix: vec![],
+        ..search
+    };
+    Ok((entries, search))
+}
+
+/// The rows of the table a [`Search`] reads, made from the entries of an index holding every
+/// column the query reads, keeping those that satisfy the search conditions. The columns the
+/// index doesn't hold are left NULL.
+pub struct CoveringRows<'a> {
+    entries: BoxedOperator<'a>,
+    /// Position in an entry and ordinal in the row of each column the index holds.
+    places: Vec<(usize, usize)>,
+    width: usize,
+    filter: Predicate,
+}
+
+impl<'a> CoveringRows<'a> {
+    pub fn new(
+        db: &Database,
+        search: &Search,
+        index: &Index,
+        entries: BoxedOperator<'a>,
+    ) -> Result<Self> {
+        let table = Table::try_from(&search.schema)?;
+        let places = index_columns(index, &table, &table.columns)
+            .into_iter()
+            .map(|(name, column)| (column.idx, table.columns[&name].idx))
+            .collect();
+        Ok(CoveringRows {
+            entries,
+            places,
+            width: table.columns.len(),
+            filter: Predicate::new(&search.conds, &table.columns, &db.collations),
+        })
+    }
+}
+
+impl Operator for CoveringRows<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        while let Some(entry) = self.entries.next()? {
+            let mut row = vec![Value::Null; self.width];
+            for &(place, idx) in &self.places {
+                row[idx] = entry.get(place).cloned().unwrap_or(Value::Null);
+            }
+            if self.filter.matches(&row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The rows of `child` that make every condition TRUE.
+pub struct Filter<'a> {
+    child: BoxedOperator<'a>,
+    predicate: Predicate,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(
+        child: BoxedOperator<'a>,
+        conds: Vec<Expr>,
+        columns: &HashMap<String, Column>,
+        collations: &Collations,
+    ) -> Self {
+        Filter::with_predicate(child, Predicate::new(&conds, columns, collations))
+    }
+
+    /// The rows of `child` that satisfy conditions already bound to its columns.
+    pub fn with_predicate(child: BoxedOperator<'a>, predicate: Predicate) -> Self {
+        Filter { child, predicate }
+    }
+}
+
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        while let Some(row) = self.child.next()? {
+            if self.predicate.matches(&row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The rows of `child`, each changed by a function, such as one appending the values of
+/// subqueries.
+pub struct Map<'a> {
+    child: BoxedOperator<'a>,
+    f: Box<dyn FnMut(Row) -> Result<Row> + 'a>,
+}
+
+impl<'a> Map<'a> {
+    pub fn new(child: BoxedOperator<'a>, f: impl FnMut(Row) -> Result<Row> + 'a) -> Self {
+        Map {
+            child,
+            f: Box::new(f),
+        }
+    }
+}
+
+impl Operator for Map<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        match self.child.next()? {
+            Some(row) => (self.f)(row).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The values of the result expressions for each row of `child`.
+pub struct Project<'a> {
+    child: BoxedOperator<'a>,
+    /// The result expressions, bound to the columns of the rows of `child`.
+    exprs: Vec<BoundExpr>,
+}
+
+impl<'a> Project<'a> {
+    pub fn new(
+        child: BoxedOperator<'a>,
+        exprs: Vec<Expr>,
+        columns: &HashMap<String, Column>,
+        collations: &Collations,
+    ) -> Self {
+        Project {
+            child,
+            exprs: exprs
+                .iter()
+                .map(|expr| BoundExpr::new(expr, columns, collations))
+                .collect(),
+        }
+    }
+}
+
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        self.child
+            .next()?
+            .map(|row| self.exprs.iter().map(|expr| expr.eval(&row)).collect())
+            .transpose()
+    }
+}
+
+/// The rows of `child` sorted by ORDER BY terms, compared by `keys`, spilling to temporary
+/// files when they don't fit in memory. Every row is pulled before the first is given.
+pub struct Sort<'a> {
+    child: BoxedOperator<'a>,
+    /// The expressions of the ORDER BY terms, bound to the columns of the rows of `child`.
+    order_by: Vec<BoundExpr>,
+    keys: Vec<SortKey>,
+    sorted: Option<SortedRows>,
+}
+
+impl<'a> Sort<'a> {
+    pub fn new(
+        child: BoxedOperator<'a>,
+        order_by: Vec<OrderingTerm>,
+        keys: Vec<SortKey>,
+        columns: &HashMap<String, Column>,
+        collations: &Collations,
+    ) -> Self {
+        Sort {
+            child,
+            order_by: order_by
+                .iter()
+                .map(|term| BoundExpr::new(&term.expr, columns, collations))
+                .collect(),
+            keys,
+            sorted: None,
+        }
+    }
+}
+
+impl Operator for Sort<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.sorted.is_none() {
+            let mut sorter = Sorter::new(mem::take(&mut self.keys), SORTER_MEMORY_BUDGET);
+            while let Some(row) = self.child.next()? {
+                let key = self
+                    .order_by
+                    .iter()
+                    .map(|expr| expr.eval(&row))
+                    .collect::<Result<_>>()?;
+                sorter.push(key, row)?;
+            }
+            self.sorted = Some(sorter.finish()?);
+        }
+        self.sorted.as_mut().and_then(Iterator::next).transpose()
+    }
+}
+
+/// A row for each group of the rows of `child` with equal GROUP BY values, or a single one
+/// for all of them without GROUP BY, with the values of the aggregate calls appended. Every
+/// row is pulled before the first group is given.
+pub struct Aggregate<'a> {
+    child: BoxedOperator<'a>,
+    group_by: Vec<Expr>,
+    calls: Vec<Expr>,
+    columns: &'a HashMap<String, Column>,
+    collations: &'a Collations,
+    groups: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Aggregate<'a> {
+    pub fn new(
+        child: BoxedOperator<'a>,
+        group_by: Vec<Expr>,
+        calls: Vec<Expr>,
+        columns: &'a HashMap<String, Column>,
+        collations: &'a Collations,
+    ) -> Self {
+        Aggregate {
+            child,
+            group_by,
+            calls,
+            columns,
+            collations,
+            groups: None,
+        }
+    }
+}
+
+impl Operator for Aggregate<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.groups.is_none() {
+            let groups = group_rows(
+                rows(&mut self.child),
+                &self.group_by,
+                &self.calls,
+                self.columns,
+                self.collations,
+            )?;
+            self.groups = Some(groups.into_iter());
+        }
+        Ok(self.groups.as_mut().and_then(Iterator::next))
+    }
+}
+
+/// The rows of `child` with the values of the window function calls appended, computed once
+/// every row is pulled.
+pub struct Window<'a> {
+    child: BoxedOperator<'a>,
+    calls: Vec<Expr>,
+    columns: &'a HashMap<String, Column>,
+    collations: &'a Collations,
+    rows: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Window<'a> {
+    pub fn new(
+        child: BoxedOperator<'a>,
+        calls: Vec<Expr>,
+        columns: &'a HashMap<String, Column>,
+        collations: &'a Collations,
+    ) -> Self {
+        Window {
+            child,
+            calls,
+            columns,
+            collations,
+            rows: None,
+        }
+    }
+}
+
+impl Operator for Window<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.rows.is_none() {
+            let rows = rows(&mut self.child).collect::<Result<_>>()?;
+            let rows = window_rows(rows, &self.calls, self.columns, self.collations)?;
+            self.rows = Some(rows.into_iter());
+        }
+        Ok(self.rows.as_mut().and_then(Iterator::next))
+    }
+}
+
+/// The rows of `child` that aren't equal to one before them, compared by `keys`. Only the
+/// last row is kept to compare with when equal rows are `grouped` together.
+pub struct Distinct<'a> {
+    child: BoxedOperator<'a>,
+    seen: sorter::Distinct,
+}
+
+impl<'a> Distinct<'a> {
+    pub fn new(child: BoxedOperator<'a>, keys: Vec<SortKey>, grouped: bool) -> Self {
+        Distinct {
+            child,
+            seen: sorter::Distinct::new(keys, grouped),
+        }
+    }
+}
+
+impl Operator for Distinct<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        while let Some(row) = self.child.next()? {
+            if self.seen.insert(&row) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// At most `limit` rows of `child`, after skipping the first `offset`. No row is pulled once
+/// they are all given, so the scans under it stop reading pages.
+pub struct Limit<'a> {
+    child: BoxedOperator<'a>,
+    offset: usize,
+    limit: usize,
+}
+
+impl<'a> Limit<'a> {
+    pub fn new(child: BoxedOperator<'a>, offset: usize, limit: usize) -> Self {
+        Limit {
+            child,
+            offset,
+            limit,
+        }
+    }
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        while self.offset > 0 {
+            if self.child.next()?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        if self.limit == 0 {
+            return Ok(None);
+        }
+        self.limit -= 1;
+        self.child.next()
+    }
+}
+
+type InnerFn<'a> = Box<dyn FnMut(&Row) -> Result<BoxedOperator<'a>> + 'a>;
+
+/// Joins each row of `outer` with the rows `inner` finds for it by nested loops, keeping the
+/// joined rows that satisfy the `on` terms, and then the `after` ones. A LEFT JOIN pads the
+/// outer rows nothing matched with NULLs, up to `left` columns.
+pub struct Join<'a> {
+    outer: BoxedOperator<'a>,
+    inner: InnerFn<'a>,
+    on: Predicate,
+    after: Predicate,
+    left: Option<usize>,
+    /// The outer row being joined, the inner rows left for it and whether one matched.
+    current: Option<(Row, BoxedOperator<'a>, bool)>,
+}
+
+impl<'a> Join<'a> {
+    pub fn new(
+        outer: BoxedOperator<'a>,
+        inner: impl FnMut(&Row) -> Result<BoxedOperator<'a>> + 'a,
+        on: Predicate,
+        after: Predicate,
+        left: Option<usize>,
+    ) -> Self {
+        Join {
+            outer,
+            inner: Box::new(inner),
+            on,
+            after,
+            left,
+            current: None,
+        }
+    }
+}
+
+impl Operator for Join<'_> {
+    fn next(&mut self) -> Result<Option<Row>> {
+        loop {
+            if let Some((row, inner, matched)) = &mut self.current {
+                while let Some(found) = inner.next()? {
+                    let joined = [row.as_slice(), &found].concat();
+                    if !self.on.matches(&joined)? {
+                        continue;
+                    }
+                    *matched = true;
+                    if self.after.matches(&joined)? {
+                        return Ok(Some(joined));
+                    }
+                }
+                if let Some((mut row, _, false)) = self.current.take() {
+                    if let Some(width) = self.left {
+                        row.resize(width, Value::Null);
+                        if self.after.matches(&row)? {
+                            return Ok(Some(row));
+                        }
+                    }
+                }
+            }
+            let row = match self.outer.next()? {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+            let inner = (self.inner)(&row)?;
+            self.current = Some((row, inner, false));
+        }
+    }
+}
+
+#[cfg(test)]
+fn values<'a>(rows: &[&[i64]]) -> BoxedOperator<'a> {
+    let rows: Vec<Row> = rows
+        .iter()
+        .map(|row| row.iter().map(|&n| Value::Integer(n)).collect())
+        .collect();
+    Box::new(Values::new(rows.into_iter()))
+}
+
+#[cfg(test)]
+fn column(name: &str, idx: usize) -> (String, Column) {
+    use super::tables::CellType;
+    let column = Column {
+        idx,
+        name: name.to_string(),
+        cell_type: CellType::Integer,
+        nullable: true,
+        pk: false,
+        collation: None,
+    };
+    (name.to_string(), column)
+}
+
+#[test]
+fn test_pipeline() {
+    use crate::parsers::sql::Operator as Op;
+    let columns = HashMap::from([column("a", 0), column("b", 1)]);
+    let collations = Collations::default();
+    let binary = collations.get("BINARY").unwrap().clone();
+    let key = SortKey {
+        desc: true,
+        nulls_first: false,
+        collation: binary.clone(),
+    };
+    let rows = values(&[&[1, 10], &[2, 20], &[3, 10], &[4, 30], &[5, 20], &[6, 40]]);
+    let a = || Expr::Column("a".to_string());
+    let b = || Expr::Column("b".to_string());
+    let filter = Filter::new(
+        rows,
+        vec![Expr::binary(
+            a(),
+            Op::Greater,
+            Expr::Literal(Value::Integer(1)),
+        )],
+        &columns,
+        &collations,
+    );
+    let order_by = vec![OrderingTerm {
+        expr: b(),
+        desc: true,
+        nulls: None,
+    }];
+    let sort = Sort::new(Box::new(filter), order_by, vec![key], &columns, &collations);
+    let project = Project::new(Box::new(sort), vec![b()], &columns, &collations);
+    let keys = vec![SortKey {
+        desc: false,
+        nulls_first: true,
+        collation: binary,
+    }];
+    let distinct = Distinct::new(Box::new(project), keys, false);
+    let limit = Limit::new(Box::new(distinct), 1, 2);
+    let expected: Vec<Row> = vec![vec![Value::Integer(30)], vec![Value::Integer(20)]];
+    assert_eq!(collect(limit).unwrap(), expected);
+}
+
+#[test]
+fn test_left_join() {
+    use crate::parsers::sql::Operator as Op;
+    let columns = HashMap::from([column("a", 0), column("b", 1)]);
+    let collations = Collations::default();
+    let inner = |row: &Row| {
+        // Each outer row finds the inner rows up to its own value
+        let n = match row[0] {
+            Value::Integer(n) => n,
+            _ => 0,
+        };
+        let rows: Vec<Row> = (1..n).map(|m| vec![Value::Integer(m)]).collect();
+        Ok(Box::new(Values::new(rows.into_iter())) as BoxedOperator)
+    };
+    let on = vec![Expr::binary(
+        Expr::Column("b".to_string()),
+        Op::Ne,
+        Expr::Literal(Value::Integer(2)),
+    )];
+    let join = Join::new(
+        values(&[&[1], &[3]]),
+        inner,
+        Predicate::new(&on, &columns, &collations),
+        Predicate::default(),
+        Some(2),
+    );
+    let expected: Vec<Row> = vec![
+        vec![Value::Integer(1), Value::Null],
+        vec![Value::Integer(3), Value::Integer(1)],
+    ];
+    assert_eq!(collect(join).unwrap(), expected);
+}
+
+#[test]
+fn test_scan_error() {
+    let db =
+        super::db::test_database(&[("CREATE TABLE t (a integer)", vec![vec![Value::Integer(1)]])]);
+    let schema = db.get_schemas().remove("t").unwrap();
+    let read = |pgno: u64| {
+        let search = Search::new(
+            NonZeroU64::new(pgno).unwrap(),
+            None,
+            None,
+            schema.clone(),
+            vec![],
+        );
+        let mut rows = search_rows(&db, search, false)?;
+        let mut all = vec![];
+        while let Some(row) = rows.next()? {
+            all.push(row);
+        }
+        Ok::<_, anyhow::Error>(all)
+    };
+
+    assert_eq!(read(2).unwrap(), vec![vec![Value::Integer(1)]]);
+    // A page past the end of the file fails the scan rather than ending it quietly
+    assert!(read(9).is_err());
+}
@@ -57,10 +57,7 @@ pub enum PageKind {
 
 impl PageKind {
     pub fn is_interior(self) -> bool {
-        match self {
-            Self::IndexInterior | Self::TableInterior => true,
-            _ => false,
-        }
+        matches!(self, Self::IndexInterior | Self::TableInterior)
     }
 }
 
@@ -78,6 +75,7 @@ impl TryFrom<u8> for PageKind {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct BtreeHeader {
     /// Page type
@@ -108,7 +106,7 @@ impl<'a> BtreeHeader {
                 rightmost_pointer,
             ),
         ) = tuple((
-            map_res(u8, |n| PageKind::try_from(n)),
+            map_res(u8, PageKind::try_from),
             be_u16,
             be_u16,
             be_u16,
@@ -130,7 +128,7 @@ impl<'a> BtreeHeader {
     }
 
     /// Parse a cell based on the type of Btree.
-    pub fn parse_cell(&'a self, input: &'a [u8]) -> IResult<&[u8], Cell<'a>> {
+    pub fn parse_cell(&'a self, input: &'a [u8]) -> IResult<&'a [u8], Cell<'a>> {
         match self.kind {
             PageKind::TableLeaf => {
                 let (input, (size, row_id)) = tuple((varint, varint))(input)?;
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use super::{
+    indexes::Index,
+    schemas::{Schema, SchemaType},
+};
+
+/// A step of a query plan and the steps it is made of, as `EXPLAIN QUERY PLAN` shows them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlanNode {
+    pub detail: String,
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    pub fn new(detail: impl Into<String>) -> Self {
+        PlanNode {
+            detail: detail.into(),
+            children: vec![],
+        }
+    }
+
+    pub fn with_children(detail: impl Into<String>, children: Vec<PlanNode>) -> Self {
+        PlanNode {
+            detail: detail.into(),
+            children,
+        }
+    }
+}
+
+/// How the rows of a table are read.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Access {
+    /// Every row, in rowid order.
+    Scan,
+    /// Every row, in the order of the index with this rootpage.
+    IndexScan(u64),
+    /// The row an integer primary key names.
+    Rowid,
+    /// The rows whose rowids lie in a range, flagged whether it is bounded below and above.
+    RowidRange(bool, bool),
+    /// The rows under a key of the index with this rootpage, of this many of its columns.
+    IndexSearch(u64, usize),
+    /// Every entry of the index with this rootpage, which holds all the columns needed.
+    CoveringIndexScan(u64),
+    /// The entries under a key of the index with this rootpage, of this many of its columns,
+    /// which hold all the columns needed.
+    CoveringIndexSearch(u64, usize),
+    /// The rows hashed under a key, an expression of the table's own columns.
+    Hash(String),
+    /// The rows of a VALUES clause, a single one for a SELECT without FROM.
+    Values(usize),
+}
+
+/// The plan line reading the rows of `name`, in SQLite's words: a SCAN reads the whole
+/// table, while a SEARCH only reads the rows under a key.
+pub fn access_detail(name: &str, access: &Access, schemas: &HashMap<String, Schema>) -> String {
+    let index = |rootpage: u64| {
+        schemas
+            .values()
+            .filter(|s| s.stype == SchemaType::Index && s.rootpage == rootpage)
+            .find_map(|s| Index::try_from(s).ok())
+    };
+    let kind = match access {
+        Access::CoveringIndexScan(_) | Access::CoveringIndexSearch(..) => "COVERING INDEX",
+        _ => "INDEX",
+    };
+    match access {
+        Access::Scan => format!("SCAN {name}"),
+        Access::IndexScan(rootpage) | Access::CoveringIndexScan(rootpage) => {
+            match index(*rootpage) {
+                Some(index) => format!("SCAN {name} USING {kind} {}", index.name),
+                None => format!("SCAN {name}"),
+            }
+        }
+        Access::Rowid => format!("SEARCH {name} USING INTEGER PRIMARY KEY (rowid=?)"),
+        Access::RowidRange(lower, upper) => {
+            let bounds = [lower.then_some("rowid>?"), upper.then_some("rowid<?")];
+            let bounds: Vec<_> = bounds.into_iter().flatten().collect();
+            format!(
+                "SEARCH {name} USING INTEGER PRIMARY KEY ({})",
+                bounds.join(" AND ")
+            )
+        }
+        Access::IndexSearch(rootpage, columns) | Access::CoveringIndexSearch(rootpage, columns) => {
+            match index(*rootpage).filter(|i| !i.columns.is_empty()) {
+                Some(index) => {
+                    let keys = index.columns.iter().take((*columns).max(1));
+                    let keys: Vec<_> = keys.map(|c| format!("{}=?", c.name)).collect();
+                    format!(
+                        "SEARCH {name} USING {kind} {} ({})",
+                        index.name,
+                        keys.join(" AND ")
+                    )
+                }
+                None => format!("SEARCH {name} USING {kind}"),
+            }
+        }
+        Access::Hash(key) => format!("SEARCH {name} USING HASH TABLE ({key}=?)"),
+        Access::Values(1) => "SCAN CONSTANT ROW".to_string(),
+        Access::Values(rows) => format!("SCAN {rows}-ROW VALUES CLAUSE"),
+    }
+}
+
+/// The lines of a plan under a `QUERY PLAN` heading, drawn as a tree like the sqlite3 shell
+/// does.
+pub fn render(plan: &[PlanNode]) -> Vec<String> {
+    let mut lines = vec!["QUERY PLAN".to_string()];
+    draw(plan, "", &mut lines);
+    lines
+}
+
+fn draw(nodes: &[PlanNode], indent: &str, lines: &mut Vec<String>) {
+    for (i, node) in nodes.iter().enumerate() {
+        let last = i + 1 == nodes.len();
+        let (branch, below) = match last {
+            true => ("`--", "   "),
+            false => ("|--", "|  "),
+        };
+        lines.push(format!("{indent}{branch}{}", node.detail));
+        draw(&node.children, &format!("{indent}{below}"), lines);
+    }
+}
+
+#[test]
+fn test_render() {
+    let plan = [
+        PlanNode::with_children(
+            "MATERIALIZE t",
+            vec![
+                PlanNode::new("SCAN emp"),
+                PlanNode::new("USE TEMP B-TREE FOR ORDER BY"),
+            ],
+        ),
+        PlanNode::new("SCAN t"),
+        PlanNode::with_children(
+            "SCALAR SUBQUERY 1",
+            vec![PlanNode::with_children(
+                "MATERIALIZE (subquery-1)",
+                vec![PlanNode::new("SCAN depts")],
+            )],
+        ),
+    ];
+    assert_eq!(
+        render(&plan),
+        [
+            "QUERY PLAN",
+            "|--MATERIALIZE t",
+            "|  |--SCAN emp",
+            "|  `--USE TEMP B-TREE FOR ORDER BY",
+            "|--SCAN t",
+            "`--SCALAR SUBQUERY 1",
+            "   `--MATERIALIZE (subquery-1)",
+            "      `--SCAN depts",
+        ]
+    );
+}
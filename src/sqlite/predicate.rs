@@ -0,0 +1,368 @@
+use anyhow::Result;
+use std::{collections::HashMap, rc::Rc};
+
+use super::{
+    collation::{CollationFn, Collations, BINARY},
+    db::Search,
+    functions::ScalarFunction,
+    tables::{CellType, Column, Table},
+};
+use crate::parsers::{
+    sql::{arithmetic, compare, unary, Expr, Operator, UnaryOperator},
+    value::Value,
+};
+
+/// An expression with its columns bound to their place in the row, and the affinities,
+/// collating sequences and functions of its operators resolved, so evaluating it looks
+/// nothing up by name.
+enum Bound {
+    Value(Value),
+    Column(usize),
+    Not(Box<Bound>),
+    And(Box<Bound>, Box<Bound>),
+    Or(Box<Bound>, Box<Bound>),
+    Unary(UnaryOperator, Box<Bound>),
+    Arithmetic(Operator, Box<Bound>, Box<Bound>),
+    Compare {
+        op: Operator,
+        lhs: Box<Bound>,
+        rhs: Box<Bound>,
+        /// Affinities applied to the left and to the right operand before comparing them.
+        affinities: (Option<CellType>, Option<CellType>),
+        collation: CollationFn,
+    },
+    IsNull(Box<Bound>),
+    NotNull(Box<Bound>),
+    Function {
+        function: ScalarFunction,
+        args: Vec<Bound>,
+        collation: CollationFn,
+    },
+}
+
+impl Bound {
+    /// Bind `expr` to the rows `columns` describes.
+    fn new(expr: &Expr, columns: &HashMap<String, Column>, collations: &Collations) -> Self {
+        let bind = |expr: &Expr| Box::new(Bound::new(expr, columns, collations));
+        let collating = |name: &str| -> CollationFn {
+            match collations.get(name).or_else(|| collations.get(BINARY)) {
+                Some(cmp) => cmp.clone(),
+                None => Rc::new(|a: &str, b: &str| a.cmp(b)),
+            }
+        };
+        match expr {
+            Expr::Literal(value) => Bound::Value(value.clone()),
+            Expr::Column(name) => match columns.get(name) {
+                Some(column) => Bound::Column(column.idx),
+                None => Bound::Value(Value::Null),
+            },
+            Expr::Not(expr) => Bound::Not(bind(expr)),
+            Expr::Binary(lhs, Operator::And, rhs) => Bound::And(bind(lhs), bind(rhs)),
+            Expr::Binary(lhs, Operator::Or, rhs) => Bound::Or(bind(lhs), bind(rhs)),
+            Expr::Collate(expr, _) => Bound::new(expr, columns, collations),
+            Expr::Unary(op, expr) => Bound::Unary(*op, bind(expr)),
+            Expr::Binary(lhs, op, rhs) if op.is_arithmetic() => {
+                Bound::Arithmetic(*op, bind(lhs), bind(rhs))
+            }
+            Expr::Binary(lhs, op, rhs) => Bound::Compare {
+                op: *op,
+                lhs: bind(lhs),
+                rhs: bind(rhs),
+                affinities: Expr::operand_affinities(lhs, rhs, columns),
+                collation: collating(Expr::comparison_collation(lhs, rhs, columns)),
+            },
+            Expr::IsNull(expr) => Bound::IsNull(bind(expr)),
+            Expr::NotNull(expr) => Bound::NotNull(bind(expr)),
+            Expr::Function { name, args, .. } => match ScalarFunction::from_name(name) {
+                Some(function) if expr.aggregate().is_none() => Bound::Function {
+                    function,
+                    args: args
+                        .iter()
+                        .map(|arg| Bound::new(arg, columns, collations))
+                        .collect(),
+                    // Functions comparing text use the collation of their first column
+                    collation: collating(
+                        args.iter()
+                            .find_map(|arg| arg.collation(columns))
+                            .map_or(BINARY, |(name, _)| name),
+                    ),
+                },
+                _ => Bound::Value(Value::Null),
+            },
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let lower = Expr::binary(*expr.clone(), Operator::GreaterEq, *low.clone());
+                let upper = Expr::binary(*expr.clone(), Operator::LessEq, *high.clone());
+                let between = bind(&Expr::binary(lower, Operator::And, upper));
+                match negated {
+                    true => Bound::Not(between),
+                    false => *between,
+                }
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // Each item is compared as `expr = item` would be, any match making it TRUE
+                let found = list
+                    .iter()
+                    .map(|item| bind(&Expr::binary(*expr.clone(), Operator::Eq, item.clone())))
+                    .reduce(|found, eq| Box::new(Bound::Or(found, eq)))
+                    .unwrap_or_else(|| Box::new(Bound::Value(Value::from(false))));
+                match negated {
+                    true => Bound::Not(found),
+                    false => *found,
+                }
+            }
+            // Subqueries and windows are computed by the executor, and read like columns
+            Expr::InSelect { .. } | Expr::Subquery(_) | Expr::Exists(_) | Expr::Over { .. } => {
+                Bound::Value(Value::Null)
+            }
+        }
+    }
+
+    /// Add the ordinals of the columns the expression reads to `columns`.
+    fn columns(&self, columns: &mut Vec<usize>) {
+        match self {
+            Bound::Value(_) => {}
+            Bound::Column(idx) => columns.push(*idx),
+            Bound::Not(expr)
+            | Bound::Unary(_, expr)
+            | Bound::IsNull(expr)
+            | Bound::NotNull(expr) => expr.columns(columns),
+            Bound::And(lhs, rhs)
+            | Bound::Or(lhs, rhs)
+            | Bound::Arithmetic(_, lhs, rhs)
+            | Bound::Compare { lhs, rhs, .. } => {
+                lhs.columns(columns);
+                rhs.columns(columns);
+            }
+            Bound::Function { args, .. } => args.iter().for_each(|arg| arg.columns(columns)),
+        }
+    }
+
+    fn eval(&self, row: &[Value]) -> Result<Value> {
+        Ok(match self {
+            Bound::Value(value) => value.clone(),
+            Bound::Column(idx) => row.get(*idx).cloned().unwrap_or(Value::Null),
+            Bound::Not(expr) => match expr.eval(row)?.truth() {
+                Some(truth) => Value::from(!truth),
+                None => Value::Null,
+            },
+            Bound::And(lhs, rhs) => match (lhs.eval(row)?.truth(), rhs.eval(row)?.truth()) {
+                (Some(false), _) | (_, Some(false)) => Value::from(false),
+                (Some(true), Some(true)) => Value::from(true),
+                _ => Value::Null,
+            },
+            Bound::Or(lhs, rhs) => match (lhs.eval(row)?.truth(), rhs.eval(row)?.truth()) {
+                (Some(true), _) | (_, Some(true)) => Value::from(true),
+                (Some(false), Some(false)) => Value::from(false),
+                _ => Value::Null,
+            },
+            Bound::Unary(op, expr) => unary(*op, expr.eval(row)?),
+            Bound::Arithmetic(op, lhs, rhs) => arithmetic(*op, lhs.eval(row)?, rhs.eval(row)?),
+            Bound::Compare {
+                op,
+                lhs,
+                rhs,
+                affinities,
+                collation,
+            } => {
+                let (l, r) = (lhs.eval(row)?, rhs.eval(row)?);
+                let (l, r) = match affinities {
+                    (Some(affinity), _) => (l.apply_affinity(*affinity), r),
+                    (_, Some(affinity)) => (l, r.apply_affinity(*affinity)),
+                    (None, None) => (l, r),
+                };
+                compare(*op, &l, &r, collation.as_ref())
+            }
+            Bound::IsNull(expr) => Value::from(expr.eval(row)? == Value::Null),
+            Bound::NotNull(expr) => Value::from(expr.eval(row)? != Value::Null),
+            Bound::Function {
+                function,
+                args,
+                collation,
+            } => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>>>()?;
+                function.call(&values, collation.as_ref())?
+            }
+        })
+    }
+}
+
+/// An expression bound once to the columns of the rows it is evaluated for. This is how
+/// every expression is evaluated, [`Expr::eval`] included.
+pub struct BoundExpr(Bound);
+
+impl BoundExpr {
+    pub fn new(expr: &Expr, columns: &HashMap<String, Column>, collations: &Collations) -> Self {
+        BoundExpr(Bound::new(expr, columns, collations))
+    }
+
+    /// The value of the expression for `row`, or the error SQLite would raise computing it.
+    pub fn eval(&self, row: &[Value]) -> Result<Value> {
+        self.0.eval(row)
+    }
+}
+
+/// Conditions bound once to the columns of the rows they are checked against. Clones share
+/// the bound conditions.
+#[derive(Clone, Default)]
+pub struct Predicate {
+    conds: Rc<[Bound]>,
+}
+
+impl Predicate {
+    pub fn new(conds: &[Expr], columns: &HashMap<String, Column>, collations: &Collations) -> Self {
+        Predicate {
+            conds: conds
+                .iter()
+                .map(|cond| Bound::new(cond, columns, collations))
+                .collect(),
+        }
+    }
+
+    /// The conditions of a [`Search`], bound to the columns of the table it reads.
+    pub fn for_search(search: &Search, collations: &Collations) -> Self {
+        if search.conds.is_empty() {
+            return Predicate::default();
+        }
+        match Table::try_from(&search.schema) {
+            Ok(table) => Predicate::new(&search.conds, &table.columns, collations),
+            // No row satisfies conditions on a table whose definition can't be read
+            Err(_) => Predicate {
+                conds: Rc::new([Bound::Value(Value::Null)]),
+            },
+        }
+    }
+
+    /// Ordinals of the columns the conditions read, in order and without repeats.
+    pub fn columns(&self) -> Vec<usize> {
+        let mut columns = vec![];
+        self.conds
+            .iter()
+            .for_each(|cond| cond.columns(&mut columns));
+        columns.sort_unstable();
+        columns.dedup();
+        columns
+    }
+
+    /// Whether the row makes all of the conditions TRUE, not FALSE or NULL.
+    pub fn matches(&self, row: &[Value]) -> Result<bool> {
+        for cond in self.conds.iter() {
+            if cond.eval(row)?.truth() != Some(true) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+fn search(sql: &str, conds: &str) -> Search {
+    use super::schemas::{Schema, SchemaType};
+    use crate::parsers::sql::Select;
+    use std::{num::NonZeroU64, str::FromStr};
+
+    let schema = Schema {
+        stype: SchemaType::Table,
+        name: "t".to_string(),
+        table_name: "t".to_string(),
+        rootpage: 2,
+        sql: sql.to_string(),
+    };
+    let conds = Select::from_str(&format!("select * from t where {conds}"))
+        .unwrap()
+        .conds;
+    Search::new(NonZeroU64::new(2).unwrap(), None, None, schema, conds)
+}
+
+#[test]
+fn test_matches_eval() {
+    let search = search(
+        "CREATE TABLE t (id integer primary key, name text collate nocase, n integer)",
+        "name = 'BOB' and n between 2 and '5' and n not in (3, 4) and abs(-n) > 1",
+    );
+    let collations = Collations::default();
+    let predicate = Predicate::for_search(&search, &collations);
+    let table = Table::try_from(&search.schema).unwrap();
+    let rows = [
+        vec![Value::Null, Value::Text("bob".into()), Value::Integer(2)],
+        vec![Value::Null, Value::Text("bob".into()), Value::Integer(3)],
+        vec![Value::Null, Value::Text("Bob".into()), Value::Integer(5)],
+        vec![Value::Null, Value::Text("alice".into()), Value::Integer(2)],
+        vec![Value::Null, Value::Null, Value::Integer(2)],
+    ];
+    let expected = [true, false, true, false, false];
+    for (row, expected) in rows.iter().zip(expected) {
+        let eval = search
+            .conds
+            .iter()
+            .all(|cond| cond.eval(row, &table.columns, &collations).unwrap().truth() == Some(true));
+        assert_eq!(eval, expected);
+        assert_eq!(predicate.matches(row).unwrap(), expected);
+    }
+}
+
+/// Run with `cargo test --release bench_bound_scan -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_bound_scan() {
+    use std::time::Instant;
+
+    let search = search(
+        "CREATE TABLE companies (id integer primary key, name text, domain text, \
+         year_founded text, industry text, size_range text, locality text, country text, \
+         current_employees text, total_employees text)",
+        "country = 'peru' and year_founded > 1990",
+    );
+    let collations = Collations::default();
+    let rows: Vec<Vec<Value>> = (0..200_000)
+        .map(|i| {
+            let mut row = vec![Value::Null; 10];
+            row[1] = Value::Text(format!("company {i}"));
+            row[3] = Value::Text((1950 + i % 70).to_string());
+            row[7] = Value::Text(["peru", "chile", "spain", "india"][i % 4].to_string());
+            row
+        })
+        .collect();
+
+    // Reparsing the table for every condition of every row, as scans used to
+    let start = Instant::now();
+    let reparsed = rows
+        .iter()
+        .filter(|row| {
+            search.conds.iter().all(|cond| {
+                Table::try_from(&search.schema).is_ok_and(|table| {
+                    cond.eval(row, &table.columns, &collations).unwrap().truth() == Some(true)
+                })
+            })
+        })
+        .count();
+    let reparsing = start.elapsed();
+
+    let start = Instant::now();
+    let predicate = Predicate::for_search(&search, &collations);
+    let bound = rows
+        .iter()
+        .filter(|row| predicate.matches(row).unwrap())
+        .count();
+    let binding = start.elapsed();
+
+    assert_eq!(reparsed, bound);
+    println!(
+        "{} rows: reparsed {:?}, bound {:?} ({:.0}x)",
+        rows.len(),
+        reparsing,
+        binding,
+        reparsing.as_secs_f64() / binding.as_secs_f64()
+    );
+}
@@ -0,0 +1,363 @@
+use anyhow::Result;
+use std::{cmp::Ordering, collections::BTreeSet, mem, rc::Rc};
+
+use super::{
+    collation::CollationFn,
+    db::Row,
+    spill::{entry_size, Run, SpillFile},
+};
+use crate::parsers::value::Value;
+
+/// Bytes of rows a [`Sorter`] keeps in memory before it spills a sorted run to disk.
+pub const SORTER_MEMORY_BUDGET: usize = 32 * 1024 * 1024;
+
+/// How a single ORDER BY term orders its values.
+#[derive(Clone)]
+pub struct SortKey {
+    pub desc: bool,
+    pub nulls_first: bool,
+    pub collation: CollationFn,
+}
+
+impl SortKey {
+    pub fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if self.nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if self.nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            (a, b) if self.desc => a.compare_collated(b, self.collation.as_ref()).reverse(),
+            (a, b) => a.compare_collated(b, self.collation.as_ref()),
+        }
+    }
+}
+
+/// Compare two sorter entries by their leading key values.
+fn compare_entries(keys: &[SortKey], a: &Row, b: &Row) -> Ordering {
+    keys.iter()
+        .zip(a.iter().zip(b))
+        .map(|(key, (a, b))| key.compare(a, b))
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// External merge sorter: rows are sorted in memory until they outgrow the budget, then
+/// written out as sorted runs that are merged back when the rows are read.
+pub struct Sorter {
+    keys: Vec<SortKey>,
+    budget: usize,
+    buffer: Vec<Row>,
+    buffered: usize,
+    runs: Vec<SpillFile>,
+}
+
+impl Sorter {
+    pub fn new(keys: Vec<SortKey>, budget: usize) -> Self {
+        Sorter {
+            keys,
+            budget,
+            buffer: vec![],
+            buffered: 0,
+            runs: vec![],
+        }
+    }
+
+    /// Number of key values each row is ordered by.
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Add a row, ordered by the `key` values computed for it.
+    pub fn push(&mut self, key: Vec<Value>, row: Row) -> Result<()> {
+        let mut entry = key;
+        entry.extend(row);
+        self.buffered += entry_size(&entry);
+        self.buffer.push(entry);
+        if self.buffered > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.sort_buffer();
+        self.runs.push(SpillFile::create("sort", &self.buffer)?);
+        self.buffer.clear();
+        self.buffered = 0;
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        let keys = &self.keys;
+        self.buffer.sort_by(|a, b| compare_entries(keys, a, b));
+    }
+
+    /// Whether any rows had to be written to temporary files.
+    pub fn spilled(&self) -> bool {
+        !self.runs.is_empty()
+    }
+
+    /// Finish the input and return the rows in sorted order.
+    pub fn finish(mut self) -> Result<SortedRows> {
+        if self.spilled() && !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        self.sort_buffer();
+
+        let mut runs = vec![];
+        let mut heads = vec![];
+        for file in mem::take(&mut self.runs) {
+            let mut run = Run::open(file)?;
+            heads.push(run.next_entry()?);
+            runs.push(run);
+        }
+        Ok(SortedRows {
+            keys: mem::take(&mut self.keys),
+            memory: mem::take(&mut self.buffer).into_iter(),
+            runs,
+            heads,
+        })
+    }
+}
+
+/// Sorted output of a [`Sorter`], merging its spilled runs when there are any.
+pub struct SortedRows {
+    keys: Vec<SortKey>,
+    memory: std::vec::IntoIter<Row>,
+    runs: Vec<Run>,
+    heads: Vec<Option<Row>>,
+}
+
+impl SortedRows {
+    fn next_entry(&mut self) -> Result<Option<Row>> {
+        if self.runs.is_empty() {
+            return Ok(self.memory.next());
+        }
+
+        // Ties go to the earlier run, which keeps the sort stable across runs
+        let mut smallest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some(entry) = head {
+                let is_smaller = match smallest.and_then(|s| self.heads[s].as_ref()) {
+                    Some(best) => compare_entries(&self.keys, entry, best).is_lt(),
+                    None => true,
+                };
+                if is_smaller {
+                    smallest = Some(i);
+                }
+            }
+        }
+        match smallest {
+            Some(i) => {
+                let next = self.runs[i].next_entry()?;
+                Ok(mem::replace(&mut self.heads[i], next))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Iterator for SortedRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key_len = self.keys.len();
+        match self.next_entry() {
+            Ok(entry) => entry.map(|mut entry| Ok(entry.split_off(key_len))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// A row ordered by the keys of the [`Distinct`] filter holding it.
+struct DistinctRow {
+    keys: Rc<[SortKey]>,
+    row: Row,
+}
+
+impl Ord for DistinctRow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_entries(&self.keys, &self.row, &other.row)
+    }
+}
+
+impl PartialOrd for DistinctRow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DistinctRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for DistinctRow {}
+
+/// Filter for `SELECT DISTINCT` that lets a row through only the first time it is seen.
+/// Rows are equal when each pair of values compares equal by its key, so NULLs match NULLs.
+pub struct Distinct {
+    keys: Rc<[SortKey]>,
+    seen: BTreeSet<DistinctRow>,
+    /// Whether equal rows arrive next to each other, so only the last one is remembered.
+    grouped: bool,
+}
+
+impl Distinct {
+    pub fn new(keys: Vec<SortKey>, grouped: bool) -> Self {
+        Distinct {
+            keys: keys.into(),
+            seen: BTreeSet::new(),
+            grouped,
+        }
+    }
+
+    /// Remember the row, returning whether no equal row came before it.
+    pub fn insert(&mut self, row: &Row) -> bool {
+        let entry = DistinctRow {
+            keys: self.keys.clone(),
+            row: row.clone(),
+        };
+        if self.grouped {
+            if self.seen.contains(&entry) {
+                return false;
+            }
+            self.seen.clear();
+        }
+        self.seen.insert(entry)
+    }
+
+    /// Whether a row equal to this one was remembered.
+    pub fn contains(&self, row: &Row) -> bool {
+        self.seen.contains(&DistinctRow {
+            keys: self.keys.clone(),
+            row: row.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+fn sort_with_budget(keys: Vec<SortKey>, rows: Vec<Row>, budget: usize) -> (Vec<Row>, bool) {
+    let mut sorter = Sorter::new(keys, budget);
+    for row in rows {
+        sorter.push(vec![row[0].clone()], row).unwrap();
+    }
+    let spilled = sorter.spilled();
+    let sorted = sorter.finish().unwrap().collect::<Result<_>>().unwrap();
+    (sorted, spilled)
+}
+
+#[test]
+fn test_sorter_nulls_and_direction() {
+    use super::collation::{Collations, BINARY};
+    use Value::*;
+    let binary = Collations::default().get(BINARY).unwrap().clone();
+    let rows = vec![
+        vec![Integer(2)],
+        vec![Null],
+        vec![Text("a".to_string())],
+        vec![Float(1.5)],
+    ];
+    let key = |desc, nulls_first| SortKey {
+        desc,
+        nulls_first,
+        collation: binary.clone(),
+    };
+
+    let (asc, _) = sort_with_budget(vec![key(false, true)], rows.clone(), usize::MAX);
+    assert_eq!(
+        asc,
+        vec![
+            vec![Null],
+            vec![Float(1.5)],
+            vec![Integer(2)],
+            vec![Text("a".to_string())]
+        ]
+    );
+    let (desc, _) = sort_with_budget(vec![key(true, true)], rows, usize::MAX);
+    assert_eq!(
+        desc,
+        vec![
+            vec![Null],
+            vec![Text("a".to_string())],
+            vec![Integer(2)],
+            vec![Float(1.5)]
+        ]
+    );
+}
+
+#[test]
+fn test_sorter_spills_and_merges() {
+    use super::collation::Collations;
+    use Value::*;
+    let nocase = Collations::default().get("NOCASE").unwrap().clone();
+    let key = SortKey {
+        desc: false,
+        nulls_first: false,
+        collation: nocase,
+    };
+    let rows: Vec<Row> = (0..500)
+        .map(|i| {
+            let name = match i % 4 {
+                0 => Null,
+                1 => Text(format!("Name {}", (i * 7919) % 500)),
+                2 => Text(format!("name {}", (i * 104_729) % 500)),
+                _ => Integer((i * 31) % 97 - 50),
+            };
+            vec![name, Integer(i), Blob(vec![i as u8; i as usize % 5])]
+        })
+        .collect();
+
+    let (in_memory, spilled) = sort_with_budget(vec![key.clone()], rows.clone(), usize::MAX);
+    assert!(!spilled);
+    let (merged, spilled) = sort_with_budget(vec![key], rows, 2048);
+    assert!(spilled);
+    assert_eq!(merged, in_memory);
+    assert_eq!(merged.first().unwrap()[0], Integer(-50));
+    assert_eq!(merged.last().unwrap()[0], Null);
+}
+
+#[test]
+fn test_distinct() {
+    use super::collation::Collations;
+    use Value::*;
+    let collations = Collations::default();
+    let key = |name| SortKey {
+        desc: false,
+        nulls_first: true,
+        collation: collations.get(name).unwrap().clone(),
+    };
+    let rows = [
+        vec![Text("a".to_string()), Integer(1)],
+        vec![Text("A".to_string()), Float(1.0)],
+        vec![Null, Integer(1)],
+        vec![Text("b".to_string()), Integer(2)],
+        vec![Null, Integer(1)],
+        vec![Text("a".to_string()), Integer(1)],
+    ];
+    let distinct = |keys: Vec<SortKey>, grouped| {
+        let mut distinct = Distinct::new(keys, grouped);
+        rows.iter()
+            .filter(|row| distinct.insert(row))
+            .map(|row| row[0].clone())
+            .collect::<Vec<_>>()
+    };
+
+    let a = || Text("a".to_string());
+    let b = || Text("b".to_string());
+    assert_eq!(
+        distinct(vec![key("NOCASE"), key("BINARY")], false),
+        vec![a(), Null, b()]
+    );
+    assert_eq!(
+        distinct(vec![key("BINARY"), key("BINARY")], false),
+        vec![a(), Text("A".to_string()), Null, b()]
+    );
+    // Grouped filters only compare against the previous row
+    assert_eq!(
+        distinct(vec![key("NOCASE"), key("BINARY")], true),
+        vec![a(), Null, b(), Null, a()]
+    );
+}
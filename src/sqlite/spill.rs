@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    mem,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::db::Row;
+use crate::parsers::{
+    payload::{encode_record, Payload},
+    value::Value,
+    varint::{encode_varint, varint},
+};
+
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Approximate heap footprint of a row, used to decide when to spill.
+pub fn entry_size(entry: &Row) -> usize {
+    entry
+        .iter()
+        .map(|v| {
+            mem::size_of::<Value>()
+                + match v {
+                    Value::Text(s) => s.len(),
+                    Value::Blob(b) => b.len(),
+                    _ => 0,
+                }
+        })
+        .sum()
+}
+
+/// Temporary file of rows set aside for a later pass, removed when dropped.
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    /// Write `entries` to a new file whose name says which operator it belongs to.
+    pub fn create(operator: &str, entries: &[Row]) -> Result<Self> {
+        let mut writer = SpillWriter::create(operator)?;
+        for entry in entries {
+            writer.write(entry)?;
+        }
+        writer.finish()
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Appends rows to a [`SpillFile`] as length-prefixed records.
+pub struct SpillWriter {
+    writer: BufWriter<File>,
+    file: SpillFile,
+}
+
+impl SpillWriter {
+    pub fn create(operator: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "esquilait-{}-{}-{}",
+            operator,
+            process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = SpillFile { path };
+        Ok(SpillWriter {
+            writer: BufWriter::new(File::create(&file.path)?),
+            file,
+        })
+    }
+
+    pub fn write(&mut self, entry: &Row) -> Result<()> {
+        let record = encode_record(entry);
+        let mut len = vec![];
+        encode_varint(record.len() as u64, &mut len);
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<SpillFile> {
+        self.writer.flush()?;
+        Ok(self.file)
+    }
+}
+
+/// Reader over the entries of a [`SpillFile`], in the order they were written.
+pub struct Run {
+    reader: BufReader<File>,
+    _file: SpillFile,
+}
+
+impl Run {
+    pub fn open(file: SpillFile) -> Result<Self> {
+        Ok(Run {
+            reader: BufReader::new(File::open(&file.path)?),
+            _file: file,
+        })
+    }
+
+    pub fn next_entry(&mut self) -> Result<Option<Row>> {
+        // A varint is at most 9 bytes and ends at the first byte without its high bit
+        let mut len_bytes = vec![];
+        loop {
+            let mut byte = [0u8];
+            match self.reader.read_exact(&mut byte) {
+                Ok(()) => len_bytes.push(byte[0]),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && len_bytes.is_empty() => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+            if byte[0] < 0x80 || len_bytes.len() == 9 {
+                break;
+            }
+        }
+        let (_, len) = varint(&len_bytes).map_err(|e| anyhow!("reading spilled rows: {}", e))?;
+
+        let mut record = vec![0u8; len as usize];
+        self.reader.read_exact(&mut record)?;
+        let payload = Payload {
+            size: len,
+            payload: &record,
+            overflow: None,
+        };
+        let (_, entry) = payload
+            .parse()
+            .map_err(|e| anyhow!("reading spilled rows: {}", e))?;
+        Ok(Some(entry))
+    }
+}
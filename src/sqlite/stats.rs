@@ -0,0 +1,501 @@
+use anyhow::{anyhow, bail, Result};
+use std::{cmp::Ordering, collections::HashMap, num::NonZeroU64};
+
+use super::{
+    collation::BINARY,
+    cursor::{IndexScan, TableScan},
+    db::{Database, Row, Search},
+    indexes::Index,
+    pages::PageKind,
+    schemas::{Schema, SchemaType},
+    tables::Table,
+};
+use crate::parsers::{payload::Payload, value::Value};
+
+/// Rows a table is assumed to have when `ANALYZE` hasn't counted them, as SQLite assumes.
+pub const DEFAULT_TABLE_ROWS: f64 = 1_000_000.0;
+
+/// Rows an index key is assumed to match when `ANALYZE` hasn't measured the index.
+pub const DEFAULT_KEY_ROWS: f64 = 10.0;
+
+/// Rows of a scan that reading one row by its rowid costs as much as, for the pages it
+/// reads on the way down the table b-tree.
+const LOOKUP_COST: f64 = 3.0;
+
+/// What `ANALYZE` measured of an index, from its `sqlite_stat1` and `sqlite_stat4` rows.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct IndexStat {
+    /// Lowercased name of the table the index belongs to.
+    pub table: String,
+    /// Entries in the index, which is the number of rows of its table unless it is partial.
+    pub rows: u64,
+    /// Average number of rows sharing the values of the first one, two, ... columns.
+    pub per_prefix: Vec<u64>,
+    /// Keys sampled by `sqlite_stat4`, with the number of rows equal to their first column.
+    pub samples: Vec<(Value, u64)>,
+}
+
+/// The statistics `ANALYZE` stores for the planner: how many rows each table has, and how
+/// many rows the keys of each index match.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Statistics {
+    tables: HashMap<String, u64>,
+    indexes: HashMap<String, IndexStat>,
+}
+
+impl Statistics {
+    /// Read the `sqlite_stat1` table, and the `sqlite_stat4` one when present. A database
+    /// that was never analyzed has no statistics.
+    pub fn load(db: &Database) -> Result<Self> {
+        let mut stats = Statistics::default();
+        for row in all_rows(db, "sqlite_stat1")? {
+            if let [Value::Text(table), idx, Value::Text(stat), ..] = &row[..] {
+                let idx = match idx {
+                    Value::Text(idx) => Some(idx.as_str()),
+                    _ => None,
+                };
+                stats.add_stat1(table, idx, stat);
+            }
+        }
+        for row in all_rows(db, "sqlite_stat4")? {
+            if let [_, Value::Text(idx), Value::Text(neq), _, _, Value::Blob(sample), ..] = &row[..]
+            {
+                let payload = Payload {
+                    size: sample.len() as u64,
+                    payload: sample,
+                    overflow: None,
+                };
+                let (_, key) = payload
+                    .parse()
+                    .map_err(|e| anyhow!("reading sqlite_stat4: {}", e))?;
+                let eq = neq.split_whitespace().next().and_then(|n| n.parse().ok());
+                if let (Some(value), Some(eq)) = (key.into_iter().next(), eq) {
+                    let stat = stats.indexes.entry(idx.to_lowercase()).or_default();
+                    stat.samples.push((value, eq));
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Record a row of `sqlite_stat1`: the row count of a table, alone or followed by the
+    /// average rows per key prefix of one of its indexes. Words that aren't numbers, such as
+    /// `unordered`, are flags that don't change the estimates.
+    pub fn add_stat1(&mut self, table: &str, idx: Option<&str>, stat: &str) {
+        let numbers: Vec<u64> = stat
+            .split_whitespace()
+            .map_while(|word| word.parse().ok())
+            .collect();
+        let Some((&rows, per_prefix)) = numbers.split_first() else {
+            return;
+        };
+        match idx.filter(|idx| !idx.eq_ignore_ascii_case(table)) {
+            // Samples of the index taken before describe entries it may no longer have
+            Some(idx) => {
+                let stat = IndexStat {
+                    table: table.to_lowercase(),
+                    rows,
+                    per_prefix: per_prefix.to_vec(),
+                    samples: vec![],
+                };
+                self.indexes.insert(idx.to_lowercase(), stat);
+            }
+            None => {
+                self.tables.insert(table.to_lowercase(), rows);
+            }
+        }
+    }
+
+    /// Replace what was known of the tables and indexes [`analyze`] read with the rows it
+    /// computed, forgetting those that are now empty and got none.
+    pub fn extend(&mut self, analysis: &Analysis) {
+        for table in &analysis.tables {
+            self.tables.remove(table);
+            self.indexes.retain(|_, stat| stat.table != *table);
+        }
+        for index in &analysis.indexes {
+            self.indexes.remove(index);
+        }
+        for (table, idx, stat) in &analysis.rows {
+            self.add_stat1(table, idx.as_deref(), stat);
+        }
+    }
+
+    /// Estimated rows of a table: its own count when it has no indexes, else the entries of
+    /// its largest index, since partial ones leave rows out.
+    pub fn table_rows(&self, table: &str) -> f64 {
+        let table = table.to_lowercase();
+        let indexed = self
+            .indexes
+            .values()
+            .filter(|stat| stat.table == table)
+            .map(|stat| stat.rows)
+            .max();
+        match self.tables.get(&table).copied().or(indexed) {
+            Some(rows) => rows as f64,
+            None => DEFAULT_TABLE_ROWS,
+        }
+    }
+
+    /// Whether `ANALYZE` measured the index, so its estimates aren't guesses.
+    pub fn measured(&self, index: &Index) -> bool {
+        self.indexes.contains_key(&index.name.to_lowercase())
+    }
+
+    /// Estimated rows whose first `columns` indexed columns equal a key starting with `key`,
+    /// compared by `collation`: the count of a sampled key when `key` is one and the only
+    /// column, else the average for any key of that many columns. Indexes `ANALYZE` didn't
+    /// measure are guessed to find a row less for each column after the first, down to 5
+    /// rows, as SQLite guesses.
+    pub fn equality_rows(
+        &self,
+        index: &Index,
+        key: &Value,
+        columns: usize,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> f64 {
+        if index.unique && index.columns.len() == columns {
+            return 1.0;
+        }
+        let guess = (DEFAULT_KEY_ROWS + 1.0 - columns as f64).max(5.0);
+        let stat = match self.indexes.get(&index.name.to_lowercase()) {
+            Some(stat) => stat,
+            None => return guess.min(self.table_rows(&index.table_name)),
+        };
+        let sampled = stat
+            .samples
+            .iter()
+            .find(|(sample, _)| sample.compare_collated(key, collation).is_eq())
+            .filter(|_| columns == 1);
+        match (sampled, stat.per_prefix.get(columns.saturating_sub(1))) {
+            (Some(&(_, eq)), _) => eq as f64,
+            (None, Some(&per_key)) => per_key as f64,
+            (None, None) => guess.min(stat.rows as f64),
+        }
+    }
+}
+
+/// A row of `sqlite_stat1`: the table, its index or `None` for a table without indexes, and
+/// the row count followed by the average rows sharing each key prefix of the index.
+pub type Stat1 = (String, Option<String>, String);
+
+/// What `ANALYZE` found: the `sqlite_stat1` rows it computed, and the tables and indexes it
+/// read, which may have got none.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub rows: Vec<Stat1>,
+    /// Lowercased names of the tables read along with all of their indexes.
+    pub tables: Vec<String>,
+    /// Lowercased names of the indexes read on their own.
+    pub indexes: Vec<String>,
+}
+
+/// Compute the `sqlite_stat1` rows of `ANALYZE` by reading the b-trees of every table, or of
+/// the table or index named by `target`, a table being analyzed along with its indexes.
+/// Empty tables and indexes get no rows, as in SQLite.
+pub fn analyze(db: &Database, target: Option<&str>) -> Result<Analysis> {
+    let mut schemas = db.get_schemas_vec();
+    schemas.sort_by(|a, b| a.name.cmp(&b.name));
+    let named = |s: &Schema, name: &str| s.name.eq_ignore_ascii_case(name);
+    let (tables, indexes): (Vec<_>, Vec<_>) = match target {
+        None => (
+            schemas
+                .iter()
+                .filter(|s| s.stype == SchemaType::Table && !s.name.starts_with("sqlite_"))
+                .collect(),
+            vec![],
+        ),
+        Some(name) => match schemas.iter().find(|s| named(s, name)) {
+            Some(s) if s.stype == SchemaType::Table => (vec![s], vec![]),
+            Some(s) if s.stype == SchemaType::Index => (vec![], vec![s]),
+            _ => bail!("no such table or index: {}", name),
+        },
+    };
+
+    let mut analysis = Analysis::default();
+    for table in tables {
+        analysis.tables.push(table.name.to_lowercase());
+        let table_indexes: Vec<_> = schemas
+            .iter()
+            .filter(|s| {
+                s.stype == SchemaType::Index && s.table_name.eq_ignore_ascii_case(&table.name)
+            })
+            .filter(|s| Index::try_from(*s).is_ok())
+            .collect();
+        if table_indexes.is_empty() {
+            let count = count_rows(db, table)?;
+            if count > 0 {
+                analysis
+                    .rows
+                    .push((table.name.clone(), None, count.to_string()));
+            }
+        }
+        for index in table_indexes {
+            analysis.rows.extend(analyze_index(db, index, &schemas)?);
+        }
+    }
+    for index in indexes {
+        analysis.indexes.push(index.name.to_lowercase());
+        analysis.rows.extend(analyze_index(db, index, &schemas)?);
+    }
+    Ok(analysis)
+}
+
+/// The `sqlite_stat1` row of an index, counting the distinct values of each prefix of its
+/// key while walking the entries in order.
+fn analyze_index(db: &Database, schema: &Schema, schemas: &[Schema]) -> Result<Option<Stat1>> {
+    let index = Index::try_from(schema)?;
+    let table = match schemas
+        .iter()
+        .find(|s| s.stype == SchemaType::Table && s.name.eq_ignore_ascii_case(&index.table_name))
+    {
+        Some(s) => Table::try_from(s)?,
+        None => bail!("no such table: {}", index.table_name),
+    };
+    let collations = (0..index.columns.len())
+        .map(|i| {
+            let name = index
+                .collation(i, &table)
+                .unwrap_or_else(|| BINARY.to_string());
+            db.collations
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| anyhow!("no such collation sequence: {}", name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let pgno = NonZeroU64::new(schema.rootpage)
+        .ok_or_else(|| anyhow!("invalid rootpage of {}: {}", schema.name, schema.rootpage))?;
+
+    let mut count: u64 = 0;
+    let mut distinct = vec![0u64; collations.len()];
+    let mut previous: Option<Row> = None;
+    for entry in IndexScan::new(db, pgno, false) {
+        let entry = entry?;
+        // Entries are sorted, so a prefix is new once any of its columns differs
+        let differs = match &previous {
+            Some(previous) => collations
+                .iter()
+                .enumerate()
+                .position(|(i, cmp)| {
+                    let (a, b) = (previous.get(i), entry.get(i));
+                    match (a, b) {
+                        (Some(a), Some(b)) => a.compare_collated(b, cmp.as_ref()).is_ne(),
+                        _ => false,
+                    }
+                })
+                .unwrap_or(collations.len()),
+            None => 0,
+        };
+        for n in &mut distinct[differs..] {
+            *n += 1;
+        }
+        count += 1;
+        previous = Some(entry);
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut stat = count.to_string();
+    for n in distinct {
+        stat.push_str(&format!(" {}", count.div_ceil(n)));
+    }
+    Ok(Some((index.table_name, Some(schema.name.clone()), stat)))
+}
+
+/// Number of rows of a table, counting the cells of the leaves of its b-tree rather than
+/// reading them.
+fn count_rows(db: &Database, schema: &Schema) -> Result<u64> {
+    let pgno = NonZeroU64::new(schema.rootpage)
+        .ok_or_else(|| anyhow!("invalid rootpage of {}: {}", schema.name, schema.rootpage))?;
+    let mut pending = vec![pgno];
+    let mut count = 0;
+    while let Some(pgno) = pending.pop() {
+        let page = db.get_page(pgno)?;
+        match page.header.kind {
+            PageKind::TableLeaf => count += page.header.cell_count as u64,
+            PageKind::TableInterior => {
+                pending.extend(page.cells().filter_map(|cell| cell.next_page()));
+                pending.extend(
+                    page.header
+                        .rightmost_pointer
+                        .and_then(|pgno| NonZeroU64::new(pgno.into())),
+                );
+            }
+            _ => bail!("page {} of {} is not a table page", pgno, schema.name),
+        }
+    }
+    Ok(count)
+}
+
+/// Every row of a table, or none when it doesn't exist.
+fn all_rows(db: &Database, name: &str) -> Result<Vec<Row>> {
+    let schema = match db.get_schemas().remove(name) {
+        Some(schema) => schema,
+        None => return Ok(vec![]),
+    };
+    let pgno = NonZeroU64::new(schema.rootpage)
+        .ok_or_else(|| anyhow!("invalid rootpage of {}: {}", name, schema.rootpage))?;
+    let search = Search::new(pgno, None, None, schema, vec![]);
+    TableScan::new(db, search, false).collect()
+}
+
+/// Cost of reading every row of a table of `rows` rows, counted in rows read.
+pub fn scan_cost(rows: f64) -> f64 {
+    rows
+}
+
+/// Cost of reading the row a rowid names in a table of `rows` rows.
+pub fn rowid_cost(rows: f64) -> f64 {
+    rows.max(2.0).log2()
+}
+
+/// Cost of finding `found` rows of a table of `rows` rows through an index: seeking the key,
+/// then reading each entry and looking its row up by rowid.
+pub fn index_cost(rows: f64, found: f64) -> f64 {
+    rowid_cost(rows) + found * (1.0 + LOOKUP_COST)
+}
+
+/// Cost of finding `found` rows of a table of `rows` rows through an index whose entries hold
+/// every column the query reads: seeking the key and reading each entry, with no lookups.
+pub fn covering_cost(rows: f64, found: f64) -> f64 {
+    rowid_cost(rows) + found
+}
+
+/// Cost of finding rows through several indexes at once, each finding `found` rows of a
+/// table of `rows` rows: seeking every key and reading its entries, then looking up the
+/// rows under all of them, taking the conditions to be independent.
+pub fn intersection_cost(rows: f64, found: &[f64]) -> f64 {
+    let rows = rows.max(1.0);
+    let seeks: f64 = found.iter().map(|&found| rowid_cost(rows) + found).sum();
+    let matched = found
+        .iter()
+        .fold(rows, |matched, &found| matched * found / rows);
+    seeks + matched * (1.0 + LOOKUP_COST)
+}
+
+#[test]
+fn test_estimates() {
+    let index: Index = "CREATE INDEX idx_country ON companies (country)"
+        .parse()
+        .unwrap();
+    let binary = |a: &str, b: &str| a.cmp(b);
+    let peru = Value::Text("peru".to_string());
+
+    let mut stats = Statistics::default();
+    assert_eq!(stats.table_rows("companies"), DEFAULT_TABLE_ROWS);
+    assert_eq!(
+        stats.equality_rows(&index, &peru, 1, &binary),
+        DEFAULT_KEY_ROWS
+    );
+
+    stats.add_stat1("companies", Some("idx_country"), "20000 2500 unordered");
+    stats.add_stat1("depts", None, "4");
+    assert_eq!(stats.table_rows("Companies"), 20000.0);
+    assert_eq!(stats.table_rows("depts"), 4.0);
+    assert_eq!(stats.equality_rows(&index, &peru, 1, &binary), 2500.0);
+    // An index matching an eighth of the rows still beats reading them all
+    assert!(index_cost(20000.0, 2500.0) < scan_cost(20000.0));
+    assert!(index_cost(20000.0, 10000.0) > scan_cost(20000.0));
+    // Without lookups, even reading half of the entries beats reading every row
+    assert!(covering_cost(20000.0, 10000.0) < scan_cost(20000.0));
+    assert!(covering_cost(20000.0, 10.0) < index_cost(20000.0, 10.0));
+
+    // Two keys each matching a tenth of the rows are best found by intersecting
+    assert!(intersection_cost(10000.0, &[1000.0, 1000.0]) < index_cost(10000.0, 1000.0));
+    assert!(intersection_cost(10000.0, &[1.0, 1000.0]) > index_cost(10000.0, 1.0));
+
+    let stat = stats.indexes.get_mut("idx_country").unwrap();
+    stat.samples.push((Value::Text("Peru".to_string()), 9000));
+    assert_eq!(stats.equality_rows(&index, &peru, 1, &binary), 2500.0);
+    let nocase = |a: &str, b: &str| a.to_lowercase().cmp(&b.to_lowercase());
+    assert_eq!(stats.equality_rows(&index, &peru, 1, &nocase), 9000.0);
+}
+
+#[test]
+fn test_prefix_estimates() {
+    let index: Index = "CREATE INDEX idx_place ON companies (country, city)"
+        .parse()
+        .unwrap();
+    let binary = |a: &str, b: &str| a.cmp(b);
+    let peru = Value::Text("Peru".to_string());
+
+    // Unmeasured, a key of both columns is guessed to find fewer rows than one of the first
+    let mut stats = Statistics::default();
+    assert_eq!(stats.equality_rows(&index, &peru, 1, &binary), 10.0);
+    assert_eq!(stats.equality_rows(&index, &peru, 2, &binary), 9.0);
+
+    // Measured, each key reads the average of its own prefix, and samples only the first
+    stats.add_stat1("companies", Some("idx_place"), "20000 2500 40");
+    stats
+        .indexes
+        .get_mut("idx_place")
+        .unwrap()
+        .samples
+        .push((peru.clone(), 9000));
+    assert_eq!(stats.equality_rows(&index, &peru, 1, &binary), 9000.0);
+    assert_eq!(stats.equality_rows(&index, &peru, 2, &binary), 40.0);
+
+    let unique: Index = "CREATE UNIQUE INDEX idx_place ON companies (country, city)"
+        .parse()
+        .unwrap();
+    assert_eq!(stats.equality_rows(&unique, &peru, 1, &binary), 9000.0);
+    assert_eq!(stats.equality_rows(&unique, &peru, 2, &binary), 1.0);
+}
+
+#[test]
+fn test_extend() {
+    let stat1 = |table: &str, idx: Option<&str>, stat: &str| {
+        (table.to_string(), idx.map(String::from), stat.to_string())
+    };
+    let mut stats = Statistics::default();
+    stats.extend(&Analysis {
+        rows: vec![
+            stat1("emp", Some("idx_recent"), "2 1"),
+            stat1("emp", Some("idx_emp_dept"), "6 2"),
+            stat1("depts", None, "4"),
+        ],
+        tables: vec!["emp".to_string(), "depts".to_string()],
+        indexes: vec![],
+    });
+    // A partial index holds fewer entries than the table has rows
+    assert_eq!(stats.table_rows("emp"), 6.0);
+    assert_eq!(stats.table_rows("depts"), 4.0);
+
+    let sales = Value::Text("sales".to_string());
+    let dept = stats.indexes.get_mut("idx_emp_dept").unwrap();
+    dept.samples.push((sales.clone(), 5));
+    stats.extend(&Analysis {
+        rows: vec![stat1("emp", Some("idx_emp_dept"), "8 4")],
+        tables: vec![],
+        indexes: vec!["idx_emp_dept".to_string()],
+    });
+    assert_eq!(stats.table_rows("emp"), 8.0);
+    assert_eq!(stats.indexes["idx_emp_dept"].per_prefix, vec![4]);
+    // The samples taken before don't outweigh the new averages
+    let index: Index = "CREATE INDEX idx_emp_dept ON emp (dept)".parse().unwrap();
+    let binary = |a: &str, b: &str| a.cmp(b);
+    assert_eq!(stats.equality_rows(&index, &sales, 1, &binary), 4.0);
+    assert!(stats.measured(&index));
+
+    // Tables and indexes that are now empty get no rows, and lose what was known of them
+    stats.extend(&Analysis {
+        rows: vec![],
+        tables: vec!["emp".to_string(), "depts".to_string()],
+        indexes: vec![],
+    });
+    assert!(!stats.measured(&index));
+    assert!(stats.indexes.is_empty());
+    assert_eq!(stats.table_rows("depts"), DEFAULT_TABLE_ROWS);
+}
+
+#[test]
+fn test_analyze_counts_rows() {
+    let rows = (1..=3).map(|n| vec![Value::Integer(n)]).collect();
+    let db = super::db::test_database(&[
+        ("CREATE TABLE t (a integer)", rows),
+        ("CREATE TABLE empty (a integer)", vec![]),
+    ]);
+    let analysis = analyze(&db, None).unwrap();
+    assert_eq!(analysis.rows, [("t".to_string(), None, "3".to_string())]);
+    assert_eq!(analysis.tables, ["empty", "t"]);
+}
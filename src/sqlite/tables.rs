@@ -1,16 +1,16 @@
 use anyhow::Error;
-use itertools::Itertools;
 use std::{collections::HashMap, str::FromStr};
 
-use crate::parsers::sql::{create_sql, Select, SelectColumns};
+use crate::parsers::sql::create_sql;
 
 use super::schemas::Schema;
 
+/// Type affinity of a column, derived from its declared type name.
 #[derive(Debug, PartialEq, Clone, Copy, PartialOrd, Eq, Ord)]
 pub enum CellType {
-    Null,
     Integer,
     Float,
+    Numeric,
     Text,
     Blob,
 }
@@ -18,13 +18,19 @@ pub enum CellType {
 impl std::str::FromStr for CellType {
     type Err = &'static str;
 
+    /// Determine the affinity of a declared type following SQLite's rules, in order.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let t = match s {
-            "integer" => CellType::Integer,
-            "float" => CellType::Float,
-            "text" => CellType::Text,
-            "blob" => CellType::Blob,
-            _ => CellType::Null,
+        let s = s.to_ascii_uppercase();
+        let t = if s.contains("INT") {
+            CellType::Integer
+        } else if s.contains("CHAR") || s.contains("CLOB") || s.contains("TEXT") {
+            CellType::Text
+        } else if s.contains("BLOB") || s.is_empty() {
+            CellType::Blob
+        } else if s.contains("REAL") || s.contains("FLOA") || s.contains("DOUB") {
+            CellType::Float
+        } else {
+            CellType::Numeric
         };
         Ok(t)
     }
@@ -37,6 +43,8 @@ pub struct Column {
     pub cell_type: CellType,
     pub nullable: bool,
     pub pk: bool,
+    /// Collating sequence declared with `COLLATE`, BINARY when absent.
+    pub collation: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -47,16 +55,20 @@ pub struct Table {
 }
 
 impl Table {
-    /// Get [`Column`]s corresponding in a [`Select`]
-    pub fn select(&self, sel: &Select) -> Vec<Column> {
-        match &sel.columns {
-            SelectColumns::Columns(cols) => cols
-                .iter()
-                .flat_map(|sc| self.columns.get(sc).cloned())
-                .collect(),
-            SelectColumns::Count => Vec::new(),
-            SelectColumns::All => self.columns.values().sorted().cloned().collect(),
-        }
+    /// The column a name refers to, matched ignoring case like SQLite does.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.get(name).or_else(|| {
+            self.columns
+                .values()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// Position of the INTEGER PRIMARY KEY column, which aliases the rowid and so is stored
+    /// as NULL in the table's records.
+    pub fn rowid_alias(&self) -> Option<usize> {
+        let column = self.columns.get(self.key.as_ref()?)?;
+        (column.cell_type == CellType::Integer).then_some(column.idx)
     }
 }
 
@@ -0,0 +1,1384 @@
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, num::NonZeroU64};
+
+use super::{
+    aggregate::{Accumulator, AggregateKind},
+    collation::{CollationFn, BINARY},
+    cursor::{IndexScan, TableScan},
+    db::{Database, Row, Search},
+    functions::ScalarFunction,
+    schemas::SchemaType,
+    sorter::{SortKey, SortedRows, Sorter, SORTER_MEMORY_BUDGET},
+    tables::{CellType, Column},
+};
+use crate::parsers::{
+    sql::{arithmetic, compare, unary, Expr, Operator, UnaryOperator},
+    value::Value,
+};
+
+/// How a sorter orders the values of one of its key columns.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyColumn {
+    pub collation: String,
+    pub desc: bool,
+    pub nulls_first: bool,
+}
+
+/// An instruction of a bytecode program, modelled on SQLite's VDBE opcodes. Values live in
+/// numbered registers and rows are read through numbered cursors. Unlike SQLite's, the
+/// comparison opcodes store their result in a register rather than jump, and only `IfNot`
+/// jumps on a value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Insn {
+    /// Start the program by jumping to the code loading its constants.
+    Init {
+        target: usize,
+    },
+    Goto {
+        target: usize,
+    },
+    Halt,
+    /// Open a cursor on the table or index b-tree rooted at page `root`. Index cursors
+    /// compare keys by the `collation` the index was built with.
+    OpenRead {
+        cursor: usize,
+        root: u64,
+        name: String,
+        collation: Option<String>,
+    },
+    /// Open a cursor on a sorter, ordering the records inserted by their leading `keys`.
+    SorterOpen {
+        cursor: usize,
+        keys: Vec<KeyColumn>,
+    },
+    /// Move to the first entry, or jump to `target` when there is none.
+    Rewind {
+        cursor: usize,
+        target: usize,
+    },
+    /// Move to the last entry, or jump to `target` when there is none.
+    Last {
+        cursor: usize,
+        target: usize,
+    },
+    /// Move to the next entry, and jump to `target` unless there was none.
+    Next {
+        cursor: usize,
+        target: usize,
+    },
+    /// Move to the previous entry, and jump to `target` unless there was none.
+    Prev {
+        cursor: usize,
+        target: usize,
+    },
+    /// Move a table cursor to the row whose rowid is in register `rowid`, or jump to
+    /// `target` when there is no such row.
+    SeekRowid {
+        cursor: usize,
+        target: usize,
+        rowid: usize,
+    },
+    /// Move an index cursor to the first entry whose key is not below register `key`, or
+    /// jump to `target` when there is none.
+    SeekGE {
+        cursor: usize,
+        target: usize,
+        key: usize,
+    },
+    /// Jump to `target` when the key of the index entry is above register `key`.
+    IdxGT {
+        cursor: usize,
+        target: usize,
+        key: usize,
+    },
+    /// Read the rowid the index entry points at.
+    IdxRowid {
+        cursor: usize,
+        dest: usize,
+    },
+    Column {
+        cursor: usize,
+        column: usize,
+        dest: usize,
+        name: String,
+    },
+    Integer {
+        value: i64,
+        dest: usize,
+    },
+    Real {
+        value: f64,
+        dest: usize,
+    },
+    String8 {
+        value: String,
+        dest: usize,
+    },
+    Blob {
+        value: Vec<u8>,
+        dest: usize,
+    },
+    Null {
+        dest: usize,
+    },
+    SCopy {
+        src: usize,
+        dest: usize,
+    },
+    /// Convert the value of a register to a type affinity.
+    Affinity {
+        reg: usize,
+        affinity: CellType,
+    },
+    /// Compare two registers with one of the comparison operators, ordering text by
+    /// `collation`.
+    Compare {
+        op: Operator,
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+        collation: String,
+    },
+    /// Apply an arithmetic, concatenation or bitwise operator.
+    Arithmetic {
+        op: Operator,
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    And {
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    Or {
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    Not {
+        src: usize,
+        dest: usize,
+    },
+    Unary {
+        op: UnaryOperator,
+        src: usize,
+        dest: usize,
+    },
+    IsNull {
+        src: usize,
+        dest: usize,
+    },
+    NotNull {
+        src: usize,
+        dest: usize,
+    },
+    /// Set the collating sequence of the next function or aggregate step.
+    CollSeq {
+        collation: String,
+    },
+    /// Call a scalar function with the `count` registers from `args`.
+    Function {
+        name: String,
+        args: usize,
+        count: usize,
+        dest: usize,
+    },
+    /// Feed the `count` registers from `args` to the aggregate whose state is in `acc`.
+    AggStep {
+        name: String,
+        distinct: bool,
+        args: usize,
+        count: usize,
+        acc: usize,
+    },
+    /// Replace the state of an aggregate with its result.
+    AggFinal {
+        name: String,
+        count: usize,
+        acc: usize,
+    },
+    /// Jump to `target` when the register is false, or NULL and `null` is set.
+    IfNot {
+        src: usize,
+        target: usize,
+        null: bool,
+    },
+    /// When the register is positive, decrement it and jump to `target`.
+    IfPos {
+        reg: usize,
+        target: usize,
+    },
+    /// Decrement the register, and jump to `target` once it reaches zero.
+    DecrJumpZero {
+        reg: usize,
+        target: usize,
+    },
+    /// Insert the `count` registers from `start` into a sorter, keys first.
+    SorterInsert {
+        cursor: usize,
+        start: usize,
+        count: usize,
+    },
+    /// Sort the records of a sorter and move to the first one, or jump to `target` when
+    /// there is none.
+    SorterSort {
+        cursor: usize,
+        target: usize,
+    },
+    /// Move to the next sorted record, and jump to `target` unless there was none.
+    SorterNext {
+        cursor: usize,
+        target: usize,
+    },
+    /// Produce a row of the `count` registers from `start`.
+    ResultRow {
+        start: usize,
+        count: usize,
+    },
+}
+
+/// The registers from `start` as SQLite's comments name them.
+fn registers(start: usize, count: usize) -> String {
+    match count {
+        0 => String::new(),
+        1 => format!("r[{start}]"),
+        _ => format!("r[{start}..{}]", start + count - 1),
+    }
+}
+
+impl Insn {
+    /// The opcode's name and its P1, P2, P3, P4 and P5 operands, with a comment saying what
+    /// it does, as `EXPLAIN` lists them.
+    fn operands(&self) -> (&'static str, [i64; 3], String, u16, String) {
+        let n = |n: usize| n as i64;
+        match self {
+            Insn::Init { target } => (
+                "Init",
+                [0, n(*target), 0],
+                String::new(),
+                0,
+                format!("Start at {target}"),
+            ),
+            Insn::Goto { target } => ("Goto", [0, n(*target), 0], String::new(), 0, String::new()),
+            Insn::Halt => ("Halt", [0, 0, 0], String::new(), 0, String::new()),
+            Insn::OpenRead {
+                cursor,
+                root,
+                name,
+                collation,
+            } => (
+                "OpenRead",
+                [n(*cursor), *root as i64, 0],
+                collation
+                    .as_ref()
+                    .map_or(String::new(), |c| format!("k(2,{})", key_collation(c))),
+                0,
+                format!("root={root}; {name}"),
+            ),
+            Insn::SorterOpen { cursor, keys } => {
+                let keys: Vec<_> = keys
+                    .iter()
+                    .map(|k| {
+                        format!(
+                            "{}{}",
+                            if k.desc { "-" } else { "" },
+                            key_collation(&k.collation)
+                        )
+                    })
+                    .collect();
+                (
+                    "SorterOpen",
+                    [n(*cursor), n(keys.len()), 0],
+                    format!("k({},{})", keys.len(), keys.join(",")),
+                    0,
+                    String::new(),
+                )
+            }
+            Insn::Rewind { cursor, target } => jump("Rewind", *cursor, *target),
+            Insn::Last { cursor, target } => jump("Last", *cursor, *target),
+            Insn::Next { cursor, target } => jump("Next", *cursor, *target),
+            Insn::Prev { cursor, target } => jump("Prev", *cursor, *target),
+            Insn::SeekRowid {
+                cursor,
+                target,
+                rowid,
+            } => (
+                "SeekRowid",
+                [n(*cursor), n(*target), n(*rowid)],
+                String::new(),
+                0,
+                format!("intkey={}", registers(*rowid, 1)),
+            ),
+            Insn::SeekGE {
+                cursor,
+                target,
+                key,
+            } => (
+                "SeekGE",
+                [n(*cursor), n(*target), n(*key)],
+                "1".to_string(),
+                0,
+                format!("key={}", registers(*key, 1)),
+            ),
+            Insn::IdxGT {
+                cursor,
+                target,
+                key,
+            } => (
+                "IdxGT",
+                [n(*cursor), n(*target), n(*key)],
+                "1".to_string(),
+                0,
+                format!("key={}", registers(*key, 1)),
+            ),
+            Insn::IdxRowid { cursor, dest } => (
+                "IdxRowid",
+                [n(*cursor), n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}=rowid", registers(*dest, 1)),
+            ),
+            Insn::Column {
+                cursor,
+                column,
+                dest,
+                name,
+            } => (
+                "Column",
+                [n(*cursor), n(*column), n(*dest)],
+                String::new(),
+                0,
+                format!("{}={name}", registers(*dest, 1)),
+            ),
+            Insn::Integer { value, dest } => (
+                "Integer",
+                [*value, n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}={value}", registers(*dest, 1)),
+            ),
+            Insn::Real { value, dest } => (
+                "Real",
+                [0, n(*dest), 0],
+                Value::Float(*value).to_string(),
+                0,
+                format!("{}={}", registers(*dest, 1), Value::Float(*value)),
+            ),
+            Insn::String8 { value, dest } => (
+                "String8",
+                [0, n(*dest), 0],
+                value.clone(),
+                0,
+                format!("{}='{value}'", registers(*dest, 1)),
+            ),
+            Insn::Blob { value, dest } => (
+                "Blob",
+                [n(value.len()), n(*dest), 0],
+                value.iter().map(|b| format!("{b:02X}")).collect(),
+                0,
+                format!("{}=blob", registers(*dest, 1)),
+            ),
+            Insn::Null { dest } => (
+                "Null",
+                [0, n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}=NULL", registers(*dest, 1)),
+            ),
+            Insn::SCopy { src, dest } => (
+                "SCopy",
+                [n(*src), n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}={}", registers(*dest, 1), registers(*src, 1)),
+            ),
+            Insn::Affinity { reg, affinity } => (
+                "Affinity",
+                [n(*reg), 1, 0],
+                affinity_code(*affinity).to_string(),
+                0,
+                format!("affinity({})", registers(*reg, 1)),
+            ),
+            Insn::Compare {
+                op,
+                lhs,
+                rhs,
+                dest,
+                collation,
+            } => {
+                let (name, symbol) = match op {
+                    Operator::Eq => ("Eq", "=="),
+                    Operator::Ne => ("Ne", "!="),
+                    Operator::Less => ("Lt", "<"),
+                    Operator::LessEq => ("Le", "<="),
+                    Operator::Greater => ("Gt", ">"),
+                    Operator::GreaterEq => ("Ge", ">="),
+                    Operator::Is => ("Is", "IS"),
+                    _ => ("IsNot", "IS NOT"),
+                };
+                (
+                    name,
+                    [n(*lhs), n(*rhs), n(*dest)],
+                    format!("({collation})"),
+                    0,
+                    format!(
+                        "{}=({} {symbol} {})",
+                        registers(*dest, 1),
+                        registers(*lhs, 1),
+                        registers(*rhs, 1)
+                    ),
+                )
+            }
+            Insn::Arithmetic { op, lhs, rhs, dest } => {
+                let (name, symbol) = match op {
+                    Operator::Add => ("Add", "+"),
+                    Operator::Subtract => ("Subtract", "-"),
+                    Operator::Multiply => ("Multiply", "*"),
+                    Operator::Divide => ("Divide", "/"),
+                    Operator::Remainder => ("Remainder", "%"),
+                    Operator::Concat => ("Concat", "||"),
+                    Operator::BitAnd => ("BitAnd", "&"),
+                    Operator::BitOr => ("BitOr", "|"),
+                    Operator::ShiftLeft => ("ShiftLeft", "<<"),
+                    _ => ("ShiftRight", ">>"),
+                };
+                binary(name, symbol, *lhs, *rhs, *dest)
+            }
+            Insn::And { lhs, rhs, dest } => binary("And", "&&", *lhs, *rhs, *dest),
+            Insn::Or { lhs, rhs, dest } => binary("Or", "||", *lhs, *rhs, *dest),
+            Insn::Not { src, dest } => unary_insn("Not", "!", *src, *dest),
+            Insn::Unary { op, src, dest } => match op {
+                UnaryOperator::BitNot => unary_insn("BitNot", "~", *src, *dest),
+                _ => unary_insn("Negate", "-", *src, *dest),
+            },
+            Insn::IsNull { src, dest } => (
+                "IsNull",
+                [n(*src), n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}=({} IS NULL)", registers(*dest, 1), registers(*src, 1)),
+            ),
+            Insn::NotNull { src, dest } => (
+                "NotNull",
+                [n(*src), n(*dest), 0],
+                String::new(),
+                0,
+                format!("{}=({} NOT NULL)", registers(*dest, 1), registers(*src, 1)),
+            ),
+            Insn::CollSeq { collation } => (
+                "CollSeq",
+                [0, 0, 0],
+                format!("({collation})"),
+                0,
+                String::new(),
+            ),
+            Insn::Function {
+                name,
+                args,
+                count,
+                dest,
+            } => (
+                "Function",
+                [0, n(*args), n(*dest)],
+                format!("{name}({count})"),
+                *count as u16,
+                format!("{}=func({})", registers(*dest, 1), registers(*args, *count)),
+            ),
+            Insn::AggStep {
+                name,
+                distinct,
+                args,
+                count,
+                acc,
+            } => (
+                "AggStep",
+                [0, n(*args), n(*acc)],
+                format!(
+                    "{}{name}({count})",
+                    if *distinct { "distinct " } else { "" }
+                ),
+                *count as u16,
+                format!(
+                    "accum={} step({})",
+                    registers(*acc, 1),
+                    registers(*args, *count)
+                ),
+            ),
+            Insn::AggFinal { name, count, acc } => (
+                "AggFinal",
+                [n(*acc), n(*count), 0],
+                format!("{name}({count})"),
+                0,
+                format!("accum={} N={count}", registers(*acc, 1)),
+            ),
+            Insn::IfNot { src, target, null } => (
+                "IfNot",
+                [n(*src), n(*target), i64::from(*null)],
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::IfPos { reg, target } => (
+                "IfPos",
+                [n(*reg), n(*target), 1],
+                String::new(),
+                0,
+                format!(
+                    "if {r}>0 then {r}-=1, goto {target}",
+                    r = registers(*reg, 1)
+                ),
+            ),
+            Insn::DecrJumpZero { reg, target } => (
+                "DecrJumpZero",
+                [n(*reg), n(*target), 0],
+                String::new(),
+                0,
+                format!("if (--{})==0 goto {target}", registers(*reg, 1)),
+            ),
+            Insn::SorterInsert {
+                cursor,
+                start,
+                count,
+            } => (
+                "SorterInsert",
+                [n(*cursor), n(*start), n(*count)],
+                String::new(),
+                0,
+                format!("key={}", registers(*start, *count)),
+            ),
+            Insn::SorterSort { cursor, target } => jump("SorterSort", *cursor, *target),
+            Insn::SorterNext { cursor, target } => jump("SorterNext", *cursor, *target),
+            Insn::ResultRow { start, count } => (
+                "ResultRow",
+                [n(*start), n(*count), 0],
+                String::new(),
+                0,
+                format!("output={}", registers(*start, *count)),
+            ),
+        }
+    }
+
+    /// Point the instruction's jump at `target`.
+    fn set_target(&mut self, to: usize) {
+        match self {
+            Insn::Init { target }
+            | Insn::Goto { target }
+            | Insn::Rewind { target, .. }
+            | Insn::Last { target, .. }
+            | Insn::Next { target, .. }
+            | Insn::Prev { target, .. }
+            | Insn::SeekRowid { target, .. }
+            | Insn::SeekGE { target, .. }
+            | Insn::IdxGT { target, .. }
+            | Insn::IfNot { target, .. }
+            | Insn::IfPos { target, .. }
+            | Insn::DecrJumpZero { target, .. }
+            | Insn::SorterSort { target, .. }
+            | Insn::SorterNext { target, .. } => *target = to,
+            insn => unreachable!("{:?} does not jump", insn),
+        }
+    }
+}
+
+fn jump(
+    name: &'static str,
+    cursor: usize,
+    target: usize,
+) -> (&'static str, [i64; 3], String, u16, String) {
+    (
+        name,
+        [cursor as i64, target as i64, 0],
+        String::new(),
+        0,
+        String::new(),
+    )
+}
+
+fn binary(
+    name: &'static str,
+    symbol: &str,
+    lhs: usize,
+    rhs: usize,
+    dest: usize,
+) -> (&'static str, [i64; 3], String, u16, String) {
+    (
+        name,
+        [lhs as i64, rhs as i64, dest as i64],
+        String::new(),
+        0,
+        format!(
+            "{}={} {symbol} {}",
+            registers(dest, 1),
+            registers(lhs, 1),
+            registers(rhs, 1)
+        ),
+    )
+}
+
+fn unary_insn(
+    name: &'static str,
+    symbol: &str,
+    src: usize,
+    dest: usize,
+) -> (&'static str, [i64; 3], String, u16, String) {
+    (
+        name,
+        [src as i64, dest as i64, 0],
+        String::new(),
+        0,
+        format!("{}={symbol}{}", registers(dest, 1), registers(src, 1)),
+    )
+}
+
+/// A collating sequence as SQLite abbreviates it in key descriptions.
+fn key_collation(name: &str) -> &str {
+    match name.eq_ignore_ascii_case(BINARY) {
+        true => "B",
+        false => name,
+    }
+}
+
+/// The letter SQLite uses for an affinity in P4 of `Affinity`.
+fn affinity_code(affinity: CellType) -> char {
+    match affinity {
+        CellType::Blob => 'A',
+        CellType::Text => 'B',
+        CellType::Numeric => 'C',
+        CellType::Integer => 'D',
+        CellType::Float => 'E',
+    }
+}
+
+/// A compiled statement: instructions run from the first until one halts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    insns: Vec<Insn>,
+    registers: usize,
+}
+
+impl Program {
+    /// The lines `EXPLAIN` prints for the program, laid out like the sqlite3 shell does.
+    pub fn listing(&self) -> Vec<String> {
+        let line = |cells: [&str; 8]| {
+            format!(
+                "{:<4}  {:<13}  {:<4}  {:<4}  {:<4}  {:<13}  {:<2}  {}",
+                cells[0], cells[1], cells[2], cells[3], cells[4], cells[5], cells[6], cells[7]
+            )
+            .trim_end()
+            .to_string()
+        };
+        let mut lines = vec![
+            line(["addr", "opcode", "p1", "p2", "p3", "p4", "p5", "comment"]),
+            line([
+                "----",
+                "-------------",
+                "----",
+                "----",
+                "----",
+                "-------------",
+                "--",
+                "-------------",
+            ]),
+        ];
+        for (addr, insn) in self.insns.iter().enumerate() {
+            let (name, [p1, p2, p3], p4, p5, comment) = insn.operands();
+            lines.push(line([
+                &addr.to_string(),
+                name,
+                &p1.to_string(),
+                &p2.to_string(),
+                &p3.to_string(),
+                &p4,
+                &p5.to_string(),
+                &comment,
+            ]));
+        }
+        lines
+    }
+
+    /// Ordinals of the columns the program reads from each cursor, in order.
+    fn columns(&self) -> HashMap<usize, Vec<usize>> {
+        let mut columns: HashMap<usize, Vec<usize>> = HashMap::new();
+        for insn in &self.insns {
+            if let Insn::Column { cursor, column, .. } = insn {
+                columns.entry(*cursor).or_default().push(*column);
+            }
+        }
+        for read in columns.values_mut() {
+            read.sort_unstable();
+            read.dedup();
+        }
+        columns
+    }
+
+    /// Run the program against the database, returning the rows it produces.
+    pub fn run(&self, db: &Database) -> Result<Vec<Row>> {
+        let mut vm = Vm {
+            db,
+            registers: vec![Value::Null; self.registers + 1],
+            cursors: HashMap::new(),
+            accumulators: HashMap::new(),
+            collation: None,
+            rows: vec![],
+            columns: self.columns(),
+        };
+        let mut pc = 0;
+        while let Some(insn) = self.insns.get(pc) {
+            pc = match vm.step(insn)? {
+                Flow::Next => pc + 1,
+                Flow::Jump(target) => target,
+                Flow::Halt => break,
+            };
+        }
+        Ok(vm.rows)
+    }
+}
+
+/// What the expressions of a program read: the columns of the row under a cursor, and
+/// values already computed into registers, such as aggregate results, by name.
+pub struct Scope<'c> {
+    pub cursor: usize,
+    pub columns: &'c HashMap<String, Column>,
+    pub registers: HashMap<String, usize>,
+}
+
+/// Assembles a program, handing out registers and resolving jumps once their target is
+/// known.
+#[derive(Default)]
+pub struct Builder {
+    insns: Vec<Insn>,
+    registers: usize,
+}
+
+impl Builder {
+    /// Append an instruction, returning its address.
+    pub fn emit(&mut self, insn: Insn) -> usize {
+        self.insns.push(insn);
+        self.insns.len() - 1
+    }
+
+    /// Address of the next instruction to be emitted.
+    pub fn here(&self) -> usize {
+        self.insns.len()
+    }
+
+    /// Point the jumps at `addrs` to the next instruction to be emitted.
+    pub fn resolve(&mut self, addrs: &[usize]) {
+        let target = self.here();
+        for &addr in addrs {
+            self.insns[addr].set_target(target);
+        }
+    }
+
+    /// Hand out `count` consecutive registers, returning the first.
+    pub fn alloc(&mut self, count: usize) -> usize {
+        let first = self.registers + 1;
+        self.registers += count;
+        first
+    }
+
+    /// Compile an expression into a new register, returning it.
+    pub fn expr(&mut self, expr: &Expr, scope: &Scope) -> Result<usize> {
+        let dest = self.alloc(1);
+        self.expr_into(expr, scope, dest)?;
+        Ok(dest)
+    }
+
+    /// Compile an expression, leaving its value in register `dest`.
+    pub fn expr_into(&mut self, expr: &Expr, scope: &Scope, dest: usize) -> Result<()> {
+        match expr {
+            Expr::Literal(value) => {
+                self.emit(match value.clone() {
+                    Value::Null => Insn::Null { dest },
+                    Value::Integer(value) => Insn::Integer { value, dest },
+                    Value::Float(value) => Insn::Real { value, dest },
+                    Value::Text(value) => Insn::String8 { value, dest },
+                    Value::Blob(value) => Insn::Blob { value, dest },
+                });
+            }
+            Expr::Column(name) => {
+                match (scope.registers.get(name), scope.columns.get(name)) {
+                    (Some(&src), _) => self.emit(Insn::SCopy { src, dest }),
+                    (None, Some(column)) => self.emit(Insn::Column {
+                        cursor: scope.cursor,
+                        column: column.idx,
+                        dest,
+                        name: column.name.clone(),
+                    }),
+                    (None, None) => self.emit(Insn::Null { dest }),
+                };
+            }
+            Expr::Not(expr) => {
+                let src = self.expr(expr, scope)?;
+                self.emit(Insn::Not { src, dest });
+            }
+            Expr::Unary(UnaryOperator::Plus, expr) | Expr::Collate(expr, _) => {
+                self.expr_into(expr, scope, dest)?
+            }
+            Expr::Unary(op, expr) => {
+                let src = self.expr(expr, scope)?;
+                self.emit(Insn::Unary { op: *op, src, dest });
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let collation = Expr::comparison_collation(lhs, rhs, scope.columns).to_uppercase();
+                let affinities = match op.is_arithmetic() {
+                    true => (None, None),
+                    false => Expr::operand_affinities(lhs, rhs, scope.columns),
+                };
+                let (lhs, rhs) = (self.expr(lhs, scope)?, self.expr(rhs, scope)?);
+                for (reg, affinity) in [(lhs, affinities.0), (rhs, affinities.1)] {
+                    if let Some(affinity) = affinity {
+                        self.emit(Insn::Affinity { reg, affinity });
+                    }
+                }
+                self.emit(match op {
+                    Operator::And => Insn::And { lhs, rhs, dest },
+                    Operator::Or => Insn::Or { lhs, rhs, dest },
+                    op if op.is_arithmetic() => Insn::Arithmetic {
+                        op: *op,
+                        lhs,
+                        rhs,
+                        dest,
+                    },
+                    op => Insn::Compare {
+                        op: *op,
+                        lhs,
+                        rhs,
+                        dest,
+                        collation,
+                    },
+                });
+            }
+            Expr::IsNull(expr) => {
+                let src = self.expr(expr, scope)?;
+                self.emit(Insn::IsNull { src, dest });
+            }
+            Expr::NotNull(expr) => {
+                let src = self.expr(expr, scope)?;
+                self.emit(Insn::NotNull { src, dest });
+            }
+            Expr::Function { name, args, .. } if expr.aggregate().is_none() => {
+                if ScalarFunction::from_name(name).is_none() {
+                    self.emit(Insn::Null { dest });
+                    return Ok(());
+                }
+                let first = self.alloc(args.len());
+                for (i, arg) in args.iter().enumerate() {
+                    self.expr_into(arg, scope, first + i)?;
+                }
+                // Functions comparing text use the collation of their first column argument
+                if let Some((collation, _)) =
+                    args.iter().find_map(|arg| arg.collation(scope.columns))
+                {
+                    self.emit(Insn::CollSeq {
+                        collation: collation.to_uppercase(),
+                    });
+                }
+                self.emit(Insn::Function {
+                    name: name.to_lowercase(),
+                    args: first,
+                    count: args.len(),
+                    dest,
+                });
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let lower = Expr::binary(*expr.clone(), Operator::GreaterEq, *low.clone());
+                let upper = Expr::binary(*expr.clone(), Operator::LessEq, *high.clone());
+                let between = Expr::binary(lower, Operator::And, upper);
+                match negated {
+                    true => self.expr_into(&Expr::Not(Box::new(between)), scope, dest)?,
+                    false => self.expr_into(&between, scope, dest)?,
+                }
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // Matching any item makes it true, and otherwise a NULL comparison leaves it
+                // unknown, just like ORing the equalities together
+                let found = list
+                    .iter()
+                    .map(|item| Expr::binary(*expr.clone(), Operator::Eq, item.clone()))
+                    .reduce(|found, eq| Expr::binary(found, Operator::Or, eq))
+                    .unwrap_or(Expr::Literal(Value::from(false)));
+                match negated {
+                    true => self.expr_into(&Expr::Not(Box::new(found)), scope, dest)?,
+                    false => self.expr_into(&found, scope, dest)?,
+                }
+            }
+            Expr::Function { .. } => bail!("aggregate calls are only compiled as steps"),
+            Expr::InSelect { .. } | Expr::Subquery(_) | Expr::Exists(_) => {
+                bail!("subqueries are not compiled to bytecode")
+            }
+            Expr::Over { .. } => bail!("window functions are not compiled to bytecode"),
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Program {
+        Program {
+            insns: self.insns,
+            registers: self.registers,
+        }
+    }
+}
+
+/// What the program does after an instruction.
+enum Flow {
+    Next,
+    Jump(usize),
+    Halt,
+}
+
+/// A b-tree or sorter a program reads, and the entry it is at.
+enum Cursor<'db> {
+    Table {
+        search: Search,
+        scan: Option<Box<TableScan<'db>>>,
+        row: Option<Row>,
+    },
+    Index {
+        pgno: NonZeroU64,
+        collation: CollationFn,
+        scan: Option<IndexScan<'db>>,
+        entry: Option<Row>,
+    },
+    Sorter {
+        sorter: Option<Sorter>,
+        rows: Option<SortedRows>,
+        row: Option<Row>,
+    },
+}
+
+impl Cursor<'_> {
+    fn current(&self) -> Option<&Row> {
+        match self {
+            Cursor::Table { row, .. } | Cursor::Sorter { row, .. } => row.as_ref(),
+            Cursor::Index { entry, .. } => entry.as_ref(),
+        }
+    }
+
+    /// Move to the next entry of a scan, returning whether there was one.
+    fn advance(&mut self) -> Result<bool> {
+        Ok(match self {
+            Cursor::Table { scan, row, .. } => {
+                *row = scan.as_mut().and_then(Iterator::next).transpose()?;
+                row.is_some()
+            }
+            Cursor::Index { scan, entry, .. } => {
+                *entry = scan.as_mut().and_then(Iterator::next).transpose()?;
+                entry.is_some()
+            }
+            Cursor::Sorter { rows, row, .. } => {
+                *row = rows.as_mut().and_then(Iterator::next).transpose()?;
+                row.is_some()
+            }
+        })
+    }
+}
+
+/// The state of a running program.
+struct Vm<'db> {
+    db: &'db Database,
+    registers: Vec<Value>,
+    cursors: HashMap<usize, Cursor<'db>>,
+    /// Aggregates being computed, by the register their result goes to.
+    accumulators: HashMap<usize, Accumulator>,
+    /// Collating sequence set by `CollSeq` for the next function or aggregate step.
+    collation: Option<String>,
+    rows: Vec<Row>,
+    /// Columns the program reads from each cursor, the only ones table cursors decode.
+    columns: HashMap<usize, Vec<usize>>,
+}
+
+impl<'db> Vm<'db> {
+    fn cursor(&mut self, cursor: usize) -> Result<&mut Cursor<'db>> {
+        self.cursors
+            .get_mut(&cursor)
+            .ok_or_else(|| anyhow!("cursor {} is not open", cursor))
+    }
+
+    fn collation(&self, name: &str) -> Result<CollationFn> {
+        self.db
+            .collations
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no such collation sequence: {}", name))
+    }
+
+    /// Take the collation set for the next call, BINARY when none was.
+    fn take_collation(&mut self) -> Result<CollationFn> {
+        let name = self.collation.take();
+        self.collation(name.as_deref().unwrap_or(BINARY))
+    }
+
+    fn step(&mut self, insn: &Insn) -> Result<Flow> {
+        let db = self.db;
+        let jump_unless = |moved: bool, target: usize| match moved {
+            true => Flow::Next,
+            false => Flow::Jump(target),
+        };
+        let jump_if = |moved: bool, target: usize| match moved {
+            true => Flow::Jump(target),
+            false => Flow::Next,
+        };
+        match insn {
+            Insn::Init { target } | Insn::Goto { target } => return Ok(Flow::Jump(*target)),
+            Insn::Halt => return Ok(Flow::Halt),
+            Insn::OpenRead {
+                cursor,
+                root,
+                name,
+                collation,
+            } => {
+                let pgno =
+                    NonZeroU64::new(*root).ok_or_else(|| anyhow!("invalid rootpage: {}", root))?;
+                let opened = match collation {
+                    Some(collation) => Cursor::Index {
+                        pgno,
+                        collation: self.collation(collation)?,
+                        scan: None,
+                        entry: None,
+                    },
+                    None => {
+                        let schema = db
+                            .get_schemas_vec()
+                            .into_iter()
+                            .find(|s| s.stype == SchemaType::Table && s.rootpage == *root)
+                            .ok_or_else(|| anyhow!("no such table: {}", name))?;
+                        Cursor::Table {
+                            search: Search::new(pgno, None, None, schema, vec![]).with_columns(
+                                self.columns.get(cursor).cloned().unwrap_or_default(),
+                            ),
+                            scan: None,
+                            row: None,
+                        }
+                    }
+                };
+                self.cursors.insert(*cursor, opened);
+            }
+            Insn::SorterOpen { cursor, keys } => {
+                let keys = keys
+                    .iter()
+                    .map(|key| {
+                        Ok(SortKey {
+                            desc: key.desc,
+                            nulls_first: key.nulls_first,
+                            collation: self.collation(&key.collation)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let sorter = Cursor::Sorter {
+                    sorter: Some(Sorter::new(keys, SORTER_MEMORY_BUDGET)),
+                    rows: None,
+                    row: None,
+                };
+                self.cursors.insert(*cursor, sorter);
+            }
+            Insn::Rewind { cursor, target } | Insn::Last { cursor, target } => {
+                let reverse = matches!(insn, Insn::Last { .. });
+                let opened = self.cursor(*cursor)?;
+                match opened {
+                    Cursor::Table { search, scan, .. } => {
+                        *scan = Some(Box::new(TableScan::new(db, search.clone(), reverse)))
+                    }
+                    Cursor::Index { pgno, scan, .. } => {
+                        *scan = Some(IndexScan::new(db, *pgno, reverse))
+                    }
+                    Cursor::Sorter { .. } => bail!("sorters are rewound by SorterSort"),
+                }
+                return Ok(jump_unless(opened.advance()?, *target));
+            }
+            Insn::Next { cursor, target }
+            | Insn::Prev { cursor, target }
+            | Insn::SorterNext { cursor, target } => {
+                return Ok(jump_if(self.cursor(*cursor)?.advance()?, *target));
+            }
+            Insn::SeekRowid {
+                cursor,
+                target,
+                rowid,
+            } => {
+                let rowid = match self.registers[*rowid] {
+                    Value::Integer(rowid) => u64::try_from(rowid).ok(),
+                    _ => None,
+                };
+                let Cursor::Table { search, row, .. } = self.cursor(*cursor)? else {
+                    bail!("SeekRowid needs a table cursor");
+                };
+                *row = rowid
+                    .and_then(|rowid| db.seek_rowid(search.pgno, rowid, search.columns.as_deref()));
+                return Ok(jump_unless(row.is_some(), *target));
+            }
+            Insn::SeekGE {
+                cursor,
+                target,
+                key,
+            } => {
+                let key = self.registers[*key].clone();
+                let Cursor::Index {
+                    pgno,
+                    collation,
+                    scan,
+                    ..
+                } = self.cursor(*cursor)?
+                else {
+                    bail!("SeekGE needs an index cursor");
+                };
+                *scan = Some(IndexScan::seek(db, *pgno, &key, collation.as_ref())?);
+                let moved = self.cursor(*cursor)?.advance()?;
+                return Ok(jump_unless(moved, *target));
+            }
+            Insn::IdxGT {
+                cursor,
+                target,
+                key,
+            } => {
+                let key = &self.registers[*key];
+                let Some(Cursor::Index {
+                    collation, entry, ..
+                }) = self.cursors.get(cursor)
+                else {
+                    bail!("IdxGT needs an index cursor");
+                };
+                let above = entry
+                    .as_ref()
+                    .and_then(|entry| entry.first())
+                    .is_some_and(|k| k.compare_collated(key, collation.as_ref()).is_gt());
+                return Ok(jump_if(above, *target));
+            }
+            Insn::IdxRowid { cursor, dest } => {
+                let rowid = self
+                    .cursor(*cursor)?
+                    .current()
+                    .and_then(|e| e.last())
+                    .cloned();
+                self.registers[*dest] = rowid.unwrap_or(Value::Null);
+            }
+            Insn::Column {
+                cursor,
+                column,
+                dest,
+                ..
+            } => {
+                let value = self
+                    .cursor(*cursor)?
+                    .current()
+                    .and_then(|row| row.get(*column))
+                    .cloned();
+                self.registers[*dest] = value.unwrap_or(Value::Null);
+            }
+            Insn::Integer { value, dest } => self.registers[*dest] = Value::Integer(*value),
+            Insn::Real { value, dest } => self.registers[*dest] = Value::Float(*value),
+            Insn::String8 { value, dest } => self.registers[*dest] = Value::Text(value.clone()),
+            Insn::Blob { value, dest } => self.registers[*dest] = Value::Blob(value.clone()),
+            Insn::Null { dest } => self.registers[*dest] = Value::Null,
+            Insn::SCopy { src, dest } => self.registers[*dest] = self.registers[*src].clone(),
+            Insn::Affinity { reg, affinity } => {
+                let value = std::mem::replace(&mut self.registers[*reg], Value::Null);
+                self.registers[*reg] = value.apply_affinity(*affinity);
+            }
+            Insn::Compare {
+                op,
+                lhs,
+                rhs,
+                dest,
+                collation,
+            } => {
+                let collation = self.collation(collation)?;
+                self.registers[*dest] = compare(
+                    *op,
+                    &self.registers[*lhs],
+                    &self.registers[*rhs],
+                    collation.as_ref(),
+                );
+            }
+            Insn::Arithmetic { op, lhs, rhs, dest } => {
+                let (l, r) = (self.registers[*lhs].clone(), self.registers[*rhs].clone());
+                self.registers[*dest] = arithmetic(*op, l, r);
+            }
+            Insn::And { lhs, rhs, dest } | Insn::Or { lhs, rhs, dest } => {
+                let (l, r) = (self.registers[*lhs].truth(), self.registers[*rhs].truth());
+                // Either side decides the result when it is FALSE for AND, or TRUE for OR
+                let decisive = matches!(insn, Insn::Or { .. });
+                self.registers[*dest] = match (l, r) {
+                    (Some(l), _) if l == decisive => Value::from(decisive),
+                    (_, Some(r)) if r == decisive => Value::from(decisive),
+                    (Some(_), Some(_)) => Value::from(!decisive),
+                    _ => Value::Null,
+                };
+            }
+            Insn::Not { src, dest } => {
+                self.registers[*dest] = match self.registers[*src].truth() {
+                    Some(truth) => Value::from(!truth),
+                    None => Value::Null,
+                };
+            }
+            Insn::Unary { op, src, dest } => {
+                self.registers[*dest] = unary(*op, self.registers[*src].clone());
+            }
+            Insn::IsNull { src, dest } => {
+                self.registers[*dest] = Value::from(self.registers[*src] == Value::Null);
+            }
+            Insn::NotNull { src, dest } => {
+                self.registers[*dest] = Value::from(self.registers[*src] != Value::Null);
+            }
+            Insn::CollSeq { collation } => self.collation = Some(collation.clone()),
+            Insn::Function {
+                name,
+                args,
+                count,
+                dest,
+            } => {
+                let function = ScalarFunction::from_name(name)
+                    .ok_or_else(|| anyhow!("no such function: {}", name))?;
+                let collation = self.take_collation()?;
+                let value =
+                    function.call(&self.registers[*args..args + count], collation.as_ref())?;
+                self.registers[*dest] = value;
+            }
+            Insn::AggStep {
+                name,
+                distinct,
+                args,
+                count,
+                acc,
+            } => {
+                let collation = self.take_collation()?;
+                if !self.accumulators.contains_key(acc) {
+                    let kind = AggregateKind::from_call(name, *count)
+                        .ok_or_else(|| anyhow!("no such aggregate: {}", name))?;
+                    let accumulator = Accumulator::new(kind, *distinct, collation);
+                    self.accumulators.insert(*acc, accumulator);
+                }
+                if let Some(accumulator) = self.accumulators.get_mut(acc) {
+                    accumulator.step(&self.registers[*args..args + count])?;
+                }
+            }
+            Insn::AggFinal { name, count, acc } => {
+                // Without any step the aggregate is over no rows at all
+                let value = match self.accumulators.remove(acc) {
+                    Some(accumulator) => accumulator.finish(),
+                    None => {
+                        let kind = AggregateKind::from_call(name, *count)
+                            .ok_or_else(|| anyhow!("no such aggregate: {}", name))?;
+                        Accumulator::new(kind, false, self.collation(BINARY)?).finish()
+                    }
+                };
+                self.registers[*acc] = value;
+            }
+            Insn::IfNot { src, target, null } => {
+                let jumps = match self.registers[*src].truth() {
+                    Some(truth) => !truth,
+                    None => *null,
+                };
+                return Ok(jump_if(jumps, *target));
+            }
+            Insn::IfPos { reg, target } => {
+                if let Value::Integer(n) = &mut self.registers[*reg] {
+                    if *n > 0 {
+                        *n -= 1;
+                        return Ok(Flow::Jump(*target));
+                    }
+                }
+            }
+            Insn::DecrJumpZero { reg, target } => {
+                if let Value::Integer(n) = &mut self.registers[*reg] {
+                    *n -= 1;
+                    if *n == 0 {
+                        return Ok(Flow::Jump(*target));
+                    }
+                }
+            }
+            Insn::SorterInsert {
+                cursor,
+                start,
+                count,
+            } => {
+                let record = self.registers[*start..start + count].to_vec();
+                let Cursor::Sorter {
+                    sorter: Some(sorter),
+                    ..
+                } = self.cursor(*cursor)?
+                else {
+                    bail!("SorterInsert needs an open sorter");
+                };
+                let keys = sorter.key_count();
+                let mut key = record;
+                let row = key.split_off(keys.min(key.len()));
+                sorter.push(key, row)?;
+            }
+            Insn::SorterSort { cursor, target } => {
+                let Cursor::Sorter { sorter, rows, .. } = self.cursor(*cursor)? else {
+                    bail!("SorterSort needs a sorter cursor");
+                };
+                if let Some(sorter) = sorter.take() {
+                    *rows = Some(sorter.finish()?);
+                }
+                return Ok(jump_unless(self.cursor(*cursor)?.advance()?, *target));
+            }
+            Insn::ResultRow { start, count } => {
+                self.rows
+                    .push(self.registers[*start..start + count].to_vec());
+            }
+        }
+        Ok(Flow::Next)
+    }
+}
+
+#[test]
+fn test_listing() {
+    use super::tables::Table;
+    let table: Table =
+        "CREATE TABLE emp (id integer primary key, name text collate nocase, salary integer)"
+            .parse()
+            .unwrap();
+    let scope = Scope {
+        cursor: 0,
+        columns: &table.columns,
+        registers: HashMap::new(),
+    };
+    let cond = Expr::binary(
+        Expr::binary(
+            Expr::Column("salary".to_string()),
+            Operator::Greater,
+            Expr::Literal(Value::Text("10".to_string())),
+        ),
+        Operator::And,
+        Expr::binary(
+            Expr::Column("name".to_string()),
+            Operator::Eq,
+            Expr::Literal(Value::Text("bob".to_string())),
+        ),
+    );
+    let mut b = Builder::default();
+    let init = b.emit(Insn::Init { target: 0 });
+    b.resolve(&[init]);
+    b.expr(&cond, &scope).unwrap();
+    b.emit(Insn::Halt);
+    assert_eq!(
+        b.finish().listing(),
+        [
+            "addr  opcode         p1    p2    p3    p4             p5  comment",
+            "----  -------------  ----  ----  ----  -------------  --  -------------",
+            "0     Init           0     1     0                    0   Start at 1",
+            "1     Column         0     2     3                    0   r[3]=salary",
+            "2     String8        0     4     0     10             0   r[4]='10'",
+            "3     Affinity       4     1     0     C              0   affinity(r[4])",
+            "4     Gt             3     4     2     (BINARY)       0   r[2]=(r[3] > r[4])",
+            "5     Column         0     1     6                    0   r[6]=name",
+            "6     String8        0     7     0     bob            0   r[7]='bob'",
+            "7     Affinity       7     1     0     B              0   affinity(r[7])",
+            "8     Eq             6     7     5     (NOCASE)       0   r[5]=(r[6] == r[7])",
+            "9     And            2     5     1                    0   r[1]=r[2] && r[5]",
+            "10    Halt           0     0     0                    0",
+        ]
+    );
+}
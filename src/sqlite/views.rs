@@ -0,0 +1,99 @@
+use anyhow::{bail, Error, Result};
+use std::str::FromStr;
+
+use crate::parsers::sql::{create_view_sql, Select};
+
+use super::{
+    schemas::Schema,
+    tables::{Column, Table},
+};
+
+/// A named SELECT, whose rows are read as those of a table.
+#[derive(Debug, PartialEq, Clone)]
+pub struct View {
+    pub name: String,
+    /// Names given to the result columns of the SELECT, if listed after the view's name.
+    pub columns: Vec<String>,
+    pub select: Select,
+}
+
+impl View {
+    /// The view as a table, from the table of its SELECT's result columns, which are renamed
+    /// by the view's column list when it has one.
+    pub fn table(&self, table: Table) -> Result<Table> {
+        if self.columns.is_empty() {
+            return Ok(Table {
+                name: self.name.clone(),
+                ..table
+            });
+        }
+        if self.columns.len() != table.columns.len() {
+            bail!(
+                "expected {} columns for '{}' but got {}",
+                self.columns.len(),
+                self.name,
+                table.columns.len()
+            );
+        }
+        let columns = table
+            .columns
+            .into_values()
+            .map(|column| {
+                let name = self.columns[column.idx].clone();
+                (name.clone(), Column { name, ..column })
+            })
+            .collect();
+        Ok(Table {
+            name: self.name.clone(),
+            columns,
+            key: None,
+        })
+    }
+}
+
+impl TryFrom<&Schema> for View {
+    type Error = Error;
+    fn try_from(value: &Schema) -> std::result::Result<Self, Self::Error> {
+        value.sql.parse()
+    }
+}
+
+impl FromStr for View {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        create_view_sql(s)
+    }
+}
+
+#[test]
+fn test_view_columns() {
+    let view: View = "CREATE VIEW IF NOT EXISTS pay (team, total) AS select dept, sum(salary) from emp group by dept"
+        .parse()
+        .unwrap();
+    assert_eq!(view.name, "pay");
+    assert_eq!(view.columns, ["team", "total"]);
+    assert_eq!(view.select.group_by.len(), 1);
+
+    let table: Table = "create table t (dept text, total integer)".parse().unwrap();
+    let table = view.table(table).unwrap();
+    assert_eq!(table.name, "pay");
+    assert_eq!(table.column("TEAM").map(|c| c.idx), Some(0));
+    assert_eq!(table.column("total").map(|c| c.idx), Some(1));
+
+    let view: View = "create temp view rich as select name from emp where salary > 5000"
+        .parse()
+        .unwrap();
+    assert!(view.columns.is_empty());
+    let table: Table = "create table t (name text, salary integer)"
+        .parse()
+        .unwrap();
+    assert!(view.table(table).is_ok());
+    let view = View {
+        columns: vec!["a".to_string()],
+        ..view
+    };
+    let table: Table = "create table t (name text, salary integer)"
+        .parse()
+        .unwrap();
+    assert!(view.table(table).is_err());
+}
@@ -0,0 +1,539 @@
+use anyhow::{anyhow, bail, Result};
+use std::{cmp::Ordering, collections::HashMap, mem};
+
+use super::{
+    aggregate::{as_real, Accumulator, AggregateKind},
+    collation::{CollationFn, Collations, BINARY},
+    db::Row,
+    functions::check_call,
+    predicate::BoundExpr,
+    sorter::SortKey,
+    tables::Column,
+};
+use crate::parsers::{
+    sql::{Expr, FrameBound, Window},
+    value::Value,
+};
+
+/// The functions that can be called with OVER, recognised by name and number of arguments.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WindowKind {
+    RowNumber,
+    /// The row number of the first peer of the row, so equal rows share a rank with gaps after.
+    Rank,
+    /// The number of the row's group of peers, so ranks have no gaps.
+    DenseRank,
+    /// `ntile(n)`, which splits the partition into `n` groups as even in size as possible.
+    Ntile,
+    /// `lag(expr, offset, default)`, the value of `expr` `offset` rows before in the partition.
+    Lag,
+    /// Like `lag`, but reading the rows after.
+    Lead,
+    FirstValue,
+    LastValue,
+    /// An aggregate computed over the frame of each row.
+    Aggregate(AggregateKind),
+}
+
+impl WindowKind {
+    pub fn from_call(name: &str, args: usize) -> Option<Self> {
+        let kind = match (name.to_ascii_lowercase().as_str(), args) {
+            ("row_number", 0) => WindowKind::RowNumber,
+            ("rank", 0) => WindowKind::Rank,
+            ("dense_rank", 0) => WindowKind::DenseRank,
+            ("ntile", 1) => WindowKind::Ntile,
+            ("lag", 1..=3) => WindowKind::Lag,
+            ("lead", 1..=3) => WindowKind::Lead,
+            ("first_value", 1) => WindowKind::FirstValue,
+            ("last_value", 1) => WindowKind::LastValue,
+            _ => return AggregateKind::from_call(name, args).map(WindowKind::Aggregate),
+        };
+        Some(kind)
+    }
+
+    /// Whether the function can only be called over a window.
+    pub fn is_window_only(name: &str) -> bool {
+        matches!(
+            name.to_ascii_lowercase().as_str(),
+            "row_number"
+                | "rank"
+                | "dense_rank"
+                | "ntile"
+                | "lag"
+                | "lead"
+                | "first_value"
+                | "last_value"
+        )
+    }
+}
+
+/// Check that the function called with OVER is a window function or an aggregate, with as
+/// many arguments as it takes.
+pub fn check_window_call(call: &Expr) -> Result<()> {
+    let (name, args, distinct) = match call {
+        Expr::Function {
+            name,
+            args,
+            distinct,
+        } => (name, args.len(), *distinct),
+        _ => bail!("{:?} is not a function call", call),
+    };
+    match WindowKind::from_call(name, args) {
+        Some(_) if distinct => bail!("DISTINCT is not supported for window functions"),
+        Some(_) => Ok(()),
+        None if WindowKind::is_window_only(name) => {
+            bail!("wrong number of arguments to function {}()", name)
+        }
+        None => {
+            check_call(name, args)?;
+            bail!("{}() may not be used as a window function", name)
+        }
+    }
+}
+
+/// A frame bound, with its offset evaluated.
+#[derive(Clone, Copy)]
+enum Bound {
+    UnboundedPreceding,
+    Preceding(f64),
+    CurrentRow,
+    Following(f64),
+    UnboundedFollowing,
+}
+
+impl Bound {
+    fn new(bound: &FrameBound, rows: bool, which: &str, collations: &Collations) -> Result<Self> {
+        let offset = |expr: &Expr| {
+            let value = expr.eval(&[], &HashMap::new(), collations)?;
+            match value {
+                Value::Integer(n) if n >= 0 => Ok(n as f64),
+                Value::Float(f) if f >= 0.0 && !rows => Ok(f),
+                _ if rows => bail!("frame {} offset must be a non-negative integer", which),
+                _ => bail!("frame {} offset must be a non-negative number", which),
+            }
+        };
+        Ok(match bound {
+            FrameBound::UnboundedPreceding => Bound::UnboundedPreceding,
+            FrameBound::Preceding(expr) => Bound::Preceding(offset(expr)?),
+            FrameBound::CurrentRow => Bound::CurrentRow,
+            FrameBound::Following(expr) => Bound::Following(offset(expr)?),
+            FrameBound::UnboundedFollowing => Bound::UnboundedFollowing,
+        })
+    }
+
+    /// Position of the bound from the start to the end of the partition.
+    fn order(&self) -> u8 {
+        match self {
+            Bound::UnboundedPreceding => 0,
+            Bound::Preceding(_) => 1,
+            Bound::CurrentRow => 2,
+            Bound::Following(_) => 3,
+            Bound::UnboundedFollowing => 4,
+        }
+    }
+}
+
+/// A partition's rows in window order, and what the frames of a call are computed from.
+struct Partition<'p> {
+    /// Positions in the input rows, in window order.
+    rows: &'p [usize],
+    /// Start and end, exclusive, of the peers of each row.
+    peers: Vec<(usize, usize)>,
+    /// Value of the first ORDER BY term for each row, for RANGE offsets.
+    order: Vec<&'p Value>,
+}
+
+impl Partition<'_> {
+    /// Start and end, exclusive, of the frame of the `j`th row, counting rows.
+    fn rows_frame(&self, j: usize, start: Bound, end: Bound) -> (usize, usize) {
+        let len = self.rows.len();
+        let start = match start {
+            Bound::UnboundedPreceding => 0,
+            Bound::Preceding(n) => j.saturating_sub(n as usize),
+            Bound::CurrentRow => j,
+            Bound::Following(n) => j.saturating_add(n as usize),
+            Bound::UnboundedFollowing => len,
+        };
+        let end = match end {
+            Bound::UnboundedPreceding => 0,
+            Bound::Preceding(n) => (j + 1).saturating_sub(n as usize),
+            Bound::CurrentRow => j + 1,
+            Bound::Following(n) => j.saturating_add(n as usize).saturating_add(1),
+            Bound::UnboundedFollowing => len,
+        };
+        (start.min(len), end.min(len))
+    }
+
+    /// Start and end, exclusive, of the frame of the `j`th row, by ranges of ORDER BY values
+    /// around the row's. Offsets are added or subtracted the way the order goes.
+    fn range_frame(&self, j: usize, start: Bound, end: Bound, desc: bool) -> (usize, usize) {
+        let len = self.rows.len();
+        let (first, last) = self.peers[j];
+        let current = self.order[j];
+        // NULLs are only in range of each other, and all sort before or after the rest
+        let nulls_first = matches!(self.order.first(), Some(Value::Null));
+        // Rows ordered before a value
+        let before = |target: f64| {
+            self.order.partition_point(|value| match value {
+                Value::Null => nulls_first,
+                value if desc => as_real(value) > target,
+                value => as_real(value) < target,
+            })
+        };
+        // Rows ordered before or at a value
+        let through = |target: f64| {
+            self.order.partition_point(|value| match value {
+                Value::Null => nulls_first,
+                value if desc => as_real(value) >= target,
+                value => as_real(value) <= target,
+            })
+        };
+        let offset = |n: f64, sign: f64| as_real(current) + if desc { -sign * n } else { sign * n };
+        let null = matches!(current, Value::Null);
+        let start = match start {
+            Bound::UnboundedPreceding => 0,
+            Bound::CurrentRow => first,
+            _ if null => first,
+            Bound::Preceding(n) => before(offset(n, -1.0)),
+            Bound::Following(n) => before(offset(n, 1.0)),
+            Bound::UnboundedFollowing => len,
+        };
+        let end = match end {
+            Bound::UnboundedPreceding => 0,
+            Bound::CurrentRow => last,
+            _ if null => last,
+            Bound::Preceding(n) => through(offset(n, -1.0)),
+            Bound::Following(n) => through(offset(n, 1.0)),
+            Bound::UnboundedFollowing => len,
+        };
+        (start, end)
+    }
+}
+
+/// Compute the window function `calls` for `rows`, appending the value of each call to
+/// every row after the table's `columns`. Each call sorts the rows by its partition and
+/// order to compute them, but the rows come out in the order they went in.
+pub fn window_rows(
+    mut rows: Vec<Row>,
+    calls: &[Expr],
+    columns: &HashMap<String, Column>,
+    collations: &Collations,
+) -> Result<Vec<Row>> {
+    let mut values = vec![];
+    for call in calls {
+        values.push(window_values(call, &rows, columns, collations)?);
+    }
+    for (i, row) in rows.iter_mut().enumerate() {
+        row.resize(columns.len(), Value::Null);
+        row.extend(
+            values
+                .iter_mut()
+                .map(|values| mem::replace(&mut values[i], Value::Null)),
+        );
+    }
+    Ok(rows)
+}
+
+/// The value of a window function call for each row.
+fn window_values(
+    call: &Expr,
+    rows: &[Row],
+    columns: &HashMap<String, Column>,
+    collations: &Collations,
+) -> Result<Vec<Value>> {
+    let (name, args, window) = match call {
+        Expr::Over { call, window } => match call.as_ref() {
+            Expr::Function { name, args, .. } => (name, args, window.as_ref()),
+            call => bail!("{:?} is not a function call", call),
+        },
+        _ => bail!("{:?} is not a window function call", call),
+    };
+    let kind = WindowKind::from_call(name, args.len())
+        .ok_or_else(|| anyhow!("no such window function: {}", name))?;
+    let Window {
+        partition_by,
+        order_by,
+        frame,
+    } = window;
+    let collation = |expr: Option<&Expr>| -> Result<CollationFn> {
+        let name = expr.map_or(BINARY, |expr| expr.collation_name(columns));
+        collations
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no such collation sequence: {}", name))
+    };
+
+    let start = Bound::new(&frame.start, frame.rows, "starting", collations)?;
+    let end = Bound::new(&frame.end, frame.rows, "ending", collations)?;
+    if matches!(start, Bound::UnboundedFollowing)
+        || matches!(end, Bound::UnboundedPreceding)
+        || start.order() > end.order()
+    {
+        bail!("unsupported frame specification");
+    }
+    let offsets = matches!(start, Bound::Preceding(_) | Bound::Following(_))
+        || matches!(end, Bound::Preceding(_) | Bound::Following(_));
+    if !frame.rows && offsets && order_by.len() != 1 {
+        bail!("RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression");
+    }
+
+    // Rows are sorted by their partition, then by the window's ORDER BY
+    let mut keys = vec![];
+    for expr in partition_by {
+        keys.push(SortKey {
+            desc: false,
+            nulls_first: true,
+            collation: collation(Some(expr))?,
+        });
+    }
+    for term in order_by {
+        keys.push(SortKey {
+            desc: term.desc,
+            nulls_first: term.nulls_first(),
+            collation: collation(Some(&term.expr))?,
+        });
+    }
+    let bind = |expr: &Expr| BoundExpr::new(expr, columns, collations);
+    let key_exprs: Vec<_> = partition_by
+        .iter()
+        .chain(order_by.iter().map(|term| &term.expr))
+        .map(bind)
+        .collect();
+    let bound: Vec<_> = args.iter().map(bind).collect();
+    let key_values: Vec<Vec<Value>> = rows
+        .iter()
+        .map(|row| key_exprs.iter().map(|expr| expr.eval(row)).collect())
+        .collect::<Result<_>>()?;
+    let compare = |a: usize, b: usize, keys: &[SortKey], skip: usize| {
+        keys.iter()
+            .zip(key_values[a].iter().zip(&key_values[b]))
+            .skip(skip)
+            .map(|(key, (a, b))| key.compare(a, b))
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    };
+    let mut sorted: Vec<usize> = (0..rows.len()).collect();
+    sorted.sort_by(|&a, &b| compare(a, b, &keys, 0));
+    let partition_keys = &keys[..partition_by.len()];
+
+    let mut values = vec![Value::Null; rows.len()];
+    let mut first = 0;
+    while first < sorted.len() {
+        let len = sorted[first..]
+            .iter()
+            .take_while(|&&i| compare(sorted[first], i, partition_keys, 0).is_eq())
+            .count();
+        let part = &sorted[first..first + len];
+        first += len;
+
+        let mut peers = vec![(0, 0); len];
+        let mut group = vec![0; len];
+        let mut start_of_peers = 0;
+        for j in 1..=len {
+            if j == len || compare(part[j - 1], part[j], &keys, partition_by.len()).is_ne() {
+                for k in start_of_peers..j {
+                    peers[k] = (start_of_peers, j);
+                    group[k] = if start_of_peers == 0 {
+                        0
+                    } else {
+                        group[start_of_peers - 1] + 1
+                    };
+                }
+                start_of_peers = j;
+            }
+        }
+        let order = part
+            .iter()
+            .map(|&i| {
+                key_values[i]
+                    .get(partition_by.len())
+                    .unwrap_or(&Value::Null)
+            })
+            .collect();
+        let partition = Partition {
+            rows: part,
+            peers,
+            order,
+        };
+        let frame_of = |j: usize| match frame.rows {
+            true => partition.rows_frame(j, start, end),
+            false => {
+                let desc = order_by.first().is_some_and(|term| term.desc);
+                partition.range_frame(j, start, end, desc)
+            }
+        };
+        let arg = |n: usize, j: usize| match bound.get(n) {
+            Some(arg) => arg.eval(&rows[part[j]]),
+            None => Ok(Value::Null),
+        };
+
+        let mut accumulator: Option<(usize, usize, Accumulator)> = None;
+        for j in 0..len {
+            let value = match kind {
+                WindowKind::RowNumber => Value::Integer(j as i64 + 1),
+                WindowKind::Rank => Value::Integer(partition.peers[j].0 as i64 + 1),
+                WindowKind::DenseRank => Value::Integer(group[j] as i64 + 1),
+                WindowKind::Ntile => {
+                    let n = match arg(0, j)? {
+                        Value::Integer(n) if n > 0 => n as usize,
+                        _ => bail!("argument of ntile must be a positive integer"),
+                    };
+                    // The first `len % n` groups get a row more than the others
+                    let (size, larger) = (len / n, len % n);
+                    let tile = match j < larger * (size + 1) {
+                        true => j / (size + 1),
+                        false => larger + (j - larger * (size + 1)) / size,
+                    };
+                    Value::Integer(tile as i64 + 1)
+                }
+                WindowKind::Lag | WindowKind::Lead => {
+                    let offset = match args.get(1).map(|_| arg(1, j)).transpose()? {
+                        None => 1,
+                        Some(Value::Integer(n)) => n,
+                        Some(_) => bail!("second argument to {}() must be an integer", name),
+                    };
+                    let offset = if kind == WindowKind::Lag {
+                        -offset
+                    } else {
+                        offset
+                    };
+                    match (j as i64)
+                        .checked_add(offset)
+                        .filter(|k| (0..len as i64).contains(k))
+                    {
+                        Some(k) => arg(0, k as usize)?,
+                        None => arg(2, j)?,
+                    }
+                }
+                WindowKind::FirstValue | WindowKind::LastValue => {
+                    let (start, end) = frame_of(j);
+                    match (start < end, kind) {
+                        (false, _) => Value::Null,
+                        (true, WindowKind::FirstValue) => arg(0, start)?,
+                        (true, _) => arg(0, end - 1)?,
+                    }
+                }
+                WindowKind::Aggregate(aggregate) => {
+                    let (start, end) = frame_of(j);
+                    let end = end.max(start);
+                    // A frame that only grows at its end keeps adding to the same aggregate
+                    let reusable = accumulator
+                        .as_ref()
+                        .is_some_and(|(first, last, _)| *first == start && *last <= end);
+                    if !reusable {
+                        let acc = Accumulator::new(aggregate, false, collation(args.first())?);
+                        accumulator = Some((start, start, acc));
+                    }
+                    let (_, last, acc) = accumulator.as_mut().expect("accumulator was set");
+                    for k in *last..end {
+                        let values = (0..args.len())
+                            .map(|n| arg(n, k))
+                            .collect::<Result<Vec<_>>>()?;
+                        acc.step(&values)?;
+                    }
+                    *last = end;
+                    acc.finish()
+                }
+            };
+            values[part[j]] = value;
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+fn run_window(sql: &str, rows: Vec<Row>) -> Result<Vec<Value>> {
+    use crate::parsers::sql::{Select, SelectColumn};
+    use crate::sqlite::tables::Table;
+    use std::str::FromStr;
+
+    let select = Select::from_str(sql)?;
+    let call = match &select.columns[0] {
+        SelectColumn::Expr(column) => column.expr.clone(),
+        column => bail!("{:?} is not an expression", column),
+    };
+    let table: Table = "create table t (grp text, n integer)".parse()?;
+    let columns = table.columns;
+    let rows = window_rows(rows, &[call], &columns, &Collations::default())?;
+    Ok(rows.into_iter().map(|mut row| row.remove(2)).collect())
+}
+
+#[test]
+fn test_ranking_functions() {
+    let rows = || {
+        [("a", 1), ("b", 2), ("a", 2), ("a", 2), ("b", 5), ("a", 7)]
+            .into_iter()
+            .map(|(grp, n)| vec![Value::Text(grp.to_string()), Value::Integer(n)])
+            .collect::<Vec<_>>()
+    };
+    let ints = |values: &[i64]| {
+        values
+            .iter()
+            .map(|&n| Value::Integer(n))
+            .collect::<Vec<_>>()
+    };
+    let run = |sql: &str| run_window(sql, rows()).unwrap();
+
+    assert_eq!(
+        run("select row_number() over (partition by grp order by n) from t"),
+        ints(&[1, 1, 2, 3, 2, 4])
+    );
+    assert_eq!(
+        run("select rank() over (order by n) from t"),
+        ints(&[1, 2, 2, 2, 5, 6])
+    );
+    assert_eq!(
+        run("select dense_rank() over (order by n) from t"),
+        ints(&[1, 2, 2, 2, 3, 4])
+    );
+    assert_eq!(
+        run("select ntile(4) over (order by n) from t"),
+        ints(&[1, 1, 2, 2, 3, 4])
+    );
+    assert_eq!(
+        run("select lag(n, 1, 0) over (partition by grp order by n) from t"),
+        ints(&[0, 0, 1, 2, 2, 2])
+    );
+    assert!(run_window("select ntile(0) over () from t", rows()).is_err());
+}
+
+#[test]
+fn test_window_frames() {
+    let rows = || {
+        [1, 2, 2, 4, 8]
+            .into_iter()
+            .map(|n| vec![Value::Text("a".to_string()), Value::Integer(n)])
+            .collect::<Vec<_>>()
+    };
+    let ints = |values: &[i64]| {
+        values
+            .iter()
+            .map(|&n| Value::Integer(n))
+            .collect::<Vec<_>>()
+    };
+    let run = |sql: &str| run_window(sql, rows()).unwrap();
+
+    // The default frame ends with the last peer of the row
+    assert_eq!(
+        run("select sum(n) over (order by n) from t"),
+        ints(&[1, 5, 5, 9, 17])
+    );
+    assert_eq!(
+        run("select sum(n) over (order by n rows between 1 preceding and current row) from t"),
+        ints(&[1, 3, 4, 6, 12])
+    );
+    assert_eq!(
+        run("select count(*) over (order by n range between 2 preceding and 2 following) from t"),
+        ints(&[3, 4, 4, 3, 1])
+    );
+    assert_eq!(
+        run("select last_value(n) over (order by n rows between current row and unbounded following) from t"),
+        ints(&[8, 8, 8, 8, 8])
+    );
+    assert_eq!(run("select max(n) over () from t"), ints(&[8, 8, 8, 8, 8]));
+    assert!(run_window(
+        "select sum(n) over (rows between current row and 1 preceding) from t",
+        rows()
+    )
+    .is_err());
+}
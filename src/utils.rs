@@ -1,43 +1,554 @@
 use colored::Colorize;
-use std::collections::HashMap;
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    iter,
+    ops::Bound,
+};
 
 use crate::{
-    parsers::sql::{create_idx_sql, Condition},
+    parsers::{
+        sql::{Expr, Operator, OrderingTerm},
+        value::Value,
+    },
     sqlite::{
+        collation::{Collations, BINARY},
         db::Row,
+        indexes::Index,
         schemas::{Schema, SchemaType},
-        tables::{Column, Table},
+        stats::{covering_cost, index_cost, intersection_cost, scan_cost, Statistics},
+        tables::{CellType, Column, Table},
     },
 };
 
+/// An index that can answer one of the `column = literal` conditions.
+struct IndexCandidate {
+    rootpage: u64,
+    /// Key to seek, with the affinity of the column applied.
+    key: Value,
+    /// Collation the index is sorted by.
+    collation: String,
+    /// Keys the next columns of the index are equal to, with their collations.
+    prefix: Vec<(Value, String)>,
+    /// Name of the column the key is compared with.
+    column: String,
+    /// Number of columns of the index, the fewer the less there is to read of each entry.
+    width: usize,
+    /// Estimated rows under the key.
+    rows: f64,
+    /// Whether `ANALYZE` measured the index, rather than its size being guessed.
+    measured: bool,
+    /// Whether the entries hold every column the query reads, so rows needn't be looked up.
+    covering: bool,
+}
+
+impl IndexCandidate {
+    /// Cost of finding the rows through the index in a table of `rows` rows.
+    fn cost(&self, rows: f64) -> f64 {
+        match self.covering {
+            true => covering_cost(rows, self.rows),
+            false => index_cost(rows, self.rows),
+        }
+    }
+}
+
+/// The keys of the `column = literal` conditions comparing `column` by `collation`, with the
+/// affinity of the column applied.
+fn equality_keys<'a>(
+    conds: &'a [Expr],
+    column: &'a Column,
+    collation: &'a str,
+    table: &'a Table,
+) -> impl Iterator<Item = Value> + 'a {
+    conds.iter().filter_map(move |cond| {
+        let (name, key) = cond.equality_key()?;
+        let compared = match cond {
+            Expr::Binary(lhs, _, rhs) => Expr::comparison_collation(lhs, rhs, &table.columns),
+            _ => return None,
+        };
+        let usable = table.columns.get(name)?.name == column.name
+            && compared.eq_ignore_ascii_case(collation);
+        usable.then(|| key.clone().apply_affinity(column.cell_type))
+    })
+}
+
+/// Every index that can answer one of the `column = literal` conditions, with the rows it
+/// is expected to find and whether it holds the `used` columns, when those are known. The
+/// equalities on the columns that follow the first one in the index narrow the key, and the
+/// indexes are taken in rootpage order so that ties are always broken the same way.
+fn index_candidates(
+    conds: &[Expr],
+    target: &str,
+    table: &Table,
+    schemas: &HashMap<String, Schema>,
+    stats: &Statistics,
+    collations: &Collations,
+    used: Option<&HashSet<String>>,
+) -> Vec<IndexCandidate> {
+    let mut indexes: Vec<_> = schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.table_name == target)
+        .collect();
+    indexes.sort_by_key(|s| s.rootpage);
+    let mut candidates = vec![];
+    for s in indexes {
+        let index = match Index::try_from(s) {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        let column = match index
+            .columns
+            .first()
+            .and_then(|c| table.columns.get(&c.name))
+        {
+            Some(column) if !index.partial && !index.columns[0].desc => column,
+            _ => continue,
+        };
+        let collation = index
+            .collation(0, table)
+            .unwrap_or_else(|| BINARY.to_string());
+        let cmp = match collations.get(&collation) {
+            Some(cmp) => cmp,
+            None => continue,
+        };
+
+        // The next columns narrow the key for as long as each is compared to a constant too
+        let mut prefix = vec![];
+        for (pos, indexed) in index.columns.iter().enumerate().skip(1) {
+            let next = table.columns.get(&indexed.name).and_then(|next| {
+                let collation = index
+                    .collation(pos, table)
+                    .unwrap_or_else(|| BINARY.to_string());
+                let key = equality_keys(conds, next, &collation, table).next()?;
+                collations.get(&collation)?;
+                Some((key, collation))
+            });
+            match next {
+                Some(key) => prefix.push(key),
+                None => break,
+            }
+        }
+
+        for key in equality_keys(conds, column, &collation, table) {
+            candidates.push(IndexCandidate {
+                rootpage: s.rootpage,
+                rows: stats.equality_rows(&index, &key, 1 + prefix.len(), cmp.as_ref()),
+                measured: stats.measured(&index),
+                covering: used.is_some_and(|used| index_covers(&index, table, used)),
+                key,
+                collation: collation.clone(),
+                prefix: prefix.clone(),
+                column: column.name.clone(),
+                width: index.columns.len(),
+            });
+        }
+    }
+    candidates
+}
+
+/// The seek of an index: its rootpage, the key its first column equals, the collation the
+/// index is sorted by, and the keys its next columns equal with their collations.
+pub type IndexKey = (u64, Value, String, Vec<(Value, String)>);
+
+/// Find the index that answers one of the `column = literal` conditions most cheaply,
+/// returning its [`IndexKey`]. Rows are estimated from the `stats` of `ANALYZE`, and an
+/// index holding every one of the `used` columns saves looking rows up. Of indexes costing
+/// the same, one whose entries come in `order_by` order is chosen, then the one with the
+/// fewest columns, then the lowest rootpage. No index is returned when reading the whole
+/// table is expected to cost less.
+#[allow(clippy::too_many_arguments)]
 pub fn find_table_index(
-    conds: &Vec<Condition>,
+    conds: &[Expr],
     target: &str,
     table: &Table,
     schemas: &HashMap<String, Schema>,
-) -> Option<(u64, String)> {
-    for cond in conds {
-        let (col_name, search_key) = cond.unbox();
-        if let Some(indexable_col) = table.columns.get(col_name) {
-            let index = schemas
-                .iter()
-                .filter(|(_, s)| s.stype == SchemaType::Index && s.table_name == target)
-                .find(|(_, s)| {
-                    let column = match create_idx_sql(&s.sql) {
-                        Ok((_, column)) => column,
-                        Err(_) => return false,
-                    };
-                    column == indexable_col.name
-                })
-                .map(|(_, s)| s.rootpage);
+    stats: &Statistics,
+    collations: &Collations,
+    used: Option<&HashSet<String>>,
+    order_by: &[OrderingTerm],
+) -> Option<IndexKey> {
+    let rows = stats.table_rows(target);
+    let ordered = |candidate: &IndexCandidate| {
+        schemas
+            .values()
+            .filter(|s| s.stype == SchemaType::Index && s.rootpage == candidate.rootpage)
+            .find_map(|s| Index::try_from(s).ok())
+            .and_then(|index| search_order(order_by, &index, 1 + candidate.prefix.len(), table))
+            .is_some()
+    };
+    index_candidates(conds, target, table, schemas, stats, collations, used)
+        .into_iter()
+        .map(|candidate| (candidate.cost(rows), ordered(&candidate), candidate))
+        .min_by(|(a, a_ordered, x), (b, b_ordered, y)| {
+            a.total_cmp(b)
+                .then(b_ordered.cmp(a_ordered))
+                .then(x.width.cmp(&y.width))
+                .then(x.rootpage.cmp(&y.rootpage))
+        })
+        .map(|(cost, _, candidate)| (cost, candidate))
+        .filter(|(cost, _)| *cost < scan_cost(rows))
+        .map(|(_, c)| (c.rootpage, c.key, c.collation, c.prefix))
+}
+
+/// Find indexes whose rowid sets, intersected, find the rows of several `column = literal`
+/// conditions more cheaply than the best single index or a scan, returning the rootpage, key
+/// and collation of each, which only seeks its first column. Only indexes `ANALYZE` measured
+/// are combined, as guessed sizes can't tell whether intersecting pays off.
+pub fn find_index_intersection(
+    conds: &[Expr],
+    target: &str,
+    table: &Table,
+    schemas: &HashMap<String, Schema>,
+    stats: &Statistics,
+    collations: &Collations,
+    used: Option<&HashSet<String>>,
+) -> Option<Vec<(u64, Value, String)>> {
+    let rows = stats.table_rows(target);
+    let candidates = index_candidates(conds, target, table, schemas, stats, collations, used);
+    let single = candidates
+        .iter()
+        .map(|c| c.cost(rows))
+        .fold(scan_cost(rows), f64::min);
+
+    // Add indexes from the most selective one on, for as long as each lowers the cost. Indexes
+    // seeking the same column find the same rows, so only the most selective one is kept.
+    // Each index is only seeked by its first column, which leaves narrower keys out
+    let mut measured: Vec<_> = candidates
+        .into_iter()
+        .filter(|c| c.measured && c.prefix.is_empty())
+        .collect();
+    measured.sort_by(|a, b| a.rows.total_cmp(&b.rows));
+    let mut seeked = HashSet::new();
+    measured.retain(|c| seeked.insert(c.column.clone()));
+    let mut chosen: Vec<IndexCandidate> = vec![];
+    let mut cost = f64::INFINITY;
+    for candidate in measured {
+        let found: Vec<_> = chosen
+            .iter()
+            .chain(iter::once(&candidate))
+            .map(|c| c.rows)
+            .collect();
+        let next = match found.len() {
+            1 => index_cost(rows, found[0]),
+            _ => intersection_cost(rows, &found),
+        };
+        if next >= cost {
+            break;
+        }
+        cost = next;
+        chosen.push(candidate);
+    }
+    (chosen.len() > 1 && cost < single).then(|| {
+        chosen
+            .into_iter()
+            .map(|c| (c.rootpage, c.key, c.collation))
+            .collect()
+    })
+}
+
+/// A b-tree that yields rows already in the order an ORDER BY asks for.
+#[derive(Debug, PartialEq)]
+pub enum OrderedScan {
+    /// The table itself, ordered by its rowid alias.
+    Table,
+    /// The index with this rootpage, followed back to the table by rowid.
+    Index(u64),
+    /// The entries of the index a search seeks under its key, followed back to the table by
+    /// rowid.
+    Search,
+}
+
+/// Find a scan that delivers rows in `order_by` order so sorting can be skipped, flagged
+/// `true` when its output has to be reversed. Of the indexes that do, the one with the
+/// fewest columns is read, then the one with the lowest rootpage.
+pub fn find_order_index(
+    order_by: &[OrderingTerm],
+    target: &str,
+    table: &Table,
+    schemas: &HashMap<String, Schema>,
+) -> Option<(OrderedScan, bool)> {
+    // B-trees are ascending with NULLs first, so read backwards they give the DESC defaults
+    let desc = order_by.first()?.desc;
+    if order_by
+        .iter()
+        .any(|term| term.desc != desc || term.nulls_first() == desc)
+    {
+        return None;
+    }
+    let columns = order_by
+        .iter()
+        .map(|term| match term.expr.uncollated() {
+            Expr::Column(name) => table.columns.get(name),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    // Rowids are unique integers, so neither collations nor later terms matter
+    let first = columns.first()?;
+    if table.key.as_ref() == Some(&first.name) && first.cell_type == CellType::Integer {
+        return Some((OrderedScan::Table, desc));
+    }
 
-            if let Some(idx) = index {
-                let sk = search_key.to_owned();
-                return Some((idx, sk));
+    schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.table_name == target)
+        .filter_map(|s| {
+            let index = Index::try_from(s).ok()?;
+            let usable = !index.partial
+                && index.columns.len() >= order_by.len()
+                && order_by
+                    .iter()
+                    .zip(&columns)
+                    .enumerate()
+                    .all(|(i, (term, column))| {
+                        let index_collation = index
+                            .collation(i, table)
+                            .unwrap_or_else(|| BINARY.to_string());
+                        !index.columns[i].desc
+                            && index.columns[i].name == column.name
+                            && index_collation
+                                .eq_ignore_ascii_case(term.expr.collation_name(&table.columns))
+                    });
+            usable.then_some((index.columns.len(), s.rootpage))
+        })
+        .min()
+        .map(|(_, rootpage)| (OrderedScan::Index(rootpage), desc))
+}
+
+/// Whether the entries of `index` under a key of its first `equal` columns come in
+/// `order_by` order, flagged `true` when they have to be read last first. Terms on those
+/// columns are the same in every entry, and the others must follow the next columns of the
+/// index in turn, or be the rowid alias once every column is followed.
+pub fn search_order(
+    order_by: &[OrderingTerm],
+    index: &Index,
+    equal: usize,
+    table: &Table,
+) -> Option<bool> {
+    let collation = |i: usize| {
+        index
+            .collation(i, table)
+            .unwrap_or_else(|| BINARY.to_string())
+    };
+    let mut terms = vec![];
+    for term in order_by {
+        let column = match term.expr.uncollated() {
+            Expr::Column(name) => table.columns.get(name)?,
+            _ => return None,
+        };
+        let compared = term.expr.collation_name(&table.columns);
+        let fixed = index
+            .columns
+            .iter()
+            .take(equal)
+            .enumerate()
+            .any(|(i, indexed)| {
+                indexed.name == column.name && collation(i).eq_ignore_ascii_case(compared)
+            });
+        if !fixed {
+            terms.push((term, column, compared));
+        }
+    }
+
+    // B-trees are ascending with NULLs first, so read backwards they give the DESC defaults
+    let desc = match terms.first() {
+        Some((term, _, _)) => term.desc,
+        None => return Some(false),
+    };
+    if terms
+        .iter()
+        .any(|(term, _, _)| term.desc != desc || term.nulls_first() == desc)
+    {
+        return None;
+    }
+    for (i, (_, column, compared)) in terms.iter().enumerate() {
+        match index.columns.get(equal + i) {
+            Some(indexed) => {
+                let follows = !indexed.desc
+                    && indexed.name == column.name
+                    && collation(equal + i).eq_ignore_ascii_case(compared);
+                if !follows {
+                    return None;
+                }
+            }
+            // Entries of equal columns come in rowid order, and rowids are unique
+            None => {
+                let rowid = table.key.as_ref() == Some(&column.name)
+                    && column.cell_type == CellType::Integer;
+                return rowid.then_some(desc);
             }
+        }
+    }
+    Some(desc)
+}
+
+/// The range of rowids the `column < literal` conditions, and the like, on the rowid alias of
+/// a table keep its rows to, unbounded on a side no condition limits. Bounds that don't read
+/// as integers are left to the conditions.
+pub fn find_rowid_range(conds: &[Expr], table: &Table) -> (Bound<i64>, Bound<i64>) {
+    let is_rowid = |name: &String| {
+        table.columns.get(name).is_some_and(|column| {
+            table.key.as_ref() == Some(&column.name) && column.cell_type == CellType::Integer
+        })
+    };
+    let mut bounds = vec![];
+    for cond in conds {
+        if let Expr::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } = cond
+        {
+            if let (Expr::Column(name), Expr::Literal(low), Expr::Literal(high)) =
+                (expr.uncollated(), low.uncollated(), high.uncollated())
+            {
+                if is_rowid(name) {
+                    bounds.push((Operator::GreaterEq, low));
+                    bounds.push((Operator::LessEq, high));
+                }
+            }
+        }
+        if let Some((name, op, value)) = cond.range_key() {
+            if is_rowid(name) {
+                bounds.push((op, value));
+            }
+        }
+    }
+
+    // The tighter of two lower bounds is the greater, and of two upper bounds the lesser
+    let lower_key = |bound: &Bound<i64>| match *bound {
+        Bound::Unbounded => (i64::MIN, false),
+        Bound::Included(n) => (n, false),
+        Bound::Excluded(n) => (n, true),
+    };
+    let upper_key = |bound: &Bound<i64>| match *bound {
+        Bound::Unbounded => (i64::MAX, true),
+        Bound::Included(n) => (n, true),
+        Bound::Excluded(n) => (n, false),
+    };
+    let (mut lower, mut upper) = (Bound::Unbounded, Bound::Unbounded);
+    for (op, value) in bounds {
+        let Value::Integer(n) = value.clone().apply_affinity(CellType::Integer) else {
+            continue;
         };
+        match op {
+            Operator::Greater => lower = cmp::max_by_key(lower, Bound::Excluded(n), lower_key),
+            Operator::GreaterEq => lower = cmp::max_by_key(lower, Bound::Included(n), lower_key),
+            Operator::Less => upper = cmp::min_by_key(upper, Bound::Excluded(n), upper_key),
+            _ => upper = cmp::min_by_key(upper, Bound::Included(n), upper_key),
+        }
     }
-    None
+    (lower, upper)
+}
+
+/// Find a scan that yields equal values of the `SELECT DISTINCT` columns next to each other:
+/// the table when the rowid alias makes every row unique, or an index led by those columns.
+pub fn find_distinct_index(
+    exprs: &[Expr],
+    target: &str,
+    table: &Table,
+    schemas: &HashMap<String, Schema>,
+) -> Option<OrderedScan> {
+    let mut columns = exprs
+        .iter()
+        .map(|expr| match expr.uncollated() {
+            Expr::Column(name) => {
+                let column = table.columns.get(name)?;
+                Some((column, expr.collation_name(&table.columns)))
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    columns.sort_by_key(|(column, _)| column.idx);
+    columns.dedup_by(|a, b| a.0.idx == b.0.idx && a.1.eq_ignore_ascii_case(b.1));
+
+    if columns.iter().any(|(column, _)| {
+        table.key.as_ref() == Some(&column.name) && column.cell_type == CellType::Integer
+    }) {
+        return Some(OrderedScan::Table);
+    }
+
+    schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.table_name == target)
+        .filter_map(|s| {
+            let index = Index::try_from(s).ok()?;
+            // The leading index columns, in any order, must be exactly the distinct ones
+            let usable = !index.partial
+                && index.columns.len() >= columns.len()
+                && columns.iter().all(|(column, collation)| {
+                    index.columns[..columns.len()]
+                        .iter()
+                        .enumerate()
+                        .any(|(i, indexed)| {
+                            let index_collation = index
+                                .collation(i, table)
+                                .unwrap_or_else(|| BINARY.to_string());
+                            indexed.name == column.name
+                                && index_collation.eq_ignore_ascii_case(collation)
+                        })
+                });
+            usable.then_some((index.columns.len(), s.rootpage))
+        })
+        // The narrowest index has the fewest pages to read
+        .min()
+        .map(|(_, rootpage)| OrderedScan::Index(rootpage))
+}
+
+/// The columns of `columns` that the entries of `index` hold, renumbered by their place in
+/// an entry: the indexed columns first, then the rowid, which stands for the rowid alias.
+pub fn index_columns(
+    index: &Index,
+    table: &Table,
+    columns: &HashMap<String, Column>,
+) -> HashMap<String, Column> {
+    columns
+        .iter()
+        .filter_map(|(name, column)| {
+            let rowid =
+                table.key.as_ref() == Some(&column.name) && column.cell_type == CellType::Integer;
+            let idx = match index.columns.iter().position(|c| c.name == column.name) {
+                Some(idx) => idx,
+                None if rowid => index.columns.len(),
+                None => return None,
+            };
+            Some((
+                name.clone(),
+                Column {
+                    idx,
+                    ..column.clone()
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Whether the entries of `index` hold every one of the `used` columns of `table`, so rows
+/// can be read from them without looking the table up.
+pub fn index_covers(index: &Index, table: &Table, used: &HashSet<String>) -> bool {
+    index_columns(index, table, &table.columns)
+        .values()
+        .filter(|column| used.contains(&column.name))
+        .count()
+        == used.len()
+}
+
+/// Find the index with the fewest columns whose entries hold every one of the `used`
+/// columns, returning its rootpage. Reading it instead of the table reads fewer pages.
+pub fn find_covering_index(
+    used: &HashSet<String>,
+    target: &str,
+    table: &Table,
+    schemas: &HashMap<String, Schema>,
+) -> Option<u64> {
+    schemas
+        .values()
+        .filter(|s| s.stype == SchemaType::Index && s.table_name == target)
+        .filter_map(|s| Some((s.rootpage, Index::try_from(s).ok()?)))
+        .filter(|(_, index)| !index.partial && index_covers(index, table, used))
+        .min_by_key(|(rootpage, index)| (index.columns.len(), *rootpage))
+        .map(|(rootpage, _)| rootpage)
 }
 
 fn truncate(s: &str, max_chars: usize) -> &str {
@@ -47,20 +558,19 @@ fn truncate(s: &str, max_chars: usize) -> &str {
     }
 }
 
-pub fn print_rows(rows: Vec<Row>, columns: Vec<Column>) {
+pub fn print_rows(rows: Vec<Row>, headers: Vec<String>) {
     use prettytable::{Cell, Row, Table};
     let mut table = Table::new();
 
     table.add_row(Row::new(
-        columns.iter().map(|c| Cell::new(c.name.as_str())).collect(),
+        headers.iter().map(|h| Cell::new(h.as_str())).collect(),
     ));
     for row in rows.clone() {
         table.add_row(Row::new(
-            columns
-                .iter()
-                .map(|c| {
-                    let text = row[c.idx].to_string();
-                    if text.len() > 10 && columns.len() > 4 {
+            row.iter()
+                .map(|value| {
+                    let text = value.to_string();
+                    if text.len() > 10 && headers.len() > 4 {
                         let text = truncate(&text, 10);
                         let text = text.to_string() + "...";
                         return Cell::new(&text[..]);
@@ -85,3 +595,299 @@ pub fn elog(msg: &str) {
 pub fn wlog(msg: &str) {
     eprint!("{} {}", "∆(w)".yellow(), msg.yellow());
 }
+
+/// The schema of the index `name` on `columns` of the table `t`.
+#[cfg(test)]
+fn index_schema(name: &str, columns: &str, rootpage: u64) -> Schema {
+    Schema {
+        stype: SchemaType::Index,
+        name: name.to_string(),
+        table_name: "t".to_string(),
+        rootpage,
+        sql: format!("CREATE INDEX {name} ON t ({columns})"),
+    }
+}
+
+#[test]
+fn test_index_covers() {
+    let table: Table = "CREATE TABLE emp (id integer primary key, name text, dept text)"
+        .parse()
+        .unwrap();
+    let index: Index = "CREATE INDEX idx_emp_dept ON emp (dept)".parse().unwrap();
+    let used = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+
+    let columns = index_columns(&index, &table, &table.columns);
+    assert_eq!(columns["dept"].idx, 0);
+    assert_eq!(columns["id"].idx, 1);
+    assert!(!columns.contains_key("name"));
+
+    assert!(index_covers(&index, &table, &used(&[])));
+    assert!(index_covers(&index, &table, &used(&["dept", "id"])));
+    assert!(!index_covers(&index, &table, &used(&["dept", "name"])));
+}
+
+#[test]
+fn test_find_index_intersection() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b integer)"
+        .parse()
+        .unwrap();
+    let schemas: HashMap<_, _> = [index_schema("idx_a", "a", 3), index_schema("idx_b", "b", 4)]
+        .into_iter()
+        .map(|s| (s.name.clone(), s))
+        .collect();
+    let conds = Select::from_str("select * from t where a = 3 and b = 4")
+        .unwrap()
+        .conds;
+    let collations = Collations::default();
+    let find = |stats: &Statistics| {
+        let found =
+            find_index_intersection(&conds, "t", &table, &schemas, stats, &collations, None);
+        found.map(|seeks| seeks.into_iter().map(|(rootpage, ..)| rootpage).collect())
+    };
+
+    // Sizes that are only guessed never make indexes worth combining
+    let mut stats = Statistics::default();
+    assert_eq!(find(&stats), None::<Vec<u64>>);
+
+    stats.add_stat1("t", Some("idx_a"), "10000 1000");
+    stats.add_stat1("t", Some("idx_b"), "10000 1429");
+    assert_eq!(find(&stats), Some(vec![3, 4]));
+
+    // A key matching a handful of rows is best filtered on its own
+    stats.add_stat1("t", Some("idx_a"), "10000 2");
+    assert_eq!(find(&stats), None);
+}
+
+#[test]
+fn test_find_index_intersection_same_column() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b integer)"
+        .parse()
+        .unwrap();
+    let schemas: HashMap<_, _> = [
+        index_schema("idx_a", "a", 3),
+        index_schema("idx_b", "b", 4),
+        index_schema("idx_ab", "a, b", 5),
+    ]
+    .into_iter()
+    .map(|s| (s.name.clone(), s))
+    .collect();
+    let collations = Collations::default();
+    let find = |sql: &str, stats: &Statistics| {
+        let conds = Select::from_str(sql).unwrap().conds;
+        let found =
+            find_index_intersection(&conds, "t", &table, &schemas, stats, &collations, None);
+        found.map(|seeks| seeks.into_iter().map(|(rootpage, ..)| rootpage).collect())
+    };
+
+    let mut stats = Statistics::default();
+    stats.add_stat1("t", Some("idx_a"), "10000 1000");
+    stats.add_stat1("t", Some("idx_b"), "10000 1429");
+    stats.add_stat1("t", Some("idx_ab"), "10000 900 100");
+
+    // Two indexes led by the same column seek the same rows, so intersecting them saves nothing
+    assert_eq!(
+        find("select * from t where a = 3", &stats),
+        None::<Vec<u64>>
+    );
+    // Seeking both columns of the wider index beats intersecting, unless that finds as many
+    assert_eq!(
+        find("select * from t where a = 3 and b = 4", &stats),
+        None::<Vec<u64>>
+    );
+    stats.add_stat1("t", Some("idx_ab"), "10000 900 900");
+    assert_eq!(
+        find("select * from t where a = 3 and b = 4", &stats),
+        Some(vec![3, 4])
+    );
+}
+
+#[test]
+fn test_find_covering_table_index() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b integer, c text)"
+        .parse()
+        .unwrap();
+    let schemas: HashMap<_, _> = [index_schema("t_a", "a", 3), index_schema("t_ab", "a, b", 4)]
+        .into_iter()
+        .map(|s| (s.name.clone(), s))
+        .collect();
+    let conds = Select::from_str("select * from t where a = 3")
+        .unwrap()
+        .conds;
+    let stats = Statistics::default();
+    let collations = Collations::default();
+    let find = |used: &[&str]| {
+        let used: HashSet<_> = used.iter().map(|n| n.to_string()).collect();
+        find_table_index(
+            &conds,
+            "t",
+            &table,
+            &schemas,
+            &stats,
+            &collations,
+            Some(&used),
+            &[],
+        )
+        .map(|(rootpage, ..)| rootpage)
+    };
+
+    // Both indexes find the same rows, but only the wider one saves the lookups
+    assert_eq!(find(&["a", "b"]), Some(4));
+    assert_eq!(find(&["id", "a", "b"]), Some(4));
+}
+
+#[test]
+fn test_find_table_index_prefix() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b integer, c text)"
+        .parse()
+        .unwrap();
+    let schemas: HashMap<_, _> = [
+        index_schema("t_ab", "a, b", 2),
+        index_schema("t_a2", "a", 4),
+        index_schema("t_a", "a", 3),
+    ]
+    .into_iter()
+    .map(|s| (s.name.clone(), s))
+    .collect();
+    let stats = Statistics::default();
+    let collations = Collations::default();
+    let find = |sql: &str| {
+        let select = Select::from_str(sql).unwrap();
+        let (conds, order_by) = (&select.conds, &select.order_by);
+        find_table_index(
+            conds,
+            "t",
+            &table,
+            &schemas,
+            &stats,
+            &collations,
+            None,
+            order_by,
+        )
+        .map(|(rootpage, _, _, prefix)| (rootpage, prefix))
+    };
+
+    // Equal costs go to the index with the fewest columns, then the lowest rootpage
+    assert_eq!(find("select * from t where a = 3"), Some((3, vec![])));
+    // Equalities on the next columns narrow the key of the index they lead into
+    let narrowed = Some((2, vec![(Value::Integer(4), BINARY.to_string())]));
+    assert_eq!(find("select * from t where b = '4' and a = 3"), narrowed);
+    // Unless another one gives the order asked for
+    assert_eq!(
+        find("select * from t where a = 3 order by b"),
+        Some((2, vec![]))
+    );
+    assert_eq!(
+        find("select * from t where a = 3 order by id"),
+        Some((3, vec![]))
+    );
+}
+
+#[test]
+fn test_find_order_index() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b integer)"
+        .parse()
+        .unwrap();
+    let schemas: HashMap<_, _> = [
+        index_schema("t_ab", "a, b", 2),
+        index_schema("t_a2", "a", 4),
+        index_schema("t_a", "a", 3),
+    ]
+    .into_iter()
+    .map(|s| (s.name.clone(), s))
+    .collect();
+    let find = |sql: &str| {
+        let order_by = Select::from_str(sql).unwrap().order_by;
+        find_order_index(&order_by, "t", &table, &schemas)
+    };
+
+    // Every index led by the column gives its order, and the narrowest, first built one is read
+    let by_a = Some((OrderedScan::Index(3), true));
+    assert_eq!(find("select * from t order by a desc"), by_a);
+    assert_eq!(
+        find("select * from t order by a, b"),
+        Some((OrderedScan::Index(2), false))
+    );
+    assert_eq!(
+        find("select * from t order by id"),
+        Some((OrderedScan::Table, false))
+    );
+}
+
+#[test]
+fn test_search_order() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer, b text, c integer)"
+        .parse()
+        .unwrap();
+    let index = Index::try_from(&index_schema("t_abc", "a, b collate nocase, c", 2)).unwrap();
+    let order = |sql: &str, equal| {
+        let order_by = Select::from_str(sql).unwrap().order_by;
+        search_order(&order_by, &index, equal, &table)
+    };
+
+    // The key columns are the same in every entry, and the next ones must follow in turn
+    assert_eq!(
+        order("select * from t order by b collate nocase", 1),
+        Some(false)
+    );
+    assert_eq!(
+        order("select * from t order by a desc, b collate nocase desc", 1),
+        Some(true)
+    );
+    assert_eq!(order("select * from t order by b", 1), None);
+    assert_eq!(order("select * from t order by c", 1), None);
+    assert_eq!(order("select * from t order by c, a", 2), Some(false));
+    // Once every column is followed, entries come in rowid order
+    assert_eq!(order("select * from t order by c, id", 2), Some(false));
+    assert_eq!(order("select * from t order by id", 1), None);
+    assert_eq!(order("select * from t order by id", 3), Some(false));
+}
+
+#[test]
+fn test_find_rowid_range() {
+    use std::str::FromStr;
+
+    use crate::parsers::sql::Select;
+
+    let table: Table = "CREATE TABLE t (id integer primary key, a integer)"
+        .parse()
+        .unwrap();
+    let range = |sql: &str| find_rowid_range(&Select::from_str(sql).unwrap().conds, &table);
+
+    assert_eq!(
+        range("select * from t where id > 5 and id <= '9' and id >= 7"),
+        (Bound::Included(7), Bound::Included(9))
+    );
+    assert_eq!(
+        range("select * from t where 5 > id and id between -3 and 8"),
+        (Bound::Included(-3), Bound::Excluded(5))
+    );
+    // Other columns, and bounds that aren't integers, are left to the conditions
+    assert_eq!(
+        range("select * from t where a > 5 and id < 2.5 and id not between 1 and 2"),
+        (Bound::Unbounded, Bound::Unbounded)
+    );
+}